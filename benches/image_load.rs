@@ -15,7 +15,8 @@ use image::metadata::Orientation;
 use image::{DynamicImage, ImageResult, RgbaImage};
 use imflow::image::{
     ImflowImageBuffer, get_orientation, get_rating, image_to_rgba_buffer, load_available_images,
-    load_image, load_thumbnail_exif, load_thumbnail_full,
+    load_heif_full, load_heif_thumbnail, load_image, load_jxl_preview, load_thumbnail_exif,
+    load_thumbnail_full,
 };
 use jpegxl_rs::Endianness;
 use jpegxl_rs::decode::{Data, PixelFormat, Pixels};
@@ -112,6 +113,7 @@ fn load_a(path: &PathBuf) -> ImflowImageBuffer {
         height,
         rgba_buffer: buffer_u32,
         rating,
+        hdr: false,
     }
 }
 
@@ -151,6 +153,7 @@ fn load_b(path: &PathBuf) -> ImflowImageBuffer {
         height,
         rgba_buffer: buffer_u32,
         rating,
+        hdr: false,
     }
 }
 
@@ -288,7 +291,53 @@ pub fn jxl_multithreading_benchmark(c: &mut Criterion) {
 
     group.finish();
 }
+pub fn heif_thumbnail_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("heif_thumbnail");
+
+    group
+        .sample_size(10)
+        .measurement_time(Duration::from_millis(500))
+        .warm_up_time(Duration::from_millis(200));
+
+    let images = load_available_images("./test_images/heif".into());
+    group.bench_function("thumbnail_track", |b| {
+        for image in images.iter().take(10) {
+            b.iter(|| load_heif_thumbnail(image));
+        }
+    });
+    group.bench_function("full_resize", |b| {
+        for image in images.iter().take(10) {
+            b.iter(|| load_heif_full(image, true));
+        }
+    });
+
+    group.finish();
+}
+pub fn jxl_thumbnail_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("jxl_thumbnail");
+
+    group
+        .sample_size(10)
+        .measurement_time(Duration::from_millis(500))
+        .warm_up_time(Duration::from_millis(200));
+
+    let images = load_available_images("./test_images/jxl".into());
+    group.bench_function("preview", |b| {
+        for image in images.iter().filter(|image| image.has_preview).take(10) {
+            b.iter(|| load_jxl_preview(image));
+        }
+    });
+    group.bench_function("full", |b| {
+        for image in images.iter().take(10) {
+            b.iter(|| load_jxl_multi(&image.path));
+        }
+    });
+
+    group.finish();
+}
 // criterion_group!(benches, thumbnail_load_benchmark);
 // criterion_group!(benches, file_load_benchmark);
+// criterion_group!(benches, heif_thumbnail_benchmark);
+// criterion_group!(benches, jxl_thumbnail_benchmark);
 criterion_group!(benches, jxl_multithreading_benchmark);
 criterion_main!(benches);
@@ -0,0 +1,11 @@
+#![no_main]
+
+use imflow::image::load_image;
+use imflow_fuzz::FuzzedImageData;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: FuzzedImageData| {
+    input.with_file(|image| {
+        let _ = load_image(image);
+    });
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use imflow::image::load_thumbnail_exif;
+use imflow_fuzz::FuzzedImageData;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: FuzzedImageData| {
+    input.with_file(|image| {
+        let _ = load_thumbnail_exif(image);
+    });
+});
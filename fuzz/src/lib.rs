@@ -0,0 +1,74 @@
+//! Shared input for the decoder fuzz targets. Run one with
+//! `cargo +nightly fuzz run fuzz_load_image` from the repository root.
+//!
+//! The targets call the loaders directly, without the `catch_unwind` the store wraps
+//! them in: libfuzzer-sys aborts on any panic, so every panic is reported as a crash.
+//! Running out of memory is reported separately, past libFuzzer's `-rss_limit_mb`.
+
+use imflow::image::{ImageData, ImageFormat};
+use libfuzzer_sys::arbitrary::{self, Arbitrary};
+use std::io::Write;
+
+/// Which decoder a fuzz input goes to.
+#[derive(Arbitrary, Debug, Clone, Copy)]
+pub enum FuzzedFormat {
+    Jpg,
+    Jxl,
+    Heif,
+    Raw,
+    Exr,
+    Psd,
+}
+
+impl FuzzedFormat {
+    fn format(self) -> ImageFormat {
+        match self {
+            FuzzedFormat::Jpg => ImageFormat::Jpg,
+            FuzzedFormat::Jxl => ImageFormat::Jxl,
+            FuzzedFormat::Heif => ImageFormat::Heif,
+            FuzzedFormat::Raw => ImageFormat::Raw,
+            FuzzedFormat::Exr => ImageFormat::Exr,
+            FuzzedFormat::Psd => ImageFormat::Psd,
+        }
+    }
+
+    /// Some of the metadata readers go by the extension rather than the contents.
+    fn extension(self) -> &'static str {
+        match self {
+            FuzzedFormat::Jpg => "jpg",
+            FuzzedFormat::Jxl => "jxl",
+            FuzzedFormat::Heif => "heic",
+            FuzzedFormat::Raw => "dng",
+            FuzzedFormat::Exr => "exr",
+            FuzzedFormat::Psd => "psd",
+        }
+    }
+}
+
+/// An `ImageData` as the loaders see it, with the file contents alongside.
+#[derive(Arbitrary, Debug)]
+pub struct FuzzedImageData {
+    pub format: FuzzedFormat,
+    pub has_preview: bool,
+    pub contents: Vec<u8>,
+}
+
+impl FuzzedImageData {
+    /// Writes the contents to a temporary file and passes `f` an `ImageData` for it.
+    /// The loaders read from paths, so there is no way around the file.
+    pub fn with_file<T>(&self, f: impl FnOnce(&ImageData) -> T) -> T {
+        let mut file = tempfile::Builder::new()
+            .suffix(&format!(".{}", self.format.extension()))
+            .tempfile()
+            .unwrap();
+        file.write_all(&self.contents).unwrap();
+        file.flush().unwrap();
+        let image = ImageData {
+            path: file.path().to_path_buf(),
+            format: self.format.format(),
+            has_preview: self.has_preview,
+            capture_date: None,
+        };
+        f(&image)
+    }
+}
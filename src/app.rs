@@ -1,19 +1,51 @@
+#[cfg(target_os = "linux")]
+use crate::dbus::{AppCommand, DbusService};
+use crate::debug_overlay::DebugOverlay;
 use crate::egui_tools::EguiRenderer;
-use egui::{Event, Key, PointerButton};
+use crate::external::{open_in_editor, reveal_in_file_manager};
+use crate::gpu_profile::{GPU_PROFILE_FEATURES, GpuProfiler};
+use crate::keymap::{Action, KeyMap};
+use crate::lanczos::{LANCZOS_MAX_SCALE, LANCZOS_MIN_SCALE, LanczosResampler};
+use crate::map_tile::{MapTiles, TileKey, draw_map_tile};
+use crate::settings::settings_ui;
+use crate::toast::{ToastKind, ToastQueue};
+use chrono::{Datelike, Months, NaiveDate};
+use egui::{Event, Key, Modifiers, MouseWheelUnit, PointerButton};
 use egui_wgpu::wgpu::SurfaceError;
 use egui_wgpu::{ScreenDescriptor, wgpu};
-use imflow::store::ImageStore;
-use std::path::PathBuf;
-use std::process::exit;
-use std::sync::Arc;
+use imflow::config::{
+    BackendSelection, BackgroundMode, Config, MAX_MAP_TILE_ZOOM, MAX_UI_SCALE, MIN_MAP_TILE_ZOOM,
+    MIN_UI_SCALE, SamplingMode, TransformMemory, WindowGeometry,
+};
+use imflow::dedup::DEFAULT_DUPLICATE_THRESHOLD;
+use imflow::export::{copy_image_to_target, save_image_buffer};
+use imflow::image::{
+    ColorLabel, FlagStatus, Histogram, ImageData, ImageMetadata, ImflowImageBuffer, MetadataCache,
+    ViewOrientation,
+};
+use imflow::manifest::ManifestFormat;
+use imflow::store::{DuplicateProgress, FlagFilter, ImageStore, LoadState, Wake, Wrapped};
+use itertools::Itertools;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
 use wgpu::util::DeviceExt;
 use wgpu::{PipelineCompilationOptions, SurfaceConfiguration};
 use winit::application::ApplicationHandler;
-use winit::dpi::{LogicalSize, PhysicalSize};
+use winit::dpi::{LogicalSize, PhysicalPosition, PhysicalSize};
 use winit::event::WindowEvent;
-use winit::event_loop::ActiveEventLoop;
+use winit::event_loop::{ActiveEventLoop, ControlFlow};
+#[cfg(target_os = "macos")]
+use winit::platform::macos::WindowAttributesExtMacOS;
+#[cfg(all(target_os = "linux", feature = "wayland"))]
+use winit::platform::wayland::WindowAttributesExtWayland;
+#[cfg(all(target_os = "linux", feature = "x11"))]
 use winit::platform::x11::WindowAttributesExtX11;
-use winit::window::{Window, WindowId};
+use winit::window::{Fullscreen, Window, WindowAttributes, WindowId};
 
 // Uniforms for transformations
 #[repr(C)]
@@ -22,31 +54,336 @@ struct Transforms {
     transform: [f32; 16], // 4x4 matrix
     width: u32,
     height: u32,
-    _padding1: u32,
-    _padding2: u32,
+    sharpen_enabled: u32,
+    sharpen_amount: f32,
+    zoom: f32, // screen pixels per image pixel
+    hdr_mode: u32,
+    use_lanczos: u32,
+    resampled_width: u32,
+    resampled_height: u32,
+    alpha_background: u32,
+    nearest: u32,
+    clipping_enabled: u32,
+    // sRGB levels from 0 to 1 at or beyond which a channel counts as clipped
+    clip_high: f32,
+    clip_low: f32,
+    opacity: f32,
+    _padding: u32,
 }
 
 pub(crate) struct TransformData {
     pan_x: f32,
     pan_y: f32,
     zoom: f32,
+    // Where `pan_x`, `pan_y` and `zoom` are animating towards
+    target_pan_x: f32,
+    target_pan_y: f32,
+    target_zoom: f32,
+    // Pan speed left over from a drag, in physical screen pixels per second
+    pan_velocity_x: f32,
+    pan_velocity_y: f32,
     width: u32,
     height: u32,
+    sharpen: bool,
+    sharpen_amount: f32,
+    /// Whether clipped highlights and shadows are marked, see `CLIP_HIGH`
+    clipping: bool,
+    /// Rotation and mirroring applied on top of the decoded image
+    orientation: ViewOrientation,
+    hdr: bool,
+    hdr_override: Option<bool>,
+}
+
+impl TransformData {
+    fn snap_to_target(&mut self) {
+        self.zoom = self.target_zoom;
+        self.pan_x = self.target_pan_x;
+        self.pan_y = self.target_pan_y;
+    }
+
+    /// Moves the displayed zoom and pan a fraction `alpha` of the way to the target.
+    /// Zoom is interpolated in log space so zooming in and out feel equally fast.
+    /// Returns false if there was nothing left to animate.
+    fn step_toward_target(&mut self, alpha: f32) -> bool {
+        if self.zoom == self.target_zoom
+            && self.pan_x == self.target_pan_x
+            && self.pan_y == self.target_pan_y
+        {
+            return false;
+        }
+        let log_zoom = self.zoom.ln() + (self.target_zoom.ln() - self.zoom.ln()) * alpha;
+        self.zoom = log_zoom.exp();
+        self.pan_x += (self.target_pan_x - self.pan_x) * alpha;
+        self.pan_y += (self.target_pan_y - self.pan_y) * alpha;
+        // The exponential never arrives on its own
+        if (self.zoom / self.target_zoom).ln().abs() < 1e-3
+            && (self.pan_x - self.target_pan_x).abs() < 1e-4
+            && (self.pan_y - self.target_pan_y).abs() < 1e-4
+        {
+            self.snap_to_target();
+        }
+        true
+    }
+}
+
+const ZOOM_MULTIPLIER: f32 = 3.0;
+const MAX_ZOOM: f32 = 20.0;
+// Rates for held pan/zoom keys. The pan speed is in screen pixels per second at fit
+// zoom and grows with the zoom level.
+const KEY_PAN_SPEED: f32 = 600.0;
+const KEY_ZOOM_SPEED: f32 = 1.5;
+// Zoom change per line of mouse wheel scrolling
+const WHEEL_ZOOM_SPEED: f32 = 0.2;
+// Touchpads scroll in points and mouse wheels in lines, this converts between them
+const POINTS_PER_LINE: f32 = 40.0;
+// Share of the pan velocity kept over each 16 ms after a drag is released
+const INERTIA_FRICTION: f32 = 0.85;
+// Below this speed, in screen pixels per second, the glide stops
+const INERTIA_MIN_SPEED: f32 = 5.0;
+// Farthest apart, in points, that two clicks still make a double-click
+const DOUBLE_CLICK_MAX_DISTANCE: f32 = 6.0;
+// Thumbnails shown on either side of the current image in the filmstrip
+const FILMSTRIP_NEIGHBORS: usize = 8;
+const FILMSTRIP_HEIGHT: f32 = 64.0;
+// Longer side of the navigator shown while zoomed in, in points
+const MINIMAP_SIZE: f32 = 160.0;
+// How long a navigation key has to be held before it starts repeating
+const SCRUB_DELAY: Duration = Duration::from_millis(300);
+const DEFAULT_SHARPEN_AMOUNT: f32 = 1.5;
+// How far images slide during a navigation transition, in clip space where the
+// window is 2 wide
+const TRANSITION_SLIDE: f32 = 0.1;
+// Clipping warning thresholds for 254 and 1 out of 255, set halfway to the next level
+// to absorb rounding in the sRGB decode
+const CLIP_HIGH: f32 = 253.5 / 255.0;
+const CLIP_LOW: f32 = 1.5 / 255.0;
+const STAR_SIZE: f32 = 28.0;
+// Width of the progress bar shown while a folder's thumbnails load, in points
+const SPLASH_WIDTH: f32 = 320.0;
+// Change of `Config::ui_scale` per Ctrl+= or Ctrl+- press
+const UI_SCALE_STEP: f32 = 0.1;
+// With `confirm_quit`, how soon the second quit press has to follow the first
+const QUIT_CONFIRM_WINDOW: Duration = Duration::from_secs(2);
+// Tags the toast that follows a duplicate search, see `ToastQueue::push_progress`
+const DUPLICATES_TOAST: &str = "duplicates";
+// Longest frame time animations and held keys advance by, so the first frame after
+// idling doesn't jump by the whole idle time
+const MAX_FRAME_TIME: f32 = 0.1;
+// How long the window has to stay put before its size and position are saved
+const GEOMETRY_SAVE_DELAY: Duration = Duration::from_secs(1);
+// How long quitting waits for exports and decodes before abandoning them
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+const HISTOGRAM_HEIGHT: f32 = 80.0;
+// Full images are reduced to fit this size before their histogram is computed
+const HISTOGRAM_SAMPLE_SIZE: u32 = 1024;
+// Larger images are reduced to fit this size before they are copied to the clipboard,
+// some clipboard managers choke on bigger ones
+const CLIPBOARD_MAX_SIZE: u32 = 4096;
+// Height of the caption editor before it scrolls, in points
+const CAPTION_HEIGHT: f32 = 120.0;
+// Side of a day in the jump-to-date calendar, in points
+const CALENDAR_DAY_SIZE: f32 = 32.0;
+const RATING_PULSE: Duration = Duration::from_millis(300);
+const STATUS_BAR_HEIGHT: f32 = 22.0;
+// With auto-hide on, the rating window fades out over AUTO_HIDE_FADE once input
+// has stopped for AUTO_HIDE_DELAY
+const AUTO_HIDE_DELAY: Duration = Duration::from_secs(2);
+const AUTO_HIDE_FADE: Duration = Duration::from_millis(400);
+// The status bar hides once the cursor has rested this long
+const STATUS_BAR_IDLE_TIMEOUT: Duration = Duration::from_secs(3);
+// Frame size for `App::run_headless`, the same as the initial window
+const HEADLESS_SIZE: (u32, u32) = (1800, 1200);
+
+fn next_pow2(n: u32) -> u32 {
+    n.next_power_of_two()
+}
+
+/// Size of the texture a `width` × `height` image is uploaded to. Some drivers only
+/// handle power-of-two textures, the shader clips to the content.
+fn image_texture_size(width: u32, height: u32) -> wgpu::Extent3d {
+    wgpu::Extent3d {
+        width: next_pow2(width),
+        height: next_pow2(height),
+        depth_or_array_layers: 1,
+    }
+}
+
+/// Replaces `texture` with one sized for a `width` × `height` image unless it already
+/// is. Returns whether it did, in which case the bind groups using it are stale.
+fn fit_image_texture(
+    device: &wgpu::Device,
+    texture: &mut wgpu::Texture,
+    width: u32,
+    height: u32,
+) -> bool {
+    if texture.size() == image_texture_size(width, height) {
+        return false;
+    }
+    *texture = create_image_texture(device, width, height);
+    true
+}
+
+/// Writes `imbuf` into the top left corner of the image texture.
+fn upload_image(queue: &wgpu::Queue, texture: &wgpu::Texture, imbuf: &ImflowImageBuffer) {
+    let width = imbuf.width as u32;
+    let height = imbuf.height as u32;
+    let (buffer_u8, bytes_per_row) =
+        pad_rows(bytemuck::cast_slice(&imbuf.rgba_buffer), width, height);
+    queue.write_texture(
+        wgpu::TexelCopyTextureInfo {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: wgpu::TextureAspect::All,
+        },
+        &buffer_u8,
+        wgpu::TexelCopyBufferLayout {
+            offset: 0,
+            bytes_per_row: Some(bytes_per_row), // 4 bytes per ARGB pixel, padded
+            rows_per_image: Some(height),
+        },
+        wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+    );
+}
+
+/// Copies `buffer` into rows padded to `wgpu::COPY_BYTES_PER_ROW_ALIGNMENT`, returning
+/// the data along with the padded row size. Borrows when no padding is needed.
+fn pad_rows(buffer: &[u8], width: u32, height: u32) -> (Cow<'_, [u8]>, u32) {
+    let unpadded_bytes_per_row = 4 * width;
+    let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+        * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    if padded_bytes_per_row == unpadded_bytes_per_row {
+        return (Cow::Borrowed(buffer), unpadded_bytes_per_row);
+    }
+
+    let mut padded = vec![0u8; (padded_bytes_per_row * height) as usize];
+    for (src, dst) in buffer
+        .chunks_exact(unpadded_bytes_per_row as usize)
+        .zip(padded.chunks_exact_mut(padded_bytes_per_row as usize))
+    {
+        dst[..src.len()].copy_from_slice(src);
+    }
+    (Cow::Owned(padded), padded_bytes_per_row)
+}
+
+/// Columns of the 2×2 matrix that turns and mirrors the quad, whose coordinates span
+/// -1 to 1 with y pointing up.
+fn orientation_matrix(orientation: ViewOrientation) -> [[f32; 2]; 2] {
+    let mut columns = [[1.0, 0.0], [0.0, 1.0]];
+    for column in &mut columns {
+        for _ in 0..orientation.quarter_turns % 4 {
+            // A quarter turn clockwise
+            *column = [column[1], -column[0]];
+        }
+        if orientation.flipped {
+            column[0] = -column[0];
+        }
+    }
+    columns
 }
 
 #[rustfmt::skip]
 fn create_transform_matrix(data: &TransformData, scale_x: f32, scale_y: f32) -> [f32; 16] {
-    const ZOOM_MULTIPLIER: f32 = 3.0;
     let zoom = data.zoom.powf(ZOOM_MULTIPLIER);
+    let [c0, c1] = orientation_matrix(data.orientation);
 
     [
-        zoom * scale_x, 0.0,            0.0, 0.0,
-        0.0,            zoom * scale_y, 0.0, 0.0,
-        0.0,            0.0,            1.0, 0.0,
-        data.pan_x,     data.pan_y,     0.0, 1.0,
+        zoom * scale_x * c0[0], zoom * scale_y * c0[1], 0.0, 0.0,
+        zoom * scale_x * c1[0], zoom * scale_y * c1[1], 0.0, 0.0,
+        0.0,                    0.0,                    1.0, 0.0,
+        data.pan_x,             data.pan_y,             0.0, 1.0,
     ]
 }
 
+/// Maps a point in clip space back through the transform to the displayed image, as
+/// fractions of its width and height measured from the top left.
+fn clip_to_uv(data: &TransformData, scale_x: f32, scale_y: f32, clip: egui::Pos2) -> egui::Pos2 {
+    let zoom = data.zoom.powf(ZOOM_MULTIPLIER);
+    let x = (clip.x - data.pan_x) / (zoom * scale_x);
+    let y = (clip.y - data.pan_y) / (zoom * scale_y);
+    // The orientation matrix only turns and mirrors, so its transpose undoes it
+    let [c0, c1] = orientation_matrix(data.orientation);
+    let vertex_x = c0[0] * x + c0[1] * y;
+    let vertex_y = c1[0] * x + c1[1] * y;
+    egui::pos2((vertex_x + 1.0) / 2.0, (1.0 - vertex_y) / 2.0)
+}
+
+/// The part of the image that is on screen, as fractions of its width and height
+/// measured from the top left.
+fn visible_fraction(data: &TransformData, scale_x: f32, scale_y: f32) -> egui::Rect {
+    // Screen corners in clip space, which spans -1 to 1 with y pointing up
+    egui::Rect::from_points(&[
+        clip_to_uv(data, scale_x, scale_y, egui::pos2(-1.0, -1.0)),
+        clip_to_uv(data, scale_x, scale_y, egui::pos2(-1.0, 1.0)),
+        clip_to_uv(data, scale_x, scale_y, egui::pos2(1.0, -1.0)),
+        clip_to_uv(data, scale_x, scale_y, egui::pos2(1.0, 1.0)),
+    ])
+    .intersect(egui::Rect::from_min_max(
+        egui::Pos2::ZERO,
+        egui::pos2(1.0, 1.0),
+    ))
+}
+
+/// Limits a pan offset along one axis so that at least `min_visible_fraction` of the
+/// image stays on screen. `extent` is half the image size in clip space, where the
+/// screen spans -1 to 1; if the image is larger than the screen the fraction applies
+/// to the screen instead.
+fn clamp_pan(pan: f32, extent: f32, min_visible_fraction: f32) -> f32 {
+    let min_overlap = min_visible_fraction.clamp(0.0, 1.0) * (2.0 * extent).min(2.0);
+    let limit = (1.0 + extent - min_overlap).max(0.0);
+    pan.clamp(-limit, limit)
+}
+
+/// Scale applied to the quad so an image fits a view of the given size at zoom 1.0,
+/// after it is turned by `orientation`.
+fn fit_scale_for(
+    width: u32,
+    height: u32,
+    orientation: ViewOrientation,
+    view_size: (f32, f32),
+) -> (f32, f32) {
+    let (width, height) = if orientation.is_sideways() {
+        (height, width)
+    } else {
+        (width, height)
+    };
+    let image_aspect_ratio = width as f32 / height as f32;
+    let view_aspect_ratio = view_size.0 / view_size.1;
+    if view_aspect_ratio > image_aspect_ratio {
+        (image_aspect_ratio / view_aspect_ratio, 1.0)
+    } else {
+        (1.0, view_aspect_ratio / image_aspect_ratio)
+    }
+}
+
+fn create_image_texture(device: &wgpu::Device, width: u32, height: u32) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Image texture"),
+        size: image_texture_size(width, height),
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba8UnormSrgb,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::COPY_DST
+            | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    })
+}
+
+fn create_transform_buffer(device: &wgpu::Device) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Transform Uniform Buffer"),
+        size: std::mem::size_of::<Transforms>() as u64,
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
 fn setup_texture(
     device: &wgpu::Device,
     surface_config: SurfaceConfiguration,
@@ -54,35 +391,16 @@ fn setup_texture(
     height: u32,
 ) -> (
     wgpu::Texture,
-    wgpu::BindGroup,
+    wgpu::BindGroupLayout,
+    wgpu::Sampler,
+    wgpu::Sampler,
     wgpu::RenderPipeline,
     wgpu::Buffer,
 ) {
-    let texture = device.create_texture(&wgpu::TextureDescriptor {
-        label: Some("Image texture"),
-        size: wgpu::Extent3d {
-            width,
-            height,
-            depth_or_array_layers: 1,
-        },
-        mip_level_count: 1,
-        sample_count: 1,
-        dimension: wgpu::TextureDimension::D2,
-        format: wgpu::TextureFormat::Rgba8UnormSrgb,
-        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-        view_formats: &[],
-    });
+    let texture = create_image_texture(device, width, height);
 
-    let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-        address_mode_u: wgpu::AddressMode::ClampToEdge,
-        address_mode_v: wgpu::AddressMode::ClampToEdge,
-        address_mode_w: wgpu::AddressMode::ClampToEdge,
-        mag_filter: wgpu::FilterMode::Linear,
-        min_filter: wgpu::FilterMode::Linear,
-        mipmap_filter: wgpu::FilterMode::Linear,
-        ..Default::default()
-    });
+    let sampler = create_sampler(device, wgpu::FilterMode::Linear);
+    let nearest_sampler = create_sampler(device, wgpu::FilterMode::Nearest);
 
     let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
         label: Some("Texture Bind Group Layout"),
@@ -113,36 +431,29 @@ fn setup_texture(
                 },
                 count: None,
             },
-        ],
-    });
-
-    let transform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-        label: Some("Transform Uniform Buffer"),
-        size: std::mem::size_of::<Transforms>() as u64,
-        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        mapped_at_creation: false,
-    });
-
-    // Create bind group with your texture
-    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-        label: Some("Texture Bind Group"),
-        layout: &bind_group_layout,
-        entries: &[
-            wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::TextureView(&texture_view),
-            },
-            wgpu::BindGroupEntry {
-                binding: 1,
-                resource: wgpu::BindingResource::Sampler(&sampler),
+            // Lanczos-downscaled copy of the image
+            wgpu::BindGroupLayoutEntry {
+                binding: 3,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
             },
-            wgpu::BindGroupEntry {
-                binding: 2,
-                resource: transform_buffer.as_entire_binding(),
+            // Used instead of binding 1 above 100% zoom, see `SamplingMode`
+            wgpu::BindGroupLayoutEntry {
+                binding: 4,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
             },
         ],
     });
 
+    let transform_buffer = create_transform_buffer(device);
+
     let vertex_buffer_layout = wgpu::VertexBufferLayout {
         array_stride: 5 * std::mem::size_of::<f32>() as wgpu::BufferAddress,
         step_mode: wgpu::VertexStepMode::Vertex,
@@ -182,6 +493,278 @@ fn setup_texture(
             buffers: &[vertex_buffer_layout],
             compilation_options: PipelineCompilationOptions::default(),
         },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: Some("fs_main"),
+            targets: &[Some(wgpu::ColorTargetState {
+                format: surface_config.format,
+                // Only differs from replacing while an image fades in or out
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: PipelineCompilationOptions::default(),
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            ..Default::default()
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    });
+
+    (
+        texture,
+        bind_group_layout,
+        sampler,
+        nearest_sampler,
+        render_pipeline,
+        transform_buffer,
+    )
+}
+
+fn create_sampler(device: &wgpu::Device, filter: wgpu::FilterMode) -> wgpu::Sampler {
+    device.create_sampler(&wgpu::SamplerDescriptor {
+        address_mode_u: wgpu::AddressMode::ClampToEdge,
+        address_mode_v: wgpu::AddressMode::ClampToEdge,
+        address_mode_w: wgpu::AddressMode::ClampToEdge,
+        mag_filter: filter,
+        min_filter: filter,
+        mipmap_filter: filter,
+        ..Default::default()
+    })
+}
+
+fn create_image_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    texture: &wgpu::Texture,
+    resampled_texture: &wgpu::Texture,
+    sampler: &wgpu::Sampler,
+    nearest_sampler: &wgpu::Sampler,
+    transform_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let resampled_view = resampled_texture.create_view(&wgpu::TextureViewDescriptor::default());
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Texture Bind Group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&texture_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: transform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::TextureView(&resampled_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::Sampler(nearest_sampler),
+            },
+        ],
+    })
+}
+
+/// Copies `texture` into a mappable buffer, returning it with its padded row size.
+fn read_back_texture(
+    device: &wgpu::Device,
+    encoder: &mut wgpu::CommandEncoder,
+    texture: &wgpu::Texture,
+) -> (wgpu::Buffer, u32) {
+    let bytes_per_row = (4 * texture.width()).div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+        * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Readback Buffer"),
+        size: (bytes_per_row * texture.height()) as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::TexelCopyBufferInfo {
+            buffer: &buffer,
+            layout: wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(bytes_per_row),
+                rows_per_image: Some(texture.height()),
+            },
+        },
+        texture.size(),
+    );
+    (buffer, bytes_per_row)
+}
+
+/// Waits for a frame from `read_back_texture` and returns its pixels with the row
+/// padding removed.
+fn read_frame(
+    device: &wgpu::Device,
+    (buffer, bytes_per_row): (wgpu::Buffer, u32),
+    width: u32,
+) -> Vec<u8> {
+    let slice = buffer.slice(..);
+    slice.map_async(wgpu::MapMode::Read, |_| ());
+    device.poll(wgpu::Maintain::Wait);
+    let pixels = slice
+        .get_mapped_range()
+        .chunks_exact(bytes_per_row as usize)
+        .flat_map(|row| &row[..4 * width as usize])
+        .copied()
+        .collect();
+    buffer.unmap();
+    pixels
+}
+
+/// Applies the saved window geometry to `attributes`, on the monitor it was saved
+/// on or else the primary one, and shrinks it to fit that monitor. Returns the
+/// inner size the window is created with.
+fn restore_geometry(
+    attributes: WindowAttributes,
+    geometry: Option<&WindowGeometry>,
+    event_loop: &ActiveEventLoop,
+) -> (WindowAttributes, PhysicalSize<u32>) {
+    let default_size = PhysicalSize::new(1800, 1200);
+    let Some(geometry) = geometry else {
+        return (attributes.with_inner_size(default_size), default_size);
+    };
+    let monitor = event_loop
+        .available_monitors()
+        .find(|monitor| monitor.name().is_some() && monitor.name() == geometry.monitor)
+        .or_else(|| event_loop.primary_monitor())
+        .or_else(|| event_loop.available_monitors().next());
+    let Some(monitor) = monitor else {
+        let size = PhysicalSize::new(geometry.width, geometry.height);
+        return (attributes.with_inner_size(size), size);
+    };
+    let bounds = monitor.size();
+    let origin = monitor.position();
+    let size = PhysicalSize::new(
+        geometry.width.clamp(1, bounds.width.max(1)),
+        geometry.height.clamp(1, bounds.height.max(1)),
+    );
+    let mut attributes = attributes
+        .with_inner_size(size)
+        .with_maximized(geometry.maximized);
+    // Positions from a monitor that has since moved or shrunk are pulled back onto it
+    if let Some((x, y)) = geometry.position {
+        let x = x.clamp(origin.x, origin.x + (bounds.width - size.width) as i32);
+        let y = y.clamp(origin.y, origin.y + (bounds.height - size.height) as i32);
+        attributes = attributes.with_position(PhysicalPosition::new(x, y));
+    }
+    if geometry.fullscreen {
+        attributes = attributes.with_fullscreen(Some(Fullscreen::Borderless(Some(monitor))));
+    }
+    (attributes, size)
+}
+
+/// Runs `encode` on a thread of its own and sends `path` or the error to `tx`.
+fn encode_in_background(
+    tx: mpsc::Sender<Result<PathBuf, String>>,
+    wake: Option<Wake>,
+    path: PathBuf,
+    encode: impl FnOnce(&Path) -> image::ImageResult<()> + Send + 'static,
+) {
+    thread::spawn(move || {
+        let _ = tx.send(encode(&path).map(|()| path).map_err(|e| e.to_string()));
+        if let Some(wake) = wake {
+            wake();
+        }
+    });
+}
+
+/// Waits for a frame from `read_back_texture` and writes it to `path` as a PNG.
+fn save_frame(
+    device: &wgpu::Device,
+    frame: (wgpu::Buffer, u32),
+    (width, height): (u32, u32),
+    path: &Path,
+) -> image::ImageResult<()> {
+    let rgba = read_frame(device, frame, width);
+    image::save_buffer(path, &rgba, width, height, image::ExtendedColorType::Rgba8)
+}
+
+// Uniforms for the gradient background
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct Background {
+    top_color: [f32; 4],
+    bottom_color: [f32; 4],
+}
+
+impl Background {
+    fn new(config: &Config) -> Self {
+        Background {
+            top_color: config.gradient_top_color.map(|c| c as f32),
+            bottom_color: config.gradient_bottom_color.map(|c| c as f32),
+        }
+    }
+}
+
+fn setup_background(
+    device: &wgpu::Device,
+    surface_config: &SurfaceConfiguration,
+    config: &Config,
+) -> (wgpu::Buffer, wgpu::BindGroup, wgpu::RenderPipeline) {
+    let background_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Background Uniform Buffer"),
+        contents: bytemuck::cast_slice(&[Background::new(config)]),
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    });
+
+    let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("Background Bind Group Layout"),
+        entries: &[wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }],
+    });
+
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Background Bind Group"),
+        layout: &bind_group_layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: background_buffer.as_entire_binding(),
+        }],
+    });
+
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Background Shader"),
+        source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Borrowed(include_str!(
+            "background.wgsl"
+        ))),
+    });
+
+    let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Background Render Pipeline"),
+        layout: Some(
+            &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Background Pipeline Layout"),
+                bind_group_layouts: &[&bind_group_layout],
+                push_constant_ranges: &[],
+            }),
+        ),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: Some("vs_main"),
+            buffers: &[],
+            compilation_options: PipelineCompilationOptions::default(),
+        },
         fragment: Some(wgpu::FragmentState {
             module: &shader,
             entry_point: Some("fs_main"),
@@ -202,44 +785,188 @@ fn setup_texture(
         cache: None,
     });
 
-    (texture, bind_group, render_pipeline, transform_buffer)
+    (background_buffer, bind_group, render_pipeline)
+}
+
+/// Picks an adapter of the requested backend that can present to `surface`, preferring
+/// the GPU type `power_preference` asks for. Warns and returns `None` so the caller
+/// can fall back to whatever wgpu picks when there is none.
+fn select_adapter(
+    instance: &wgpu::Instance,
+    backend: BackendSelection,
+    power_preference: wgpu::PowerPreference,
+    surface: Option<&wgpu::Surface>,
+) -> Option<wgpu::Adapter> {
+    let backends = match backend {
+        BackendSelection::Vulkan => wgpu::Backends::VULKAN,
+        BackendSelection::Metal => wgpu::Backends::METAL,
+        BackendSelection::Dx12 => wgpu::Backends::DX12,
+        BackendSelection::Opengl => wgpu::Backends::GL,
+    };
+    let preferred = match power_preference {
+        wgpu::PowerPreference::LowPower => Some(wgpu::DeviceType::IntegratedGpu),
+        wgpu::PowerPreference::HighPerformance => Some(wgpu::DeviceType::DiscreteGpu),
+        _ => None,
+    };
+    let mut adapters = instance
+        .enumerate_adapters(backends)
+        .into_iter()
+        .filter(|adapter| surface.is_none_or(|surface| adapter.is_surface_supported(surface)))
+        .collect::<Vec<_>>();
+    // Stable, so the enumeration order decides among equally preferred adapters
+    adapters.sort_by_key(|adapter| Some(adapter.get_info().device_type) != preferred);
+    let adapter = adapters.into_iter().next();
+    if adapter.is_none() {
+        eprintln!(
+            "Warning: no {:?} adapter found, falling back to the default backend",
+            backend
+        );
+    }
+    adapter
+}
+
+/// A second image kept on the GPU next to the current one: image A of compare mode,
+/// pinned in the left half of the window while navigation picks image B on the right,
+/// or the previous image for flicker comparisons
+pub struct ComparePane {
+    image: ImageData,
+    transform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    width: u32,
+    height: u32,
+    hdr: bool,
+    /// Whether the texture holds the full image rather than its thumbnail
+    full: bool,
+}
+
+/// The image being faded out after navigating away from it
+pub struct TransitionState {
+    /// 1 when moving forward, -1 when moving back
+    direction: i32,
+    /// From 0 right after navigating to 1 once the new image is fully shown
+    progress: f32,
+    outgoing: ComparePane,
+}
+
+impl TransitionState {
+    /// Horizontal offsets of the outgoing and the incoming image
+    fn slide(&self) -> (f32, f32) {
+        let direction = self.direction as f32;
+        (
+            -direction * self.progress * TRANSITION_SLIDE,
+            direction * (1.0 - self.progress) * TRANSITION_SLIDE,
+        )
+    }
 }
 
 pub struct AppState {
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
     pub surface_config: wgpu::SurfaceConfiguration,
-    pub surface: wgpu::Surface<'static>,
+    /// None when rendering offscreen, see `App::run_headless`
+    pub surface: Option<wgpu::Surface<'static>>,
+    /// The window's scale factor, which can be fractional on Wayland. egui draws at
+    /// this times `Config::ui_scale`.
     pub scale_factor: f32,
     pub egui_renderer: EguiRenderer,
     pub store: ImageStore,
     pub image_texture: wgpu::Texture,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub sampler: wgpu::Sampler,
+    pub nearest_sampler: wgpu::Sampler,
     pub bind_group: wgpu::BindGroup,
     pub render_pipeline: wgpu::RenderPipeline,
     pub transform_buffer: wgpu::Buffer,
     pub transform_data: TransformData,
+    pub background_buffer: wgpu::Buffer,
+    pub background_bind_group: wgpu::BindGroup,
+    pub background_pipeline: wgpu::RenderPipeline,
+    pub lanczos: LanczosResampler,
+    /// Name of the GPU in use and the power mode it was picked with, for the help window
+    pub adapter_description: String,
+    /// `(zoom, pan_x, pan_y)` last used for each visited image
+    pub per_image_transforms: HashMap<ImageData, (f32, f32, f32)>,
+    pub compare: Option<ComparePane>,
+    /// The previously viewed image, kept resident so flicker comparisons are instant
+    pub flicker: Option<ComparePane>,
+    pub transition_state: Option<TransitionState>,
+    /// Image last uploaded to `image_texture`, to tell navigation from a reload
+    pub shown_image: Option<ImageData>,
+    pub toasts: ToastQueue,
+    /// Set when the current image or its resolution changes, see `update_histogram`
+    pub histogram_dirty: bool,
+    /// Whether a histogram is being computed on a background thread
+    pub histogram_pending: bool,
+    pub histogram_tx: mpsc::Sender<(ImageData, Histogram)>,
+    pub histogram_rx: mpsc::Receiver<(ImageData, Histogram)>,
+    /// The latest computed histogram, normalized
+    pub histogram_target: Option<[[f32; 256]; 3]>,
+    /// What the info panel draws, easing toward `histogram_target`
+    pub histogram: Option<[[f32; 256]; 3]>,
+    /// Images picked in the filmstrip for batch rating, deleting and copying
+    pub selected: HashSet<ImageData>,
+    /// Images copied to one of the copy targets, badged in the filmstrip
+    pub copied: HashSet<ImageData>,
+    /// Set with `--gpu-profile` on devices that support it
+    pub gpu_profiler: Option<GpuProfiler>,
+    pub debug_overlay: Option<DebugOverlay>,
+    /// Results of exports encoding on their own threads
+    pub export_tx: mpsc::Sender<Result<PathBuf, String>>,
+    pub export_rx: mpsc::Receiver<Result<PathBuf, String>>,
+    /// Exports whose results haven't arrived on `export_rx` yet
+    pub pending_exports: usize,
 }
 
 impl AppState {
+    /// Sets up rendering to `window`, or offscreen when there is none.
     async fn new(
         instance: &wgpu::Instance,
-        surface: wgpu::Surface<'static>,
-        window: &Window,
+        window: Option<(&Window, wgpu::Surface<'static>)>,
         width: u32,
         height: u32,
-        path: PathBuf,
+        store: ImageStore,
+        config: &Config,
     ) -> Self {
-        let power_pref = wgpu::PowerPreference::default();
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: power_pref,
-                force_fallback_adapter: false,
-                compatible_surface: Some(&surface),
-            })
-            .await
-            .expect("Failed to find an appropriate adapter");
+        let (power_pref, mode) = if config.low_power {
+            (wgpu::PowerPreference::LowPower, " (low power mode)")
+        } else if config.high_performance {
+            (
+                wgpu::PowerPreference::HighPerformance,
+                " (high performance mode)",
+            )
+        } else {
+            (wgpu::PowerPreference::default(), "")
+        };
+        let (window, surface) = window.unzip();
+        let requested = config
+            .backend
+            .and_then(|backend| select_adapter(instance, backend, power_pref, surface.as_ref()));
+        let adapter = match requested {
+            Some(adapter) => adapter,
+            None => instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: power_pref,
+                    force_fallback_adapter: false,
+                    compatible_surface: surface.as_ref(),
+                })
+                .await
+                .expect("Failed to find an appropriate adapter"),
+        };
+        let info = adapter.get_info();
+        let adapter_description = format!("{} ({}){}", info.name, info.backend, mode);
+        println!("Using adapter: {}", adapter_description);
 
-        let features = wgpu::Features::empty();
+        let mut features = wgpu::Features::empty();
+        if config.gpu_profile {
+            if adapter.features().contains(GPU_PROFILE_FEATURES) {
+                features |= GPU_PROFILE_FEATURES;
+            } else {
+                eprintln!(
+                    "{} has no timestamp queries, GPU times won't be printed",
+                    info.name
+                );
+            }
+        }
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
@@ -253,44 +980,95 @@ impl AppState {
             .await
             .expect("Failed to create device");
 
-        let swapchain_capabilities = surface.get_capabilities(&adapter);
-        let selected_format = wgpu::TextureFormat::Bgra8UnormSrgb;
-        let swapchain_format = swapchain_capabilities
-            .formats
-            .iter()
-            .find(|d| **d == selected_format)
-            .expect("failed to select proper surface texture format!");
-
-        let surface_config = wgpu::SurfaceConfiguration {
+        let (format, alpha_mode) = if let Some(surface) = &surface {
+            let swapchain_capabilities = surface.get_capabilities(&adapter);
+            let hdr_format = wgpu::TextureFormat::Rgba16Float;
+            let selected_format =
+                if config.hdr_output && swapchain_capabilities.formats.contains(&hdr_format) {
+                    hdr_format
+                } else {
+                    wgpu::TextureFormat::Bgra8UnormSrgb
+                };
+            let swapchain_format = swapchain_capabilities
+                .formats
+                .iter()
+                .find(|d| **d == selected_format)
+                .expect("failed to select proper surface texture format!");
+            (*swapchain_format, swapchain_capabilities.alpha_modes[0])
+        } else {
+            // Offscreen frames are read back as plain RGBA
+            (
+                wgpu::TextureFormat::Rgba8UnormSrgb,
+                wgpu::CompositeAlphaMode::Auto,
+            )
+        };
+
+        let surface_config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: *swapchain_format,
+            format,
             width,
             height,
             present_mode: wgpu::PresentMode::AutoVsync,
             desired_maximum_frame_latency: 0,
-            alpha_mode: swapchain_capabilities.alpha_modes[0],
+            alpha_mode,
             view_formats: vec![],
         };
 
-        surface.configure(&device, &surface_config);
+        if let Some(surface) = &surface {
+            surface.configure(&device, &surface_config);
+        }
 
         let egui_renderer = EguiRenderer::new(&device, surface_config.format, None, 1, window);
 
-        let scale_factor = 1.0;
+        let scale_factor = window.map_or(1.0, |window| window.scale_factor() as f32);
 
-        let store = ImageStore::new(path);
-
-        let (image_texture, bind_group, render_pipeline, transform_buffer) =
-            // setup_texture(&device, surface_config.clone(), 6000, 4000);
-            setup_texture(&device, surface_config.clone(), 8192, 8192);
+        let (
+            image_texture,
+            bind_group_layout,
+            sampler,
+            nearest_sampler,
+            render_pipeline,
+            transform_buffer,
+        ) =
+            // Replaced by one the size of each image as it is uploaded
+            setup_texture(&device, surface_config.clone(), 1, 1);
+        let lanczos = LanczosResampler::new(&device);
+        let bind_group = create_image_bind_group(
+            &device,
+            &bind_group_layout,
+            &image_texture,
+            &lanczos.output,
+            &sampler,
+            &nearest_sampler,
+            &transform_buffer,
+        );
+        let (background_buffer, background_bind_group, background_pipeline) =
+            setup_background(&device, &surface_config, config);
 
         let transform_data = TransformData {
             pan_x: 0.0,
             pan_y: 0.0,
             zoom: 1.0,
+            target_pan_x: 0.0,
+            target_pan_y: 0.0,
+            target_zoom: 1.0,
+            pan_velocity_x: 0.0,
+            pan_velocity_y: 0.0,
             width: 10000,
             height: 10000,
+            sharpen: false,
+            clipping: false,
+            orientation: ViewOrientation::default(),
+            sharpen_amount: DEFAULT_SHARPEN_AMOUNT,
+            hdr: false,
+            hdr_override: None,
         };
+        let (histogram_tx, histogram_rx) = mpsc::channel();
+        let (export_tx, export_rx) = mpsc::channel();
+        let gpu_profiler = GpuProfiler::new(&device, &queue);
+        let debug_overlay = (config.debug_overlay && window.is_some()).then(|| {
+            DebugOverlay::new(&device, surface_config.format, adapter_description.clone())
+        });
 
         Self {
             device,
@@ -301,234 +1079,325 @@ impl AppState {
             scale_factor,
             store,
             image_texture,
+            bind_group_layout,
+            sampler,
+            nearest_sampler,
             bind_group,
             render_pipeline,
             transform_buffer,
             transform_data,
+            background_buffer,
+            background_bind_group,
+            background_pipeline,
+            lanczos,
+            adapter_description,
+            per_image_transforms: HashMap::new(),
+            compare: None,
+            flicker: None,
+            transition_state: None,
+            shown_image: None,
+            toasts: ToastQueue::default(),
+            histogram_dirty: true,
+            histogram_pending: false,
+            histogram_tx,
+            histogram_rx,
+            histogram_target: None,
+            histogram: None,
+            selected: HashSet::new(),
+            copied: HashSet::new(),
+            gpu_profiler,
+            debug_overlay,
+            export_tx,
+            export_rx,
+            pending_exports: 0,
         }
     }
 
-    fn resize_surface(&mut self, width: u32, height: u32) {
-        self.surface_config.width = width;
-        self.surface_config.height = height;
-        self.surface.configure(&self.device, &self.surface_config);
-    }
-}
-
-pub struct App {
-    instance: wgpu::Instance,
-    state: Option<AppState>,
-    window: Option<Arc<Window>>,
-    path: PathBuf,
-}
-
-impl App {
-    pub fn new(path: PathBuf) -> Self {
-        let instance = egui_wgpu::wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
-        Self {
-            instance,
-            state: None,
-            window: None,
-            path,
+    /// Computes the histogram of the current image off the UI thread once it changed,
+    /// and moves the displayed histogram `blend` of the way toward the latest result.
+    fn update_histogram(&mut self, blend: f32) {
+        while let Ok((image, histogram)) = self.histogram_rx.try_recv() {
+            self.histogram_pending = false;
+            // Results for images navigated away from are dropped
+            if image == self.store.current_image_path {
+                self.histogram_target = Some(histogram.normalized());
+            }
+        }
+        // One computation at a time, so scrubbing doesn't pile them up
+        if self.histogram_dirty && !self.histogram_pending && !self.store.is_empty() {
+            let image = self.store.current_image_path.clone();
+            // The thumbnail stands in until the full image arrives and marks it dirty
+            // again. A reduced copy is plenty for the shape of the curves.
+            let rgba_buffer = match self.store.get_current_image() {
+                Some(full) => {
+                    full.downsample(HISTOGRAM_SAMPLE_SIZE, HISTOGRAM_SAMPLE_SIZE)
+                        .rgba_buffer
+                }
+                None => self.store.get_thumbnail().rgba_buffer.clone(),
+            };
+            let tx = self.histogram_tx.clone();
+            let wake = self.store.wake();
+            thread::spawn(move || {
+                let _ = tx.send((image, Histogram::from_rgba(&rgba_buffer)));
+                if let Some(wake) = wake {
+                    wake();
+                }
+            });
+            self.histogram_dirty = false;
+            self.histogram_pending = true;
+        }
+        let Some(target) = &self.histogram_target else {
+            return;
+        };
+        let histogram = self.histogram.get_or_insert(*target);
+        for (shown, target) in histogram.iter_mut().flatten().zip(target.iter().flatten()) {
+            *shown += (target - *shown) * blend;
+        }
+        // The easing never arrives on its own
+        if histogram
+            .iter()
+            .flatten()
+            .zip(target.iter().flatten())
+            .all(|(shown, target)| (target - shown).abs() < 1e-4)
+        {
+            *histogram = *target;
         }
     }
 
-    async fn set_window(&mut self, window: Window) {
-        let window = Arc::new(window);
-        let initial_height = 1200;
-        let initial_width = (initial_height as f32 * 1.5) as u32;
-
-        let _ = window.request_inner_size(PhysicalSize::new(initial_width, initial_height));
-
-        let surface = self
-            .instance
-            .create_surface(window.clone())
-            .expect("Failed to create surface!");
-
-        let state = AppState::new(
-            &self.instance,
-            surface,
-            &window,
-            initial_width,
-            initial_width,
-            self.path.clone(),
-        )
-        .await;
-
-        self.window.get_or_insert(window);
-        self.state.get_or_insert(state);
-
-        self.pan_zoom(0.0, 0.0, 0.0);
-        self.update_texture();
+    fn store_transform(&mut self) {
+        self.per_image_transforms.insert(
+            self.store.current_image_path.clone(),
+            (
+                self.transform_data.target_zoom,
+                self.transform_data.target_pan_x,
+                self.transform_data.target_pan_y,
+            ),
+        );
     }
 
-    fn handle_resized(&mut self, width: u32, height: u32) {
-        if width > 0 && height > 0 {
-            self.state.as_mut().unwrap().resize_surface(width, height);
-        }
-        self.pan_zoom(0.0, 0.0, 0.0);
+    pub fn clear_per_image_transforms(&mut self) {
+        self.per_image_transforms.clear();
     }
 
-    pub fn update_texture(&mut self) {
-        let state = self.state.as_mut().unwrap();
-
-        state.store.check_loaded_images();
-        let imbuf = if let Some(full) = state.store.get_current_image() {
-            full
-        } else {
-            state.store.get_thumbnail()
+    /// Draws the view as it is on screen, without the egui overlays, into a texture
+    /// of the window's size and returns its RGBA pixels.
+    fn render_view(&self, config: &Config) -> Result<(u32, u32, Vec<u8>), String> {
+        let format = self.surface_config.format;
+        let bgra = match format {
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => true,
+            wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => false,
+            _ => return Err("the view can't be exported with HDR output".into()),
         };
-        let width = imbuf.width as u32;
-        let height = imbuf.height as u32;
-        let buffer_u8 = unsafe {
-            std::slice::from_raw_parts(
-                imbuf.rgba_buffer.as_ptr() as *const u8,
-                imbuf.rgba_buffer.len() * 4,
-            )
-        };
-
-        state.transform_data.width = width;
-        state.transform_data.height = height;
-
-        state.queue.write_texture(
-            wgpu::TexelCopyTextureInfo {
-                texture: &state.image_texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            &buffer_u8,
-            wgpu::TexelCopyBufferLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * width), // 4 bytes per ARGB pixel
-                rows_per_image: Some(height),
-            },
-            wgpu::Extent3d {
+        let (width, height) = (self.surface_config.width, self.surface_config.height);
+        let target = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Export texture"),
+            size: wgpu::Extent3d {
                 width,
                 height,
                 depth_or_array_layers: 1,
             },
-        );
-
-        self.pan_zoom(0.0, 0.0, 0.0);
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = target.create_view(&wgpu::TextureViewDescriptor::default());
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Export Encoder"),
+            });
+        self.render_image(&mut encoder, &view, config, false);
+        let frame = read_back_texture(&self.device, &mut encoder, &target);
+        self.queue.submit(Some(encoder.finish()));
+        let mut pixels = read_frame(&self.device, frame, width);
+        if bgra {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        Ok((width, height, pixels))
     }
 
-    fn update_transform(&mut self) {
-        let state = self.state.as_mut().unwrap();
-
-        let image_aspect_ratio =
-            (state.transform_data.width as f32) / (state.transform_data.height as f32);
-        let window_size = self.window.as_ref().unwrap().inner_size();
-        let window_aspect_ratio = window_size.width as f32 / window_size.height as f32;
-        let mut scale_x = 1.0;
-        let mut scale_y = 1.0;
-        if window_aspect_ratio > image_aspect_ratio {
-            scale_x = image_aspect_ratio / window_aspect_ratio;
-        } else {
-            scale_y = window_aspect_ratio / image_aspect_ratio;
-        }
-        let transform = create_transform_matrix(&state.transform_data, scale_x, scale_y);
-        state.queue.write_buffer(
-            &state.transform_buffer,
+    /// Uploads the gradient colors of `config` for the background pass.
+    fn update_background(&self, config: &Config) {
+        self.queue.write_buffer(
+            &self.background_buffer,
             0,
-            bytemuck::cast_slice(&[Transforms {
-                transform,
-                width: state.transform_data.width,
-                height: state.transform_data.height,
-                _padding1: 0,
-                _padding2: 0,
-            }]),
+            bytemuck::cast_slice(&[Background::new(config)]),
         );
     }
 
-    pub fn reset_transform(&mut self) {
-        let state = self.state.as_mut().unwrap();
-        state.transform_data.zoom = 1.0;
-        state.transform_data.pan_x = 0.0;
-        state.transform_data.pan_y = 0.0;
-
-        self.update_transform();
+    /// Keeps the Lanczos-downscaled copy of the image in sync with the display scale.
+    /// Returns the size of the downscaled image when it should be shown.
+    fn update_lanczos(&mut self, pixel_scale: f32) -> Option<(u32, u32)> {
+        if !(LANCZOS_MIN_SCALE..=LANCZOS_MAX_SCALE).contains(&pixel_scale) {
+            return None;
+        }
+        let src_size = (self.transform_data.width, self.transform_data.height);
+        let dst_size = (
+            ((src_size.0 as f32 * pixel_scale).round() as u32).max(1),
+            ((src_size.1 as f32 * pixel_scale).round() as u32).max(1),
+        );
+        if self.lanczos.size() != Some(dst_size)
+            && self.lanczos.resample(
+                &self.device,
+                &self.queue,
+                &self.image_texture,
+                src_size,
+                dst_size,
+            )
+        {
+            self.update_bind_group();
+        }
+        Some(dst_size)
     }
 
-    pub fn pan_zoom(&mut self, zoom_delta: f32, pan_x: f32, pan_y: f32) {
-        let state = self.state.as_mut().unwrap();
+    /// Binds the current image texture and Lanczos output after either was replaced.
+    fn update_bind_group(&mut self) {
+        self.bind_group = create_image_bind_group(
+            &self.device,
+            &self.bind_group_layout,
+            &self.image_texture,
+            &self.lanczos.output,
+            &self.sampler,
+            &self.nearest_sampler,
+            &self.transform_buffer,
+        );
+    }
 
-        state.transform_data.zoom = (state.transform_data.zoom + zoom_delta).clamp(1.0, 20.0);
-        state.transform_data.pan_x += pan_x;
-        state.transform_data.pan_y += pan_y;
+    fn create_compare_pane(
+        &self,
+        image: ImageData,
+        imbuf: &ImflowImageBuffer,
+        full: bool,
+    ) -> ComparePane {
+        let texture = create_image_texture(&self.device, imbuf.width as u32, imbuf.height as u32);
+        upload_image(&self.queue, &texture, imbuf);
+        let (width, height) = (imbuf.width as u32, imbuf.height as u32);
+        self.pane_for_texture(image, &texture, (width, height), imbuf.hdr, full)
+    }
 
-        self.update_transform();
+    /// A pane with a copy of what the image texture shows now, made on the GPU so
+    /// the texture can take the next image.
+    fn snapshot_pane(&self, image: ImageData, full: bool) -> ComparePane {
+        let (width, height) = (self.transform_data.width, self.transform_data.height);
+        let texture = create_image_texture(&self.device, width, height);
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.copy_texture_to_texture(
+            self.image_texture.as_image_copy(),
+            texture.as_image_copy(),
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+        self.queue.submit(Some(encoder.finish()));
+        let hdr = self.transform_data.hdr;
+        self.pane_for_texture(image, &texture, (width, height), hdr, full)
     }
 
-    fn handle_redraw(&mut self) {
-        // Attempt to handle minimizing window
-        if let Some(window) = self.window.as_ref() {
-            if let Some(min) = window.is_minimized() {
-                if min {
-                    println!("Window is minimized");
-                    return;
-                }
-            }
+    /// `texture` holds a `width` × `height` image in its top left corner, see
+    /// `upload_image`.
+    fn pane_for_texture(
+        &self,
+        image: ImageData,
+        texture: &wgpu::Texture,
+        (width, height): (u32, u32),
+        hdr: bool,
+        full: bool,
+    ) -> ComparePane {
+        let transform_buffer = create_transform_buffer(&self.device);
+        // Pane A never uses the Lanczos copy, which follows the current image
+        let bind_group = create_image_bind_group(
+            &self.device,
+            &self.bind_group_layout,
+            texture,
+            &self.lanczos.output,
+            &self.sampler,
+            &self.nearest_sampler,
+            &transform_buffer,
+        );
+        ComparePane {
+            image,
+            transform_buffer,
+            bind_group,
+            width,
+            height,
+            hdr,
+            full,
         }
+    }
 
-        let state = self.state.as_mut().unwrap();
-
-        let screen_descriptor = ScreenDescriptor {
-            size_in_pixels: [state.surface_config.width, state.surface_config.height],
-            pixels_per_point: self.window.as_ref().unwrap().scale_factor() as f32
-                * state.scale_factor,
+    /// Pins the current image as A, using its thumbnail until the full image loads.
+    fn pin_compare_pane(&mut self) {
+        let image = self.store.current_image_path.clone();
+        // Makes sure there is at least a thumbnail to show
+        self.store.get_thumbnail();
+        let full = self.store.get_image(&image);
+        let Some(imbuf) = full.or_else(|| self.store.thumbnail_for(&image)) else {
+            return;
         };
+        self.compare = Some(self.create_compare_pane(image, imbuf, full.is_some()));
+    }
 
-        let surface_texture = state.surface.get_current_texture();
-
-        let surface_texture = match surface_texture {
-            Err(SurfaceError::Outdated) => {
-                // Ignoring outdated to allow resizing and minimization
-                println!("wgpu surface outdated");
-                return;
-            }
-            Err(SurfaceError::Timeout) => {
-                println!("wgpu surface timeout");
-                return;
-            }
-            Err(_) => {
-                surface_texture.expect("Failed to acquire next swap chain texture");
-                return;
-            }
-            Ok(surface_texture) => surface_texture,
+    /// Swaps the full image into pane A once it has loaded. Returns true if it did.
+    fn refresh_compare_pane(&mut self) -> bool {
+        let Some(pane) = self.compare.as_ref().filter(|pane| !pane.full) else {
+            return false;
         };
+        let Some(full) = self.store.get_image(&pane.image) else {
+            return false;
+        };
+        self.compare = Some(self.create_compare_pane(pane.image.clone(), full, true));
+        true
+    }
 
-        let surface_view = surface_texture
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
-
-        let mut encoder = state
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
-
-        // Clear buffer with black
-        {
-            let _ = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: None,
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &surface_view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.0,
-                            g: 0.0,
-                            b: 0.0,
-                            a: 1.0,
-                        }),
-                        store: wgpu::StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: None,
-                timestamp_writes: None,
-                occlusion_query_set: None,
-            });
+    /// Clears `view` to the background color, optionally drawing a gradient over it.
+    /// A solid backdrop for transparency extends around the image.
+    fn render_background(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        config: &Config,
+    ) {
+        let backdrop = config.alpha_background.color();
+        let [r, g, b, a] = backdrop.unwrap_or(config.background_color);
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Background Render Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color { r, g, b, a }),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        if backdrop.is_none() && config.background_mode == BackgroundMode::Gradient {
+            render_pass.set_pipeline(&self.background_pipeline);
+            render_pass.set_bind_group(0, &self.background_bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
         }
+    }
 
+    /// Draws the background and the image (or both compare panes) into `view`.
+    fn render_image(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        config: &Config,
+        show_flicker: bool,
+    ) {
+        self.render_background(encoder, view, config);
         {
             #[repr(C)]
             #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
@@ -560,16 +1429,15 @@ impl App {
 
             let indices: [u16; 6] = [0, 1, 2, 2, 1, 3];
 
-            let vertex_buffer =
-                state
-                    .device
-                    .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                        label: Some("Vertex Buffer"),
-                        contents: bytemuck::cast_slice(&vertices),
-                        usage: wgpu::BufferUsages::VERTEX,
-                    });
+            let vertex_buffer = self
+                .device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Vertex Buffer"),
+                    contents: bytemuck::cast_slice(&vertices),
+                    usage: wgpu::BufferUsages::VERTEX,
+                });
 
-            let index_buffer = state
+            let index_buffer = self
                 .device
                 .create_buffer_init(&wgpu::util::BufferInitDescriptor {
                     label: Some("Index Buffer"),
@@ -580,7 +1448,7 @@ impl App {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Texture Render Pass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &surface_view,
+                    view,
                     resolve_target: None,
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Load,
@@ -592,126 +1460,3570 @@ impl App {
                 occlusion_query_set: None,
             });
 
-            render_pass.set_pipeline(&state.render_pipeline);
-            render_pass.set_bind_group(0, &state.bind_group, &[]);
+            render_pass.set_pipeline(&self.render_pipeline);
 
             // Bind the vertex buffer
             render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
 
             // Draw using the index buffer
             render_pass.set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+
+            // In compare mode A takes the left half of the window and B the right
+            if let Some(pane) = &self.compare {
+                let width = self.surface_config.width as f32 / 2.0;
+                let height = self.surface_config.height as f32;
+                render_pass.set_viewport(0.0, 0.0, width, height, 0.0, 1.0);
+                render_pass.set_bind_group(0, &pane.bind_group, &[]);
+                render_pass.draw_indexed(0..6, 0, 0..1);
+                render_pass.set_viewport(width, 0.0, width, height, 0.0, 1.0);
+            }
+            let bind_group = match &self.flicker {
+                Some(pane) if show_flicker => &pane.bind_group,
+                _ => {
+                    if let Some(transition) = &self.transition_state {
+                        render_pass.set_bind_group(0, &transition.outgoing.bind_group, &[]);
+                        render_pass.draw_indexed(0..6, 0, 0..1);
+                    }
+                    &self.bind_group
+                }
+            };
+            render_pass.set_bind_group(0, bind_group, &[]);
             render_pass.draw_indexed(0..6, 0, 0..1);
         }
+    }
 
-        let rating = state.store.get_current_rating();
-        let path = state.store.current_image_path.clone();
-        let filename = path.path.file_name().unwrap();
-        let window = self.window.as_ref().unwrap();
-        {
-            state.egui_renderer.begin_frame(window);
-
-            egui::Window::new("Rating")
-                .collapsible(false)
-                .resizable(false)
-                .default_width(5.0)
-                .show(state.egui_renderer.context(), |ui| {
-                    ui.vertical_centered(|ui| {
-                        ui.label(
-                            egui::RichText::new(format!("{:.1}", rating))
-                                .size(42.0)
-                                .strong(),
-                        );
-                        ui.label(
-                            egui::RichText::new(format!("{}", filename.to_str().unwrap()))
-                                .size(10.0)
-                                .strong(),
-                        );
-                    });
-                });
+    /// Whether the view moves on its own: a zoom or pan animation, a glide after a
+    /// drag, or a crossfade.
+    fn is_animating(&self) -> bool {
+        let transform_data = &self.transform_data;
+        transform_data.zoom != transform_data.target_zoom
+            || transform_data.pan_x != transform_data.target_pan_x
+            || transform_data.pan_y != transform_data.target_pan_y
+            || transform_data.pan_velocity_x != 0.0
+            || transform_data.pan_velocity_y != 0.0
+            || self.transition_state.is_some()
+    }
 
-            state.egui_renderer.end_frame_and_draw(
-                &state.device,
-                &state.queue,
-                &mut encoder,
-                window,
-                &surface_view,
-                screen_descriptor,
-            );
+    fn resize_surface(&mut self, width: u32, height: u32) {
+        self.surface_config.width = width;
+        self.surface_config.height = height;
+        if let Some(surface) = &self.surface {
+            surface.configure(&self.device, &self.surface_config);
         }
-
-        state.queue.submit(Some(encoder.finish()));
-        surface_texture.present();
     }
 }
 
-impl ApplicationHandler for App {
-    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
-        let attributes = Window::default_attributes()
-            .with_base_size(LogicalSize::new(2000, 4000))
-            .with_resizable(true);
-        let window = event_loop.create_window(attributes).unwrap();
-        pollster::block_on(self.set_window(window));
+/// Image pixel under the cursor while Alt is held
+#[derive(Clone, Copy)]
+struct HoveredPixel {
+    x: usize,
+    y: usize,
+    rgba: [u8; 4],
+}
+
+/// Hue in degrees, saturation and value in percent.
+fn rgb_to_hsv(r: u8, g: u8, b: u8) -> (f32, f32, f32) {
+    let (r, g, b) = (r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0);
+    let max = r.max(g).max(b);
+    let delta = max - r.min(g).min(b);
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    let saturation = if max == 0.0 { 0.0 } else { delta / max };
+    (hue, saturation * 100.0, max * 100.0)
+}
+
+/// Shutter speed as photographers write it: 1/250 below a second, 2.5s above.
+fn format_exposure_time((numerator, denominator): (i32, i32)) -> String {
+    let seconds = numerator as f64 / denominator as f64;
+    if seconds < 1.0 {
+        format!("1/{}", (1.0 / seconds).round())
+    } else {
+        format!("{}s", (seconds * 10.0).round() / 10.0)
     }
+}
 
-    fn window_event(&mut self, event_loop: &ActiveEventLoop, _: WindowId, event: WindowEvent) {
-        // let egui render to process the event first
-        self.state
-            .as_mut()
-            .unwrap()
-            .egui_renderer
-            .handle_input(self.window.as_ref().unwrap(), &event);
+fn format_aperture(aperture: f64) -> String {
+    format!("f/{}", (aperture * 10.0).round() / 10.0)
+}
 
-        match event {
-            WindowEvent::CloseRequested => {
-                println!("The close button was pressed; stopping");
-                event_loop.exit();
-            }
-            WindowEvent::RedrawRequested => {
-                self.handle_redraw();
-                let (events, _keys_down, pointer) = self
-                    .state
-                    .as_ref()
-                    .unwrap()
-                    .egui_renderer
-                    .context()
-                    .input(|i| (i.events.clone(), i.keys_down.clone(), i.pointer.clone()));
+/// Coordinates like `50.06143° N, 19.93658° E`
+fn format_coordinates((latitude, longitude): (f64, f64)) -> String {
+    format!(
+        "{:.5}° {}, {:.5}° {}",
+        latitude.abs(),
+        if latitude < 0.0 { 'S' } else { 'N' },
+        longitude.abs(),
+        if longitude < 0.0 { 'W' } else { 'E' }
+    )
+}
 
-                events.iter().for_each(|e| {
-                    if let Event::Key { key, pressed, .. } = e {
-                        if !*pressed {
-                            return;
-                        }
-                        match *key {
-                            Key::ArrowLeft => {
-                                self.state.as_mut().unwrap().store.next_image(-1);
-                                self.update_texture();
-                            }
-                            Key::ArrowRight => {
-                                self.state.as_mut().unwrap().store.next_image(1);
-                                self.update_texture();
-                            }
-                            Key::ArrowUp => {
-                                let rating =
-                                    self.state.as_mut().unwrap().store.get_current_rating();
-                                self.state.as_mut().unwrap().store.set_rating(rating + 1);
+/// One line of exposure settings like `1/250 · f/2.8 · ISO 400 · 85mm`, leaving out
+/// whatever the file doesn't have.
+fn shooting_summary(metadata: &ImageMetadata) -> Option<String> {
+    let parts = [
+        metadata
+            .exposure_time
+            .filter(|&(numerator, denominator)| numerator > 0 && denominator > 0)
+            .map(format_exposure_time),
+        metadata.aperture.map(format_aperture),
+        metadata.iso.map(|iso| format!("ISO {}", iso)),
+        metadata
+            .focal_length
+            .map(|length| format!("{}mm", length.round())),
+    ];
+    let summary = parts.into_iter().flatten().collect::<Vec<_>>().join(" · ");
+    (!summary.is_empty()).then_some(summary)
+}
+
+fn format_file_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1000.0 && unit < UNITS.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// Description of a flag filter for the status bar, `None` when nothing is filtered.
+fn flag_filter_label(filter: FlagFilter) -> Option<&'static str> {
+    match (filter.picked, filter.rejected, filter.unflagged) {
+        (true, true, true) => None,
+        (true, false, true) => Some("no rejects"),
+        (true, false, false) => Some("picks only"),
+        _ => Some("custom flags"),
+    }
+}
+
+/// White for picked images and red for rejected ones.
+fn flag_color(flag: FlagStatus) -> Option<egui::Color32> {
+    match flag {
+        FlagStatus::None => None,
+        FlagStatus::Picked => Some(egui::Color32::WHITE),
+        FlagStatus::Rejected => Some(egui::Color32::RED),
+    }
+}
+
+/// A small pennant with its pole starting at `top`.
+fn paint_flag(painter: &egui::Painter, top: egui::Pos2, color: egui::Color32) {
+    painter.line_segment(
+        [top, top + egui::vec2(0.0, 12.0)],
+        egui::Stroke::new(1.5, color),
+    );
+    painter.add(egui::Shape::convex_polygon(
+        vec![top, top + egui::vec2(8.0, 3.0), top + egui::vec2(0.0, 6.0)],
+        color,
+        egui::Stroke::NONE,
+    ));
+}
+
+/// A green check mark in a dark disc centred on `center`.
+fn paint_copied_badge(painter: &egui::Painter, center: egui::Pos2) {
+    painter.circle_filled(center, 7.0, egui::Color32::from_black_alpha(180));
+    painter.add(egui::Shape::line(
+        vec![
+            center + egui::vec2(-3.5, 0.0),
+            center + egui::vec2(-1.0, 2.5),
+            center + egui::vec2(3.5, -2.5),
+        ],
+        egui::Stroke::new(1.5, egui::Color32::LIGHT_GREEN),
+    ));
+}
+
+/// A centered window with the progress of the thumbnails a new folder is loading.
+fn draw_loading_splash(ctx: &egui::Context, (loaded, total): (usize, usize)) {
+    egui::Window::new("Loading")
+        .title_bar(false)
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+        .show(ctx, |ui| {
+            ui.label(format!("Loading thumbnails: {} / {}", loaded, total));
+            ui.add(
+                egui::ProgressBar::new(loaded as f32 / total.max(1) as f32)
+                    .desired_width(SPLASH_WIDTH)
+                    .show_percentage(),
+            );
+        });
+}
+
+/// The red, green and blue curves of a normalized histogram.
+fn draw_histogram(ui: &mut egui::Ui, histogram: &[[f32; 256]; 3]) {
+    let (rect, _) = ui.allocate_exact_size(
+        egui::vec2(ui.available_width(), HISTOGRAM_HEIGHT),
+        egui::Sense::hover(),
+    );
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 2.0, egui::Color32::from_black_alpha(120));
+    let colors = [
+        egui::Color32::from_rgb(255, 90, 90),
+        egui::Color32::from_rgb(90, 255, 90),
+        egui::Color32::from_rgb(90, 150, 255),
+    ];
+    for (channel, color) in histogram.iter().zip(colors) {
+        let points = channel
+            .iter()
+            .enumerate()
+            .map(|(value, &height)| {
+                egui::pos2(
+                    rect.left() + rect.width() * value as f32 / 255.0,
+                    rect.bottom() - rect.height() * height,
+                )
+            })
+            .collect();
+        painter.add(egui::Shape::line(points, egui::Stroke::new(1.0, color)));
+    }
+}
+
+/// What the status bar and the load state popup say about an image that failed to
+/// load.
+fn load_failure_label(load_state: LoadState) -> &'static str {
+    match load_state {
+        LoadState::Missing => "File missing",
+        _ => "Can't decode",
+    }
+}
+
+/// Rows of the info panel. Values missing from the file are `None`.
+fn info_rows(
+    image: &ImageData,
+    metadata: &ImageMetadata,
+    dimensions: Option<(usize, usize)>,
+    rating: i32,
+) -> Vec<(&'static str, Option<String>)> {
+    vec![
+        (
+            "File",
+            image
+                .path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string()),
+        ),
+        (
+            "Dimensions",
+            dimensions.map(|(width, height)| format!("{} × {}", width, height)),
+        ),
+        (
+            "File size",
+            (metadata.file_size > 0).then(|| format_file_size(metadata.file_size)),
+        ),
+        (
+            "Captured",
+            metadata
+                .capture_time
+                .map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string()),
+        ),
+        ("Camera", metadata.camera.clone()),
+        ("Lens", metadata.lens.clone()),
+        ("ISO", metadata.iso.map(|iso| iso.to_string())),
+        (
+            "Shutter",
+            metadata
+                .exposure_time
+                .filter(|&(numerator, denominator)| numerator > 0 && denominator > 0)
+                .map(format_exposure_time),
+        ),
+        ("Aperture", metadata.aperture.map(format_aperture)),
+        (
+            "Focal length",
+            metadata
+                .focal_length
+                .map(|length| format!("{} mm", (length * 10.0).round() / 10.0)),
+        ),
+        ("Location", metadata.gps.map(format_coordinates)),
+        ("Rating", Some(rating.to_string())),
+        ("Label", metadata.label.clone()),
+    ]
+}
+
+/// What the Ctrl+Shift+S export writes out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ExportMode {
+    /// The decoded image at its own resolution
+    Full,
+    /// The window contents below the overlays, zoom and pan included
+    View,
+}
+
+/// The F2 rename prompt
+struct RenamePrompt {
+    stem: String,
+    error: Option<String>,
+}
+
+/// The go-to-index prompt opened from the status bar
+struct GoToPrompt {
+    /// 1-based, as shown in the status bar
+    index: String,
+    error: Option<String>,
+}
+
+/// The calendar opened with Ctrl+D
+struct DatePicker {
+    /// First day of the month shown
+    month: NaiveDate,
+    /// Images taken on each day
+    counts: BTreeMap<NaiveDate, usize>,
+}
+
+/// Draws the calendar for `picker.month`, with the days that have images
+/// highlighted and badged with their count. Returns the day clicked.
+fn date_picker_ui(ui: &mut egui::Ui, picker: &mut DatePicker) -> Option<NaiveDate> {
+    ui.horizontal(|ui| {
+        if ui.button("◀").clicked() {
+            picker.month = picker.month - Months::new(1);
+        }
+        ui.label(
+            egui::RichText::new(picker.month.format("%B %Y").to_string())
+                .strong()
+                .size(16.0),
+        );
+        if ui.button("▶").clicked() {
+            picker.month = picker.month + Months::new(1);
+        }
+    });
+    let mut picked = None;
+    egui::Grid::new("calendar")
+        .spacing([2.0, 2.0])
+        .show(ui, |ui| {
+            for weekday in ["Mo", "Tu", "We", "Th", "Fr", "Sa", "Su"] {
+                ui.label(weekday);
+            }
+            ui.end_row();
+            let offset = picker.month.weekday().num_days_from_monday();
+            for _ in 0..offset {
+                ui.label("");
+            }
+            let days = picker
+                .month
+                .iter_days()
+                .take_while(|day| day.month() == picker.month.month());
+            for day in days {
+                let count = picker.counts.get(&day).copied().unwrap_or(0);
+                let button = egui::Button::new(day.day().to_string())
+                    .min_size(egui::vec2(CALENDAR_DAY_SIZE, CALENDAR_DAY_SIZE));
+                if count == 0 {
+                    ui.add_enabled(false, button.frame(false));
+                } else {
+                    let response = ui.add(button.fill(ui.visuals().selection.bg_fill));
+                    ui.painter().text(
+                        response.rect.right_top() + egui::vec2(-2.0, 1.0),
+                        egui::Align2::RIGHT_TOP,
+                        count.to_string(),
+                        egui::FontId::proportional(9.0),
+                        ui.visuals().strong_text_color(),
+                    );
+                    if response.clicked() {
+                        picked = Some(day);
+                    }
+                }
+                if (offset + day.day0()) % 7 == 6 {
+                    ui.end_row();
+                }
+            }
+        });
+    picked
+}
+
+/// The caption being edited in the info panel, saved with Ctrl+Enter
+struct CaptionDraft {
+    image: ImageData,
+    text: String,
+}
+
+/// A navigation key being held down, which repeats after `SCRUB_DELAY`
+struct HeldNavigation {
+    key: Key,
+    action: Action,
+    pressed_at: Instant,
+    /// Repeats performed so far
+    steps: u32,
+}
+
+pub struct App {
+    instance: wgpu::Instance,
+    state: Option<AppState>,
+    window: Option<Arc<Window>>,
+    path: PathBuf,
+    /// Images piped in on stdin, opened instead of the folder at `path`
+    image_list: Option<Vec<ImageData>>,
+    config: Config,
+    burst_mode: bool,
+    show_statistics: bool,
+    show_info: bool,
+    /// Whether any overlays are drawn over the image
+    show_ui: bool,
+    /// Whether the rating window fades out while there is no input
+    auto_hide: bool,
+    /// Last key press or cursor movement, for `auto_hide`
+    last_input_at: Instant,
+    /// When a rating key was last pressed, for animating the stars
+    rating_changed_at: Option<Instant>,
+    /// Last time the cursor moved, for hiding the status bar
+    pointer_moved_at: Instant,
+    show_help: bool,
+    /// Set by anything that wants the app to quit, carried out in `about_to_wait`
+    quit_requested: bool,
+    /// First press of a quit that `confirm_quit` wants pressed twice
+    quit_pressed_at: Option<Instant>,
+    /// Last resize or move that hasn't been saved yet
+    geometry_changed_at: Option<Instant>,
+    /// Wakes the event loop from other threads, see `set_wake`
+    wake: Option<Wake>,
+    /// When egui asked for its next frame, if it did
+    repaint_at: Option<Instant>,
+    show_settings: bool,
+    show_filmstrip: bool,
+    /// Tags read by the stores, kept across folders and saved on exit
+    metadata_cache: Arc<MetadataCache>,
+    /// Filmstrip and minimap textures, kept for the thumbnails currently shown
+    thumbnail_textures: HashMap<ImageData, egui::TextureHandle>,
+    /// Where the minimap was drawn last frame, so dragging on it doesn't pan the image
+    minimap_rect: Option<egui::Rect>,
+    /// OpenStreetMap tiles for the info panel, see `Config::show_map_tile`
+    map_tiles: MapTiles,
+    /// Image the filmstrip was last scrolled to
+    filmstrip_current: Option<usize>,
+    /// Current image, its position and the image count as of the last frame, for
+    /// noticing when any of them changes
+    current_image: Option<(ImageData, usize, usize)>,
+    duplicates: Option<Vec<(ImageData, ImageData)>>,
+    hovered_pixel: Option<HoveredPixel>,
+    rename_prompt: Option<RenamePrompt>,
+    go_to_prompt: Option<GoToPrompt>,
+    date_picker: Option<DatePicker>,
+    /// Whether the Ctrl+Shift+S window asking what to export is open
+    export_prompt: bool,
+    caption_draft: Option<CaptionDraft>,
+    held_navigation: Option<HeldNavigation>,
+    /// Whether the texture holds the full image rather than the thumbnail or an
+    /// early decode
+    showing_full: bool,
+    /// Cursor position in egui points, used to tell which compare pane is focused
+    pointer_pos: Option<egui::Pos2>,
+    /// Where the last primary click landed, to tell double-clicks from two clicks
+    /// in different places
+    last_click_pos: Option<egui::Pos2>,
+    /// Key held to show the previous image in place of the current one
+    flicker_key: Option<Key>,
+    last_frame: Instant,
+    keymap: KeyMap,
+    /// Opened on first use and kept, on X11 the copied data is only served while it
+    /// is alive
+    clipboard: Option<arboard::Clipboard>,
+    /// Registered once the window is open
+    #[cfg(target_os = "linux")]
+    dbus: Option<DbusService>,
+}
+
+impl App {
+    pub fn new(path: PathBuf, config: Config) -> Self {
+        let instance = egui_wgpu::wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+        let (keymap, warnings) = KeyMap::new(&config.keys);
+        for warning in warnings {
+            eprintln!("Warning: {}", warning);
+        }
+        let auto_hide = config.auto_hide_overlays;
+        Self {
+            instance,
+            state: None,
+            window: None,
+            path,
+            image_list: None,
+            config,
+            burst_mode: false,
+            show_statistics: false,
+            show_info: false,
+            show_ui: true,
+            auto_hide,
+            last_input_at: Instant::now(),
+            pointer_moved_at: Instant::now(),
+            rating_changed_at: None,
+            show_help: false,
+            quit_requested: false,
+            quit_pressed_at: None,
+            geometry_changed_at: None,
+            wake: None,
+            repaint_at: None,
+            show_settings: false,
+            show_filmstrip: false,
+            metadata_cache: Arc::new(
+                MetadataCache::path()
+                    .map(|path| MetadataCache::load(&path))
+                    .unwrap_or_default(),
+            ),
+            thumbnail_textures: HashMap::new(),
+            minimap_rect: None,
+            map_tiles: MapTiles::new(),
+            filmstrip_current: None,
+            current_image: None,
+            duplicates: None,
+            hovered_pixel: None,
+            rename_prompt: None,
+            go_to_prompt: None,
+            date_picker: None,
+            export_prompt: false,
+            caption_draft: None,
+            held_navigation: None,
+            showing_full: false,
+            pointer_pos: None,
+            last_click_pos: None,
+            flicker_key: None,
+            last_frame: Instant::now(),
+            keymap,
+            clipboard: None,
+            #[cfg(target_os = "linux")]
+            dbus: None,
+        }
+    }
+
+    /// Shows `images` in the given order instead of the folder the app was created
+    /// with.
+    pub fn set_image_list(&mut self, images: Vec<ImageData>) {
+        self.image_list = Some(images);
+    }
+
+    /// Lets background work wake the event loop, which otherwise only redraws on
+    /// input and while something on screen is moving.
+    pub fn set_wake(&mut self, wake: Wake) {
+        self.wake = Some(wake);
+    }
+
+    fn open_store(&mut self) -> ImageStore {
+        let store = match self.image_list.take() {
+            Some(images) => {
+                ImageStore::from_images(images, &self.config, self.metadata_cache.clone())
+            }
+            None => ImageStore::new(self.path.clone(), &self.config, self.metadata_cache.clone()),
+        };
+        if let Some(wake) = &self.wake {
+            store.set_wake(wake.clone());
+        }
+        store
+    }
+
+    async fn set_window(&mut self, window: Window, initial_size: PhysicalSize<u32>) {
+        let window = Arc::new(window);
+        let PhysicalSize {
+            width: initial_width,
+            height: initial_height,
+        } = initial_size;
+
+        let surface = self
+            .instance
+            .create_surface(window.clone())
+            .expect("Failed to create surface!");
+
+        let store = self.open_store();
+        let state = AppState::new(
+            &self.instance,
+            Some((&window, surface)),
+            initial_width,
+            initial_height,
+            store,
+            &self.config,
+        )
+        .await;
+
+        if let Some(theme) = window.theme() {
+            state.egui_renderer.set_theme(theme);
+        }
+        self.window.get_or_insert(window);
+        self.state.get_or_insert(state);
+
+        self.pan_zoom(0.0, 0.0, 0.0);
+        self.update_texture();
+    }
+
+    /// Renders the first `image_count` images offscreen, without opening a window, and
+    /// prints how long the frames took. Each frame is saved as a PNG in
+    /// `screenshot_dir` if there is one.
+    pub fn run_headless(&mut self, image_count: usize, screenshot_dir: Option<&Path>) {
+        let (width, height) = HEADLESS_SIZE;
+        let store = self.open_store();
+        let state = pollster::block_on(AppState::new(
+            &self.instance,
+            None,
+            width,
+            height,
+            store,
+            &self.config,
+        ));
+        let target = state.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Offscreen texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: state.surface_config.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let target_view = target.create_view(&wgpu::TextureViewDescriptor::default());
+        let image_count = image_count.min(state.store.image_count());
+        self.state = Some(state);
+        if let Some(dir) = screenshot_dir
+            && let Err(e) = std::fs::create_dir_all(dir)
+        {
+            eprintln!("Failed to create {}: {}", dir.display(), e);
+            return;
+        }
+
+        // Each image gets a single frame, which would otherwise catch it mid-fade
+        self.config.transition_duration_ms = 0;
+        let mut frame_time = Duration::ZERO;
+        for id in 0..image_count {
+            let store = &mut self.state.as_mut().unwrap().store;
+            store.jump_to_image(id);
+            // Time the rendering of full images only, not the decoding
+            while store.load_state(&store.current_image_path) == LoadState::Loading {
+                thread::sleep(Duration::from_millis(1));
+                store.check_loaded_images();
+            }
+
+            let start = Instant::now();
+            self.update_texture();
+            let state = self.state.as_ref().unwrap();
+            let mut encoder = state
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+            state.render_image(&mut encoder, &target_view, &self.config, false);
+            let frame =
+                screenshot_dir.map(|_| read_back_texture(&state.device, &mut encoder, &target));
+            state.queue.submit(Some(encoder.finish()));
+            state.device.poll(wgpu::Maintain::Wait);
+            frame_time += start.elapsed();
+
+            if let (Some(dir), Some(frame)) = (screenshot_dir, frame) {
+                let stem = state
+                    .store
+                    .current_image_path
+                    .path
+                    .file_stem()
+                    .unwrap_or_default();
+                let path = dir.join(format!("{:04}_{}.png", id, stem.to_string_lossy()));
+                if let Err(e) = save_frame(&state.device, frame, HEADLESS_SIZE, &path) {
+                    eprintln!("Failed to save {}: {}", path.display(), e);
+                }
+            }
+        }
+        println!(
+            "Rendered {} images in {:.2?} ({:.2?} per frame)",
+            image_count,
+            frame_time,
+            frame_time / image_count.max(1) as u32
+        );
+    }
+
+    fn handle_resized(&mut self, width: u32, height: u32) {
+        if width > 0 && height > 0 {
+            self.state.as_mut().unwrap().resize_surface(width, height);
+        }
+        self.pan_zoom(0.0, 0.0, 0.0);
+    }
+
+    pub fn update_texture(&mut self) {
+        let state = self.state.as_mut().unwrap();
+
+        state.store.check_loaded_images();
+        // Reloads of the image on screen, like the full image replacing the thumbnail,
+        // leave the view alone. Compare mode keeps the zoom and pan so both panes show
+        // the same region.
+        let changed = state.shown_image.as_ref() != Some(&state.store.current_image_path);
+        let memory = self.config.transform_memory;
+        let (zoom, pan_x, pan_y) =
+            if state.compare.is_some() || !changed || memory == TransformMemory::Keep {
+                (
+                    state.transform_data.target_zoom,
+                    state.transform_data.target_pan_x,
+                    state.transform_data.target_pan_y,
+                )
+            } else if memory == TransformMemory::PerImage {
+                // Where the user left off in this image, or fitted to the screen
+                state
+                    .per_image_transforms
+                    .get(&state.store.current_image_path)
+                    .copied()
+                    .unwrap_or((1.0, 0.0, 0.0))
+            } else {
+                (1.0, 0.0, 0.0)
+            };
+        let current = state.store.current_image_path.clone();
+        let previous = state.shown_image.replace(current.clone());
+        if let Some(previous) = previous.filter(|previous| *previous != current)
+            && self.config.transition_duration_ms > 0
+            && state.compare.is_none()
+            && !state.store.is_scrubbing()
+        {
+            let direction = match state.store.image_id(&previous) {
+                Some(id) if id > state.store.current_image_id() => -1,
+                _ => 1,
+            };
+            state.transition_state = Some(TransitionState {
+                direction,
+                progress: 0.0,
+                outgoing: state.snapshot_pane(previous, self.showing_full),
+            });
+        }
+        let imbuf = if let Some(full) = state.store.get_current_image() {
+            full
+        } else {
+            state.store.get_thumbnail()
+        };
+        let upload_start = Instant::now();
+        let resized = fit_image_texture(
+            &state.device,
+            &mut state.image_texture,
+            imbuf.width as u32,
+            imbuf.height as u32,
+        );
+        upload_image(&state.queue, &state.image_texture, imbuf);
+        if let Some(overlay) = &mut state.debug_overlay {
+            overlay.uploaded(upload_start.elapsed());
+        }
+        state.transform_data.width = imbuf.width as u32;
+        state.transform_data.height = imbuf.height as u32;
+        state.transform_data.hdr = imbuf.hdr;
+        if resized {
+            state.update_bind_group();
+        }
+        state.transform_data.target_zoom = zoom;
+        state.transform_data.target_pan_x = pan_x;
+        state.transform_data.target_pan_y = pan_y;
+        state.transform_data.snap_to_target();
+        state.lanczos.invalidate();
+        state.transform_data.pan_velocity_x = 0.0;
+        state.transform_data.pan_velocity_y = 0.0;
+        state.histogram_dirty = true;
+        self.showing_full = state.store.get_current_image().is_some();
+
+        // Dispatches the Lanczos passes for the new image if they are needed
+        self.pan_zoom(0.0, 0.0, 0.0);
+    }
+
+    /// Shows early decodes of the current image while it is still loading, and swaps
+    /// in the full image once it arrives.
+    fn update_texture_partial(&mut self) {
+        let state = self.state.as_mut().unwrap();
+        state.store.check_loaded_images();
+        if state.refresh_compare_pane() {
+            self.update_transform();
+        }
+        let state = self.state.as_mut().unwrap();
+        if state.store.get_current_image().is_some() {
+            if !self.showing_full {
+                // Keep the view on the region being inspected
+                state.store_transform();
+                self.update_texture();
+            }
+            return;
+        }
+        let Some(partial) = state.store.take_partial_image() else {
+            return;
+        };
+        // Same size as the full image, so the transform carries over
+        let upload_start = Instant::now();
+        if fit_image_texture(
+            &state.device,
+            &mut state.image_texture,
+            partial.width as u32,
+            partial.height as u32,
+        ) {
+            state.update_bind_group();
+        }
+        upload_image(&state.queue, &state.image_texture, &partial);
+        if let Some(overlay) = &mut state.debug_overlay {
+            overlay.uploaded(upload_start.elapsed());
+        }
+        state.transform_data.width = partial.width as u32;
+        state.transform_data.height = partial.height as u32;
+        state.lanczos.invalidate();
+        self.update_transform();
+    }
+
+    /// Moves one step for one of the navigation actions.
+    fn navigate_by(&mut self, action: Action) {
+        let change = match action {
+            Action::NextImage | Action::NextInBurst => 1,
+            _ => -1,
+        };
+        let within_burst = matches!(action, Action::NextInBurst | Action::PrevInBurst);
+        self.navigate(change, within_burst);
+    }
+
+    /// Repeats a held navigation key at `scrub_rate` once it has been down for
+    /// `SCRUB_DELAY`, and loads the full image once it is released.
+    fn repeat_held_navigation(&mut self, keys_down: &HashSet<Key>) {
+        let Some(held) = &mut self.held_navigation else {
+            return;
+        };
+        if !keys_down.contains(&held.key) {
+            self.held_navigation = None;
+            let store = &mut self.state.as_mut().unwrap().store;
+            if store.is_scrubbing() {
+                store.set_scrubbing(false);
+                self.update_texture();
+            }
+            return;
+        }
+        let Some(held_for) = held.pressed_at.elapsed().checked_sub(SCRUB_DELAY) else {
+            return;
+        };
+        let due = (held_for.as_secs_f32() * self.config.scrub_rate) as u32 + 1;
+        let (action, steps) = (held.action, due.saturating_sub(held.steps));
+        held.steps = held.steps.max(due);
+        if steps == 0 {
+            return;
+        }
+        self.state.as_mut().unwrap().store.set_scrubbing(true);
+        for _ in 0..steps {
+            self.navigate_by(action);
+        }
+        self.update_texture();
+    }
+
+    fn navigate(&mut self, change: i32, within_burst: bool) {
+        let store = &mut self.state.as_mut().unwrap().store;
+        if !self.burst_mode {
+            let wrapped = store.next_image(change);
+            self.report_wrap(wrapped);
+        } else if within_burst {
+            store.next_image_in_burst(change);
+        } else {
+            store.next_burst(change);
+        }
+    }
+
+    fn report_wrap(&mut self, wrapped: Option<Wrapped>) {
+        match wrapped {
+            Some(Wrapped::ToStart) => self.push_toast(ToastKind::Info, "Wrapped to beginning"),
+            Some(Wrapped::ToEnd) => self.push_toast(ToastKind::Info, "Wrapped to end"),
+            None => (),
+        }
+    }
+
+    /// Size in physical pixels of the area the current image is drawn in, which is the
+    /// right half of the window in compare mode.
+    fn view_size(&self) -> (f32, f32) {
+        let state = self.state.as_ref().unwrap();
+        let panes = if state.compare.is_some() { 2.0 } else { 1.0 };
+        (
+            state.surface_config.width as f32 / panes,
+            state.surface_config.height as f32,
+        )
+    }
+
+    /// Scale applied to the quad so the image fits the view at zoom 1.0.
+    fn fit_scale(&self) -> (f32, f32) {
+        let transform_data = &self.state.as_ref().unwrap().transform_data;
+        fit_scale_for(
+            transform_data.width,
+            transform_data.height,
+            transform_data.orientation,
+            self.view_size(),
+        )
+    }
+
+    /// Zoom value at which the image fills the view along its longer side.
+    fn fit_zoom(&self) -> f32 {
+        let (scale_x, scale_y) = self.fit_scale();
+        scale_x.max(scale_y).powf(-1.0 / ZOOM_MULTIPLIER)
+    }
+
+    /// Physical screen pixels per image pixel at the given zoom value.
+    fn pixel_scale(&self, zoom: f32) -> f32 {
+        let (scale_x, _) = self.fit_scale();
+        let transform_data = &self.state.as_ref().unwrap().transform_data;
+        // The image width that ends up across the screen
+        let image_width = if transform_data.orientation.is_sideways() {
+            transform_data.height
+        } else {
+            transform_data.width
+        } as f32;
+        zoom.powf(ZOOM_MULTIPLIER) * scale_x * self.view_size().0 / image_width
+    }
+
+    /// Whether `pos` (in egui points) is over pane A in compare mode.
+    fn over_pane_a(&self, pos: egui::Pos2) -> bool {
+        let state = self.state.as_ref().unwrap();
+        let pixels_per_point = state.egui_renderer.context().pixels_per_point();
+        state.compare.is_some() && pos.x * pixels_per_point < self.view_size().0
+    }
+
+    /// The image rating keys apply to, which in compare mode is the pane under the cursor.
+    fn focused_image(&self) -> ImageData {
+        let state = self.state.as_ref().unwrap();
+        match &state.compare {
+            Some(pane) if self.pointer_pos.is_some_and(|pos| self.over_pane_a(pos)) => {
+                pane.image.clone()
+            }
+            _ => state.store.current_image_path.clone(),
+        }
+    }
+
+    /// Pins the current image as A for a side-by-side comparison, or goes back to a
+    /// single view of whichever pane has the cursor.
+    fn toggle_compare(&mut self) {
+        let focused = self.focused_image();
+        let state = self.state.as_mut().unwrap();
+        if state.compare.is_none() {
+            if !state.store.is_empty() {
+                state.pin_compare_pane();
+            }
+        } else {
+            // Still in compare mode here, so the zoom and pan carry over
+            if focused != state.store.current_image_path
+                && let Some(id) = state.store.image_id(&focused)
+            {
+                state.store.jump_to_image(id);
+                self.update_texture();
+            }
+            self.state.as_mut().unwrap().compare = None;
+        }
+        self.pan_zoom(0.0, 0.0, 0.0);
+    }
+
+    /// Shows the previously viewed image in place of the current one until `key` is
+    /// released, keeping the zoom and pan. Only works once both are fully loaded.
+    fn start_flicker(&mut self, key: Key) {
+        let state = self.state.as_mut().unwrap();
+        let Some(previous) = state.store.previous_image().cloned() else {
+            state
+                .toasts
+                .push_warning("No previous image to compare with");
+            return;
+        };
+        let Some(imbuf) = state
+            .store
+            .get_image(&previous)
+            .filter(|_| state.store.get_current_image().is_some())
+        else {
+            state
+                .toasts
+                .push_warning("Both images have to finish loading first");
+            return;
+        };
+        if state
+            .flicker
+            .as_ref()
+            .is_none_or(|pane| pane.image != previous)
+        {
+            state.flicker = Some(state.create_compare_pane(previous, imbuf, true));
+        }
+        self.flicker_key = Some(key);
+        self.update_transform();
+    }
+
+    /// Switches between fit-to-window and one image pixel per screen pixel, keeping
+    /// the point under `pos` in place on the way in.
+    fn toggle_one_to_one(&mut self, pos: Option<egui::Pos2>) {
+        let one_to_one = self.pixel_scale(1.0).powf(-1.0 / ZOOM_MULTIPLIER);
+        let state = self.state.as_ref().unwrap();
+        if (state.transform_data.target_zoom - one_to_one).abs() < 1e-3 {
+            return self.reset_transform();
+        }
+        self.set_zoom_at(one_to_one, pos);
+    }
+
+    /// Moves the crossfade between images `elapsed` seconds further.
+    fn advance_transition(&mut self, elapsed: f32) {
+        let duration = self.config.transition_duration_ms as f32 / 1000.0;
+        let state = self.state.as_mut().unwrap();
+        let Some(transition) = &mut state.transition_state else {
+            return;
+        };
+        transition.progress += elapsed / duration;
+        if transition.progress >= 1.0 {
+            state.transition_state = None;
+        }
+        self.update_transform();
+    }
+
+    /// Advances the zoom/pan animation by the time since the last frame, which is
+    /// returned in seconds.
+    fn animate_transform(&mut self) -> f32 {
+        let now = Instant::now();
+        let elapsed = now
+            .duration_since(self.last_frame)
+            .as_secs_f32()
+            .min(MAX_FRAME_TIME);
+        self.last_frame = now;
+
+        let time_constant = self.config.animation_time_constant;
+        let transform_data = &mut self.state.as_mut().unwrap().transform_data;
+        let animating = if time_constant <= 0.0 {
+            transform_data.snap_to_target();
+            false
+        } else {
+            transform_data.step_toward_target(1.0 - (-elapsed / time_constant).exp())
+        };
+        if animating {
+            self.update_transform();
+        }
+        elapsed
+    }
+
+    /// Call after changing the target transform.
+    fn transform_changed(&mut self) {
+        let state = self.state.as_mut().unwrap();
+        state.store_transform();
+        if self.config.animation_time_constant <= 0.0 {
+            state.transform_data.snap_to_target();
+        }
+        self.update_transform();
+    }
+
+    fn update_transform(&mut self) {
+        let (scale_x, scale_y) = self.fit_scale();
+        let zoom = self.pixel_scale(self.state.as_ref().unwrap().transform_data.zoom);
+        let view_size = self.view_size();
+        let state = self.state.as_mut().unwrap();
+
+        // While changing images the new one fades in over the old one
+        let (opacity, slide) = state
+            .transition_state
+            .as_ref()
+            .map_or((1.0, 0.0), |transition| {
+                (transition.progress, transition.slide().1)
+            });
+        let mut transform = create_transform_matrix(&state.transform_data, scale_x, scale_y);
+        transform[12] += slide;
+        let resampled = state.update_lanczos(zoom);
+        let (resampled_width, resampled_height) = resampled.unwrap_or_default();
+        state.queue.write_buffer(
+            &state.transform_buffer,
+            0,
+            bytemuck::cast_slice(&[Transforms {
+                transform,
+                width: state.transform_data.width,
+                height: state.transform_data.height,
+                sharpen_enabled: state.transform_data.sharpen as u32,
+                sharpen_amount: state.transform_data.sharpen_amount,
+                zoom,
+                hdr_mode: state
+                    .transform_data
+                    .hdr_override
+                    .unwrap_or(state.transform_data.hdr) as u32,
+                use_lanczos: resampled.is_some() as u32,
+                resampled_width,
+                resampled_height,
+                alpha_background: self.config.alpha_background as u32,
+                nearest: self.config.sampling.use_nearest(zoom, self.showing_full) as u32,
+                clipping_enabled: state.transform_data.clipping as u32,
+                clip_high: CLIP_HIGH,
+                clip_low: CLIP_LOW,
+                opacity,
+                _padding: 0,
+            }]),
+        );
+
+        // Same zoom and pan for the other images, fitted to their own aspect ratio
+        let outgoing = state.transition_state.as_ref().map(|transition| {
+            (
+                &transition.outgoing,
+                1.0 - transition.progress,
+                transition.slide().0,
+            )
+        });
+        let panes = state
+            .compare
+            .iter()
+            .chain(&state.flicker)
+            .map(|pane| (pane, 1.0, 0.0))
+            .chain(outgoing);
+        for (pane, opacity, slide) in panes {
+            let orientation = state.transform_data.orientation;
+            let (scale_x, scale_y) = fit_scale_for(pane.width, pane.height, orientation, view_size);
+            let mut transform = create_transform_matrix(&state.transform_data, scale_x, scale_y);
+            transform[12] += slide;
+            let pane_width = if orientation.is_sideways() {
+                pane.height
+            } else {
+                pane.width
+            };
+            let zoom = state.transform_data.zoom.powf(ZOOM_MULTIPLIER) * scale_x * view_size.0
+                / pane_width as f32;
+            state.queue.write_buffer(
+                &pane.transform_buffer,
+                0,
+                bytemuck::cast_slice(&[Transforms {
+                    transform,
+                    width: pane.width,
+                    height: pane.height,
+                    sharpen_enabled: state.transform_data.sharpen as u32,
+                    sharpen_amount: state.transform_data.sharpen_amount,
+                    zoom,
+                    hdr_mode: state.transform_data.hdr_override.unwrap_or(pane.hdr) as u32,
+                    use_lanczos: 0,
+                    resampled_width: 0,
+                    resampled_height: 0,
+                    alpha_background: self.config.alpha_background as u32,
+                    nearest: self.config.sampling.use_nearest(zoom, pane.full) as u32,
+                    clipping_enabled: state.transform_data.clipping as u32,
+                    clip_high: CLIP_HIGH,
+                    clip_low: CLIP_LOW,
+                    opacity,
+                    _padding: 0,
+                }]),
+            );
+        }
+    }
+
+    pub fn reset_transform(&mut self) {
+        let fit_zoom = self.fit_zoom();
+        let state = self.state.as_mut().unwrap();
+        state.transform_data.target_zoom = fit_zoom;
+        state.transform_data.target_pan_x = 0.0;
+        state.transform_data.target_pan_y = 0.0;
+        self.transform_changed();
+        // Fitted is what the image gets without a saved transform anyway
+        let state = self.state.as_mut().unwrap();
+        let current = &state.store.current_image_path;
+        state.per_image_transforms.remove(current);
+    }
+
+    pub fn pan_zoom(&mut self, zoom_delta: f32, pan_x: f32, pan_y: f32) {
+        let (scale_x, scale_y) = self.fit_scale();
+        let fit_zoom = self.fit_zoom();
+        let min_visible_fraction = self.config.min_visible_fraction;
+        let state = self.state.as_mut().unwrap();
+
+        let transform_data = &mut state.transform_data;
+        // Zooming below fit only happens through the 1:1 toggle, don't undo it
+        let min_zoom = transform_data.target_zoom.min(fit_zoom);
+        transform_data.target_zoom =
+            (transform_data.target_zoom + zoom_delta).clamp(min_zoom, MAX_ZOOM);
+        let zoom = transform_data.target_zoom;
+        if zoom <= fit_zoom {
+            transform_data.target_pan_x = 0.0;
+            transform_data.target_pan_y = 0.0;
+        } else {
+            let scale = zoom.powf(ZOOM_MULTIPLIER);
+            transform_data.target_pan_x = clamp_pan(
+                transform_data.target_pan_x + pan_x,
+                scale * scale_x,
+                min_visible_fraction,
+            );
+            transform_data.target_pan_y = clamp_pan(
+                transform_data.target_pan_y + pan_y,
+                scale * scale_y,
+                min_visible_fraction,
+            );
+        }
+        self.transform_changed();
+    }
+
+    /// Scales the view by `factor`, keeping the point under `pos` (in egui points) in
+    /// place, or the middle of the view without a position.
+    fn zoom_at(&mut self, factor: f32, pos: Option<egui::Pos2>) {
+        let zoom = self.state.as_ref().unwrap().transform_data.target_zoom;
+        let fit_zoom = self.fit_zoom();
+        // The scale grows with the zoom value to the power of `ZOOM_MULTIPLIER`. As
+        // in `pan_zoom`, only the 1:1 toggle goes below fit.
+        let target_zoom =
+            (zoom * factor.powf(1.0 / ZOOM_MULTIPLIER)).clamp(zoom.min(fit_zoom), MAX_ZOOM);
+        self.set_zoom_at(target_zoom, pos);
+    }
+
+    /// Zooms to `zoom`, keeping the point under `pos` (in egui points) in place, or
+    /// the middle of the view without a position.
+    fn set_zoom_at(&mut self, zoom: f32, pos: Option<egui::Pos2>) {
+        let anchor = pos.map_or(egui::Pos2::ZERO, |pos| self.clip_position(pos));
+        let transform_data = &mut self.state.as_mut().unwrap().transform_data;
+        let ratio = (zoom / transform_data.target_zoom).powf(ZOOM_MULTIPLIER);
+        transform_data.target_pan_x = anchor.x - (anchor.x - transform_data.target_pan_x) * ratio;
+        transform_data.target_pan_y = anchor.y - (anchor.y - transform_data.target_pan_y) * ratio;
+        transform_data.target_zoom = zoom;
+        // Clamps the pan to the new zoom
+        self.pan_zoom(0.0, 0.0, 0.0);
+    }
+
+    /// Zooms with mouse wheels and pans with two-finger scrolling on touchpads, or the
+    /// other way round with `swap_scroll`. Touchpads scroll in points where winit can
+    /// tell them apart from mouse wheels. Horizontal scrolling always pans.
+    fn scroll(&mut self, unit: MouseWheelUnit, delta: egui::Vec2, modifiers: &Modifiers) {
+        let touchpad = unit == MouseWheelUnit::Point;
+        // Some platforms deliver touchpad pinches as scrolling with Ctrl held
+        let pan = if modifiers.ctrl || touchpad == self.config.swap_scroll {
+            let lines = if touchpad {
+                delta.y / POINTS_PER_LINE
+            } else {
+                delta.y
+            };
+            self.pan_zoom(lines * WHEEL_ZOOM_SPEED, 0.0, 0.0);
+            egui::vec2(delta.x, 0.0)
+        } else {
+            delta
+        };
+        if pan == egui::Vec2::ZERO {
+            return;
+        }
+        let state = self.state.as_ref().unwrap();
+        let pixels_per_point = state.egui_renderer.context().pixels_per_point();
+        // Touchpads move the image with the fingers, wheel steps grow with the zoom
+        // level like the pan keys
+        let pixels = if touchpad {
+            pan * pixels_per_point
+        } else {
+            pan * POINTS_PER_LINE * pixels_per_point * state.transform_data.zoom
+        };
+        self.pan_by_pixels(pixels.x, pixels.y);
+    }
+
+    /// Pans so that the point at `fraction` of the image's width and height, measured
+    /// from the top left, is in the middle of the view.
+    fn center_on(&mut self, fraction: egui::Pos2) {
+        let (scale_x, scale_y) = self.fit_scale();
+        let transform_data = &mut self.state.as_mut().unwrap().transform_data;
+        let zoom = transform_data.target_zoom.powf(ZOOM_MULTIPLIER);
+        // The point in the quad's coordinates, turned the way it is displayed
+        let vertex = (2.0 * fraction.x - 1.0, 1.0 - 2.0 * fraction.y);
+        let [c0, c1] = orientation_matrix(transform_data.orientation);
+        let x = c0[0] * vertex.0 + c1[0] * vertex.1;
+        let y = c0[1] * vertex.0 + c1[1] * vertex.1;
+        transform_data.target_pan_x = -x * zoom * scale_x;
+        transform_data.target_pan_y = -y * zoom * scale_y;
+        transform_data.pan_velocity_x = 0.0;
+        transform_data.pan_velocity_y = 0.0;
+        // Clamps the new pan
+        self.pan_zoom(0.0, 0.0, 0.0);
+    }
+
+    /// Pans by a distance in physical screen pixels, so a dragged image stays under the
+    /// cursor at any zoom level and window size. Positive `dy` moves the image down.
+    pub fn pan_by_pixels(&mut self, dx: f32, dy: f32) {
+        // The pan is applied after scaling, in clip space, which spans 2 units per axis
+        let (view_width, view_height) = self.view_size();
+        let pan_x = 2.0 * dx / view_width;
+        let pan_y = -2.0 * dy / view_height;
+        self.pan_zoom(0.0, pan_x, pan_y);
+        // Dragging has to follow the cursor, so it isn't animated
+        let transform_data = &mut self.state.as_mut().unwrap().transform_data;
+        transform_data.pan_x = transform_data.target_pan_x;
+        transform_data.pan_y = transform_data.target_pan_y;
+        self.update_transform();
+    }
+
+    /// Keeps a released drag moving, slowing it down by `INERTIA_FRICTION`
+    /// independently of the frame rate.
+    fn apply_inertia(&mut self, elapsed: f32) {
+        let transform_data = &self.state.as_ref().unwrap().transform_data;
+        let (velocity_x, velocity_y) =
+            (transform_data.pan_velocity_x, transform_data.pan_velocity_y);
+        if velocity_x == 0.0 && velocity_y == 0.0 {
+            return;
+        }
+        let before = (transform_data.target_pan_x, transform_data.target_pan_y);
+        self.pan_by_pixels(velocity_x * elapsed, velocity_y * elapsed);
+        let transform_data = &mut self.state.as_mut().unwrap().transform_data;
+        let friction = INERTIA_FRICTION.powf(elapsed * 1000.0 / 16.0);
+        transform_data.pan_velocity_x *= friction;
+        transform_data.pan_velocity_y *= friction;
+        // Stop at the edges the pan is clamped to rather than pushing against them
+        if transform_data.target_pan_x == before.0 {
+            transform_data.pan_velocity_x = 0.0;
+        }
+        if transform_data.target_pan_y == before.1 {
+            transform_data.pan_velocity_y = 0.0;
+        }
+        if transform_data
+            .pan_velocity_x
+            .hypot(transform_data.pan_velocity_y)
+            < INERTIA_MIN_SPEED
+        {
+            transform_data.pan_velocity_x = 0.0;
+            transform_data.pan_velocity_y = 0.0;
+        }
+    }
+
+    /// Pans and zooms for as long as the bound keys are held, scaled by the frame time
+    /// so the speed doesn't depend on the frame rate.
+    fn apply_held_keys(&mut self, keys_down: &HashSet<Key>, modifiers: &Modifiers, elapsed: f32) {
+        let (mut dx, mut dy, mut zoom_delta) = (0.0, 0.0, 0.0);
+        for key in keys_down {
+            match self.keymap.action(*key, modifiers) {
+                Some(Action::PanLeft) => dx += 1.0,
+                Some(Action::PanRight) => dx -= 1.0,
+                Some(Action::PanUp) => dy += 1.0,
+                Some(Action::PanDown) => dy -= 1.0,
+                Some(Action::ZoomIn) => zoom_delta += 1.0,
+                Some(Action::ZoomOut) => zoom_delta -= 1.0,
+                _ => (),
+            }
+        }
+        if zoom_delta != 0.0 {
+            self.pan_zoom(zoom_delta * KEY_ZOOM_SPEED * elapsed, 0.0, 0.0);
+        }
+        if dx != 0.0 || dy != 0.0 {
+            // Applied immediately like a drag, so releasing the keys stops at once
+            let speed = KEY_PAN_SPEED * self.state.as_ref().unwrap().transform_data.zoom * elapsed;
+            self.pan_by_pixels(dx * speed, dy * speed);
+        }
+    }
+
+    /// Clip space position of `pos` (in egui points) within the pane it is over.
+    fn clip_position(&self, pos: egui::Pos2) -> egui::Pos2 {
+        let state = self.state.as_ref().unwrap();
+        let (view_width, view_height) = self.view_size();
+        let pixels_per_point = state.egui_renderer.context().pixels_per_point();
+        // Pane B starts halfway across the window
+        let view_x = if state.compare.is_some() && !self.over_pane_a(pos) {
+            view_width
+        } else {
+            0.0
+        };
+        egui::pos2(
+            2.0 * (pos.x * pixels_per_point - view_x) / view_width - 1.0,
+            1.0 - 2.0 * pos.y * pixels_per_point / view_height,
+        )
+    }
+
+    /// Maps a cursor position (in egui points) back through the transform to the
+    /// pixel of the displayed image under it, clamped to the image bounds.
+    fn pixel_at(&self, pos: egui::Pos2) -> Option<HoveredPixel> {
+        let state = self.state.as_ref()?;
+        let over_pane_a = self.over_pane_a(pos);
+        let store = &state.store;
+        let image = match &state.compare {
+            Some(pane) if over_pane_a => &pane.image,
+            _ => &store.current_image_path,
+        };
+        let imbuf = store
+            .get_image(image)
+            .or_else(|| store.thumbnail_for(image))?;
+        let (scale_x, scale_y) = fit_scale_for(
+            imbuf.width as u32,
+            imbuf.height as u32,
+            state.transform_data.orientation,
+            self.view_size(),
+        );
+
+        // Clip space -> quad vertex space (-1..1) -> uv
+        let egui::Pos2 { x: u, y: v } = clip_to_uv(
+            &state.transform_data,
+            scale_x,
+            scale_y,
+            self.clip_position(pos),
+        );
+        // Nothing to read over the background around the image
+        if !(0.0..=1.0).contains(&u) || !(0.0..=1.0).contains(&v) {
+            return None;
+        }
+
+        let x = ((u * imbuf.width as f32) as usize).min(imbuf.width.saturating_sub(1));
+        let y = ((v * imbuf.height as f32) as usize).min(imbuf.height.saturating_sub(1));
+        let rgba = imbuf.rgba_buffer.get(y * imbuf.width + x)?.to_le_bytes();
+        Some(HoveredPixel { x, y, rgba })
+    }
+
+    fn handle_redraw(&mut self, elapsed: f32) {
+        let frame_start = Instant::now();
+        // Attempt to handle minimizing window
+        if let Some(window) = self.window.as_ref() {
+            if let Some(min) = window.is_minimized() {
+                if min {
+                    println!("Window is minimized");
+                    return;
+                }
+            }
+        }
+
+        let zoom = self.state.as_ref().unwrap().transform_data.zoom;
+        let zoom_percent = format!("{:.0}%", self.pixel_scale(zoom) * 100.0);
+        let state = self.state.as_mut().unwrap();
+
+        let screen_descriptor = ScreenDescriptor {
+            size_in_pixels: [state.surface_config.width, state.surface_config.height],
+            pixels_per_point: state.scale_factor * self.config.ui_scale,
+        };
+
+        let surface_texture = state.surface.as_ref().unwrap().get_current_texture();
+
+        let surface_texture = match surface_texture {
+            Err(SurfaceError::Outdated) => {
+                // Ignoring outdated to allow resizing and minimization
+                println!("wgpu surface outdated");
+                return;
+            }
+            Err(SurfaceError::Timeout) => {
+                println!("wgpu surface timeout");
+                return;
+            }
+            Err(_) => {
+                surface_texture.expect("Failed to acquire next swap chain texture");
+                return;
+            }
+            Ok(surface_texture) => surface_texture,
+        };
+
+        let surface_view = surface_texture
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+
+        let mut encoder = state
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+
+        // Only the splash until the thumbnails are in, so the filmstrip and the
+        // overlays don't fill in piece by piece
+        if let Some(progress) = state.store.thumbnail_progress() {
+            state.render_background(&mut encoder, &surface_view, &self.config);
+            let window = self.window.as_ref().unwrap();
+            state.egui_renderer.begin_frame(window);
+            draw_loading_splash(state.egui_renderer.context(), progress);
+            let repaint_delay = state.egui_renderer.end_frame_and_draw(
+                &state.device,
+                &state.queue,
+                &mut encoder,
+                window,
+                &surface_view,
+                screen_descriptor,
+            );
+            self.repaint_at = Instant::now().checked_add(repaint_delay);
+            state.queue.submit(Some(encoder.finish()));
+            surface_texture.present();
+            return;
+        }
+
+        if let Some(profiler) = &mut state.gpu_profiler {
+            profiler.poll(&state.device);
+            profiler.begin(&mut encoder);
+        }
+        state.render_image(
+            &mut encoder,
+            &surface_view,
+            &self.config,
+            self.flicker_key.is_some(),
+        );
+        if let Some(profiler) = &mut state.gpu_profiler {
+            profiler.end(&mut encoder);
+        }
+
+        let path = state.store.current_image_path.clone();
+        // Name and rating follow the image on screen while flickering
+        let shown = match &state.flicker {
+            Some(pane) if self.flicker_key.is_some() => pane.image.clone(),
+            _ => path.clone(),
+        };
+        let rating = state.store.get_image_rating(&shown);
+        let filename = shown.path.file_name().unwrap_or_default();
+        let empty = state.store.is_empty();
+        // Counted among the images navigation stops at, so filtering shrinks the total
+        let position = match state.store.visible_position() {
+            (Some(position), count) => format!("{} / {}", position, count),
+            (None, count) => format!("– / {}", count),
+        };
+        let burst_label = state
+            .store
+            .current_burst()
+            .zip(state.store.burst_number())
+            .map(|(burst, (number, count))| {
+                let id = state.store.current_image_id();
+                format!(
+                    "burst {}/{}, frame {}/{}",
+                    number,
+                    count,
+                    id - burst.start + 1,
+                    burst.len()
+                )
+            });
+        let stack_label = state.store.stack_label();
+        // Stars pop up briefly after a rating key and shrink back
+        let star_scale = self
+            .rating_changed_at
+            .map(|changed_at| changed_at.elapsed().as_secs_f32() / RATING_PULSE.as_secs_f32())
+            .filter(|&progress| progress < 1.0)
+            .map_or(1.0, |progress| 1.0 + 0.3 * (1.0 - progress));
+        let mut clicked_star = None;
+        let rating_opacity = if self.auto_hide {
+            let idle = self.last_input_at.elapsed().saturating_sub(AUTO_HIDE_DELAY);
+            1.0 - (idle.as_secs_f32() / AUTO_HIDE_FADE.as_secs_f32()).min(1.0)
+        } else {
+            1.0
+        };
+        // From the metadata read at startup, never from the file
+        let shooting_summary = (self.show_ui && !empty)
+            .then(|| state.store.metadata(&shown).and_then(shooting_summary))
+            .flatten();
+        let fullscreen = self.window.as_ref().unwrap().fullscreen().is_some();
+        let hovered_pixel = self.hovered_pixel;
+        let status = (self.show_ui
+            && !empty
+            && !fullscreen
+            && self.pointer_moved_at.elapsed() < STATUS_BAR_IDLE_TIMEOUT)
+            .then(|| {
+                let metadata = state.store.metadata(&shown);
+                let dimensions = state
+                    .store
+                    .get_image(&shown)
+                    .map(|imbuf| (imbuf.width as u32, imbuf.height as u32))
+                    .or_else(|| metadata.map(|metadata| (metadata.width, metadata.height)))
+                    .filter(|&(width, height)| width > 0 && height > 0);
+                [
+                    Some(position.clone()),
+                    Some(filename.to_string_lossy().to_string()),
+                    dimensions.map(|(width, height)| format!("{} × {}", width, height)),
+                    metadata
+                        .filter(|metadata| metadata.file_size > 0)
+                        .map(|metadata| format_file_size(metadata.file_size)),
+                    Some(zoom_percent.clone()),
+                    flag_filter_label(state.store.flag_filter())
+                        .map(|label| format!("filter: {}", label)),
+                    (!state.selected.is_empty())
+                        .then(|| format!("{} selected", state.selected.len())),
+                    hovered_pixel.map(|pixel| {
+                        let [r, g, b, a] = pixel.rgba;
+                        if a == 255 {
+                            format!("{},{}  {} {} {}", pixel.x, pixel.y, r, g, b)
+                        } else {
+                            format!("{},{}  {} {} {} {}", pixel.x, pixel.y, r, g, b, a)
+                        }
+                    }),
+                ]
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>()
+            });
+        // In the corner instead while the status bar is hidden
+        let pixel_popup = hovered_pixel.filter(|_| status.is_none());
+        if self.show_info {
+            let time_constant = self.config.animation_time_constant;
+            state.update_histogram(if time_constant <= 0.0 {
+                1.0
+            } else {
+                1.0 - (-elapsed / time_constant).exp()
+            });
+        }
+        let histogram = (self.show_info && !empty)
+            .then_some(state.histogram)
+            .flatten();
+        let info = (self.show_ui && self.show_info && !empty).then(|| {
+            let metadata = state.store.metadata(&shown).cloned().unwrap_or_default();
+            // Not every format has its size in the metadata
+            let dimensions = if metadata.width > 0 && metadata.height > 0 {
+                Some((metadata.width as usize, metadata.height as usize))
+            } else {
+                state
+                    .store
+                    .get_image(&shown)
+                    .map(|imbuf| (imbuf.width, imbuf.height))
+            };
+            info_rows(&shown, &metadata, dimensions, rating)
+        });
+        // Nothing is downloaded unless the map was turned on
+        let map_tile = (info.is_some() && self.config.show_map_tile)
+            .then(|| {
+                state
+                    .store
+                    .metadata(&shown)
+                    .and_then(|metadata| metadata.gps)
+            })
+            .flatten()
+            .map(|gps| {
+                let zoom = self
+                    .config
+                    .map_tile_zoom
+                    .clamp(MIN_MAP_TILE_ZOOM, MAX_MAP_TILE_ZOOM);
+                let (key, position) = TileKey::containing(gps, zoom);
+                let tile = self
+                    .map_tiles
+                    .get(key, &state.egui_renderer, state.store.wake());
+                (tile, position)
+            });
+        // The caption as it is on disk, the draft is compared against it
+        let saved_caption = info.is_some().then(|| {
+            state
+                .store
+                .metadata(&shown)
+                .and_then(|metadata| metadata.caption.clone())
+                .unwrap_or_default()
+        });
+        if let Some(saved) = &saved_caption
+            && self
+                .caption_draft
+                .as_ref()
+                .is_none_or(|draft| draft.image != shown)
+        {
+            self.caption_draft = Some(CaptionDraft {
+                image: shown.clone(),
+                text: saved.clone(),
+            });
+        }
+        let mut save_caption = false;
+        let compare_label = state.compare.as_ref().map(|pane| {
+            format!(
+                "A: {} ({})",
+                pane.image
+                    .path
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy(),
+                state.store.get_image_rating(&pane.image)
+            )
+        });
+        let current_id = state.store.current_image_id();
+        let filmstrip = (self.show_ui && self.show_filmstrip && !empty).then(|| {
+            let neighbors = state
+                .store
+                .neighbors(FILMSTRIP_NEIGHBORS, FILMSTRIP_NEIGHBORS);
+            self.thumbnail_textures
+                .retain(|image, _| neighbors.iter().any(|(_, neighbor, _)| *neighbor == image));
+            // Thumbnails that haven't loaded yet stay placeholders instead of being decoded
+            neighbors
+                .into_iter()
+                .map(|(id, image, thumbnail)| {
+                    let flag = state.store.get_image_flag(image);
+                    let texture = thumbnail.map(|thumbnail| {
+                        self.thumbnail_textures
+                            .entry(image.clone())
+                            .or_insert_with(|| {
+                                state.egui_renderer.load_texture(
+                                    &image.path.to_string_lossy(),
+                                    [thumbnail.width, thumbnail.height],
+                                    bytemuck::cast_slice(&thumbnail.rgba_buffer),
+                                )
+                            })
+                            .clone()
+                    });
+                    let selected = state.selected.contains(image);
+                    let copied = state.copied.contains(image);
+                    (id, image.clone(), texture, flag, selected, copied)
+                })
+                .collect::<Vec<_>>()
+        });
+        if filmstrip.is_none() {
+            self.thumbnail_textures.retain(|image, _| *image == path);
+        }
+        // Only when zoomed past fit, and not for the two panes of a comparison
+        let minimap = (self.show_ui && !empty && state.compare.is_none())
+            .then_some(&state.transform_data)
+            .filter(|transform_data| transform_data.target_zoom > 1.0)
+            .and_then(|transform_data| {
+                let thumbnail = state.store.thumbnail_for(&path)?;
+                let texture = self
+                    .thumbnail_textures
+                    .entry(path.clone())
+                    .or_insert_with(|| {
+                        state.egui_renderer.load_texture(
+                            &path.path.to_string_lossy(),
+                            [thumbnail.width, thumbnail.height],
+                            bytemuck::cast_slice(&thumbnail.rgba_buffer),
+                        )
+                    })
+                    .clone();
+                let (scale_x, scale_y) = fit_scale_for(
+                    transform_data.width,
+                    transform_data.height,
+                    transform_data.orientation,
+                    (
+                        state.surface_config.width as f32,
+                        state.surface_config.height as f32,
+                    ),
+                );
+                Some((texture, visible_fraction(transform_data, scale_x, scale_y)))
+            });
+        let mut minimap_target = None;
+        let scroll_filmstrip = self.filmstrip_current != Some(current_id);
+        let mut jump_to = None;
+        let mut toggle_selected = None;
+        let flag = state.store.get_image_flag(&shown);
+        let load_state = (!empty).then(|| state.store.load_state(&path));
+        // Counts over the whole folder, then over the images the flag filter shows
+        let statistics = self.show_statistics.then(|| {
+            let store = &state.store;
+            (
+                [store.rating_histogram(false), store.rating_histogram(true)],
+                [store.label_histogram(false), store.label_histogram(true)],
+                flag_filter_label(store.flag_filter()),
+            )
+        });
+        let mut export_manifest = false;
+        let mut import_manifest = false;
+        let mut find_duplicates = false;
+        let mut submit_rename = false;
+        let mut open_go_to = false;
+        let mut submit_go_to = false;
+        let mut picked_date = None;
+        let mut export_mode = None;
+        // The config as it was before the settings window changed it
+        let mut previous_config = None;
+        let tone_mapping = match state.transform_data.hdr_override {
+            None if state.transform_data.hdr => Some("HDR tone mapping (auto)"),
+            Some(true) => Some("HDR tone mapping (forced)"),
+            _ => None,
+        };
+        // Timers that change the overlays without any input
+        let repaint_after = [
+            (star_scale > 1.0).then_some(Duration::ZERO),
+            self.auto_hide
+                .then(|| {
+                    (AUTO_HIDE_DELAY + AUTO_HIDE_FADE).checked_sub(self.last_input_at.elapsed())
+                })
+                .flatten()
+                .map(|left| left.saturating_sub(AUTO_HIDE_FADE)),
+            status
+                .as_ref()
+                .and_then(|_| STATUS_BAR_IDLE_TIMEOUT.checked_sub(self.pointer_moved_at.elapsed())),
+            (self.show_info && state.histogram != state.histogram_target).then_some(Duration::ZERO),
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+        let window = self.window.as_ref().unwrap();
+        {
+            state.egui_renderer.begin_frame(window);
+            if let Some(delay) = repaint_after {
+                state.egui_renderer.context().request_repaint_after(delay);
+            }
+
+            // Added first so it spans the whole width below the other panels
+            if let Some(status) = &status {
+                egui::TopBottomPanel::bottom("status_bar")
+                    .exact_height(STATUS_BAR_HEIGHT)
+                    .resizable(false)
+                    .show(state.egui_renderer.context(), |ui| {
+                        ui.horizontal_centered(|ui| {
+                            for (i, item) in status.iter().enumerate() {
+                                if i > 0 {
+                                    ui.separator();
+                                }
+                                let label = egui::Label::new(item).selectable(false);
+                                // The position opens the go-to prompt
+                                if i == 0 {
+                                    if ui
+                                        .add(label.sense(egui::Sense::click()))
+                                        .on_hover_text("Go to image…")
+                                        .clicked()
+                                    {
+                                        open_go_to = true;
+                                    }
+                                } else {
+                                    ui.add(label);
+                                }
+                            }
+                        });
+                    });
+            }
+
+            if let Some(info) = &info {
+                egui::SidePanel::right("info").resizable(false).show(
+                    state.egui_renderer.context(),
+                    |ui| {
+                        egui::Grid::new("info_grid").num_columns(2).show(ui, |ui| {
+                            for (name, value) in info {
+                                ui.label(*name);
+                                match value {
+                                    Some(value) => ui.add(egui::Label::new(value).selectable(true)),
+                                    None => ui.weak("unavailable"),
+                                };
+                                ui.end_row();
+                            }
+                        });
+                        if let Some((tile, position)) = &map_tile {
+                            ui.separator();
+                            draw_map_tile(ui, tile, *position);
+                        }
+                        if let Some(histogram) = &histogram {
+                            ui.separator();
+                            draw_histogram(ui, histogram);
+                        }
+                        if let (Some(saved), Some(draft)) =
+                            (&saved_caption, &mut self.caption_draft)
+                        {
+                            ui.separator();
+                            ui.horizontal(|ui| {
+                                ui.strong("Caption");
+                                if draft.text != *saved {
+                                    ui.weak("[modified]");
+                                }
+                            });
+                            egui::ScrollArea::vertical()
+                                .max_height(CAPTION_HEIGHT)
+                                .show(ui, |ui| {
+                                    let response = ui.add(
+                                        egui::TextEdit::multiline(&mut draft.text)
+                                            .desired_width(f32::INFINITY),
+                                    );
+                                    if response.has_focus()
+                                        && ui.input_mut(|i| {
+                                            i.consume_key(egui::Modifiers::COMMAND, Key::Enter)
+                                        })
+                                    {
+                                        save_caption = true;
+                                    }
+                                });
+                            ui.weak("Ctrl+Enter to save");
+                        }
+                    },
+                );
+            }
+
+            if empty {
+                egui::CentralPanel::default().frame(egui::Frame::NONE).show(
+                    state.egui_renderer.context(),
+                    |ui| {
+                        ui.centered_and_justified(|ui| {
+                            ui.label(
+                                egui::RichText::new(
+                                    "Drop a folder here or pass a path as argument",
+                                )
+                                .size(20.0),
+                            );
+                        });
+                    },
+                );
+            }
+
+            if self.show_ui && rating_opacity > 0.0 {
+                // Without a title bar, which the opacity would not reach
+                egui::Window::new("Rating")
+                    .title_bar(false)
+                    .resizable(false)
+                    .default_width(5.0)
+                    .frame(
+                        egui::Frame::window(&state.egui_renderer.context().style())
+                            .multiply_with_opacity(rating_opacity),
+                    )
+                    .show(state.egui_renderer.context(), |ui| {
+                        ui.multiply_opacity(rating_opacity);
+                        ui.vertical_centered(|ui| {
+                            ui.horizontal(|ui| {
+                                ui.spacing_mut().item_spacing.x = 2.0;
+                                for star in 1..=5 {
+                                    let color = if star <= rating {
+                                        egui::Color32::GOLD
+                                    } else {
+                                        egui::Color32::DARK_GRAY
+                                    };
+                                    let response = ui.add(
+                                        egui::Label::new(
+                                            egui::RichText::new("★")
+                                                .size(STAR_SIZE * star_scale)
+                                                .color(color),
+                                        )
+                                        .selectable(false)
+                                        .sense(egui::Sense::click()),
+                                    );
+                                    if response.clicked() {
+                                        clicked_star = Some(star);
+                                    }
+                                }
+                            });
+                            ui.label(
+                                egui::RichText::new(format!("{}", filename.to_str().unwrap()))
+                                    .size(10.0)
+                                    .strong(),
+                            );
+                            ui.label(egui::RichText::new(&position).size(10.0));
+                            ui.label(egui::RichText::new(&zoom_percent).size(10.0));
+                            if let Some(color) = flag_color(flag) {
+                                ui.label(
+                                    egui::RichText::new(format!("{:?}", flag))
+                                        .size(10.0)
+                                        .color(color),
+                                );
+                            }
+                            if let Some(load_state @ (LoadState::Missing | LoadState::Failed)) =
+                                load_state
+                            {
+                                ui.label(
+                                    egui::RichText::new(load_failure_label(load_state))
+                                        .size(10.0)
+                                        .color(egui::Color32::LIGHT_RED),
+                                );
+                            }
+                            if let Some(compare_label) = &compare_label {
+                                ui.label(egui::RichText::new(compare_label).size(10.0));
+                            }
+                            if let Some(stack_label) = &stack_label {
+                                ui.label(egui::RichText::new(stack_label).size(10.0));
+                            }
+                            if let Some(burst_label) = &burst_label {
+                                ui.label(egui::RichText::new(burst_label).size(10.0));
+                            }
+                            if let Some(tone_mapping) = tone_mapping {
+                                ui.label(egui::RichText::new(tone_mapping).size(10.0));
+                            }
+                        });
+                    });
+            }
+
+            if let Some(filmstrip) = &filmstrip {
+                self.filmstrip_current = Some(current_id);
+                egui::TopBottomPanel::bottom("filmstrip").show(
+                    state.egui_renderer.context(),
+                    |ui| {
+                        egui::ScrollArea::horizontal().show(ui, |ui| {
+                            ui.horizontal(|ui| {
+                                for (id, image, texture, flag, selected, copied) in filmstrip {
+                                    let width = texture.as_ref().map_or(1.5, |texture| {
+                                        let [width, height] = texture.size();
+                                        width as f32 / height as f32
+                                    }) * FILMSTRIP_HEIGHT;
+                                    let (rect, response) = ui.allocate_exact_size(
+                                        egui::vec2(width, FILMSTRIP_HEIGHT),
+                                        egui::Sense::click(),
+                                    );
+                                    if let Some(texture) = texture {
+                                        ui.painter().image(
+                                            texture.id(),
+                                            rect,
+                                            egui::Rect::from_min_max(
+                                                egui::pos2(0.0, 0.0),
+                                                egui::pos2(1.0, 1.0),
+                                            ),
+                                            egui::Color32::WHITE,
+                                        );
+                                    } else {
+                                        ui.painter().rect_filled(
+                                            rect,
+                                            0.0,
+                                            egui::Color32::DARK_GRAY,
+                                        );
+                                    }
+                                    if let Some(color) = flag_color(*flag) {
+                                        paint_flag(
+                                            ui.painter(),
+                                            rect.right_top() + egui::vec2(-12.0, 4.0),
+                                            color,
+                                        );
+                                    }
+                                    if *copied {
+                                        paint_copied_badge(
+                                            ui.painter(),
+                                            rect.left_bottom() + egui::vec2(10.0, -10.0),
+                                        );
+                                    }
+                                    if *selected {
+                                        ui.painter().rect_stroke(
+                                            rect,
+                                            0.0,
+                                            egui::Stroke::new(4.0, ui.visuals().selection.bg_fill),
+                                            egui::StrokeKind::Inside,
+                                        );
+                                    }
+                                    if *id == current_id {
+                                        ui.painter().rect_stroke(
+                                            rect,
+                                            0.0,
+                                            egui::Stroke::new(2.0, egui::Color32::WHITE),
+                                            egui::StrokeKind::Inside,
+                                        );
+                                        if scroll_filmstrip {
+                                            response.scroll_to_me(Some(egui::Align::Center));
+                                        }
+                                    }
+                                    if response.clicked() {
+                                        if ui.input(|i| i.modifiers.command) {
+                                            toggle_selected = Some(image.clone());
+                                        } else {
+                                            jump_to = Some(*id);
+                                        }
+                                    }
+                                }
+                            });
+                        });
+                    },
+                );
+            }
+
+            self.minimap_rect = None;
+            if let Some((texture, viewport)) = &minimap {
+                let context = state.egui_renderer.context();
+                let response = egui::Area::new(egui::Id::new("minimap"))
+                    // Clear of the panels shown so far
+                    .constrain_to(context.available_rect())
+                    .anchor(egui::Align2::RIGHT_BOTTOM, [-8.0, -8.0])
+                    .show(context, |ui| {
+                        egui::Frame::popup(ui.style())
+                            .inner_margin(2.0)
+                            .show(ui, |ui| {
+                                let [width, height] = texture.size();
+                                let size = egui::vec2(width as f32, height as f32)
+                                    * (MINIMAP_SIZE / width.max(height) as f32);
+                                let (rect, response) =
+                                    ui.allocate_exact_size(size, egui::Sense::click_and_drag());
+                                ui.painter().image(
+                                    texture.id(),
+                                    rect,
+                                    egui::Rect::from_min_max(
+                                        egui::pos2(0.0, 0.0),
+                                        egui::pos2(1.0, 1.0),
+                                    ),
+                                    egui::Color32::WHITE,
+                                );
+                                ui.painter().rect_stroke(
+                                    egui::Rect::from_min_max(
+                                        rect.lerp_inside(viewport.min.to_vec2()),
+                                        rect.lerp_inside(viewport.max.to_vec2()),
+                                    ),
+                                    0.0,
+                                    egui::Stroke::new(1.5, egui::Color32::WHITE),
+                                    egui::StrokeKind::Outside,
+                                );
+                                if response.is_pointer_button_down_on()
+                                    && let Some(pos) = response.interact_pointer_pos()
+                                {
+                                    let fraction = (pos - rect.min) / rect.size();
+                                    minimap_target = Some(egui::pos2(
+                                        fraction.x.clamp(0.0, 1.0),
+                                        fraction.y.clamp(0.0, 1.0),
+                                    ));
+                                }
+                            });
+                    });
+                self.minimap_rect = Some(response.response.rect);
+            }
+
+            // Areas are placed relative to the whole window, so keep clear of the status bar
+            let corner_y = if status.is_some() {
+                -8.0 - STATUS_BAR_HEIGHT
+            } else {
+                -8.0
+            };
+            // Shown even with the UI hidden, a thumbnail is no basis for judging sharpness
+            if let Some(
+                load_state @ (LoadState::Loading | LoadState::Missing | LoadState::Failed),
+            ) = load_state
+            {
+                egui::Area::new(egui::Id::new("load_state"))
+                    .anchor(egui::Align2::CENTER_TOP, [0.0, 8.0])
+                    .interactable(false)
+                    .show(state.egui_renderer.context(), |ui| {
+                        egui::Frame::popup(ui.style()).show(ui, |ui| {
+                            ui.horizontal(|ui| {
+                                if load_state == LoadState::Loading {
+                                    ui.spinner();
+                                    ui.label("Loading full resolution…");
+                                } else {
+                                    ui.colored_label(
+                                        egui::Color32::LIGHT_RED,
+                                        load_failure_label(load_state),
+                                    );
+                                }
+                            });
+                        });
+                    });
+            }
+
+            // The pixel readout takes the same corner while Alt is held
+            if let Some(summary) = &shooting_summary
+                && pixel_popup.is_none()
+            {
+                egui::Area::new(egui::Id::new("shooting_summary"))
+                    .anchor(egui::Align2::LEFT_BOTTOM, [8.0, corner_y])
+                    .interactable(false)
+                    .show(state.egui_renderer.context(), |ui| {
+                        ui.label(
+                            egui::RichText::new(summary)
+                                .monospace()
+                                .color(egui::Color32::from_white_alpha(180)),
+                        );
+                    });
+            }
+
+            if let Some(pixel) = pixel_popup {
+                let [r, g, b, a] = pixel.rgba;
+                let (h, s, v) = rgb_to_hsv(r, g, b);
+                egui::Area::new(egui::Id::new("pixel_value"))
+                    .anchor(egui::Align2::LEFT_BOTTOM, [8.0, corner_y])
+                    .show(state.egui_renderer.context(), |ui| {
+                        egui::Frame::popup(ui.style()).show(ui, |ui| {
+                            ui.label(format!("{}, {}", pixel.x, pixel.y));
+                            ui.label(format!("R:{} G:{} B:{} A:{}", r, g, b, a));
+                            ui.label(format!("#{:02X}{:02X}{:02X}", r, g, b));
+                            ui.label(format!("H:{:.0}° S:{:.0}% V:{:.0}%", h, s, v));
+                        });
+                    });
+            }
+
+            if let Some(prompt) = &mut self.rename_prompt {
+                egui::Window::new("Rename")
+                    .collapsible(false)
+                    .resizable(false)
+                    .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                    .show(state.egui_renderer.context(), |ui| {
+                        let response = ui.text_edit_singleline(&mut prompt.stem);
+                        if response.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter)) {
+                            submit_rename = true;
+                        } else {
+                            response.request_focus();
+                        }
+                        if let Some(error) = &prompt.error {
+                            ui.colored_label(egui::Color32::LIGHT_RED, error);
+                        }
+                    });
+            }
+
+            for error in state.store.take_errors() {
+                state.toasts.push_error(error);
+            }
+            while let Ok(result) = state.export_rx.try_recv() {
+                state.pending_exports -= 1;
+                match result {
+                    Ok(path) => state
+                        .toasts
+                        .push_info(format!("Exported to {}", path.display())),
+                    Err(e) => state.toasts.push_error(format!("Export failed: {}", e)),
+                }
+            }
+            if self.export_prompt {
+                egui::Window::new("Export")
+                    .collapsible(false)
+                    .resizable(false)
+                    .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                    .show(state.egui_renderer.context(), |ui| {
+                        ui.horizontal(|ui| {
+                            if ui.button("Full image…").clicked() {
+                                export_mode = Some(ExportMode::Full);
+                            }
+                            if ui.button("Current view…").clicked() {
+                                export_mode = Some(ExportMode::View);
+                            }
+                        });
+                    });
+            }
+            if let Some(prompt) = &mut self.go_to_prompt {
+                egui::Window::new("Go to image")
+                    .collapsible(false)
+                    .resizable(false)
+                    .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                    .show(state.egui_renderer.context(), |ui| {
+                        let response = ui.text_edit_singleline(&mut prompt.index);
+                        if response.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter)) {
+                            submit_go_to = true;
+                        } else {
+                            response.request_focus();
+                        }
+                        if let Some(error) = &prompt.error {
+                            ui.colored_label(egui::Color32::LIGHT_RED, error);
+                        }
+                    });
+            }
+
+            if let Some(picker) = &mut self.date_picker {
+                egui::Window::new("Jump to date")
+                    .collapsible(false)
+                    .resizable(false)
+                    .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                    .show(state.egui_renderer.context(), |ui| {
+                        picked_date = date_picker_ui(ui, picker);
+                    });
+            }
+
+            state.toasts.show(state.egui_renderer.context());
+
+            if self.show_help {
+                let bindings = self.keymap.bindings();
+                let adapter_description = &state.adapter_description;
+                egui::Window::new("Help")
+                    .collapsible(false)
+                    .resizable(false)
+                    .show(state.egui_renderer.context(), |ui| {
+                        egui::Grid::new("key_bindings").show(ui, |ui| {
+                            for (binding, action) in bindings {
+                                ui.label(binding.to_string());
+                                ui.label(action.name());
+                                ui.end_row();
+                            }
+                        });
+                        ui.separator();
+                        ui.label(format!("imflow {}", env!("CARGO_PKG_VERSION")));
+                        ui.label(format!("Using adapter: {}", adapter_description));
+                    });
+            }
+
+            if self.show_settings {
+                let previous = self.config.clone();
+                egui::Window::new("Settings")
+                    .open(&mut self.show_settings)
+                    .collapsible(false)
+                    .resizable(false)
+                    .show(state.egui_renderer.context(), |ui| {
+                        settings_ui(ui, &mut self.config);
+                    });
+                if self.config != previous {
+                    previous_config = Some(previous);
+                }
+            }
+
+            if let Some((ratings, labels, filter)) = statistics {
+                let duplicates = &self.duplicates;
+                egui::Window::new("Statistics")
+                    .collapsible(false)
+                    .resizable(false)
+                    .show(state.egui_renderer.context(), |ui| {
+                        egui::Grid::new("rating_histogram").show(ui, |ui| {
+                            let row = |ui: &mut egui::Ui, name: &str, counts: [usize; 2]| {
+                                ui.label(name);
+                                ui.label(counts[0].to_string());
+                                // Without a filter both columns would be the same
+                                if filter.is_some() {
+                                    ui.label(counts[1].to_string());
+                                }
+                                ui.end_row();
+                            };
+                            ui.label("");
+                            ui.label("All");
+                            if let Some(filter) = filter {
+                                ui.label(filter);
+                            }
+                            ui.end_row();
+                            row(ui, "Unrated", [ratings[0][0], ratings[1][0]]);
+                            for rating in 0..6 {
+                                let counts = [ratings[0][rating + 1], ratings[1][rating + 1]];
+                                row(ui, &format!("{} ★", rating), counts);
+                            }
+                            row(ui, "No label", [labels[0][0], labels[1][0]]);
+                            for (i, label) in ColorLabel::ALL.iter().enumerate() {
+                                row(ui, label.name(), [labels[0][i + 1], labels[1][i + 1]]);
+                            }
+                        });
+                        ui.separator();
+                        ui.horizontal(|ui| {
+                            export_manifest = ui.button("Export manifest…").clicked();
+                            import_manifest = ui.button("Import manifest…").clicked();
+                        });
+                        ui.separator();
+                        find_duplicates = ui.button("Find duplicates").clicked();
+                        if let Some(duplicates) = duplicates {
+                            ui.label(format!("{} similar pairs", duplicates.len()));
+                            egui::ScrollArea::vertical()
+                                .max_height(200.0)
+                                .show(ui, |ui| {
+                                    for (a, b) in duplicates {
+                                        ui.label(format!(
+                                            "{} ≈ {}",
+                                            a.path.file_name().unwrap().to_string_lossy(),
+                                            b.path.file_name().unwrap().to_string_lossy()
+                                        ));
+                                    }
+                                });
+                        }
+                    });
+            }
+
+            let repaint_delay = state.egui_renderer.end_frame_and_draw(
+                &state.device,
+                &state.queue,
+                &mut encoder,
+                window,
+                &surface_view,
+                screen_descriptor,
+            );
+            self.repaint_at = Instant::now().checked_add(repaint_delay);
+        }
+
+        state.queue.submit(Some(encoder.finish()));
+        if let Some(profiler) = &mut state.gpu_profiler {
+            profiler.submitted(frame_start.elapsed());
+        }
+        if let Some(overlay) = &mut state.debug_overlay {
+            overlay.frame_finished(
+                frame_start.elapsed(),
+                state
+                    .gpu_profiler
+                    .as_ref()
+                    .and_then(GpuProfiler::last_gpu_time),
+            );
+            overlay.draw(
+                &state.device,
+                &state.queue,
+                window,
+                &surface_view,
+                ScreenDescriptor {
+                    size_in_pixels: [state.surface_config.width, state.surface_config.height],
+                    pixels_per_point: state.scale_factor * self.config.ui_scale,
+                },
+                state.store.load_stats(),
+            );
+        }
+        surface_texture.present();
+
+        if let Some(previous) = previous_config {
+            self.apply_settings(&previous);
+        }
+        if let Some(mode) = export_mode {
+            self.export_prompt = false;
+            self.export_image(mode);
+        }
+        if export_manifest {
+            self.export_manifest_dialog();
+        }
+        if import_manifest {
+            self.import_manifest_dialog();
+        }
+        if submit_rename {
+            self.rename_current_image();
+        }
+        if open_go_to {
+            let id = self.state.as_ref().unwrap().store.current_image_id();
+            self.go_to_prompt = Some(GoToPrompt {
+                index: (id + 1).to_string(),
+                error: None,
+            });
+        }
+        if submit_go_to {
+            self.go_to_image();
+        }
+        if let Some(date) = picked_date {
+            self.date_picker = None;
+            if self.state.as_mut().unwrap().store.jump_to_date(date) {
+                self.update_texture();
+            }
+        }
+        if save_caption {
+            self.save_caption();
+        }
+        if let Some(star) = clicked_star {
+            // Clicking the highest lit star again clears the rating
+            self.rate(&shown, if star == rating { 0 } else { star });
+        }
+        let state = self.state.as_mut().unwrap();
+        if find_duplicates {
+            state.store.find_duplicates(DEFAULT_DUPLICATE_THRESHOLD);
+        }
+        match state.store.check_duplicates() {
+            Some(DuplicateProgress::Hashing { hashed, total }) => state.toasts.push_progress(
+                DUPLICATES_TOAST,
+                format!("Hashing thumbnails {}/{}", hashed, total),
+            ),
+            Some(DuplicateProgress::Done(duplicates)) => {
+                state.toasts.push_progress(
+                    DUPLICATES_TOAST,
+                    format!("Found {} similar pairs", duplicates.len()),
+                );
+                self.duplicates = Some(duplicates);
+            }
+            None => {}
+        }
+        if let Some(target) = minimap_target {
+            self.center_on(target);
+        }
+        if let Some(image) = toggle_selected {
+            let selected = &mut self.state.as_mut().unwrap().selected;
+            if !selected.remove(&image) {
+                selected.insert(image);
+            }
+        }
+        if let Some(id) = jump_to {
+            let state = self.state.as_mut().unwrap();
+            state.selected.clear();
+            state.store.jump_to_image(id);
+            self.update_texture();
+        }
+    }
+
+    fn export_manifest_dialog(&mut self) {
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter("Manifest", &["csv", "json"])
+            .set_file_name("manifest.csv")
+            .save_file()
+        else {
+            return;
+        };
+        let state = self.state.as_mut().unwrap();
+        match state
+            .store
+            .export_manifest(&path, ManifestFormat::from_path(&path))
+        {
+            Ok(()) => state
+                .toasts
+                .push_info(format!("Exported to {}", path.display())),
+            Err(e) => state.toasts.push_error(format!("Export failed: {}", e)),
+        }
+    }
+
+    /// Asks where to save the focused image or the view as a PNG, then encodes it on
+    /// a thread of its own. The result arrives as a toast.
+    fn export_image(&mut self, mode: ExportMode) {
+        let image = self.focused_image();
+        let state = self.state.as_ref().unwrap();
+        if state.store.is_empty() {
+            return;
+        }
+        let suffix = match mode {
+            ExportMode::Full => "export",
+            ExportMode::View => "view",
+        };
+        let stem = image.path.file_stem().unwrap_or_default().to_string_lossy();
+        let mut dialog = rfd::FileDialog::new()
+            .add_filter("PNG", &["png"])
+            .set_file_name(format!("{}-{}.png", stem, suffix));
+        if let Some(dir) = image.path.parent() {
+            dialog = dialog.set_directory(dir);
+        }
+        let Some(path) = dialog.save_file() else {
+            return;
+        };
+        let tx = state.export_tx.clone();
+        match mode {
+            ExportMode::Full => {
+                let Some(imbuf) = state.store.get_image(&image) else {
+                    self.push_toast(ToastKind::Warning, "The full image hasn't loaded yet");
+                    return;
+                };
+                let imbuf = ImflowImageBuffer {
+                    width: imbuf.width,
+                    height: imbuf.height,
+                    rgba_buffer: imbuf.rgba_buffer.clone(),
+                    rating: imbuf.rating,
+                    hdr: imbuf.hdr,
+                };
+                encode_in_background(tx, self.wake.clone(), path, move |path| {
+                    save_image_buffer(&imbuf, path)
+                });
+                self.state.as_mut().unwrap().pending_exports += 1;
+            }
+            ExportMode::View => match state.render_view(&self.config) {
+                Ok((width, height, pixels)) => {
+                    encode_in_background(tx, self.wake.clone(), path, move |path| {
+                        image::save_buffer(
+                            path,
+                            &pixels,
+                            width,
+                            height,
+                            image::ExtendedColorType::Rgba8,
+                        )
+                    });
+                    self.state.as_mut().unwrap().pending_exports += 1;
+                }
+                Err(e) => self.push_toast(ToastKind::Error, format!("Export failed: {}", e)),
+            },
+        }
+    }
+
+    fn import_manifest_dialog(&mut self) {
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter("Manifest", &["csv", "json"])
+            .pick_file()
+        else {
+            return;
+        };
+        let state = self.state.as_mut().unwrap();
+        match state.store.import_manifest(&path) {
+            Ok(report) => {
+                for path in &report.unmatched {
+                    println!("No image matches {}", path);
+                }
+                for (path, rating) in &report.invalid_ratings {
+                    println!("Ignoring rating {} of {}: not 0 to 5", rating, path);
+                }
+                for (path, error) in &report.failed {
+                    println!("Failed to write {}: {}", path, error);
+                }
+                if report.failed.is_empty() && report.invalid_ratings.is_empty() {
+                    state.toasts.push_info(report.summary());
+                } else {
+                    state.toasts.push_warning(report.summary());
+                }
+            }
+            Err(e) => state.toasts.push_error(format!("Import failed: {}", e)),
+        }
+    }
+
+    fn push_toast(&mut self, kind: ToastKind, message: impl Into<String>) {
+        self.state.as_mut().unwrap().toasts.push(kind, message);
+    }
+
+    /// Copies the focused image to the system clipboard, or its thumbnail while the
+    /// full image is still loading.
+    fn copy_image(&mut self) {
+        let image = self.focused_image();
+        let store = &self.state.as_ref().unwrap().store;
+        let (imbuf, full) = match store.get_image(&image) {
+            Some(full) => (full, true),
+            None => match store.thumbnail_for(&image) {
+                Some(thumbnail) => (thumbnail, false),
+                None => return,
+            },
+        };
+        let scaled_down = imbuf.width.max(imbuf.height) > CLIPBOARD_MAX_SIZE as usize;
+        let downsampled;
+        let imbuf = if scaled_down {
+            downsampled = imbuf.downsample(CLIPBOARD_MAX_SIZE, CLIPBOARD_MAX_SIZE);
+            &downsampled
+        } else {
+            imbuf
+        };
+        let data = arboard::ImageData {
+            width: imbuf.width,
+            height: imbuf.height,
+            bytes: Cow::Borrowed(bytemuck::cast_slice(&imbuf.rgba_buffer)),
+        };
+        let result = match &mut self.clipboard {
+            Some(clipboard) => clipboard.set_image(data),
+            None => arboard::Clipboard::new()
+                .and_then(|clipboard| self.clipboard.insert(clipboard).set_image(data)),
+        };
+        let message = if !full {
+            "Copied the thumbnail, the full image is still loading".to_string()
+        } else if scaled_down {
+            format!(
+                "Copied the image scaled down to {}×{}",
+                imbuf.width, imbuf.height
+            )
+        } else {
+            "Copied the image".to_string()
+        };
+        match result {
+            Ok(()) => self.push_toast(ToastKind::Info, message),
+            Err(e) => self.push_toast(ToastKind::Error, format!("Failed to copy the image: {}", e)),
+        }
+    }
+
+    /// Opens the focused image with the `editor` command from the config. The result
+    /// is picked up when the window gets focus back.
+    fn open_in_editor(&mut self) {
+        let image = self.focused_image();
+        if self.state.as_ref().unwrap().store.is_empty() {
+            return;
+        }
+        let Some(editor) = &self.config.editor else {
+            self.push_toast(
+                ToastKind::Warning,
+                "Set `editor` in the config file to open images in an editor",
+            );
+            return;
+        };
+        match open_in_editor(editor, &image.path) {
+            Ok(()) => self.push_toast(
+                ToastKind::Info,
+                format!(
+                    "Opened {} in {}",
+                    image.path.file_name().unwrap().to_string_lossy(),
+                    editor.split_whitespace().next().unwrap_or_default()
+                ),
+            ),
+            Err(e) => self.push_toast(
+                ToastKind::Error,
+                format!("Failed to run `{}`: {}", editor, e),
+            ),
+        }
+    }
+
+    /// Applies what the settings window changed from `previous` and saves it. Most
+    /// options are read from the config as they are used and need nothing more.
+    fn apply_settings(&mut self, previous: &Config) {
+        let state = self.state.as_mut().unwrap();
+        state.update_background(&self.config);
+        state.store.set_wrap_navigation(self.config.wrap_navigation);
+        if self.config.preload != previous.preload {
+            state.store.set_preload_strategy(self.config.preload);
+        }
+        if self.config.auto_hide_overlays != previous.auto_hide_overlays {
+            self.auto_hide = self.config.auto_hide_overlays;
+        }
+        if let Err(e) = self.config.save_changes(previous) {
+            self.push_toast(
+                ToastKind::Warning,
+                format!("Failed to save the settings: {}", e),
+            );
+        }
+        self.update_transform();
+    }
+
+    fn copy_to_target(&mut self, slot: u8) {
+        let image = self.focused_image();
+        if self.state.as_ref().unwrap().store.is_empty() {
+            return;
+        }
+        let Some(target_dir) = self.config.copy_target(slot).cloned() else {
+            self.push_toast(
+                ToastKind::Warning,
+                format!(
+                    "Set `copy_target_{}` in the config file to copy images there",
+                    slot
+                ),
+            );
+            return;
+        };
+        match copy_image_to_target(&image, &target_dir) {
+            Ok(copy) => {
+                self.state.as_mut().unwrap().copied.insert(image);
+                self.push_toast(ToastKind::Info, format!("Copied to {}", copy.display()));
+            }
+            Err(e) => self.push_toast(
+                ToastKind::Error,
+                format!("Failed to copy to {}: {}", target_dir.display(), e),
+            ),
+        }
+    }
+
+    /// Whether a prompt or the calendar is open, which takes over the keyboard.
+    fn prompt_open(&self) -> bool {
+        self.rename_prompt.is_some()
+            || self.go_to_prompt.is_some()
+            || self.date_picker.is_some()
+            || self.export_prompt
+    }
+
+    /// Turns or mirrors the displayed image without touching the file.
+    fn orient_view(&mut self, change: impl FnOnce(ViewOrientation) -> ViewOrientation) {
+        let transform_data = &mut self.state.as_mut().unwrap().transform_data;
+        transform_data.orientation = change(transform_data.orientation);
+        // The pan limits change with the aspect ratio when the image turns sideways
+        self.pan_zoom(0.0, 0.0, 0.0);
+    }
+
+    /// Rates `image`, confirming the new rating or reporting the failure as a toast.
+    fn rate(&mut self, image: &ImageData, rating: i32) {
+        let state = self.state.as_mut().unwrap();
+        match state.store.set_image_rating(image, rating) {
+            Ok(()) => {
+                let message = if rating == 0 {
+                    "Rating cleared".to_string()
+                } else {
+                    format!("Rated {} ★", rating)
+                };
+                self.push_toast(ToastKind::Info, message);
+                #[cfg(target_os = "linux")]
+                if let Some(dbus) = &self.dbus {
+                    dbus.rating_changed(&image.path, rating);
+                }
+            }
+            Err(e) => self.push_toast(
+                ToastKind::Error,
+                format!(
+                    "Failed to rate {}: {}",
+                    image.path.file_name().unwrap().to_string_lossy(),
+                    e
+                ),
+            ),
+        }
+    }
+
+    /// Gives every selected image `rating`, reporting the failures individually.
+    fn rate_selected(&mut self, rating: i32) {
+        let state = self.state.as_mut().unwrap();
+        let mut rated = 0;
+        for image in state.selected.iter().sorted_by_key(|image| &image.path) {
+            match state.store.set_image_rating(image, rating) {
+                Ok(()) => {
+                    rated += 1;
+                    #[cfg(target_os = "linux")]
+                    if let Some(dbus) = &self.dbus {
+                        dbus.rating_changed(&image.path, rating);
+                    }
+                }
+                Err(e) => state.toasts.push_error(format!(
+                    "Failed to rate {}: {}",
+                    image.path.file_name().unwrap().to_string_lossy(),
+                    e
+                )),
+            }
+        }
+        let message = if rating == 0 {
+            format!("Cleared the rating of {} images", rated)
+        } else {
+            format!("Rated {} images {} ★", rated, rating)
+        };
+        self.push_toast(ToastKind::Info, message);
+    }
+
+    fn perform(&mut self, action: Action) {
+        match action {
+            Action::NextImage | Action::PrevImage | Action::NextInBurst | Action::PrevInBurst => {
+                self.navigate_by(action);
+                self.update_texture();
+            }
+            Action::JumpFirst | Action::JumpLast | Action::PageForward | Action::PageBack => {
+                let page_size = self.config.page_size as i32;
+                let store = &mut self.state.as_mut().unwrap().store;
+                let wrapped = match action {
+                    Action::JumpFirst => {
+                        store.jump_first();
+                        None
+                    }
+                    Action::JumpLast => {
+                        store.jump_last();
+                        None
+                    }
+                    Action::PageForward => store.next_image(page_size),
+                    _ => store.next_image(-page_size),
+                };
+                self.report_wrap(wrapped);
+                self.update_texture();
+            }
+            Action::NextBurst | Action::PrevBurst => {
+                let change = if action == Action::NextBurst { 1 } else { -1 };
+                self.state.as_mut().unwrap().store.skip_to_burst(change);
+                self.update_texture();
+            }
+            Action::JumpToDate => {
+                let store = &self.state.as_ref().unwrap().store;
+                let counts = store.capture_date_counts();
+                // Open on the month of the current image, or the latest one with photos
+                let Some(date) = store
+                    .current_image_path
+                    .capture_date
+                    .filter(|date| counts.contains_key(date))
+                    .or_else(|| counts.keys().next_back().copied())
+                else {
+                    self.push_toast(ToastKind::Warning, "No images have a capture date");
+                    return;
+                };
+                self.date_picker = Some(DatePicker {
+                    month: date.with_day(1).unwrap(),
+                    counts,
+                });
+            }
+            Action::Rate(rating) if !self.state.as_ref().unwrap().selected.is_empty() => {
+                self.rate_selected(rating as i32);
+                self.rating_changed_at = Some(Instant::now());
+            }
+            Action::RateUp | Action::RateDown | Action::Rate(_) => {
+                let image = self.focused_image();
+                let state = self.state.as_mut().unwrap();
+                if state.store.is_empty() {
+                    return;
+                }
+                let rating = match action {
+                    Action::Rate(rating) => rating as i32,
+                    Action::RateUp => state.store.get_image_rating(&image) + 1,
+                    _ => state.store.get_image_rating(&image) - 1,
+                };
+                self.rate(&image, rating);
+                self.rating_changed_at = Some(Instant::now());
+            }
+            Action::ToggleOneToOne => self.toggle_one_to_one(None),
+            Action::Fit => self.reset_transform(),
+            // Repeated every frame in `apply_held_keys` rather than on key press
+            Action::PanLeft
+            | Action::PanRight
+            | Action::PanUp
+            | Action::PanDown
+            | Action::ZoomIn
+            | Action::ZoomOut => (),
+            Action::ToggleBurstMode => {
+                self.burst_mode = !self.burst_mode;
+                let message = if self.burst_mode {
+                    "Burst mode on"
+                } else {
+                    "Burst mode off"
+                };
+                self.push_toast(ToastKind::Info, message);
+            }
+            Action::CycleStackMember => {
+                self.state.as_mut().unwrap().store.cycle_stack_member();
+                self.update_texture();
+            }
+            Action::Delete if !self.state.as_ref().unwrap().selected.is_empty() => {
+                self.delete_selected_dialog()
+            }
+            Action::Delete => self.delete_current_image_dialog(),
+            Action::Rename => self.open_rename_prompt(),
+            Action::OpenInEditor => self.open_in_editor(),
+            Action::RevealInFileManager => {
+                let image = self.focused_image();
+                if self.state.as_ref().unwrap().store.is_empty() {
+                    return;
+                }
+                if let Err(e) = reveal_in_file_manager(&image.path) {
+                    self.push_toast(
+                        ToastKind::Error,
+                        format!("Failed to open the file manager: {}", e),
+                    );
+                }
+            }
+            Action::OpenFolder => {
+                let mut dialog = rfd::FileDialog::new();
+                if let Some(dir) = self.path.canonicalize().ok().filter(|dir| dir.is_dir()) {
+                    dialog = dialog.set_directory(dir);
+                }
+                if let Some(path) = dialog.pick_folder() {
+                    self.reload_store(path);
+                }
+            }
+            Action::ToggleSharpen => {
+                let transform_data = &mut self.state.as_mut().unwrap().transform_data;
+                transform_data.sharpen = !transform_data.sharpen;
+                let message = if transform_data.sharpen {
+                    "Sharpening on"
+                } else {
+                    "Sharpening off"
+                };
+                self.push_toast(ToastKind::Info, message);
+                self.update_transform();
+            }
+            Action::ToggleClipping => {
+                let transform_data = &mut self.state.as_mut().unwrap().transform_data;
+                transform_data.clipping = !transform_data.clipping;
+                let message = if transform_data.clipping {
+                    "Clipping warning on"
+                } else {
+                    "Clipping warning off"
+                };
+                self.push_toast(ToastKind::Info, message);
+                self.update_transform();
+            }
+            Action::RotateClockwise => self.orient_view(|view| view.rotated(1)),
+            Action::RotateCounterclockwise => self.orient_view(|view| view.rotated(-1)),
+            Action::FlipHorizontal => self.orient_view(ViewOrientation::flipped_horizontally),
+            Action::FlipVertical => self.orient_view(ViewOrientation::flipped_vertically),
+            Action::SaveOrientation => {
+                let state = self.state.as_mut().unwrap();
+                let orientation = state.transform_data.orientation;
+                if state.store.is_empty() || orientation == ViewOrientation::default() {
+                    return;
+                }
+                let image = state.store.current_image_path.clone();
+                match state.store.apply_orientation(&image, orientation) {
+                    Ok(()) => {
+                        // The decoder applies the new orientation from here on
+                        state.transform_data.orientation = ViewOrientation::default();
+                        // The store decoded the thumbnail again, the texture is still the old one
+                        self.thumbnail_textures.remove(&image);
+                        self.update_texture();
+                        self.push_toast(ToastKind::Info, "Orientation saved");
+                    }
+                    Err(e) => self.push_toast(
+                        ToastKind::Error,
+                        format!(
+                            "Failed to save orientation of {}: {}",
+                            image.path.file_name().unwrap().to_string_lossy(),
+                            e
+                        ),
+                    ),
+                }
+            }
+            Action::CycleHdr => {
+                let transform_data = &mut self.state.as_mut().unwrap().transform_data;
+                // Cycle automatic -> forced on -> forced off
+                transform_data.hdr_override = match transform_data.hdr_override {
+                    None => Some(true),
+                    Some(true) => Some(false),
+                    Some(false) => None,
+                };
+                let message = match transform_data.hdr_override {
+                    None => "HDR tone mapping automatic",
+                    Some(true) => "HDR tone mapping forced on",
+                    Some(false) => "HDR tone mapping forced off",
+                };
+                self.push_toast(ToastKind::Info, message);
+                self.update_transform();
+            }
+            Action::CycleAlphaBackground => {
+                self.config.alpha_background = self.config.alpha_background.next();
+                if let Err(e) =
+                    Config::save_setting("alpha_background", self.config.alpha_background)
+                {
+                    self.push_toast(
+                        ToastKind::Warning,
+                        format!("Failed to save the background: {}", e),
+                    );
+                }
+                let message = format!("Background: {:?}", self.config.alpha_background);
+                self.push_toast(ToastKind::Info, message);
+                self.update_transform();
+            }
+            Action::CycleSampling => {
+                self.config.sampling = self.config.sampling.next();
+                if let Err(e) = Config::save_setting("sampling", self.config.sampling) {
+                    self.push_toast(
+                        ToastKind::Warning,
+                        format!("Failed to save the sampling mode: {}", e),
+                    );
+                }
+                let message = match self.config.sampling {
+                    SamplingMode::Auto => "Sampling: nearest above 100% zoom",
+                    SamplingMode::Linear => "Sampling: always linear",
+                    SamplingMode::Nearest => "Sampling: always nearest",
+                };
+                self.push_toast(ToastKind::Info, message);
+                self.update_transform();
+            }
+            Action::CycleTransformMemory => {
+                self.config.transform_memory = self.config.transform_memory.next();
+                if let Err(e) =
+                    Config::save_setting("transform_memory", self.config.transform_memory)
+                {
+                    self.push_toast(
+                        ToastKind::Warning,
+                        format!("Failed to save the transform mode: {}", e),
+                    );
+                }
+                let message = match self.config.transform_memory {
+                    TransformMemory::PerImage => "Zoom and pan: remembered per image",
+                    TransformMemory::Keep => "Zoom and pan: kept across images",
+                    TransformMemory::Reset => "Zoom and pan: reset on every image",
+                };
+                self.push_toast(ToastKind::Info, message);
+            }
+            Action::ClearTransforms => {
+                self.state.as_mut().unwrap().clear_per_image_transforms();
+                self.reset_transform();
+            }
+            Action::Flag(flag) => {
+                let image = self.focused_image();
+                let state = self.state.as_mut().unwrap();
+                if state.store.is_empty() {
+                    return;
+                }
+                match state.store.set_image_flag(&image, flag) {
+                    Ok(()) => {
+                        let message = match flag {
+                            FlagStatus::None => "Flag cleared",
+                            FlagStatus::Picked => "Picked",
+                            FlagStatus::Rejected => "Rejected",
+                        };
+                        self.push_toast(ToastKind::Info, message);
+                    }
+                    Err(e) => self.push_toast(
+                        ToastKind::Error,
+                        format!(
+                            "Failed to flag {}: {}",
+                            image.path.file_name().unwrap().to_string_lossy(),
+                            e
+                        ),
+                    ),
+                }
+            }
+            Action::CycleFlagFilter => {
+                let store = &mut self.state.as_mut().unwrap().store;
+                // All images -> hide rejects -> picks only
+                let filter = store.flag_filter();
+                if filter == FlagFilter::default() {
+                    store.filter_flags(true, false, true);
+                } else if filter.unflagged {
+                    store.filter_flags(true, false, false);
+                } else {
+                    store.filter_flags(true, true, true);
+                }
+                let label = flag_filter_label(store.flag_filter()).unwrap_or("all images");
+                self.push_toast(ToastKind::Info, format!("Showing {}", label));
+            }
+            Action::SelectAll => {
+                let state = self.state.as_mut().unwrap();
+                state.selected = state.store.visible_images().into_iter().collect();
+                let message = format!("Selected {} images", state.selected.len());
+                self.push_toast(ToastKind::Info, message);
+            }
+            Action::ClearSelection => self.state.as_mut().unwrap().selected.clear(),
+            Action::CopyImage => self.copy_image(),
+            Action::CopyPaths => {
+                let state = self.state.as_ref().unwrap();
+                if state.store.is_empty() {
+                    return;
+                }
+                let mut paths = if state.selected.is_empty() {
+                    vec![state.store.current_image_path.path.clone()]
+                } else {
+                    state
+                        .selected
+                        .iter()
+                        .map(|image| image.path.clone())
+                        .collect()
+                };
+                paths.sort();
+                let text = paths
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .join("\n");
+                state.egui_renderer.context().copy_text(text);
+                let message = if paths.len() == 1 {
+                    "Copied the path".to_string()
+                } else {
+                    format!("Copied {} paths", paths.len())
+                };
+                self.push_toast(ToastKind::Info, message);
+            }
+            Action::CopyToTarget(slot) => self.copy_to_target(slot),
+            Action::ToggleStatistics => self.show_statistics = !self.show_statistics,
+            Action::ToggleInfo => self.show_info = !self.show_info,
+            Action::ToggleUi => self.show_ui = !self.show_ui,
+            Action::ToggleAutoHide => {
+                self.auto_hide = !self.auto_hide;
+                let message = if self.auto_hide {
+                    "Auto-hide on"
+                } else {
+                    "Auto-hide off"
+                };
+                self.push_toast(ToastKind::Info, message);
+            }
+            Action::ToggleFilmstrip => self.show_filmstrip = !self.show_filmstrip,
+            Action::ToggleCompare => self.toggle_compare(),
+            // Needs the key to know when it is released, see `start_flicker`
+            Action::FlickerPrevious => (),
+            Action::Export => self.export_prompt = true,
+            Action::ToggleDebugOverlay => {
+                let state = self.state.as_mut().unwrap();
+                state.debug_overlay = match state.debug_overlay.take() {
+                    Some(_) => None,
+                    None => Some(DebugOverlay::new(
+                        &state.device,
+                        state.surface_config.format,
+                        state.adapter_description.clone(),
+                    )),
+                };
+            }
+            Action::UiZoomIn => self.set_ui_scale(self.config.ui_scale + UI_SCALE_STEP),
+            Action::UiZoomOut => self.set_ui_scale(self.config.ui_scale - UI_SCALE_STEP),
+            Action::UiZoomReset => self.set_ui_scale(1.0),
+            Action::ToggleSettings => self.show_settings = !self.show_settings,
+            Action::ToggleHelp => self.show_help = !self.show_help,
+            Action::Quit => self.request_quit(),
+        }
+    }
+
+    /// Writes the window's size, position and state into the config file. While
+    /// maximized or fullscreen, the size and position saved before are kept so the
+    /// window can be restored to them.
+    fn save_window_geometry(&mut self) -> io::Result<()> {
+        self.geometry_changed_at = None;
+        let Some(window) = &self.window else {
+            return Ok(());
+        };
+        let maximized = window.is_maximized();
+        let fullscreen = window.fullscreen().is_some();
+        let size = window.inner_size();
+        let mut geometry = WindowGeometry {
+            width: size.width,
+            height: size.height,
+            position: window
+                .outer_position()
+                .ok()
+                .map(|position| (position.x, position.y)),
+            maximized,
+            fullscreen,
+            monitor: window.current_monitor().and_then(|monitor| monitor.name()),
+        };
+        if (maximized || fullscreen)
+            && let Some(previous) = &self.config.window
+        {
+            geometry.width = previous.width;
+            geometry.height = previous.height;
+            geometry.position = previous.position;
+        }
+        if self.config.window.as_ref() == Some(&geometry) {
+            return Ok(());
+        }
+        Config::save_setting("window", &geometry)?;
+        self.config.window = Some(geometry);
+        Ok(())
+    }
+
+    /// Whether the next frame should follow right away instead of waiting for input:
+    /// while the view animates, and while keys are held for panning, zooming or
+    /// scrubbing.
+    fn wants_next_frame(&self, keys_held: bool) -> bool {
+        keys_held || self.held_navigation.is_some() || self.state.as_ref().unwrap().is_animating()
+    }
+
+    /// Resizes the overlays, leaving the image as it is, and remembers the size.
+    fn set_ui_scale(&mut self, scale: f32) {
+        // Whole steps, so repeated presses don't drift
+        let scale =
+            (scale.clamp(MIN_UI_SCALE, MAX_UI_SCALE) / UI_SCALE_STEP).round() * UI_SCALE_STEP;
+        if scale == self.config.ui_scale {
+            return;
+        }
+        self.config.ui_scale = scale;
+        if let Err(e) = Config::save_setting("ui_scale", scale) {
+            self.push_toast(
+                ToastKind::Warning,
+                format!("Failed to save the interface size: {}", e),
+            );
+        }
+        self.push_toast(
+            ToastKind::Info,
+            format!("Interface size {:.0}%", scale * 100.0),
+        );
+    }
+
+    /// Quits on the next turn of the event loop, or with `confirm_quit` only when
+    /// pressed again shortly after.
+    fn request_quit(&mut self) {
+        if self.config.confirm_quit
+            && self
+                .quit_pressed_at
+                .is_none_or(|at| at.elapsed() >= QUIT_CONFIRM_WINDOW)
+        {
+            self.quit_pressed_at = Some(Instant::now());
+            self.push_toast(ToastKind::Info, "Press again to quit");
+            return;
+        }
+        self.quit_requested = true;
+    }
+
+    /// Lets the exports still encoding finish and stops the loader, giving each up
+    /// to `SHUTDOWN_TIMEOUT`. Metadata is written to the files as it changes, only the
+    /// cache of what was read is saved here.
+    fn shutdown(&mut self) {
+        if let Err(e) = self.save_window_geometry() {
+            eprintln!("Failed to remember the window size: {}", e);
+        }
+        if let Some(path) = MetadataCache::path()
+            && let Err(e) = self.metadata_cache.save(&path)
+        {
+            eprintln!("Failed to save the metadata cache: {}", e);
+        }
+        let Some(state) = self.state.as_mut() else {
+            return;
+        };
+        let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+        while state.pending_exports > 0 {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match state.export_rx.recv_timeout(timeout) {
+                Ok(Ok(path)) => println!("Exported to {}", path.display()),
+                Ok(Err(e)) => eprintln!("Export failed: {}", e),
+                Err(_) => {
+                    eprintln!("Gave up on {} unfinished exports", state.pending_exports);
+                    break;
+                }
+            }
+            state.pending_exports -= 1;
+        }
+        if !state.store.shutdown(SHUTDOWN_TIMEOUT) {
+            eprintln!("Gave up waiting for the image loader");
+        }
+    }
+
+    /// Replaces the store with one for the images in `path`.
+    fn reload_store(&mut self, path: PathBuf) {
+        let state = self.state.as_mut().unwrap();
+        state.store = ImageStore::new(path.clone(), &self.config, self.metadata_cache.clone());
+        if let Some(wake) = &self.wake {
+            state.store.set_wake(wake.clone());
+        }
+        state.clear_per_image_transforms();
+        state.compare = None;
+        state.flicker = None;
+        state.transition_state = None;
+        // Nothing to crossfade from across folders
+        state.shown_image = None;
+        if let Err(e) = Config::save_setting("last_folder", &path) {
+            self.push_toast(
+                ToastKind::Warning,
+                format!("Failed to remember the folder: {}", e),
+            );
+        }
+        self.path = path;
+        self.duplicates = None;
+        let state = self.state.as_mut().unwrap();
+        state.selected.clear();
+        state.copied.clear();
+        self.update_texture();
+    }
+
+    fn open_rename_prompt(&mut self) {
+        let path = &self.state.as_ref().unwrap().store.current_image_path.path;
+        let stem = path.file_stem().unwrap().to_string_lossy().to_string();
+        self.rename_prompt = Some(RenamePrompt { stem, error: None });
+    }
+
+    /// Calls `current_image_changed` once per change of the current image, however
+    /// it was reached.
+    fn check_current_image_changed(&mut self) {
+        let store = &self.state.as_ref().unwrap().store;
+        let current = (!store.is_empty()).then(|| {
+            (
+                store.current_image_path.clone(),
+                store.current_image_id(),
+                store.image_count(),
+            )
+        });
+        if current != self.current_image {
+            self.current_image = current;
+            self.current_image_changed();
+        }
+    }
+
+    fn current_image_changed(&mut self) {
+        if !self.config.sticky_orientation {
+            let transform_data = &mut self.state.as_mut().unwrap().transform_data;
+            if transform_data.orientation != ViewOrientation::default() {
+                transform_data.orientation = ViewOrientation::default();
+                self.pan_zoom(0.0, 0.0, 0.0);
+            }
+        }
+        let title = match &self.current_image {
+            Some((image, id, count)) => format!(
+                "{} ({}/{}) — imflow",
+                image.path.file_name().unwrap_or_default().to_string_lossy(),
+                id + 1,
+                count
+            ),
+            None => "imflow".to_string(),
+        };
+        self.window.as_ref().unwrap().set_title(&title);
+        #[cfg(target_os = "linux")]
+        if let (Some(dbus), Some((image, _, _))) = (&self.dbus, &self.current_image) {
+            dbus.image_changed(&image.path);
+        }
+    }
+
+    /// Carries out the method calls D-Bus clients made since the last frame.
+    #[cfg(target_os = "linux")]
+    fn handle_dbus_commands(&mut self) {
+        let Some(dbus) = &self.dbus else {
+            return;
+        };
+        let commands = std::iter::from_fn(|| dbus.try_recv()).collect::<Vec<_>>();
+        for command in commands {
+            match command {
+                AppCommand::NextImage => self.perform(Action::NextImage),
+                AppCommand::PreviousImage => self.perform(Action::PrevImage),
+                AppCommand::SetRating(rating) => self.perform(Action::Rate(rating)),
+            }
+        }
+    }
+
+    fn go_to_image(&mut self) {
+        let Some(prompt) = &mut self.go_to_prompt else {
+            return;
+        };
+        let store = &mut self.state.as_mut().unwrap().store;
+        match prompt.index.trim().parse::<usize>() {
+            Ok(index) if (1..=store.image_count()).contains(&index) => {
+                store.jump_to_image(index - 1);
+                self.go_to_prompt = None;
+                self.update_texture();
+            }
+            _ => prompt.error = Some(format!("Enter a number from 1 to {}", store.image_count())),
+        }
+    }
+
+    fn rename_current_image(&mut self) {
+        let Some(prompt) = &mut self.rename_prompt else {
+            return;
+        };
+        let state = self.state.as_mut().unwrap();
+        let old = state.store.current_image_path.clone();
+        match state.store.rename_current_image(prompt.stem.trim()) {
+            Ok(()) => {
+                let new = state.store.current_image_path.clone();
+                state.toasts.push_info(format!(
+                    "Renamed to {}",
+                    new.path.file_name().unwrap().to_string_lossy()
+                ));
+                if let Some(transform) = state.per_image_transforms.remove(&old) {
+                    state.per_image_transforms.insert(new.clone(), transform);
+                }
+                if state.copied.remove(&old) {
+                    state.copied.insert(new);
+                }
+                self.rename_prompt = None;
+            }
+            Err(e) => prompt.error = Some(e.to_string()),
+        }
+    }
+
+    fn save_caption(&mut self) {
+        let Some(draft) = &mut self.caption_draft else {
+            return;
+        };
+        // Captions are trimmed when read, so untrimmed text would never match
+        draft.text = draft.text.trim().to_string();
+        let state = self.state.as_mut().unwrap();
+        match state.store.set_image_caption(&draft.image, &draft.text) {
+            Ok(()) => state.toasts.push_info("Caption saved"),
+            Err(e) => state.toasts.push_error(format!(
+                "Failed to save caption for {}: {}",
+                draft.image.path.file_name().unwrap().to_string_lossy(),
+                e
+            )),
+        }
+    }
+
+    /// Asks once before trashing the current image together with its whole stack.
+    fn delete_current_image_dialog(&mut self) {
+        let state = self.state.as_mut().unwrap();
+        let store = &mut state.store;
+        let names = store
+            .stack_members(&store.current_image_path)
+            .iter()
+            .map(|member| {
+                member
+                    .path
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string()
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let confirmed = rfd::MessageDialog::new()
+            .set_title("Delete image")
+            .set_description(format!("Move {} to the trash?", names))
+            .set_buttons(rfd::MessageButtons::YesNo)
+            .show()
+            == rfd::MessageDialogResult::Yes;
+        if !confirmed {
+            return;
+        }
+        if let Err(e) = store.delete_current_image() {
+            state
+                .toasts
+                .push_error(format!("Failed to delete {}: {}", names, e));
+            return;
+        }
+        state
+            .toasts
+            .push_info(format!("Moved {} to the trash", names));
+        if state.store.is_empty() {
+            self.quit_requested = true;
+            return;
+        }
+        self.update_texture();
+    }
+
+    /// Asks once before trashing every selected image together with their stacks.
+    fn delete_selected_dialog(&mut self) {
+        let state = self.state.as_mut().unwrap();
+        let images = state.selected.iter().cloned().collect::<Vec<_>>();
+        let confirmed = rfd::MessageDialog::new()
+            .set_title("Delete images")
+            .set_description(format!(
+                "Move {} selected images to the trash?",
+                images.len()
+            ))
+            .set_buttons(rfd::MessageButtons::YesNo)
+            .show()
+            == rfd::MessageDialogResult::Yes;
+        if !confirmed {
+            return;
+        }
+        if let Err(e) = state.store.delete_images(&images) {
+            state
+                .toasts
+                .push_error(format!("Failed to delete the selection: {}", e));
+            return;
+        }
+        state.selected.clear();
+        state
+            .toasts
+            .push_info(format!("Moved {} images to the trash", images.len()));
+        if state.store.is_empty() {
+            self.quit_requested = true;
+            return;
+        }
+        self.update_texture();
+    }
+}
+
+impl ApplicationHandler for App {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        #[allow(unused_mut)]
+        let mut attributes = Window::default_attributes()
+            .with_base_size(LogicalSize::new(2000, 4000))
+            .with_resizable(true);
+        // Lets the desktop match the window to its .desktop entry. Both traits have
+        // `with_name`, so they are called by path.
+        #[cfg(all(target_os = "linux", feature = "wayland"))]
+        {
+            attributes = WindowAttributesExtWayland::with_name(attributes, "imflow", "imflow");
+        }
+        #[cfg(all(target_os = "linux", feature = "x11"))]
+        {
+            attributes = WindowAttributesExtX11::with_name(attributes, "imflow", "imflow");
+        }
+        // The file name is in the overlay already
+        #[cfg(target_os = "macos")]
+        {
+            attributes = attributes.with_title_hidden(true);
+        }
+        let (attributes, initial_size) =
+            restore_geometry(attributes, self.config.window.as_ref(), event_loop);
+        let window = event_loop.create_window(attributes).unwrap();
+        if !self.config.decorations {
+            window.set_decorations(false);
+        }
+        pollster::block_on(self.set_window(window, initial_size));
+        #[cfg(target_os = "linux")]
+        if self.dbus.is_none()
+            && let Some(wake) = self.wake.clone()
+        {
+            let dbus = DbusService::start(wake);
+            if let Some((image, _, _)) = &self.current_image {
+                dbus.image_changed(&image.path);
+            }
+            self.dbus = Some(dbus);
+        }
+
+        if self.state.as_ref().unwrap().store.is_empty()
+            && let Some(path) = rfd::FileDialog::new().pick_folder()
+        {
+            self.reload_store(path);
+        }
+    }
+
+    fn user_event(&mut self, _: &ActiveEventLoop, _: ()) {
+        if let Some(window) = &self.window {
+            window.request_redraw();
+        }
+    }
+
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        if self
+            .geometry_changed_at
+            .is_some_and(|at| at.elapsed() >= GEOMETRY_SAVE_DELAY)
+            && let Err(e) = self.save_window_geometry()
+        {
+            self.push_toast(
+                ToastKind::Warning,
+                format!("Failed to remember the window size: {}", e),
+            );
+        }
+        if std::mem::take(&mut self.quit_requested) {
+            self.shutdown();
+            event_loop.exit();
+            return;
+        }
+        let now = Instant::now();
+        if self.repaint_at.is_some_and(|at| at <= now) {
+            self.repaint_at = None;
+            if let Some(window) = &self.window {
+                window.request_redraw();
+            }
+        }
+        let save_geometry_at = self
+            .geometry_changed_at
+            .map(|changed_at| changed_at + GEOMETRY_SAVE_DELAY);
+        event_loop.set_control_flow(
+            match self.repaint_at.into_iter().chain(save_geometry_at).min() {
+                Some(at) => ControlFlow::WaitUntil(at),
+                None => ControlFlow::Wait,
+            },
+        );
+    }
+
+    /// Tears the renderer down before the window it draws into.
+    fn exiting(&mut self, _: &ActiveEventLoop) {
+        self.state = None;
+        self.window = None;
+    }
+
+    fn window_event(&mut self, event_loop: &ActiveEventLoop, _: WindowId, event: WindowEvent) {
+        // let egui render to process the event first
+        self.state
+            .as_mut()
+            .unwrap()
+            .egui_renderer
+            .handle_input(self.window.as_ref().unwrap(), &event);
+        // Anything but a redraw may change what is on screen
+        if !matches!(event, WindowEvent::RedrawRequested) {
+            self.window.as_ref().unwrap().request_redraw();
+        }
+
+        match event {
+            WindowEvent::CloseRequested => {
+                println!("The close button was pressed; stopping");
+                self.shutdown();
+                event_loop.exit();
+            }
+            WindowEvent::RedrawRequested => {
+                let elapsed = self.animate_transform();
+                self.apply_inertia(elapsed);
+                self.advance_transition(elapsed);
+                self.update_texture_partial();
+                #[cfg(target_os = "linux")]
+                self.handle_dbus_commands();
+                self.handle_redraw(elapsed);
+                let (events, keys_down, pointer, modifiers, multi_touch) = self
+                    .state
+                    .as_ref()
+                    .unwrap()
+                    .egui_renderer
+                    .context()
+                    .input(|i| {
+                        (
+                            i.events.clone(),
+                            i.keys_down.clone(),
+                            i.pointer.clone(),
+                            i.modifiers,
+                            i.multi_touch(),
+                        )
+                    });
+
+                self.pointer_pos = pointer.hover_pos();
+                // Text fields in the panels own the keyboard while focused
+                let typing = self
+                    .state
+                    .as_ref()
+                    .unwrap()
+                    .egui_renderer
+                    .context()
+                    .wants_keyboard_input();
+                events.iter().for_each(|e| {
+                    if let Event::Key {
+                        key,
+                        pressed,
+                        repeat,
+                        modifiers,
+                        ..
+                    } = e
+                    {
+                        // Auto-repeat from the OS is ignored, navigation repeats on its
+                        // own timer and nothing else should
+                        if !*pressed || *repeat {
+                            return;
+                        }
+                        self.last_input_at = Instant::now();
+                        // The prompts own the keyboard while they are open
+                        if self.prompt_open() {
+                            if *key == Key::Escape {
+                                self.rename_prompt = None;
+                                self.go_to_prompt = None;
+                                self.date_picker = None;
+                                self.export_prompt = false;
+                            }
+                            return;
+                        }
+                        if typing {
+                            return;
+                        }
+                        if let Some(action) = self.keymap.action(*key, modifiers) {
+                            if matches!(
+                                action,
+                                Action::NextImage
+                                    | Action::PrevImage
+                                    | Action::NextInBurst
+                                    | Action::PrevInBurst
+                            ) {
+                                self.held_navigation = Some(HeldNavigation {
+                                    key: *key,
+                                    action,
+                                    pressed_at: Instant::now(),
+                                    steps: 0,
+                                });
                             }
-                            Key::ArrowDown => {
-                                let rating =
-                                    self.state.as_mut().unwrap().store.get_current_rating();
-                                self.state.as_mut().unwrap().store.set_rating(rating - 1);
+                            if action == Action::FlickerPrevious {
+                                self.start_flicker(*key);
                             }
-                            Key::Backtick => self.state.as_mut().unwrap().store.set_rating(0),
-                            Key::Num0 => self.state.as_mut().unwrap().store.set_rating(0),
-                            Key::Num1 => self.state.as_mut().unwrap().store.set_rating(1),
-                            Key::Num2 => self.state.as_mut().unwrap().store.set_rating(2),
-                            Key::Num3 => self.state.as_mut().unwrap().store.set_rating(3),
-                            Key::Num4 => self.state.as_mut().unwrap().store.set_rating(4),
-                            Key::Num5 => self.state.as_mut().unwrap().store.set_rating(5),
-                            Key::Escape => exit(0),
-                            _ => {}
+                            self.perform(action);
+                        }
+                    } else if *e == Event::Copy && !typing && !self.prompt_open() {
+                        // egui turns Ctrl+C into a copy event instead of a key press
+                        if let Some(action) = self.keymap.copy_action(&modifiers) {
+                            self.perform(action);
                         }
-                    } else if let Event::MouseWheel { delta, .. } = e {
-                        self.pan_zoom(delta.y * 0.2, 0.0, 0.0);
+                    } else if let Event::MouseWheel {
+                        unit,
+                        delta,
+                        modifiers,
+                    } = e
+                    {
+                        self.scroll(*unit, *delta, modifiers);
+                    } else if let Event::Zoom(factor) = e {
+                        // Touchpad pinches
+                        self.zoom_at(*factor, self.pointer_pos);
                     } else if let Event::PointerButton {
                         button, pressed, ..
                     } = e
@@ -722,16 +5034,240 @@ impl ApplicationHandler for App {
                     }
                 });
 
-                if pointer.primary_down() && pointer.is_moving() {
-                    self.pan_zoom(0.0, pointer.delta().x * 0.001, pointer.delta().y * -0.001);
+                // Touchscreen pinches
+                if let Some(touch) = multi_touch
+                    && touch.zoom_delta != 1.0
+                {
+                    self.zoom_at(touch.zoom_delta, Some(touch.center_pos));
+                }
+
+                if !self.prompt_open() && !typing {
+                    self.apply_held_keys(&keys_down, &modifiers, elapsed);
+                }
+                self.repeat_held_navigation(&keys_down);
+                if self
+                    .flicker_key
+                    .is_some_and(|key| !keys_down.contains(&key))
+                {
+                    self.flicker_key = None;
+                }
+
+                // Presses on the minimap move the view through it instead
+                let on_minimap = pointer
+                    .press_origin()
+                    .zip(self.minimap_rect)
+                    .is_some_and(|(origin, rect)| rect.contains(origin));
+
+                // egui only counts releases close to their press as clicks, so neither
+                // click of a double-click can be the end of a drag
+                if pointer.button_clicked(PointerButton::Primary) {
+                    let click_pos = pointer.interact_pos();
+                    let last_click_pos = std::mem::replace(&mut self.last_click_pos, click_pos);
+                    let close = click_pos
+                        .zip(last_click_pos)
+                        .is_some_and(|(a, b)| a.distance(b) <= DOUBLE_CLICK_MAX_DISTANCE);
+                    if pointer.button_double_clicked(PointerButton::Primary) && close && !on_minimap
+                    {
+                        self.toggle_one_to_one(click_pos);
+                    }
+                }
+
+                if pointer.primary_pressed() || pointer.button_pressed(PointerButton::Middle) {
+                    // Grabbing the image stops it
+                    let transform_data = &mut self.state.as_mut().unwrap().transform_data;
+                    transform_data.pan_velocity_x = 0.0;
+                    transform_data.pan_velocity_y = 0.0;
+                } else if self.config.use_inertia
+                    && !on_minimap
+                    && (pointer.primary_released()
+                        || pointer.button_released(PointerButton::Middle))
+                    && !pointer.could_any_button_be_click()
+                {
+                    let pixels_per_point = self
+                        .state
+                        .as_ref()
+                        .unwrap()
+                        .egui_renderer
+                        .context()
+                        .pixels_per_point();
+                    // Zero when the cursor rested before the button came up
+                    let velocity = pointer.velocity() * pixels_per_point;
+                    let transform_data = &mut self.state.as_mut().unwrap().transform_data;
+                    transform_data.pan_velocity_x = velocity.x;
+                    transform_data.pan_velocity_y = velocity.y;
+                }
+
+                // The middle button pans too, for those used to it from other viewers
+                if (pointer.primary_down() || pointer.middle_down())
+                    && pointer.is_moving()
+                    && !on_minimap
+                {
+                    let pixels_per_point = self
+                        .state
+                        .as_ref()
+                        .unwrap()
+                        .egui_renderer
+                        .context()
+                        .pixels_per_point();
+                    let delta = pointer.delta() * pixels_per_point;
+                    self.pan_by_pixels(delta.x, delta.y);
+                }
+
+                if pointer.is_moving() {
+                    self.pointer_moved_at = Instant::now();
+                    self.last_input_at = Instant::now();
                 }
 
-                self.window.as_ref().unwrap().request_redraw();
+                // Only look the pixel up again when the cursor moves, so the readout
+                // doesn't flicker while it rests on an edge
+                if !modifiers.alt {
+                    self.hovered_pixel = None;
+                } else if pointer.is_moving() || self.hovered_pixel.is_none() {
+                    self.hovered_pixel = pointer.hover_pos().and_then(|pos| self.pixel_at(pos));
+                }
+
+                self.check_current_image_changed();
+                // Input is handled after drawing, so it shows on the next frame
+                if !events.is_empty() || self.wants_next_frame(!keys_down.is_empty()) {
+                    self.window.as_ref().unwrap().request_redraw();
+                }
+            }
+            WindowEvent::DroppedFile(path) if path.is_dir() => self.reload_store(path),
+            // Coming back from an external editor
+            WindowEvent::Focused(true)
+                if self
+                    .state
+                    .as_mut()
+                    .unwrap()
+                    .store
+                    .reload_current_if_changed() =>
+            {
+                let current = &self.state.as_ref().unwrap().store.current_image_path;
+                self.thumbnail_textures.remove(current);
+                // The old image stays up until `update_texture_partial` swaps in the
+                // new decode, rather than decoding the thumbnail here
+                self.showing_full = false;
+            }
+            WindowEvent::ThemeChanged(theme) => {
+                self.state.as_ref().unwrap().egui_renderer.set_theme(theme);
+            }
+            // Fractional scaling on Wayland, or the window moving to another monitor
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                self.state.as_mut().unwrap().scale_factor = scale_factor as f32;
+                // The fit is in physical pixels, which a new monitor may have changed
+                // without a resize of its own
+                let size = self.window.as_ref().unwrap().inner_size();
+                self.handle_resized(size.width, size.height);
             }
             WindowEvent::Resized(new_size) => {
                 self.handle_resized(new_size.width, new_size.height);
+                self.geometry_changed_at = Some(Instant::now());
             }
+            WindowEvent::Moved(_) => self.geometry_changed_at = Some(Instant::now()),
             _ => (),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn next_pow2_rounds_up() {
+        assert_eq!(next_pow2(1), 1);
+        assert_eq!(next_pow2(2), 2);
+        assert_eq!(next_pow2(3), 4);
+        assert_eq!(next_pow2(100), 128);
+        assert_eq!(next_pow2(4096), 4096);
+        assert_eq!(next_pow2(6000), 8192);
+    }
+
+    #[test]
+    fn image_texture_is_sized_from_the_image() {
+        let imbuf = ImflowImageBuffer {
+            width: 100,
+            height: 100,
+            rgba_buffer: vec![0; 100 * 100],
+            rating: 0,
+            hdr: false,
+        };
+        let size = image_texture_size(imbuf.width as u32, imbuf.height as u32);
+        assert_eq!((size.width, size.height), (128, 128));
+        let size = image_texture_size(6000, 4000);
+        assert_eq!((size.width, size.height), (8192, 4096));
+    }
+
+    /// How much of the clip-space screen, from -1 to 1, an image of half size `extent`
+    /// centred at `pan` covers.
+    fn overlap(pan: f32, extent: f32) -> f32 {
+        ((pan + extent).min(1.0) - (pan - extent).max(-1.0)).max(0.0)
+    }
+
+    #[test]
+    fn clamp_pan_keeps_part_of_the_image_on_screen() {
+        let view = (1600.0, 900.0);
+        // Landscape, portrait, square and a panorama
+        for (width, height) in [(6000, 4000), (4000, 6000), (3000, 3000), (12000, 3000)] {
+            let (scale_x, scale_y) = fit_scale_for(width, height, ViewOrientation::default(), view);
+            for zoom in [1.0, 2.5, 8.0] {
+                for extent in [scale_x * zoom, scale_y * zoom] {
+                    for fraction in [0.0, 0.25, 1.0] {
+                        let required = fraction * (2.0 * extent).min(2.0);
+                        for pan in [-100.0, 100.0] {
+                            let clamped = clamp_pan(pan, extent, fraction);
+                            // Pushed as far as it goes, exactly the required part is left
+                            assert!(
+                                (overlap(clamped, extent) - required).abs() < 1e-4,
+                                "{}x{} zoom {} extent {} fraction {}: pan {}",
+                                width,
+                                height,
+                                zoom,
+                                extent,
+                                fraction,
+                                clamped
+                            );
+                        }
+                        assert_eq!(clamp_pan(0.0, extent, fraction), 0.0);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn clamp_pan_leaves_small_offsets_alone() {
+        assert_eq!(clamp_pan(0.3, 0.5, 0.5), 0.3);
+        assert_eq!(clamp_pan(-0.3, 2.0, 0.5), -0.3);
+        // Keeping half of an image smaller than the screen stops its centre at the edge
+        assert_eq!(clamp_pan(5.0, 0.25, 0.5), 1.0);
+        assert_eq!(clamp_pan(-5.0, 0.5, 0.5), -1.0);
+    }
+
+    #[test]
+    fn pad_rows_borrows_aligned_rows() {
+        // 64 pixels are exactly 256 bytes
+        let buffer = vec![7u8; 64 * 4 * 3];
+        let (padded, bytes_per_row) = pad_rows(&buffer, 64, 3);
+        assert!(matches!(padded, Cow::Borrowed(_)));
+        assert_eq!(bytes_per_row, 256);
+    }
+
+    #[test]
+    fn pad_rows_pads_each_row_with_zeros() {
+        let (width, height) = (100, 3);
+        let buffer = (0..width * height * 4)
+            .map(|i| (i % 251 + 1) as u8)
+            .collect::<Vec<_>>();
+        let (padded, bytes_per_row) = pad_rows(&buffer, width, height);
+        assert_eq!(bytes_per_row, 512);
+        assert_eq!(padded.len(), 512 * height as usize);
+        for (row, source) in padded
+            .chunks_exact(512)
+            .zip(buffer.chunks_exact(width as usize * 4))
+        {
+            assert_eq!(&row[..400], source);
+            assert!(row[400..].iter().all(|&byte| byte == 0));
+        }
+    }
+}
@@ -0,0 +1,25 @@
+struct Background {
+    top_color: vec4<f32>,
+    bottom_color: vec4<f32>,
+};
+@group(0) @binding(0) var<uniform> background: Background;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) t: f32,
+};
+
+// Single triangle covering the whole screen
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    out.t = uv.y;
+    return out;
+}
+
+@fragment
+fn fs_main(@location(0) t: f32) -> @location(0) vec4<f32> {
+    return mix(background.bottom_color, background.top_color, clamp(t, 0.0, 1.0));
+}
@@ -0,0 +1,175 @@
+use crate::image::{
+    FileBytes, MetadataCache, load_available_images, load_image, load_thumbnail, mmap_read,
+};
+use serde::Serialize;
+use std::fmt;
+use std::fs::{self, File};
+use std::hint::black_box;
+use std::io;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+// Stride at which a mapped file is touched to page all of it in
+const PAGE_SIZE: usize = 4096;
+
+/// Load times of one file, in milliseconds. `None` when the decoder panicked.
+#[derive(Debug, Serialize)]
+pub struct FileTiming {
+    pub path: String,
+    pub thumbnail_ms: Option<f64>,
+    pub full_ms: Option<f64>,
+    /// Reading the whole file onto the heap
+    pub read_ms: Option<f64>,
+    /// Mapping the whole file and paging it in
+    pub mapped_ms: Option<f64>,
+}
+
+/// Summary of one kind of load over every file that loaded, in milliseconds.
+#[derive(Debug, Default, Serialize)]
+pub struct TimingStats {
+    pub count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub p95: f64,
+}
+
+impl TimingStats {
+    fn new(mut times: Vec<f64>) -> Self {
+        if times.is_empty() {
+            return TimingStats::default();
+        }
+        times.sort_by(f64::total_cmp);
+        // Nearest rank
+        let p95 = times[((times.len() as f64 * 0.95).ceil() as usize).max(1) - 1];
+        TimingStats {
+            count: times.len(),
+            min: times[0],
+            max: times[times.len() - 1],
+            mean: times.iter().sum::<f64>() / times.len() as f64,
+            p95,
+        }
+    }
+}
+
+impl fmt::Display for TimingStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} files, min {:.1} ms, max {:.1} ms, mean {:.1} ms, p95 {:.1} ms",
+            self.count, self.min, self.max, self.mean, self.p95
+        )
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BenchmarkReport {
+    pub files: Vec<FileTiming>,
+    pub thumbnail: TimingStats,
+    pub full: TimingStats,
+    pub read: TimingStats,
+    pub mapped: TimingStats,
+    pub total_ms: f64,
+}
+
+impl fmt::Display for BenchmarkReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let format_time =
+            |time: Option<f64>| time.map_or("failed".to_string(), |time| format!("{:.1} ms", time));
+        for file in &self.files {
+            writeln!(
+                f,
+                "{}: thumbnail {}, full {}, read {}, mapped {}",
+                file.path,
+                format_time(file.thumbnail_ms),
+                format_time(file.full_ms),
+                format_time(file.read_ms),
+                format_time(file.mapped_ms)
+            )?;
+        }
+        writeln!(f, "Thumbnail: {}", self.thumbnail)?;
+        writeln!(f, "Full: {}", self.full)?;
+        writeln!(f, "Read: {}", self.read)?;
+        writeln!(f, "Mapped: {}", self.mapped)?;
+        write!(f, "Total: {:.1} ms", self.total_ms)
+    }
+}
+
+fn time_load<T, E>(load: impl FnOnce() -> Result<T, E>) -> Option<f64> {
+    let start = Instant::now();
+    panic::catch_unwind(panic::AssertUnwindSafe(load))
+        .ok()?
+        .ok()?;
+    Some(millis(start.elapsed()))
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Asks the kernel to drop `path` from the page cache, so the next read of it goes
+/// to the storage. Elsewhere only the first read of a file is measured cold.
+#[cfg(target_os = "linux")]
+fn evict_from_page_cache(path: &Path) {
+    use std::os::fd::AsRawFd;
+    if let Ok(file) = File::open(path) {
+        // Safety: the descriptor stays open for the whole call
+        unsafe {
+            libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED);
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn evict_from_page_cache(_path: &Path) {}
+
+/// Time to get all of `path` into memory, read onto the heap or mapped and paged in.
+/// Compares the two ways `read_file` can take, which differ most on network storage.
+fn time_read(path: &Path, map: bool) -> Option<f64> {
+    evict_from_page_cache(path);
+    time_load(|| -> io::Result<()> {
+        let bytes = if map {
+            FileBytes::Mapped(mmap_read(path)?)
+        } else {
+            FileBytes::Read(fs::read(path)?)
+        };
+        // A mapped file is only read when its pages are touched
+        black_box(
+            bytes
+                .iter()
+                .step_by(PAGE_SIZE)
+                .map(|&byte| byte as u64)
+                .sum::<u64>(),
+        );
+        Ok(())
+    })
+}
+
+/// Loads the thumbnail and then the full image of every image in `dir`, one at a
+/// time so each measurement is the latency of a single load. Metadata is cached
+/// by the thumbnail load, so the full load is timed without it. Each file is also
+/// read and mapped whole, on Linux from a cold page cache.
+pub fn benchmark_directory(dir: PathBuf) -> BenchmarkReport {
+    let start = Instant::now();
+    // Empty rather than the saved one, so the first load of each file reads its tags
+    let metadata_cache = MetadataCache::default();
+    let files = load_available_images(dir)
+        .iter()
+        .map(|image| FileTiming {
+            path: image.path.display().to_string(),
+            thumbnail_ms: time_load(|| load_thumbnail(image, &metadata_cache)),
+            full_ms: time_load(|| load_image(image, &metadata_cache)),
+            read_ms: time_read(&image.path, false),
+            mapped_ms: time_read(&image.path, true),
+        })
+        .collect::<Vec<_>>();
+    BenchmarkReport {
+        thumbnail: TimingStats::new(files.iter().filter_map(|file| file.thumbnail_ms).collect()),
+        full: TimingStats::new(files.iter().filter_map(|file| file.full_ms).collect()),
+        read: TimingStats::new(files.iter().filter_map(|file| file.read_ms).collect()),
+        mapped: TimingStats::new(files.iter().filter_map(|file| file.mapped_ms).collect()),
+        files,
+        total_ms: millis(start.elapsed()),
+    }
+}
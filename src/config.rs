@@ -0,0 +1,434 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackgroundMode {
+    #[default]
+    Solid,
+    Gradient,
+}
+
+/// Graphics API to render with. `None` in the config lets wgpu pick.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendSelection {
+    Vulkan,
+    Metal,
+    Dx12,
+    Opengl,
+}
+
+/// Parses a `--backend` value, where `auto` means no preference.
+pub fn parse_backend(name: &str) -> Result<Option<BackendSelection>, String> {
+    match name.to_ascii_lowercase().as_str() {
+        "auto" => Ok(None),
+        "vulkan" => Ok(Some(BackendSelection::Vulkan)),
+        "metal" => Ok(Some(BackendSelection::Metal)),
+        "dx12" => Ok(Some(BackendSelection::Dx12)),
+        "opengl" => Ok(Some(BackendSelection::Opengl)),
+        _ => Err(format!(
+            "expected vulkan, metal, dx12, opengl or auto, got `{}`",
+            name
+        )),
+    }
+}
+
+/// What shows through the transparent parts of an image.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlphaBackground {
+    Black,
+    Gray,
+    White,
+    #[default]
+    Checkerboard,
+}
+
+impl AlphaBackground {
+    pub fn next(self) -> Self {
+        match self {
+            AlphaBackground::Black => AlphaBackground::Gray,
+            AlphaBackground::Gray => AlphaBackground::White,
+            AlphaBackground::White => AlphaBackground::Checkerboard,
+            AlphaBackground::Checkerboard => AlphaBackground::Black,
+        }
+    }
+
+    /// Linear RGBA of the solid modes, which the area around the image takes on too
+    pub fn color(self) -> Option<[f64; 4]> {
+        match self {
+            AlphaBackground::Black => Some([0.0, 0.0, 0.0, 1.0]),
+            // sRGB 50% gray
+            AlphaBackground::Gray => Some([0.214, 0.214, 0.214, 1.0]),
+            AlphaBackground::White => Some([1.0, 1.0, 1.0, 1.0]),
+            AlphaBackground::Checkerboard => None,
+        }
+    }
+}
+
+/// How image pixels are filtered when drawn to the screen.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SamplingMode {
+    /// Smooth below 100% zoom, sharp pixel edges above it once the full image is shown
+    #[default]
+    Auto,
+    Linear,
+    Nearest,
+}
+
+impl SamplingMode {
+    pub fn next(self) -> Self {
+        match self {
+            SamplingMode::Auto => SamplingMode::Linear,
+            SamplingMode::Linear => SamplingMode::Nearest,
+            SamplingMode::Nearest => SamplingMode::Auto,
+        }
+    }
+
+    /// Whether to sample without interpolation at `pixel_scale` screen pixels per image
+    /// pixel. Thumbnails stay smooth in auto mode, as they are always shown enlarged.
+    pub fn use_nearest(self, pixel_scale: f32, full: bool) -> bool {
+        match self {
+            SamplingMode::Auto => full && pixel_scale >= 1.0,
+            SamplingMode::Linear => false,
+            SamplingMode::Nearest => true,
+        }
+    }
+}
+
+/// What happens to the zoom and pan when moving to another image.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransformMemory {
+    /// Each image comes back the way it was left, or fitted the first time
+    #[default]
+    PerImage,
+    /// The view stays as it is, for same-size bursts
+    Keep,
+    /// Every image starts fitted to the window
+    Reset,
+}
+
+impl TransformMemory {
+    pub fn next(self) -> Self {
+        match self {
+            TransformMemory::PerImage => TransformMemory::Keep,
+            TransformMemory::Keep => TransformMemory::Reset,
+            TransformMemory::Reset => TransformMemory::PerImage,
+        }
+    }
+}
+
+/// Which images around the current one are decoded ahead of time, e.g.
+/// `preload = { kind = "symmetric", radius = 8 }`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase", tag = "kind")]
+pub enum PreloadStrategy {
+    /// A fixed number of images on either side
+    Linear { ahead: usize, behind: usize },
+    /// The same number of images on both sides
+    Symmetric { radius: usize },
+    /// Leans toward the direction of the last few navigation steps
+    Adaptive,
+}
+
+impl Default for PreloadStrategy {
+    fn default() -> Self {
+        PreloadStrategy::Linear {
+            ahead: 16,
+            behind: 0,
+        }
+    }
+}
+
+/// The window as it was last left, in physical pixels. The size is the inner
+/// size, and both size and position are those from before maximizing.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WindowGeometry {
+    pub width: u32,
+    pub height: u32,
+    /// Outer position, unknown on Wayland
+    pub position: Option<(i32, i32)>,
+    pub maximized: bool,
+    pub fullscreen: bool,
+    /// Name of the monitor the window was on
+    pub monitor: Option<String>,
+}
+
+/// Where ratings go when the rated image is a stack of several files.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StackRating {
+    /// Write the rating into every member of the stack
+    #[default]
+    All,
+    /// Write RAW ratings into an XMP sidecar instead of the RAW file itself
+    Sidecar,
+}
+
+/// Range of `Config::ui_scale`.
+pub const MIN_UI_SCALE: f32 = 0.5;
+pub const MAX_UI_SCALE: f32 = 3.0;
+
+/// Range of `Config::map_tile_zoom`, the levels tile.openstreetmap.org serves.
+pub const MIN_MAP_TILE_ZOOM: u8 = 1;
+pub const MAX_MAP_TILE_ZOOM: u8 = 19;
+
+/// User configuration, read from `config.toml` in the imflow config directory.
+/// Colors are linear RGBA, as passed to wgpu.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub background_color: [f64; 4],
+    pub background_mode: BackgroundMode,
+    pub gradient_top_color: [f64; 4],
+    pub gradient_bottom_color: [f64; 4],
+    pub alpha_background: AlphaBackground,
+    pub sampling: SamplingMode,
+    pub transform_memory: TransformMemory,
+    pub hdr_output: bool,
+    /// Prefer the integrated GPU on dual-GPU systems, to save battery
+    pub low_power: bool,
+    /// Prefer the discrete GPU; ignored when `low_power` is set
+    pub high_performance: bool,
+    /// Graphics API to try first, see `BackendSelection`
+    pub backend: Option<BackendSelection>,
+    /// Print GPU and CPU frame times, where timestamp queries are supported
+    pub gpu_profile: bool,
+    /// Show loader and frame statistics in a window over everything else
+    pub debug_overlay: bool,
+    /// Whether the window gets a title bar and borders
+    pub decorations: bool,
+    /// File extensions in order of display preference for RAW+JPEG style stacks
+    pub stack_priority: Vec<String>,
+    pub stack_rating: StackRating,
+    /// Share of the image (or of the window, when zoomed in past it) that panning
+    /// must keep on screen
+    pub min_visible_fraction: f32,
+    /// Seconds for zoom and pan animations to cover ~63% of the distance; 0 disables them
+    pub animation_time_constant: f32,
+    /// Length of the crossfade between images when navigating; 0 disables it
+    pub transition_duration_ms: u64,
+    /// Keep a dragged image gliding after the mouse button is released
+    pub use_inertia: bool,
+    /// Zoom with two-finger touchpad scrolling and pan with the mouse wheel instead
+    /// of the other way round
+    pub swap_scroll: bool,
+    /// Images per second to advance while a navigation key is held
+    pub scrub_rate: f32,
+    /// Images skipped by the page_forward/page_back actions
+    pub page_size: usize,
+    /// Fade the rating window out while there is no keyboard or mouse input
+    pub auto_hide_overlays: bool,
+    /// Size of the overlays relative to the display's scale factor; the image is
+    /// unaffected
+    pub ui_scale: f32,
+    /// Whether moving past the last image continues at the first one and vice versa
+    pub wrap_navigation: bool,
+    /// Only quit when Escape is pressed twice in a row, against accidental quits
+    pub confirm_quit: bool,
+    /// Show an OpenStreetMap tile around the GPS position in the info panel. Tiles
+    /// are downloaded from tile.openstreetmap.org, so this is off by default.
+    pub show_map_tile: bool,
+    /// Zoom level of the map tile, from 1 (the whole world) to 19 (a few buildings)
+    pub map_tile_zoom: u8,
+    /// Seconds between the capture times of two frames that still make them one burst
+    pub burst_max_gap: f32,
+    /// Command that `E` opens the current image with, e.g. `"gimp {path}"`
+    pub editor: Option<String>,
+    /// Read ratings and colour labels from Darktable sidecars and write ratings to them
+    pub darktable_compat: bool,
+    /// Files of at least this many megabytes are memory-mapped rather than read
+    /// into memory before decoding. Another program truncating a mapped file
+    /// crashes the viewer, so lowering this trades safety for speed
+    pub mmap_threshold_mb: u64,
+    /// Keep the view rotation and flip when moving to another image
+    pub sticky_orientation: bool,
+    /// The folder opened last, shown on launch when no path is given
+    pub last_folder: Option<PathBuf>,
+    /// Restored when the window is created
+    pub window: Option<WindowGeometry>,
+    /// Folders that Ctrl+1 to Ctrl+5 copy the current image into
+    pub copy_target_1: Option<PathBuf>,
+    pub copy_target_2: Option<PathBuf>,
+    pub copy_target_3: Option<PathBuf>,
+    pub copy_target_4: Option<PathBuf>,
+    pub copy_target_5: Option<PathBuf>,
+    pub preload: PreloadStrategy,
+    /// Key binding overrides, e.g. `Space = "next_image"` or `"Alt+S" = "none"`
+    pub keys: BTreeMap<String, String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            background_color: [0.05, 0.05, 0.05, 1.0],
+            background_mode: BackgroundMode::Solid,
+            gradient_top_color: [0.08, 0.08, 0.08, 1.0],
+            gradient_bottom_color: [0.01, 0.01, 0.01, 1.0],
+            alpha_background: AlphaBackground::Checkerboard,
+            sampling: SamplingMode::Auto,
+            transform_memory: TransformMemory::PerImage,
+            hdr_output: false,
+            low_power: false,
+            high_performance: false,
+            backend: None,
+            gpu_profile: false,
+            debug_overlay: false,
+            decorations: true,
+            stack_priority: ["jpg", "jpeg", "heic", "heif", "jxl"]
+                .map(String::from)
+                .to_vec(),
+            stack_rating: StackRating::All,
+            min_visible_fraction: 0.25,
+            animation_time_constant: 0.05,
+            transition_duration_ms: 150,
+            use_inertia: true,
+            swap_scroll: false,
+            scrub_rate: 15.0,
+            page_size: 10,
+            auto_hide_overlays: false,
+            ui_scale: 1.0,
+            wrap_navigation: false,
+            confirm_quit: false,
+            show_map_tile: false,
+            map_tile_zoom: 15,
+            burst_max_gap: 2.0,
+            editor: None,
+            darktable_compat: false,
+            mmap_threshold_mb: 64,
+            sticky_orientation: false,
+            last_folder: None,
+            window: None,
+            copy_target_1: None,
+            copy_target_2: None,
+            copy_target_3: None,
+            copy_target_4: None,
+            copy_target_5: None,
+            preload: PreloadStrategy::default(),
+            keys: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("imflow").join("config.toml"))
+    }
+
+    /// Loads the config file, falling back to defaults if it is missing or invalid.
+    pub fn load() -> Self {
+        let Some(path) = Self::path() else {
+            return Config::default();
+        };
+        match fs::read_to_string(&path) {
+            Ok(contents) => toml::from_str(&contents).unwrap_or_else(|e| {
+                eprintln!("Invalid config {}: {}", path.display(), e);
+                Config::default()
+            }),
+            Err(_) => Config::default(),
+        }
+    }
+
+    /// The folder configured as `copy_target_<slot>`, for slots 1 to 5.
+    pub fn copy_target(&self, slot: u8) -> Option<&PathBuf> {
+        match slot {
+            1 => self.copy_target_1.as_ref(),
+            2 => self.copy_target_2.as_ref(),
+            3 => self.copy_target_3.as_ref(),
+            4 => self.copy_target_4.as_ref(),
+            5 => self.copy_target_5.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Puts the options of the settings window back to their defaults. Paths,
+    /// key bindings and startup-only options are kept.
+    pub fn restore_default_settings(&mut self) {
+        let defaults = Config::default();
+        self.background_color = defaults.background_color;
+        self.background_mode = defaults.background_mode;
+        self.gradient_top_color = defaults.gradient_top_color;
+        self.gradient_bottom_color = defaults.gradient_bottom_color;
+        self.alpha_background = defaults.alpha_background;
+        self.sampling = defaults.sampling;
+        self.transform_memory = defaults.transform_memory;
+        self.animation_time_constant = defaults.animation_time_constant;
+        self.transition_duration_ms = defaults.transition_duration_ms;
+        self.use_inertia = defaults.use_inertia;
+        self.swap_scroll = defaults.swap_scroll;
+        self.scrub_rate = defaults.scrub_rate;
+        self.page_size = defaults.page_size;
+        self.auto_hide_overlays = defaults.auto_hide_overlays;
+        self.ui_scale = defaults.ui_scale;
+        self.wrap_navigation = defaults.wrap_navigation;
+        self.confirm_quit = defaults.confirm_quit;
+        self.show_map_tile = defaults.show_map_tile;
+        self.map_tile_zoom = defaults.map_tile_zoom;
+        self.preload = defaults.preload;
+    }
+
+    /// Writes one setting into the config file, leaving the rest of it alone. Unlike
+    /// saving the whole `Config`, this doesn't persist command line overrides.
+    pub fn save_setting(key: &str, value: impl Serialize) -> io::Result<()> {
+        let value = toml::Value::try_from(value).map_err(io::Error::other)?;
+        Self::save_settings([(key.to_string(), value)])
+    }
+
+    /// Writes the settings that differ from `previous` into the config file, see
+    /// `save_setting`.
+    pub fn save_changes(&self, previous: &Config) -> io::Result<()> {
+        let previous = toml::Table::try_from(previous).map_err(io::Error::other)?;
+        let changed = toml::Table::try_from(self)
+            .map_err(io::Error::other)?
+            .into_iter()
+            .filter(|(key, value)| previous.get(key) != Some(value));
+        Self::save_settings(changed)
+    }
+
+    fn save_settings(settings: impl IntoIterator<Item = (String, toml::Value)>) -> io::Result<()> {
+        let path = Self::path()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no config directory"))?;
+        let mut table = match fs::read_to_string(&path) {
+            Ok(contents) => contents.parse::<toml::Table>().map_err(io::Error::other)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => toml::Table::new(),
+            Err(e) => return Err(e),
+        };
+        table.extend(settings);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&path, table.to_string())
+    }
+}
+
+fn srgb_to_linear(value: f64) -> f64 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Parses an sRGB hex color (`1a1a1a`, `#1a1a1a` or with an alpha byte) into linear RGBA.
+pub fn parse_hex_color(hex: &str) -> Result<[f64; 4], String> {
+    let digits = hex.trim_start_matches('#');
+    if !(digits.len() == 6 || digits.len() == 8) || !digits.is_ascii() {
+        return Err(format!("expected RRGGBB or RRGGBBAA, got `{}`", hex));
+    }
+    let mut color = [1.0; 4];
+    for (i, channel) in color.iter_mut().enumerate().take(digits.len() / 2) {
+        let byte = u8::from_str_radix(&digits[i * 2..i * 2 + 2], 16)
+            .map_err(|_| format!("invalid hex color `{}`", hex))?;
+        *channel = byte as f64 / 255.0;
+    }
+    for channel in &mut color[..3] {
+        *channel = srgb_to_linear(*channel);
+    }
+    Ok(color)
+}
@@ -0,0 +1,158 @@
+use imflow::store::Wake;
+use std::path::Path;
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use zbus::blocking::Connection;
+use zbus::blocking::connection::Builder;
+use zbus::interface;
+use zbus::object_server::SignalEmitter;
+
+const BUS_NAME: &str = "org.imflow.Viewer";
+const OBJECT_PATH: &str = "/org/imflow/Viewer";
+
+/// Requests from D-Bus clients, carried out on the UI thread.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AppCommand {
+    NextImage,
+    PreviousImage,
+    SetRating(u8),
+}
+
+/// Signals waiting to be emitted by the service thread.
+enum Signal {
+    ImageChanged(String),
+    RatingChanged(String, i32),
+}
+
+/// The object served at `OBJECT_PATH`.
+struct Viewer {
+    commands: mpsc::Sender<AppCommand>,
+    /// Gets the app to look at `commands`
+    wake: Wake,
+    current_path: Arc<Mutex<String>>,
+}
+
+impl Viewer {
+    fn send(&self, command: AppCommand) {
+        let _ = self.commands.send(command);
+        (self.wake)();
+    }
+}
+
+#[interface(name = "org.imflow.Viewer")]
+impl Viewer {
+    fn next_image(&self) {
+        self.send(AppCommand::NextImage);
+    }
+
+    fn previous_image(&self) {
+        self.send(AppCommand::PreviousImage);
+    }
+
+    fn set_rating(&self, rating: i32) -> zbus::fdo::Result<()> {
+        if !(0..=5).contains(&rating) {
+            return Err(zbus::fdo::Error::InvalidArgs(format!(
+                "expected a rating from 0 to 5, got {}",
+                rating
+            )));
+        }
+        self.send(AppCommand::SetRating(rating as u8));
+        Ok(())
+    }
+
+    fn get_current_path(&self) -> String {
+        self.current_path.lock().unwrap().clone()
+    }
+
+    #[zbus(signal)]
+    async fn image_changed(emitter: &SignalEmitter<'_>, path: &str) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn rating_changed(
+        emitter: &SignalEmitter<'_>,
+        path: &str,
+        rating: i32,
+    ) -> zbus::Result<()>;
+}
+
+/// Remote control over the session bus as `org.imflow.Viewer`.
+///
+/// The connection lives on a thread of its own, which also emits the signals. The
+/// app polls `try_recv` for the method calls that change what is shown.
+pub struct DbusService {
+    commands: mpsc::Receiver<AppCommand>,
+    signals: mpsc::Sender<Signal>,
+    current_path: Arc<Mutex<String>>,
+}
+
+impl DbusService {
+    /// Starts connecting to the session bus. Without one the service stays silent
+    /// after printing why. `wake` is called after every command sent to the app.
+    pub fn start(wake: Wake) -> Self {
+        let (commands_tx, commands) = mpsc::channel();
+        let (signals, signals_rx) = mpsc::channel();
+        let current_path = Arc::new(Mutex::new(String::new()));
+        let viewer = Viewer {
+            commands: commands_tx,
+            wake,
+            current_path: current_path.clone(),
+        };
+        thread::spawn(move || {
+            let connection = match connect(viewer) {
+                Ok(connection) => connection,
+                Err(e) => {
+                    eprintln!("Failed to register {} on D-Bus: {}", BUS_NAME, e);
+                    return;
+                }
+            };
+            // Ends once the app drops its sender
+            for signal in signals_rx {
+                if let Err(e) = emit(&connection, signal) {
+                    eprintln!("Failed to emit a D-Bus signal: {}", e);
+                }
+            }
+        });
+        DbusService {
+            commands,
+            signals,
+            current_path,
+        }
+    }
+
+    pub fn try_recv(&self) -> Option<AppCommand> {
+        self.commands.try_recv().ok()
+    }
+
+    pub fn image_changed(&self, path: &Path) {
+        let path = path.to_string_lossy().to_string();
+        *self.current_path.lock().unwrap() = path.clone();
+        let _ = self.signals.send(Signal::ImageChanged(path));
+    }
+
+    pub fn rating_changed(&self, path: &Path, rating: i32) {
+        let path = path.to_string_lossy().to_string();
+        let _ = self.signals.send(Signal::RatingChanged(path, rating));
+    }
+}
+
+fn connect(viewer: Viewer) -> zbus::Result<Connection> {
+    Builder::session()?
+        .name(BUS_NAME)?
+        .serve_at(OBJECT_PATH, viewer)?
+        .build()
+}
+
+fn emit(connection: &Connection, signal: Signal) -> zbus::Result<()> {
+    let viewer = connection
+        .object_server()
+        .interface::<_, Viewer>(OBJECT_PATH)?;
+    let emitter = viewer.signal_emitter();
+    zbus::block_on(async {
+        match signal {
+            Signal::ImageChanged(path) => Viewer::image_changed(emitter, &path).await,
+            Signal::RatingChanged(path, rating) => {
+                Viewer::rating_changed(emitter, &path, rating).await
+            }
+        }
+    })
+}
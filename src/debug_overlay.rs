@@ -0,0 +1,210 @@
+use crate::egui_tools::EguiRenderer;
+use egui_wgpu::{ScreenDescriptor, wgpu};
+use imflow::store::LoadStats;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use winit::window::Window;
+
+// Span the decode throughput is averaged over
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(2);
+// Frames the frame times are averaged over
+const SMOOTHING_FRAMES: usize = 60;
+
+/// Mean of the last `SMOOTHING_FRAMES` samples, kept as a running sum.
+#[derive(Default)]
+struct RollingAverage {
+    samples: VecDeque<Duration>,
+    sum: Duration,
+}
+
+impl RollingAverage {
+    fn push(&mut self, sample: Duration) {
+        if self.samples.len() == SMOOTHING_FRAMES
+            && let Some(oldest) = self.samples.pop_front()
+        {
+            self.sum -= oldest;
+        }
+        self.samples.push_back(sample);
+        self.sum += sample;
+    }
+
+    fn mean(&self) -> Option<Duration> {
+        (!self.samples.is_empty()).then(|| self.sum / self.samples.len() as u32)
+    }
+}
+
+/// Loader and frame statistics drawn over everything else with `--debug-overlay`
+/// or F3.
+///
+/// The overlay has an egui context of its own and is submitted after the frame, so
+/// building and drawing it doesn't count towards the measured frame time.
+pub struct DebugOverlay {
+    renderer: EguiRenderer,
+    /// `LoadStats::decoded` on recent frames, oldest first
+    decoded_samples: VecDeque<(Instant, usize)>,
+    /// CPU time of recent frames up to their submission
+    frame_times: RollingAverage,
+    gpu_times: RollingAverage,
+    /// CPU time of the last upload of the current image, and when it finished
+    upload: Option<(Duration, Instant)>,
+    adapter_description: String,
+}
+
+impl DebugOverlay {
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        adapter_description: String,
+    ) -> Self {
+        DebugOverlay {
+            renderer: EguiRenderer::new(device, format, None, 1, None),
+            decoded_samples: VecDeque::new(),
+            frame_times: RollingAverage::default(),
+            gpu_times: RollingAverage::default(),
+            upload: None,
+            adapter_description,
+        }
+    }
+
+    /// Records the CPU time of a frame, and its GPU time when the profiler has one.
+    pub fn frame_finished(&mut self, frame_time: Duration, gpu_time: Option<Duration>) {
+        self.frame_times.push(frame_time);
+        if let Some(gpu_time) = gpu_time {
+            self.gpu_times.push(gpu_time);
+        }
+    }
+
+    pub fn uploaded(&mut self, upload_time: Duration) {
+        self.upload = Some((upload_time, Instant::now()));
+    }
+
+    /// Loads per second over roughly the last `THROUGHPUT_WINDOW`.
+    fn throughput(&mut self, decoded: usize) -> f64 {
+        let now = Instant::now();
+        // A new store counts from zero again
+        if self
+            .decoded_samples
+            .back()
+            .is_some_and(|&(_, last)| last > decoded)
+        {
+            self.decoded_samples.clear();
+        }
+        self.decoded_samples.push_back((now, decoded));
+        // The newest sample older than the window stays as the baseline
+        while self
+            .decoded_samples
+            .get(1)
+            .is_some_and(|&(time, _)| now - time >= THROUGHPUT_WINDOW)
+        {
+            self.decoded_samples.pop_front();
+        }
+        let (start, first) = self.decoded_samples[0];
+        let elapsed = (now - start).as_secs_f64();
+        if elapsed > 0.0 {
+            (decoded - first) as f64 / elapsed
+        } else {
+            0.0
+        }
+    }
+
+    /// Draws the overlay onto `view` with an encoder of its own.
+    pub fn draw(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        window: &Window,
+        view: &wgpu::TextureView,
+        screen_descriptor: ScreenDescriptor,
+        stats: LoadStats,
+    ) {
+        let format_ms = |time: Duration| format!("{:.2} ms", time.as_secs_f64() * 1000.0);
+        let rows = [
+            ("Active workers", stats.active_workers.to_string()),
+            ("Loading", stats.loading.to_string()),
+            ("Full images", stats.loaded.to_string()),
+            ("Thumbnails", stats.thumbnails.to_string()),
+            (
+                "Store memory",
+                format!("{:.0} MB", stats.memory as f64 / 1_000_000.0),
+            ),
+            (
+                "Memory",
+                resident_memory().map_or("n/a".into(), |bytes| {
+                    format!("{:.0} MB", bytes as f64 / 1_000_000.0)
+                }),
+            ),
+            (
+                "Frame",
+                self.frame_times.mean().map_or("n/a".into(), format_ms),
+            ),
+            (
+                "GPU frame",
+                self.gpu_times.mean().map_or("n/a".into(), format_ms),
+            ),
+            (
+                "Decodes",
+                format!("{:.1}/s", self.throughput(stats.decoded)),
+            ),
+            (
+                "Decode",
+                stats.current_decode_time.map_or("n/a".into(), format_ms),
+            ),
+            (
+                "Upload",
+                self.upload
+                    .map_or("n/a".into(), |(time, _)| format_ms(time)),
+            ),
+            (
+                "Since upload",
+                self.upload.map_or("n/a".into(), |(_, at)| {
+                    format!("{:.1} s", at.elapsed().as_secs_f64())
+                }),
+            ),
+            ("Adapter", self.adapter_description.clone()),
+        ];
+
+        self.renderer.begin_frame(window);
+        egui::Window::new("Debug")
+            .anchor(egui::Align2::RIGHT_TOP, [-8.0, 8.0])
+            .collapsible(false)
+            .resizable(false)
+            .interactable(false)
+            .show(self.renderer.context(), |ui| {
+                egui::Grid::new("debug_stats")
+                    .num_columns(2)
+                    .show(ui, |ui| {
+                        for (name, value) in rows {
+                            ui.label(name);
+                            ui.monospace(value);
+                            ui.end_row();
+                        }
+                    });
+            });
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Debug Overlay Encoder"),
+        });
+        self.renderer.end_frame_and_draw(
+            device,
+            queue,
+            &mut encoder,
+            window,
+            view,
+            screen_descriptor,
+        );
+        queue.submit(Some(encoder.finish()));
+    }
+}
+
+/// Resident memory of the process in bytes.
+#[cfg(target_os = "linux")]
+fn resident_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kilobytes = line.split_whitespace().nth(1)?.parse::<u64>().ok()?;
+    Some(kilobytes * 1024)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn resident_memory() -> Option<u64> {
+    None
+}
@@ -0,0 +1,76 @@
+use crate::image::{ImageData, ImflowImageBuffer};
+use std::f32::consts::PI;
+
+const HASH_INPUT_SIZE: usize = 32;
+const HASH_BLOCK_SIZE: usize = 8;
+pub const DEFAULT_DUPLICATE_THRESHOLD: u32 = 10;
+
+/// Averages the buffer down to a 32×32 greyscale image.
+fn downscale_grey(buf: &ImflowImageBuffer) -> [f32; HASH_INPUT_SIZE * HASH_INPUT_SIZE] {
+    let mut sums = [0.0; HASH_INPUT_SIZE * HASH_INPUT_SIZE];
+    let mut counts = [0u32; HASH_INPUT_SIZE * HASH_INPUT_SIZE];
+    for y in 0..buf.height {
+        let cell_y = y * HASH_INPUT_SIZE / buf.height;
+        for x in 0..buf.width {
+            let cell_x = x * HASH_INPUT_SIZE / buf.width;
+            let [r, g, b, _] = buf.rgba_buffer[y * buf.width + x].to_le_bytes();
+            let luma = 0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32;
+            sums[cell_y * HASH_INPUT_SIZE + cell_x] += luma;
+            counts[cell_y * HASH_INPUT_SIZE + cell_x] += 1;
+        }
+    }
+    for (sum, count) in sums.iter_mut().zip(counts) {
+        *sum /= count.max(1) as f32;
+    }
+    sums
+}
+
+/// 64-bit DCT-based perceptual hash. Visually similar images (re-encodes, resizes,
+/// small edits) produce hashes a small Hamming distance apart.
+pub fn compute_phash(buf: &ImflowImageBuffer) -> u64 {
+    let pixels = downscale_grey(buf);
+    let n = HASH_INPUT_SIZE as f32;
+
+    // Only the low-frequency 8×8 corner of the 2D DCT-II is needed
+    let mut coefficients = [0.0; HASH_BLOCK_SIZE * HASH_BLOCK_SIZE];
+    for v in 0..HASH_BLOCK_SIZE {
+        for u in 0..HASH_BLOCK_SIZE {
+            let mut sum = 0.0;
+            for y in 0..HASH_INPUT_SIZE {
+                let cos_y = ((2 * y + 1) as f32 * v as f32 * PI / (2.0 * n)).cos();
+                for x in 0..HASH_INPUT_SIZE {
+                    let cos_x = ((2 * x + 1) as f32 * u as f32 * PI / (2.0 * n)).cos();
+                    sum += pixels[y * HASH_INPUT_SIZE + x] * cos_x * cos_y;
+                }
+            }
+            coefficients[v * HASH_BLOCK_SIZE + u] = sum;
+        }
+    }
+
+    let mut sorted = coefficients;
+    sorted.sort_by(f32::total_cmp);
+    let median = (sorted[31] + sorted[32]) / 2.0;
+
+    coefficients
+        .iter()
+        .enumerate()
+        .filter(|(_, coefficient)| **coefficient > median)
+        .fold(0, |hash, (bit, _)| hash | 1 << bit)
+}
+
+pub fn phash_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Returns every pair of images whose hashes are less than `threshold` bits apart.
+pub fn duplicate_pairs(hashes: &[(ImageData, u64)], threshold: u32) -> Vec<(ImageData, ImageData)> {
+    let mut pairs = Vec::new();
+    for (i, (a, hash_a)) in hashes.iter().enumerate() {
+        for (b, hash_b) in &hashes[i + 1..] {
+            if phash_distance(*hash_a, *hash_b) < threshold {
+                pairs.push((a.clone(), b.clone()));
+            }
+        }
+    }
+    pairs
+}
@@ -1,19 +1,22 @@
-use egui::Context;
+use egui::{ColorImage, Context, TextureHandle, TextureOptions, ViewportId};
 use egui_wgpu::wgpu::{CommandEncoder, Device, Queue, StoreOp, TextureFormat, TextureView};
 use egui_wgpu::{Renderer, ScreenDescriptor, wgpu};
 use egui_winit::State;
+use std::time::Duration;
 use winit::event::WindowEvent;
-use winit::window::Window;
+use winit::window::{Theme, Window};
 
 pub struct EguiRenderer {
-    state: State,
+    context: Context,
+    /// Window integration, missing when rendering offscreen
+    state: Option<State>,
     renderer: Renderer,
     frame_started: bool,
 }
 
 impl EguiRenderer {
     pub fn context(&self) -> &Context {
-        self.state.egui_ctx()
+        &self.context
     }
 
     pub fn new(
@@ -21,18 +24,23 @@ impl EguiRenderer {
         output_color_format: TextureFormat,
         output_depth_format: Option<TextureFormat>,
         msaa_samples: u32,
-        window: &Window,
+        window: Option<&Window>,
     ) -> EguiRenderer {
         let egui_context = Context::default();
+        // Ctrl+= and Ctrl+- go to the app's own `ui_scale`, which would overwrite
+        // egui's zoom on the next frame anyway
+        egui_context.options_mut(|options| options.zoom_with_keyboard = false);
 
-        let egui_state = egui_winit::State::new(
-            egui_context,
-            egui::viewport::ViewportId::ROOT,
-            &window,
-            Some(window.scale_factor() as f32),
-            None,
-            Some(2 * 1024), // default dimension is 2048
-        );
+        let egui_state = window.map(|window| {
+            egui_winit::State::new(
+                egui_context.clone(),
+                ViewportId::ROOT,
+                &window,
+                Some(window.scale_factor() as f32),
+                None,
+                Some(2 * 1024), // default dimension is 2048
+            )
+        });
         let egui_renderer = Renderer::new(
             device,
             output_color_format,
@@ -42,14 +50,38 @@ impl EguiRenderer {
         );
 
         EguiRenderer {
+            context: egui_context,
             state: egui_state,
             renderer: egui_renderer,
             frame_started: false,
         }
     }
 
+    /// Uploads an RGBA8 image for use in egui widgets. The texture is freed once the
+    /// last handle to it is dropped.
+    pub fn load_texture(&self, name: &str, size: [usize; 2], rgba: &[u8]) -> TextureHandle {
+        self.context().load_texture(
+            name,
+            ColorImage::from_rgba_unmultiplied(size, rgba),
+            TextureOptions::LINEAR,
+        )
+    }
+
     pub fn handle_input(&mut self, window: &Window, event: &WindowEvent) {
-        let _ = self.state.on_window_event(window, event);
+        if let Some(state) = &mut self.state {
+            let _ = state.on_window_event(window, event);
+        }
+    }
+
+    /// Follows the system light or dark theme, which egui would otherwise only pick up
+    /// on the next frame.
+    pub fn set_theme(&self, theme: Theme) {
+        let (theme, visuals) = match theme {
+            Theme::Dark => (egui::Theme::Dark, egui::Visuals::dark()),
+            Theme::Light => (egui::Theme::Light, egui::Visuals::light()),
+        };
+        self.context().set_theme(theme);
+        self.context().set_visuals(visuals);
     }
 
     pub fn ppp(&mut self, v: f32) {
@@ -57,11 +89,25 @@ impl EguiRenderer {
     }
 
     pub fn begin_frame(&mut self, window: &Window) {
-        let raw_input = self.state.take_egui_input(window);
-        self.state.egui_ctx().begin_pass(raw_input);
+        let raw_input = match &mut self.state {
+            Some(state) => state.take_egui_input(window),
+            // Without the integration nothing tells egui how large the window is
+            None => {
+                let size = window.inner_size();
+                let size = egui::vec2(size.width as f32, size.height as f32)
+                    / window.scale_factor() as f32;
+                egui::RawInput {
+                    screen_rect: Some(egui::Rect::from_min_size(egui::Pos2::ZERO, size)),
+                    ..Default::default()
+                }
+            }
+        };
+        self.context.begin_pass(raw_input);
         self.frame_started = true;
     }
 
+    /// Draws the frame begun with `begin_frame` and returns how soon egui wants the
+    /// next one, `Duration::MAX` when only new input would change it.
     pub fn end_frame_and_draw(
         &mut self,
         device: &Device,
@@ -70,22 +116,26 @@ impl EguiRenderer {
         window: &Window,
         window_surface_view: &TextureView,
         screen_descriptor: ScreenDescriptor,
-    ) {
+    ) -> Duration {
         if !self.frame_started {
             panic!("begin_frame must be called before end_frame_and_draw can be called!");
         }
 
         self.ppp(screen_descriptor.pixels_per_point);
 
-        let full_output = self.state.egui_ctx().end_pass();
+        let full_output = self.context.end_pass();
+        let repaint_delay = full_output
+            .viewport_output
+            .get(&ViewportId::ROOT)
+            .map_or(Duration::MAX, |viewport| viewport.repaint_delay);
 
-        self.state
-            .handle_platform_output(window, full_output.platform_output);
+        if let Some(state) = &mut self.state {
+            state.handle_platform_output(window, full_output.platform_output);
+        }
 
         let tris = self
-            .state
-            .egui_ctx()
-            .tessellate(full_output.shapes, self.state.egui_ctx().pixels_per_point());
+            .context
+            .tessellate(full_output.shapes, self.context.pixels_per_point());
         for (id, image_delta) in &full_output.textures_delta.set {
             self.renderer
                 .update_texture(device, queue, *id, image_delta);
@@ -114,5 +164,6 @@ impl EguiRenderer {
         }
 
         self.frame_started = false;
+        repaint_delay
     }
 }
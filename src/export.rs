@@ -0,0 +1,295 @@
+use crate::image::{
+    ImageData, ImflowImageBuffer, MetadataCache, darktable_sidecar_path, load_available_images,
+    load_thumbnail, missing_placeholder,
+};
+use ab_glyph::{Font, FontRef, PxScale, ScaleFont, point};
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, ImageResult, Rgba, RgbaImage};
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const CONTACT_SHEET_CELL_WIDTH: u32 = 256;
+pub const CONTACT_SHEET_CELL_HEIGHT: u32 = 224;
+// Height of the file name strip at the bottom of each cell, in pixels
+const LABEL_HEIGHT: u32 = 24;
+const LABEL_SIZE: f32 = 14.0;
+const STAR_SIZE: f32 = 20.0;
+// Space between a thumbnail and the edge of its cell
+const CELL_PADDING: u32 = 6;
+const BACKGROUND: Rgba<u8> = Rgba([24, 24, 24, 255]);
+const LABEL_COLOR: Rgba<u8> = Rgba([220, 220, 220, 255]);
+const STAR_COLOR: Rgba<u8> = Rgba([255, 200, 40, 255]);
+const SHADOW_COLOR: Rgba<u8> = Rgba([0, 0, 0, 160]);
+
+/// The fonts egui ships with, in fallback order. The first one with a glyph for a
+/// character draws it, so the stars come from the emoji fonts.
+fn fonts() -> Vec<FontRef<'static>> {
+    [
+        epaint_default_fonts::UBUNTU_LIGHT,
+        epaint_default_fonts::HACK_REGULAR,
+        epaint_default_fonts::NOTO_EMOJI_REGULAR,
+        epaint_default_fonts::EMOJI_ICON,
+    ]
+    .into_iter()
+    .filter_map(|data| FontRef::try_from_slice(data).ok())
+    .collect()
+}
+
+fn font_for<'a>(fonts: &'a [FontRef<'static>], c: char) -> Option<&'a FontRef<'static>> {
+    fonts
+        .iter()
+        .find(|font| font.glyph_id(c).0 != 0)
+        .or(fonts.first())
+}
+
+fn text_width(fonts: &[FontRef<'static>], text: &str, size: f32) -> f32 {
+    text.chars()
+        .filter_map(|c| {
+            let font = font_for(fonts, c)?.as_scaled(PxScale::from(size));
+            Some(font.h_advance(font.glyph_id(c)))
+        })
+        .sum()
+}
+
+/// Blends `text` onto `canvas` with its baseline starting at `(x, baseline)`.
+fn draw_text(
+    canvas: &mut RgbaImage,
+    fonts: &[FontRef<'static>],
+    text: &str,
+    (mut x, baseline): (f32, f32),
+    size: f32,
+    color: Rgba<u8>,
+) {
+    for c in text.chars() {
+        let Some(font) = font_for(fonts, c) else {
+            return;
+        };
+        let scaled = font.as_scaled(PxScale::from(size));
+        let glyph = scaled
+            .glyph_id(c)
+            .with_scale_and_position(size, point(x, baseline));
+        x += scaled.h_advance(glyph.id);
+        let Some(outline) = font.outline_glyph(glyph) else {
+            continue;
+        };
+        let bounds = outline.px_bounds();
+        outline.draw(|glyph_x, glyph_y, coverage| {
+            let px = bounds.min.x as i64 + glyph_x as i64;
+            let py = bounds.min.y as i64 + glyph_y as i64;
+            if px < 0 || py < 0 || px >= canvas.width() as i64 || py >= canvas.height() as i64 {
+                return;
+            }
+            let alpha = coverage.clamp(0.0, 1.0) * color[3] as f32 / 255.0;
+            let pixel = canvas.get_pixel_mut(px as u32, py as u32);
+            for channel in 0..3 {
+                pixel[channel] = (pixel[channel] as f32 * (1.0 - alpha)
+                    + color[channel] as f32 * alpha)
+                    .round() as u8;
+            }
+        });
+    }
+}
+
+/// Shortens `text` with an ellipsis until it is at most `max_width` pixels wide.
+fn fit_text(fonts: &[FontRef<'static>], text: &str, size: f32, max_width: f32) -> String {
+    if text_width(fonts, text, size) <= max_width {
+        return text.to_string();
+    }
+    let mut chars = text.chars().collect::<Vec<_>>();
+    while !chars.is_empty() {
+        chars.pop();
+        let shortened = format!("{}…", chars.iter().collect::<String>());
+        if text_width(fonts, &shortened, size) <= max_width {
+            return shortened;
+        }
+    }
+    String::new()
+}
+
+fn to_rgba_image(imbuf: &ImflowImageBuffer) -> RgbaImage {
+    // Each u32 holds the bytes of one pixel in memory order
+    let bytes = bytemuck::cast_slice(&imbuf.rgba_buffer).to_vec();
+    RgbaImage::from_raw(imbuf.width as u32, imbuf.height as u32, bytes)
+        .expect("buffer size matches the image size")
+}
+
+/// Writes `imbuf` to `path`, whose extension picks the format.
+pub fn save_image_buffer(imbuf: &ImflowImageBuffer, path: &Path) -> ImageResult<()> {
+    to_rgba_image(imbuf).save(path)
+}
+
+/// Tiles `images` into a grid `cols` wide, each fitted into a `cell_width` ×
+/// `cell_height` cell with its rating drawn as stars in the top left. With `names`,
+/// the matching name is written along the bottom of each cell.
+pub fn generate_contact_sheet(
+    images: &[&ImflowImageBuffer],
+    names: Option<&[String]>,
+    cols: usize,
+    cell_width: u32,
+    cell_height: u32,
+) -> DynamicImage {
+    let cols = cols.clamp(1, images.len().max(1));
+    let rows = images.len().div_ceil(cols).max(1);
+    let mut sheet = RgbaImage::from_pixel(
+        cell_width * cols as u32,
+        cell_height * rows as u32,
+        BACKGROUND,
+    );
+    let fonts = fonts();
+    let label_height = if names.is_some() { LABEL_HEIGHT } else { 0 };
+    let max_width = cell_width.saturating_sub(2 * CELL_PADDING).max(1);
+    let max_height = cell_height
+        .saturating_sub(2 * CELL_PADDING + label_height)
+        .max(1);
+
+    for (index, imbuf) in images.iter().enumerate() {
+        let cell_x = (index % cols) as u32 * cell_width;
+        let cell_y = (index / cols) as u32 * cell_height;
+        if imbuf.width > 0 && imbuf.height > 0 {
+            let scale = (max_width as f32 / imbuf.width as f32)
+                .min(max_height as f32 / imbuf.height as f32);
+            let width = ((imbuf.width as f32 * scale).round() as u32).max(1);
+            let height = ((imbuf.height as f32 * scale).round() as u32).max(1);
+            let thumbnail =
+                imageops::resize(&to_rgba_image(imbuf), width, height, FilterType::Triangle);
+            imageops::overlay(
+                &mut sheet,
+                &thumbnail,
+                (cell_x + (cell_width - width) / 2) as i64,
+                (cell_y + CELL_PADDING + (max_height - height) / 2) as i64,
+            );
+        }
+        if imbuf.rating > 0 {
+            let stars = "★".repeat(imbuf.rating.min(5) as usize);
+            let origin = (
+                (cell_x + CELL_PADDING + 4) as f32,
+                (cell_y + CELL_PADDING) as f32 + STAR_SIZE,
+            );
+            // A drop shadow keeps the stars readable on bright thumbnails
+            draw_text(
+                &mut sheet,
+                &fonts,
+                &stars,
+                (origin.0 + 1.0, origin.1 + 1.0),
+                STAR_SIZE,
+                SHADOW_COLOR,
+            );
+            draw_text(&mut sheet, &fonts, &stars, origin, STAR_SIZE, STAR_COLOR);
+        }
+        if let Some(name) = names.and_then(|names| names.get(index)) {
+            let label = fit_text(&fonts, name, LABEL_SIZE, max_width as f32);
+            let width = text_width(&fonts, &label, LABEL_SIZE);
+            draw_text(
+                &mut sheet,
+                &fonts,
+                &label,
+                (
+                    cell_x as f32 + (cell_width as f32 - width) / 2.0,
+                    (cell_y + cell_height) as f32 - (label_height as f32 - LABEL_SIZE) / 2.0 - 2.0,
+                ),
+                LABEL_SIZE,
+                LABEL_COLOR,
+            );
+        }
+    }
+    DynamicImage::ImageRgba8(sheet)
+}
+
+/// Writes a contact sheet of the images in `dir` to `output`, whose extension picks
+/// the format. With `rows`, only the first `columns` × `rows` images are included.
+/// Returns the number of images on the sheet.
+pub fn export_contact_sheet(
+    dir: PathBuf,
+    output: &Path,
+    columns: usize,
+    rows: Option<usize>,
+    labels: bool,
+) -> ImageResult<usize> {
+    let limit = rows.map_or(usize::MAX, |rows| rows * columns);
+    let images = load_available_images(dir)
+        .into_iter()
+        .take(limit)
+        .collect::<Vec<_>>();
+    let metadata_cache = MetadataCache::default();
+    // Files that don't decode keep their place on the sheet
+    let thumbnails = images
+        .iter()
+        .map(|image| {
+            load_thumbnail(image, &metadata_cache).unwrap_or_else(|_| missing_placeholder())
+        })
+        .collect::<Vec<_>>();
+    let names = images
+        .iter()
+        .map(|image| {
+            image
+                .path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string()
+        })
+        .collect::<Vec<_>>();
+    let sheet = generate_contact_sheet(
+        &thumbnails.iter().collect::<Vec<_>>(),
+        labels.then_some(names.as_slice()),
+        columns,
+        CONTACT_SHEET_CELL_WIDTH,
+        CONTACT_SHEET_CELL_HEIGHT,
+    );
+    // The sheet is opaque, and JPEG can't store an alpha channel
+    let sheet = sheet.to_rgb8();
+    image::save_buffer(
+        output,
+        &sheet,
+        sheet.width(),
+        sheet.height(),
+        image::ExtendedColorType::Rgb8,
+    )?;
+    Ok(thumbnails.len())
+}
+
+/// Copies `src` into `target_dir`, creating the folder if needed, and returns the
+/// path of the copy. XMP sidecars (`IMG_1234.xmp` and Darktable's `IMG_1234.CR2.xmp`)
+/// are copied along under the matching name. A file already there is never
+/// overwritten: the copy and its sidecars get a `-1`, `-2`, … suffix until none of
+/// their names is taken.
+pub fn copy_image_to_target(src: &ImageData, target_dir: &Path) -> io::Result<PathBuf> {
+    fs::create_dir_all(target_dir)?;
+    let file_name = src
+        .path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "the image has no file name"))?;
+    let stem = src.path.file_stem().unwrap_or(file_name);
+    let extension = src.path.extension();
+    let sidecar = Some(src.path.with_extension("xmp")).filter(|sidecar| sidecar.is_file());
+    let darktable_sidecar =
+        Some(darktable_sidecar_path(&src.path)).filter(|sidecar| sidecar.is_file());
+    let taken = |target: &Path| {
+        target.exists()
+            || (sidecar.is_some() && target.with_extension("xmp").exists())
+            || (darktable_sidecar.is_some() && darktable_sidecar_path(target).exists())
+    };
+    let mut target = target_dir.join(file_name);
+    let mut suffix = 1;
+    while taken(&target) {
+        let mut name = OsString::from(stem);
+        name.push(format!("-{}", suffix));
+        if let Some(extension) = extension {
+            name.push(".");
+            name.push(extension);
+        }
+        target = target_dir.join(name);
+        suffix += 1;
+    }
+    fs::copy(&src.path, &target)?;
+
+    if let Some(sidecar) = sidecar {
+        fs::copy(&sidecar, target.with_extension("xmp"))?;
+    }
+    if let Some(sidecar) = darktable_sidecar {
+        fs::copy(&sidecar, darktable_sidecar_path(&target))?;
+    }
+    Ok(target)
+}
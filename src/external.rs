@@ -0,0 +1,82 @@
+use std::io;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Starts `command` with `{path}` replaced by `path`, or with `path` appended if the
+/// placeholder is missing. Arguments are split on whitespace before the substitution,
+/// so paths with spaces stay one argument.
+pub fn open_in_editor(command: &str, path: &Path) -> io::Result<()> {
+    let path = path.to_string_lossy();
+    let mut args = command
+        .split_whitespace()
+        .map(|arg| arg.replace("{path}", &path))
+        .collect::<Vec<_>>();
+    if !command.contains("{path}") {
+        args.push(path.to_string());
+    }
+    if args.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the editor command is empty",
+        ));
+    }
+    spawn_detached(Command::new(&args[0]).args(&args[1..]))
+}
+
+/// Shows `path` selected in the system file manager.
+pub fn reveal_in_file_manager(path: &Path) -> io::Result<()> {
+    #[cfg(target_os = "macos")]
+    return spawn_detached(Command::new("open").arg("-R").arg(path));
+
+    #[cfg(target_os = "windows")]
+    return spawn_detached(Command::new("explorer").arg(format!("/select,{}", path.display())));
+
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    {
+        // The FileManager1 interface selects the file, but not every desktop has it
+        let shown = Command::new("dbus-send")
+            .args([
+                "--session",
+                "--dest=org.freedesktop.FileManager1",
+                "--type=method_call",
+                "/org/freedesktop/FileManager1",
+                "org.freedesktop.FileManager1.ShowItems",
+            ])
+            .arg(format!("array:string:{}", file_uri(path)))
+            .arg("string:")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success());
+        if shown {
+            return Ok(());
+        }
+        let parent = path.parent().unwrap_or(Path::new("."));
+        spawn_detached(Command::new("xdg-open").arg(parent))
+    }
+}
+
+fn spawn_detached(command: &mut Command) -> io::Result<()> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    // Reaps the process whenever it exits, without holding up the viewer
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+fn file_uri(path: &Path) -> String {
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    let mut uri = String::from("file://");
+    for byte in path.to_string_lossy().bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-_.~".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            uri.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    uri
+}
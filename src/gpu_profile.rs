@@ -0,0 +1,169 @@
+use egui_wgpu::wgpu;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+// Frames summarized in each printed report
+const REPORT_FRAMES: usize = 100;
+
+/// Features the profiler needs, requested only when `--gpu-profile` is passed.
+pub const GPU_PROFILE_FEATURES: wgpu::Features =
+    wgpu::Features::TIMESTAMP_QUERY.union(wgpu::Features::TIMESTAMP_QUERY_INSIDE_ENCODERS);
+
+/// A frame whose timestamps are being copied back from the GPU.
+struct PendingFrame {
+    /// Set by the `map_async` callback
+    mapped: Arc<OnceLock<Result<(), wgpu::BufferAsyncError>>>,
+    cpu_time: Duration,
+}
+
+/// Times the image passes on the GPU with a pair of timestamp queries and prints
+/// min/max/mean of GPU and CPU frame times every `REPORT_FRAMES` frames.
+///
+/// The readback is asynchronous: one frame is measured at a time, and frames drawn
+/// while its timestamps are still on their way back go unmeasured.
+pub struct GpuProfiler {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    /// Nanoseconds per timestamp tick
+    period: f32,
+    /// Whether timestamps were written into the encoder of the frame being drawn
+    recording: bool,
+    pending: Option<PendingFrame>,
+    /// `(gpu, cpu)` frame times in microseconds since the last report
+    samples: Vec<(f64, f64)>,
+    /// GPU time of the latest measured frame
+    last_gpu_time: Option<Duration>,
+    /// Whether a failed readback was reported, so it isn't again every frame
+    map_error_logged: bool,
+}
+
+impl GpuProfiler {
+    /// Returns `None` when the device was created without `GPU_PROFILE_FEATURES`.
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Self> {
+        if !device.features().contains(GPU_PROFILE_FEATURES) {
+            return None;
+        }
+        let size = 2 * std::mem::size_of::<u64>() as u64;
+        Some(GpuProfiler {
+            query_set: device.create_query_set(&wgpu::QuerySetDescriptor {
+                label: Some("Frame Timestamps"),
+                ty: wgpu::QueryType::Timestamp,
+                count: 2,
+            }),
+            resolve_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Timestamp Resolve Buffer"),
+                size,
+                usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            }),
+            readback_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Timestamp Readback Buffer"),
+                size,
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            period: queue.get_timestamp_period(),
+            recording: false,
+            pending: None,
+            samples: Vec::with_capacity(REPORT_FRAMES),
+            last_gpu_time: None,
+            map_error_logged: false,
+        })
+    }
+
+    /// Marks the start of the measured work, unless a readback is still in flight.
+    pub fn begin(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        self.recording = self.pending.is_none();
+        if self.recording {
+            encoder.write_timestamp(&self.query_set, 0);
+        }
+    }
+
+    /// Marks the end of the measured work and queues the copy of both timestamps.
+    pub fn end(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        if !self.recording {
+            return;
+        }
+        encoder.write_timestamp(&self.query_set, 1);
+        encoder.resolve_query_set(&self.query_set, 0..2, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(
+            &self.resolve_buffer,
+            0,
+            &self.readback_buffer,
+            0,
+            self.resolve_buffer.size(),
+        );
+    }
+
+    /// Starts reading back the timestamps once the frame was submitted. `cpu_time`
+    /// is how long the frame took to prepare on the CPU.
+    pub fn submitted(&mut self, cpu_time: Duration) {
+        if !std::mem::take(&mut self.recording) {
+            return;
+        }
+        let mapped = Arc::new(OnceLock::new());
+        let result_slot = mapped.clone();
+        self.readback_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let _ = result_slot.set(result);
+            });
+        self.pending = Some(PendingFrame { mapped, cpu_time });
+    }
+
+    /// Collects the timestamps of the last measured frame if they have arrived,
+    /// without waiting for the GPU.
+    pub fn poll(&mut self, device: &wgpu::Device) {
+        let Some(pending) = &self.pending else {
+            return;
+        };
+        device.poll(wgpu::Maintain::Poll);
+        let Some(result) = pending.mapped.get() else {
+            return;
+        };
+        let cpu_time = pending.cpu_time;
+        // A failed map is dropped, so the next frame is measured again
+        if let Err(e) = result {
+            if !std::mem::replace(&mut self.map_error_logged, true) {
+                eprintln!("GPU profiling: reading back timestamps failed: {}", e);
+            }
+            self.pending = None;
+            return;
+        }
+        self.pending = None;
+        let timestamps: [u64; 2] = {
+            let range = self.readback_buffer.slice(..).get_mapped_range();
+            bytemuck::pod_read_unaligned(&range)
+        };
+        self.readback_buffer.unmap();
+        let ticks = timestamps[1].saturating_sub(timestamps[0]);
+        let gpu_us = ticks as f64 * self.period as f64 / 1000.0;
+        self.last_gpu_time = Some(Duration::from_secs_f64(gpu_us / 1_000_000.0));
+        self.samples
+            .push((gpu_us, cpu_time.as_secs_f64() * 1_000_000.0));
+        if self.samples.len() >= REPORT_FRAMES {
+            self.report();
+        }
+    }
+
+    pub fn last_gpu_time(&self) -> Option<Duration> {
+        self.last_gpu_time
+    }
+
+    fn report(&mut self) {
+        let summarize = |times: Vec<f64>| {
+            let min = times.iter().copied().fold(f64::INFINITY, f64::min);
+            let max = times.iter().copied().fold(0.0, f64::max);
+            let mean = times.iter().sum::<f64>() / times.len() as f64;
+            format!("min {:.0} µs, max {:.0} µs, mean {:.0} µs", min, max, mean)
+        };
+        let (gpu, cpu): (Vec<f64>, Vec<f64>) = self.samples.drain(..).unzip();
+        println!(
+            "GPU frame time over {} frames: {}",
+            gpu.len(),
+            summarize(gpu)
+        );
+        println!("CPU frame time: {}", summarize(cpu));
+    }
+}
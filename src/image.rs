@@ -1,37 +1,105 @@
+use chrono::NaiveDate;
+use chrono::NaiveDateTime;
+use chrono::TimeDelta;
 use image::DynamicImage;
-use image::RgbaImage;
+use image::imageops;
 use image::imageops::FilterType;
 use image::metadata::Orientation;
 use itertools::Itertools;
+#[cfg(feature = "jxl")]
 use jpegxl_rs::Endianness;
+#[cfg(feature = "jxl")]
 use jpegxl_rs::decode::PixelFormat;
+#[cfg(feature = "jxl")]
 use jpegxl_rs::decoder_builder;
-use libheif_rs::{HeifContext, LibHeif, RgbChroma};
+#[cfg(feature = "jxl")]
+use jpegxl_sys::common::types::{JxlBool, JxlDataType, JxlEndianness, JxlPixelFormat};
+#[cfg(feature = "jxl")]
+use jpegxl_sys::decode::*;
+#[cfg(feature = "jxl")]
+use jpegxl_sys::metadata::codestream_header::JxlPreviewHeader;
+#[cfg(feature = "heif")]
+use libheif_rs::{
+    HeifContext, Image, ImageHandle, ItemId, LibHeif, RgbChroma, TransferCharacteristics,
+};
+use memmap2::Mmap;
+use rayon::prelude::*;
 use rexiv2::Metadata;
+use serde::{Deserialize, Serialize};
 use zune_image::codecs::jpeg::JpegDecoder;
 use zune_image::codecs::qoi::zune_core::colorspace::ColorSpace;
 use zune_image::codecs::qoi::zune_core::options::DecoderOptions;
 
+use std::collections::HashMap;
+#[cfg(feature = "jxl")]
+use std::ffi::c_int;
+use std::fmt;
 use std::fs;
 use std::fs::File;
-use std::fs::read;
+use std::io;
 use std::io::BufReader;
 use std::io::Cursor;
-use std::mem;
+use std::io::Read;
+#[cfg(feature = "jxl")]
+use std::mem::MaybeUninit;
+use std::ops::Deref;
+#[cfg(any(feature = "heif", feature = "jxl"))]
+use std::path::Path;
 use std::path::PathBuf;
-use std::time::Instant;
+#[cfg(feature = "jxl")]
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Mutex, Once, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
+
+// Files are read in pieces of this size so progressive JPEGs can be shown early
+const READ_CHUNK_SIZE: usize = 256 * 1024;
+const PARTIAL_DECODE_INTERVAL: Duration = Duration::from_millis(100);
+const NO_RAW_PREVIEW: &str = "the RAW file has no embedded preview";
 
 #[derive(Clone, Eq, Hash, PartialEq, PartialOrd)]
 pub enum ImageFormat {
     Jpg,
     Jxl,
     Heif,
+    Raw,
+    Exr,
+    Psd,
+}
+
+// Scale applied to the linear values of EXR images before they are clipped to the
+// displayable range
+const EXR_EXPOSURE: f32 = 0.5;
+
+// Resource in a PSD file holding the thumbnail of Photoshop 5.0 and later
+const PSD_THUMBNAIL_RESOURCE: u16 = 1036;
+
+const RAW_EXTENSIONS: [&str; 10] = [
+    "cr2", "cr3", "nef", "arw", "dng", "raf", "orf", "rw2", "pef", "srw",
+];
+
+impl ImageFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ImageFormat::Jpg => "JPEG",
+            ImageFormat::Jxl => "JXL",
+            ImageFormat::Heif => "HEIF",
+            ImageFormat::Raw => "RAW",
+            ImageFormat::Exr => "EXR",
+            ImageFormat::Psd => "PSD",
+        }
+    }
 }
 
 #[derive(Clone, Eq, Hash, PartialEq, PartialOrd)]
 pub struct ImageData {
     pub path: PathBuf,
     pub format: ImageFormat,
+    /// JXL files only: the codestream carries a smaller preview frame
+    pub has_preview: bool,
+    /// Day the photo was taken according to its EXIF data, read when the directory
+    /// is listed
+    pub capture_date: Option<NaiveDate>,
 }
 
 pub struct ImflowImageBuffer {
@@ -39,25 +107,695 @@ pub struct ImflowImageBuffer {
     pub height: usize,
     pub rgba_buffer: Vec<u32>,
     pub rating: i32,
+    pub hdr: bool,
+}
+
+/// Why an image or thumbnail couldn't be loaded.
+#[derive(Debug)]
+pub enum LoadError {
+    /// The file couldn't be read, usually because it is gone
+    Read(io::Error),
+    /// The file is there, but isn't an image the decoder understands
+    Decode(String),
+}
+
+impl LoadError {
+    fn decode(error: impl fmt::Display) -> Self {
+        LoadError::Decode(error.to_string())
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(error: io::Error) -> Self {
+        LoadError::Read(error)
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Read(error) => write!(f, "{}", error),
+            LoadError::Decode(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl ImflowImageBuffer {
+    /// A copy that fits within `max_width` × `max_height`, keeping the aspect ratio.
+    /// Each output pixel is the average of the block of pixels it covers. Images that
+    /// already fit are copied as they are.
+    pub fn downsample(&self, max_width: u32, max_height: u32) -> ImflowImageBuffer {
+        let scale = if self.width == 0 || self.height == 0 {
+            1.0
+        } else {
+            (max_width as f64 / self.width as f64)
+                .min(max_height as f64 / self.height as f64)
+                .min(1.0)
+        };
+        let width = ((self.width as f64 * scale).round() as usize).clamp(1, self.width.max(1));
+        let height = ((self.height as f64 * scale).round() as usize).clamp(1, self.height.max(1));
+        let mut rgba_buffer = vec![0; width * height];
+        if self.width > 0 && self.height > 0 {
+            rgba_buffer
+                .par_chunks_mut(width)
+                .enumerate()
+                .for_each(|(y, row)| {
+                    let rows = y * self.height / height..(y + 1) * self.height / height;
+                    for (x, pixel) in row.iter_mut().enumerate() {
+                        let columns = x * self.width / width..(x + 1) * self.width / width;
+                        let mut sum = [0u32; 4];
+                        for source_y in rows.clone() {
+                            let start = source_y * self.width;
+                            for source in
+                                &self.rgba_buffer[start + columns.start..start + columns.end]
+                            {
+                                for (sum, channel) in sum.iter_mut().zip(source.to_ne_bytes()) {
+                                    *sum += channel as u32;
+                                }
+                            }
+                        }
+                        let count = (rows.len() * columns.len()) as u32;
+                        *pixel = u32::from_ne_bytes(sum.map(|sum| (sum / count) as u8));
+                    }
+                });
+        }
+        ImflowImageBuffer {
+            width,
+            height,
+            rgba_buffer,
+            rating: self.rating,
+            hdr: self.hdr,
+        }
+    }
+}
+
+/// Pixel counts for each 8-bit value of the red, green and blue channels.
+pub struct Histogram {
+    pub channels: [[u32; 256]; 3],
+}
+
+impl Histogram {
+    pub fn from_rgba(rgba_buffer: &[u32]) -> Self {
+        let mut channels = [[0; 256]; 3];
+        for pixel in bytemuck::cast_slice::<u32, [u8; 4]>(rgba_buffer) {
+            for (channel, &value) in channels.iter_mut().zip(pixel) {
+                channel[value as usize] += 1;
+            }
+        }
+        Histogram { channels }
+    }
+
+    /// The counts scaled so the tallest bin of any channel is 1.
+    pub fn normalized(&self) -> [[f32; 256]; 3] {
+        let max = self
+            .channels
+            .iter()
+            .flatten()
+            .copied()
+            .max()
+            .unwrap_or(0)
+            .max(1) as f32;
+        self.channels
+            .map(|channel| channel.map(|count| count as f32 / max))
+    }
+}
+
+/// A grey checkerboard shown in place of an image whose file has gone missing or
+/// has nothing that can be decoded.
+pub fn missing_placeholder() -> ImflowImageBuffer {
+    let size = 64;
+    let rgba_buffer = (0..size * size)
+        .map(|i| {
+            let (x, y) = (i % size, i / size);
+            let value: u8 = if (x / 8 + y / 8) % 2 == 0 { 0x40 } else { 0x60 };
+            u32::from_le_bytes([value, value, value, 0xff])
+        })
+        .collect();
+    ImflowImageBuffer {
+        width: size,
+        height: size,
+        rgba_buffer,
+        rating: 0,
+        hdr: false,
+    }
+}
+
+pub fn get_rating(image: &ImageData, metadata_cache: &MetadataCache) -> i32 {
+    metadata_cache.get(image).rating.unwrap_or(0)
+}
+
+pub fn write_rating(image: &ImageData, rating: i32) -> Result<(), rexiv2::Rexiv2Error> {
+    let meta = Metadata::new_from_path(&image.path)?;
+    meta.set_tag_numeric("Xmp.xmp.Rating", rating)?;
+    meta.save_to_file(&image.path)
+}
+
+/// Writes the rating into the XMP sidecar next to `image` (`IMG_1234.xmp`), creating
+/// it if needed, so the original file is left untouched.
+pub fn write_sidecar_rating(image: &ImageData, rating: i32) -> Result<(), rexiv2::Rexiv2Error> {
+    let sidecar = image.path.with_extension("xmp");
+    if !sidecar.exists() {
+        fs::write(&sidecar, EMPTY_XMP_PACKET)
+            .map_err(|e| rexiv2::Rexiv2Error::Internal(Some(e.to_string())))?;
+    }
+    write_xmp_rating(&sidecar, rating)
+}
+
+fn write_xmp_rating(path: &Path, rating: i32) -> Result<(), rexiv2::Rexiv2Error> {
+    let meta = Metadata::new_from_path(path)?;
+    meta.set_tag_numeric("Xmp.xmp.Rating", rating)?;
+    meta.save_to_file(path)
+}
+
+static DARKTABLE_COMPAT: AtomicBool = AtomicBool::new(false);
+
+/// Reads ratings and colour labels from Darktable's sidecars (`IMG_1234.CR2.xmp`)
+/// and writes ratings back into them, for images that have one.
+pub fn set_darktable_compat(enabled: bool) {
+    if enabled {
+        register_darktable_namespace();
+    }
+    DARKTABLE_COMPAT.store(enabled, Ordering::Relaxed);
+}
+
+/// Lets exiv2 read the `darktable:` tags and keep them when it rewrites a sidecar.
+fn register_darktable_namespace() {
+    static REGISTER: Once = Once::new();
+    REGISTER.call_once(|| {
+        if let Err(e) = rexiv2::register_xmp_namespace("http://darktable.sf.net/", "darktable") {
+            eprintln!("Failed to register the darktable XMP namespace: {}", e);
+        }
+    });
+}
+
+/// The Darktable sidecar of `image`, if Darktable compatibility is on and the
+/// sidecar exists.
+pub fn darktable_sidecar(image: &ImageData) -> Option<PathBuf> {
+    if !DARKTABLE_COMPAT.load(Ordering::Relaxed) {
+        return None;
+    }
+    Some(darktable_sidecar_path(&image.path)).filter(|sidecar| sidecar.is_file())
+}
+
+/// Darktable names sidecars after the whole file name, extension included.
+pub fn darktable_sidecar_path(path: &Path) -> PathBuf {
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(".xmp");
+    PathBuf::from(sidecar)
+}
+
+/// Writes the rating into the Darktable sidecar of `image`, see `darktable_sidecar`.
+/// The rest of the sidecar, including Darktable's history, is left as it is.
+pub fn write_darktable_rating(sidecar: &Path, rating: i32) -> Result<(), rexiv2::Rexiv2Error> {
+    register_darktable_namespace();
+    write_xmp_rating(sidecar, rating)
+}
+
+/// The colour labels Darktable and Lightroom share.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorLabel {
+    Red,
+    Yellow,
+    Green,
+    Blue,
+    Purple,
+}
+
+impl ColorLabel {
+    pub const ALL: [ColorLabel; 5] = [
+        ColorLabel::Red,
+        ColorLabel::Yellow,
+        ColorLabel::Green,
+        ColorLabel::Blue,
+        ColorLabel::Purple,
+    ];
+
+    /// Maps the values Darktable stores in `Xmp.darktable.colorlabels`.
+    pub fn from_darktable(value: i32) -> Option<Self> {
+        match value {
+            0 => Some(ColorLabel::Red),
+            1 => Some(ColorLabel::Yellow),
+            2 => Some(ColorLabel::Green),
+            3 => Some(ColorLabel::Blue),
+            4 => Some(ColorLabel::Purple),
+            _ => None,
+        }
+    }
+
+    /// The name as written to `Xmp.xmp.Label`.
+    pub fn name(self) -> &'static str {
+        match self {
+            ColorLabel::Red => "Red",
+            ColorLabel::Yellow => "Yellow",
+            ColorLabel::Green => "Green",
+            ColorLabel::Blue => "Blue",
+            ColorLabel::Purple => "Purple",
+        }
+    }
+
+    /// The label named `name` in `Xmp.xmp.Label`, ignoring case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|label| label.name().eq_ignore_ascii_case(name.trim()))
+    }
+}
+
+/// The first colour label Darktable set on `image`, read from its Darktable sidecar
+/// or, failing that, from the file itself.
+pub fn get_darktable_colorlabel(image: &ImageData) -> Option<ColorLabel> {
+    register_darktable_namespace();
+    let sidecar = darktable_sidecar_path(&image.path);
+    let path = if sidecar.is_file() {
+        sidecar.as_path()
+    } else {
+        image.path.as_path()
+    };
+    darktable_colorlabel(&Metadata::new_from_path(path).ok()?)
+}
+
+fn darktable_colorlabel(meta: &Metadata) -> Option<ColorLabel> {
+    meta.get_tag_multiple_strings("Xmp.darktable.colorlabels")
+        .ok()?
+        .iter()
+        .find_map(|value| ColorLabel::from_darktable(value.trim().parse().ok()?))
+}
+
+const EMPTY_XMP_PACKET: &str = concat!(
+    "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n",
+    "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n",
+    "<rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\"/>\n",
+    "</x:xmpmeta>\n",
+    "<?xpacket end=\"w\"?>\n",
+);
+
+pub fn write_label(image: &ImageData, label: &str) -> Result<(), rexiv2::Rexiv2Error> {
+    let meta = Metadata::new_from_path(&image.path)?;
+    meta.set_tag_string("Xmp.xmp.Label", label)?;
+    meta.save_to_file(&image.path)
+}
+
+/// Writes the caption to both XMP and IPTC, since viewers tend to read only one of
+/// them. An empty caption removes the tags.
+pub fn write_caption(image: &ImageData, caption: &str) -> Result<(), rexiv2::Rexiv2Error> {
+    let meta = Metadata::new_from_path(&image.path)?;
+    if caption.is_empty() {
+        meta.clear_tag("Xmp.dc.description");
+        meta.clear_tag("Iptc.Application2.Caption");
+    } else {
+        meta.set_tag_string("Xmp.dc.description", caption)?;
+        meta.set_tag_string("Iptc.Application2.Caption", caption)?;
+    }
+    meta.save_to_file(&image.path)
+}
+
+/// Pick/reject marker of an image, independent of its rating.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum FlagStatus {
+    #[default]
+    None,
+    Picked,
+    Rejected,
+}
+
+// Flags are kept as hierarchical keywords, since `Xmp.xmp.Label` holds colour labels
+const FLAG_TAG: &str = "Xmp.lr.hierarchicalSubject";
+
+impl FlagStatus {
+    fn from_subject(subject: &str) -> Option<Self> {
+        match subject {
+            "Flag|Picked" => Some(FlagStatus::Picked),
+            "Flag|Rejected" => Some(FlagStatus::Rejected),
+            _ => None,
+        }
+    }
+
+    fn subject(self) -> Option<&'static str> {
+        match self {
+            FlagStatus::None => None,
+            FlagStatus::Picked => Some("Flag|Picked"),
+            FlagStatus::Rejected => Some("Flag|Rejected"),
+        }
+    }
+}
+
+/// Replaces the flag keyword of `image`, keeping any other hierarchical keywords.
+pub fn write_flag(image: &ImageData, flag: FlagStatus) -> Result<(), rexiv2::Rexiv2Error> {
+    let meta = Metadata::new_from_path(&image.path)?;
+    let mut subjects = meta.get_tag_multiple_strings(FLAG_TAG).unwrap_or_default();
+    subjects.retain(|subject| FlagStatus::from_subject(subject).is_none());
+    subjects.extend(flag.subject().map(String::from));
+    if subjects.is_empty() {
+        meta.clear_tag(FLAG_TAG);
+    } else {
+        meta.set_tag_multiple_strings(
+            FLAG_TAG,
+            &subjects.iter().map(String::as_str).collect::<Vec<_>>(),
+        )?;
+    }
+    meta.save_to_file(&image.path)
+}
+
+pub fn get_orientation(image: &ImageData, metadata_cache: &MetadataCache) -> u8 {
+    metadata_cache.get(image).orientation
+}
+
+/// One of the eight ways to rotate and mirror an image: `quarter_turns` clockwise
+/// rotations followed by a horizontal flip if `flipped`. EXIF orientations describe
+/// the same transforms.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ViewOrientation {
+    pub quarter_turns: u8,
+    pub flipped: bool,
 }
 
-pub fn get_rating(image: &ImageData) -> i32 {
-    let meta = Metadata::new_from_path(&image.path);
-    match meta {
-        Ok(meta) => {
-            let rating = meta.get_tag_numeric("Xmp.xmp.Rating");
-            rating
+impl ViewOrientation {
+    /// Maps an EXIF orientation value, treating unknown values as upright.
+    pub fn from_exif(value: u8) -> Self {
+        let (quarter_turns, flipped) = match value {
+            2 => (0, true),
+            3 => (2, false),
+            4 => (2, true),
+            5 => (1, true),
+            6 => (1, false),
+            7 => (3, true),
+            8 => (3, false),
+            _ => (0, false),
+        };
+        ViewOrientation {
+            quarter_turns,
+            flipped,
+        }
+    }
+
+    pub fn to_exif(self) -> u8 {
+        match (self.quarter_turns % 4, self.flipped) {
+            (0, false) => 1,
+            (0, true) => 2,
+            (2, false) => 3,
+            (2, true) => 4,
+            (1, true) => 5,
+            (1, false) => 6,
+            (3, true) => 7,
+            _ => 8,
+        }
+    }
+
+    /// `self` followed by `next`. A flip reverses the direction of the rotations
+    /// after it, which is all there is to composing these.
+    pub fn then(self, next: ViewOrientation) -> Self {
+        let turns = if self.flipped {
+            4 - next.quarter_turns % 4
+        } else {
+            next.quarter_turns
+        };
+        ViewOrientation {
+            quarter_turns: (self.quarter_turns + turns) % 4,
+            flipped: self.flipped != next.flipped,
         }
-        Err(e) => panic!("{:?}", e),
     }
+
+    /// Rotated by `quarter_turns` clockwise, or counterclockwise when negative.
+    pub fn rotated(self, quarter_turns: i32) -> Self {
+        self.then(ViewOrientation {
+            quarter_turns: quarter_turns.rem_euclid(4) as u8,
+            flipped: false,
+        })
+    }
+
+    pub fn flipped_horizontally(self) -> Self {
+        self.then(ViewOrientation {
+            quarter_turns: 0,
+            flipped: true,
+        })
+    }
+
+    pub fn flipped_vertically(self) -> Self {
+        self.then(ViewOrientation {
+            quarter_turns: 2,
+            flipped: true,
+        })
+    }
+
+    /// Whether width and height trade places.
+    pub fn is_sideways(self) -> bool {
+        self.quarter_turns % 2 == 1
+    }
+}
+
+/// Sets the EXIF orientation of `image`, the decoders apply it when they next load it.
+pub fn write_orientation(image: &ImageData, orientation: u8) -> Result<(), rexiv2::Rexiv2Error> {
+    let meta = Metadata::new_from_path(&image.path)?;
+    meta.set_orientation(match orientation {
+        2 => rexiv2::Orientation::HorizontalFlip,
+        3 => rexiv2::Orientation::Rotate180,
+        4 => rexiv2::Orientation::VerticalFlip,
+        5 => rexiv2::Orientation::Rotate90HorizontalFlip,
+        6 => rexiv2::Orientation::Rotate90,
+        7 => rexiv2::Orientation::Rotate90VerticalFlip,
+        8 => rexiv2::Orientation::Rotate270,
+        _ => rexiv2::Orientation::Normal,
+    });
+    meta.save_to_file(&image.path)
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct ImageMetadata {
+    /// `None` when the image has never been rated
+    pub rating: Option<i32>,
+    pub label: Option<String>,
+    pub flag: FlagStatus,
+    pub capture_time: Option<NaiveDateTime>,
+    pub camera: Option<String>,
+    pub lens: Option<String>,
+    pub iso: Option<u32>,
+    /// Exposure time in seconds as a fraction, e.g. `(1, 250)`
+    pub exposure_time: Option<(i32, i32)>,
+    pub aperture: Option<f64>,
+    /// Focal length in millimeters
+    pub focal_length: Option<f64>,
+    /// `(latitude, longitude)` in degrees, negative south and west
+    pub gps: Option<(f64, f64)>,
+    pub width: u32,
+    pub height: u32,
+    /// EXIF orientation, 0 if unknown
+    pub orientation: u8,
+    pub keywords: Vec<String>,
+    /// Description, falling back to the IPTC headline
+    pub caption: Option<String>,
+    /// Size of the file in bytes
+    pub file_size: u64,
+}
+
+/// Parsed metadata keyed by path and modification time, so that loading an image
+/// only reads its tags again after the file changed. Entries are small, so nothing
+/// is ever evicted apart from stale versions of a file. The store shares it with
+/// its loader threads, and `save` keeps it for the next start.
+#[derive(Default)]
+pub struct MetadataCache {
+    entries: Mutex<HashMap<(PathBuf, SystemTime), ImageMetadata>>,
 }
 
-pub fn get_orientation(image: &ImageData) -> u8 {
-    let meta = Metadata::new_from_path(&image.path);
-    match meta {
-        Ok(meta) => meta.get_orientation() as u8,
-        Err(e) => panic!("{:?}", e),
+/// One entry of the file written by `MetadataCache::save`.
+#[derive(Serialize, Deserialize)]
+struct SavedMetadata {
+    path: PathBuf,
+    modified: SystemTime,
+    metadata: ImageMetadata,
+}
+
+impl MetadataCache {
+    /// `~/.cache/imflow/metadata.json` on Linux.
+    pub fn path() -> Option<PathBuf> {
+        dirs::cache_dir().map(|dir| dir.join("imflow").join("metadata.json"))
+    }
+
+    /// The cache saved at `path` by an earlier session, or an empty one if there is
+    /// none or it can't be read.
+    pub fn load(path: &Path) -> Self {
+        let saved = fs::read(path)
+            .ok()
+            .and_then(|data| serde_json::from_slice::<Vec<SavedMetadata>>(&data).ok())
+            .unwrap_or_default();
+        let entries = saved
+            .into_iter()
+            .map(|saved| ((saved.path, saved.modified), saved.metadata))
+            .collect();
+        MetadataCache {
+            entries: Mutex::new(entries),
+        }
     }
+
+    /// Writes the cache to `path` for `load`. Entries of files that were changed or
+    /// deleted since they were read are left out, so the file doesn't keep growing.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let saved = self
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|((path, modified), _)| {
+                fs::metadata(path)
+                    .and_then(|metadata| metadata.modified())
+                    .ok()
+                    == Some(*modified)
+            })
+            .map(|((path, modified), metadata)| SavedMetadata {
+                path: path.clone(),
+                modified: *modified,
+                metadata: metadata.clone(),
+            })
+            .collect::<Vec<_>>();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_vec(&saved)?)
+    }
+
+    pub fn get(&self, image: &ImageData) -> ImageMetadata {
+        let Some(modified) = fs::metadata(&image.path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+        else {
+            return load_metadata(image);
+        };
+        let key = (image.path.clone(), modified);
+        if let Some(metadata) = self.entries.lock().unwrap().get(&key) {
+            return metadata.clone();
+        }
+        // Read without holding the lock, the loader threads share the cache
+        let metadata = load_metadata(image);
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|(path, _), _| *path != image.path);
+        entries.insert(key, metadata.clone());
+        metadata
+    }
+
+    pub fn invalidate(&self, image: &ImageData) {
+        self.entries
+            .lock()
+            .unwrap()
+            .retain(|(path, _), _| *path != image.path);
+    }
+}
+
+pub fn load_metadata(image: &ImageData) -> ImageMetadata {
+    let file_size = fs::metadata(&image.path).map_or(0, |metadata| metadata.len());
+    let Ok(meta) = Metadata::new_from_path(&image.path) else {
+        return ImageMetadata {
+            file_size,
+            ..Default::default()
+        };
+    };
+    let mut rating = if meta.has_tag("Xmp.xmp.Rating") {
+        Some(meta.get_tag_numeric("Xmp.xmp.Rating"))
+    } else {
+        None
+    };
+    let mut label = meta.get_tag_string("Xmp.xmp.Label").ok();
+    // Darktable keeps its edits in the sidecar, which wins over the file
+    if let Some(sidecar) = darktable_sidecar(image)
+        && let Ok(sidecar) = Metadata::new_from_path(sidecar)
+    {
+        if sidecar.has_tag("Xmp.xmp.Rating") {
+            rating = Some(sidecar.get_tag_numeric("Xmp.xmp.Rating"));
+        }
+        if label.is_none() {
+            label = darktable_colorlabel(&sidecar).map(|label| label.name().to_string());
+        }
+    }
+    let camera = match (
+        meta.get_tag_string("Exif.Image.Make"),
+        meta.get_tag_string("Exif.Image.Model"),
+    ) {
+        (Ok(make), Ok(model)) if model.starts_with(make.trim()) => Some(model),
+        (Ok(make), Ok(model)) => Some(format!("{} {}", make.trim(), model)),
+        (_, Ok(model)) => Some(model),
+        _ => None,
+    };
+    let keywords = ["Xmp.dc.subject", "Iptc.Application2.Keywords"]
+        .into_iter()
+        .flat_map(|tag| meta.get_tag_multiple_strings(tag).unwrap_or_default())
+        .unique()
+        .collect();
+    ImageMetadata {
+        rating,
+        label,
+        flag: meta
+            .get_tag_multiple_strings(FLAG_TAG)
+            .unwrap_or_default()
+            .iter()
+            .find_map(|subject| FlagStatus::from_subject(subject))
+            .unwrap_or_default(),
+        capture_time: get_capture_time(&meta),
+        camera: camera.map(|camera| camera.trim().to_string()),
+        lens: meta
+            .get_tag_string("Exif.Photo.LensModel")
+            .ok()
+            .map(|lens| lens.trim().to_string())
+            .filter(|lens| !lens.is_empty()),
+        iso: meta.get_iso_speed().map(|iso| iso as u32),
+        exposure_time: meta
+            .get_exposure_time()
+            .map(|time| (*time.numer(), *time.denom())),
+        aperture: meta.get_fnumber(),
+        focal_length: meta.get_focal_length(),
+        gps: meta
+            .get_gps_info()
+            .map(|gps| (gps.latitude, gps.longitude))
+            .filter(|(latitude, longitude)| latitude.is_finite() && longitude.is_finite()),
+        width: meta.get_pixel_width().max(0) as u32,
+        height: meta.get_pixel_height().max(0) as u32,
+        orientation: meta.get_orientation() as u8,
+        keywords,
+        caption: get_caption(&meta),
+        file_size,
+    }
+}
+
+fn get_caption(meta: &Metadata) -> Option<String> {
+    let xmp = meta.get_tag_string("Xmp.dc.description").ok().map(|text| {
+        // Language alternatives come back as `lang="x-default" text`
+        match text.strip_prefix("lang=\"") {
+            Some(rest) => rest
+                .split_once("\" ")
+                .map_or(String::new(), |(_, text)| text.to_string()),
+            None => text,
+        }
+    });
+    [
+        meta.get_tag_string("Iptc.Application2.Caption").ok(),
+        xmp,
+        meta.get_tag_string("Iptc.Application2.Headline").ok(),
+    ]
+    .into_iter()
+    .flatten()
+    .map(|caption| caption.trim().to_string())
+    .find(|caption| !caption.is_empty())
+}
+
+fn read_capture_date(path: &Path) -> Option<NaiveDate> {
+    let meta = Metadata::new_from_path(path).ok()?;
+    get_capture_time(&meta).map(|capture_time| capture_time.date())
+}
+
+fn get_capture_time(meta: &Metadata) -> Option<NaiveDateTime> {
+    let date_time = meta
+        .get_tag_string("Exif.Photo.DateTimeOriginal")
+        .or_else(|_| meta.get_tag_string("Exif.Image.DateTime"))
+        .ok()?;
+    let mut capture_time =
+        NaiveDateTime::parse_from_str(date_time.trim(), "%Y:%m:%d %H:%M:%S").ok()?;
+
+    // Bursts are usually shot within a single second, so the sub-second part matters
+    if let Ok(subsec) = meta.get_tag_string("Exif.Photo.SubSecTimeOriginal") {
+        let digits = subsec.trim();
+        if let Ok(value) = digits.parse::<i64>() {
+            let millis = value * 1000 / 10_i64.pow(digits.len() as u32);
+            capture_time += TimeDelta::milliseconds(millis);
+        }
+    }
+    Some(capture_time)
 }
 
 fn swap_wh<T>(width: T, height: T, orientation: Orientation) -> (T, T) {
@@ -74,37 +812,192 @@ fn swap_wh<T>(width: T, height: T, orientation: Orientation) -> (T, T) {
     (width, height)
 }
 
+// Names the dynamic loader knows libheif and libjxl by, per platform
+#[cfg(feature = "heif")]
+const LIBHEIF_NAMES: &[&str] = &["libheif.so.1", "libheif.1.dylib", "libheif.dll", "heif.dll"];
+#[cfg(feature = "jxl")]
+const LIBJXL_NAMES: &[&str] = &["libjxl.so.0.11", "libjxl.0.11.dylib", "jxl.dll"];
+
+/// Opens the first of `names` the dynamic loader finds and calls its version
+/// function `symbol`. None if no library or no such function was found.
+#[cfg(any(feature = "heif", feature = "jxl"))]
+fn library_version(names: &[&str], symbol: &[u8]) -> Option<u32> {
+    names.iter().find_map(|name| {
+        // SAFETY: loading runs only the library's own initializers, and both version
+        // functions take no arguments and return a 32-bit integer
+        unsafe {
+            let library = libloading::Library::new(name).ok()?;
+            let version = library.get::<unsafe extern "C" fn() -> u32>(symbol).ok()?;
+            Some(version())
+        }
+    })
+}
+
+/// Whether libheif can be used. Probed once by loading it by name; when it isn't
+/// found or is a version the bindings don't support, HEIF files are left out of
+/// the listing instead of failing on the first decode.
+#[cfg(feature = "heif")]
+pub fn heif_available() -> bool {
+    static AVAILABLE: OnceLock<bool> = OnceLock::new();
+    *AVAILABLE.get_or_init(|| {
+        let available = library_version(LIBHEIF_NAMES, b"heif_get_version_number_major")
+            .is_some_and(|major| major > 0)
+            && LibHeif::new_checked().is_ok();
+        if !available {
+            eprintln!("HEIF support unavailable: libheif not found");
+        }
+        available
+    })
+}
+
+#[cfg(not(feature = "heif"))]
+pub fn heif_available() -> bool {
+    false
+}
+
+/// Whether libjxl can be used, see [`heif_available`].
+#[cfg(feature = "jxl")]
+pub fn jxl_available() -> bool {
+    static AVAILABLE: OnceLock<bool> = OnceLock::new();
+    *AVAILABLE.get_or_init(|| {
+        let available =
+            library_version(LIBJXL_NAMES, b"JxlDecoderVersion").is_some_and(|version| version > 0);
+        if !available {
+            eprintln!("JXL support unavailable: libjxl not found");
+        }
+        available
+    })
+}
+
+#[cfg(not(feature = "jxl"))]
+pub fn jxl_available() -> bool {
+    false
+}
+
 fn get_format(path: &PathBuf) -> Option<ImageFormat> {
     if !path.is_file() {
         return None;
     }
-    let os_str = path.extension().unwrap().to_ascii_lowercase();
-    let extension = &os_str.to_str().unwrap();
+    let os_str = path.extension()?.to_ascii_lowercase();
+    let extension = &os_str.to_str()?;
     if ["heic", "heif"].contains(extension) {
-        Some(ImageFormat::Heif)
+        heif_available().then_some(ImageFormat::Heif)
     } else if ["jpg", "jpeg"].contains(extension) {
         Some(ImageFormat::Jpg)
     } else if ["jxl"].contains(extension) {
-        Some(ImageFormat::Jxl)
+        jxl_available().then_some(ImageFormat::Jxl)
+    } else if RAW_EXTENSIONS.contains(extension) {
+        Some(ImageFormat::Raw)
+    } else if *extension == "exr" {
+        Some(ImageFormat::Exr)
+    } else if *extension == "psd" {
+        Some(ImageFormat::Psd)
     } else {
         None
     }
 }
 
-pub fn load_image(image: &ImageData) -> ImflowImageBuffer {
+// Camera files stay below this and are read, see `mmap_read` for why mapping is
+// risky. The first plan was 8 MB, which maps nearly every RAW and large JPEG, while
+// mapping only saves a copy that matters for files of tens of megabytes. Compare
+// the two on your storage with the read and mapped times of `--benchmark`.
+static MMAP_THRESHOLD: AtomicU64 = AtomicU64::new(64_000_000);
+
+/// Files of at least `bytes` are mapped into memory instead of being read.
+pub fn set_mmap_threshold(bytes: u64) {
+    MMAP_THRESHOLD.store(bytes, Ordering::Relaxed);
+}
+
+/// The contents of a file, read onto the heap or mapped, see `read_file`.
+pub enum FileBytes {
+    Read(Vec<u8>),
+    Mapped(Mmap),
+}
+
+impl Deref for FileBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            FileBytes::Read(data) => data,
+            FileBytes::Mapped(map) => map,
+        }
+    }
+}
+
+/// Maps `path` into memory read-only, so the decoder pages it in as it goes instead
+/// of the whole file being copied onto the heap first.
+///
+/// Nothing stops another process from changing the file while it is mapped. If it
+/// is written to, the decoder sees the bytes change under it; if it is truncated,
+/// touching the pages past the new end kills the viewer with SIGBUS, which
+/// `catch_unwind` can't recover from. An editor saving over the image being
+/// decoded, which is what the reload on change follows, can do exactly that.
+/// Reading the file doesn't have these problems, which is why only files above the
+/// threshold of `read_file` are mapped.
+pub fn mmap_read(path: &Path) -> Result<Mmap, std::io::Error> {
+    let file = File::open(path)?;
+    // Safety: this is only sound while the file isn't modified or truncated during
+    // the decode, which can't be guaranteed, see above. The risk is taken for large
+    // files in exchange for not copying them.
+    unsafe { Mmap::map(&file) }
+}
+
+/// Reads `path`, mapping it instead when it is above the threshold set by
+/// `set_mmap_threshold`.
+pub fn read_file(path: &Path) -> Result<FileBytes, std::io::Error> {
+    read_file_chunked(path, |_| false)
+}
+
+/// Like `read_file`, but reads in chunks and passes what was read so far to
+/// `on_chunk` after each, until it returns false and the rest is read at once. A
+/// mapped file isn't passed at all.
+pub fn read_file_chunked(
+    path: &Path,
+    mut on_chunk: impl FnMut(&[u8]) -> bool,
+) -> Result<FileBytes, std::io::Error> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    if len >= MMAP_THRESHOLD.load(Ordering::Relaxed) {
+        return mmap_read(path).map(FileBytes::Mapped);
+    }
+    let mut data = Vec::with_capacity(len as usize);
+    loop {
+        let read = (&mut file)
+            .take(READ_CHUNK_SIZE as u64)
+            .read_to_end(&mut data)?;
+        if read == 0 {
+            break;
+        }
+        if !on_chunk(&data) {
+            file.read_to_end(&mut data)?;
+            break;
+        }
+    }
+    Ok(FileBytes::Read(data))
+}
+
+pub fn load_image(
+    image: &ImageData,
+    metadata_cache: &MetadataCache,
+) -> Result<ImflowImageBuffer, LoadError> {
     let total_start = Instant::now();
 
     match image.format {
+        #[cfg(feature = "heif")]
         ImageFormat::Heif => {
-            let img = load_heif(image, false);
+            let img = load_heif(image, metadata_cache, false)?;
             let total_time = total_start.elapsed();
-            println!("Total HEIF loading time: {:?}", total_time);
-            img
+            eprintln!("Total HEIF loading time: {:?}", total_time);
+            Ok(img)
         }
+        #[cfg(not(feature = "heif"))]
+        ImageFormat::Heif => unreachable!("built without HEIF support"),
+        #[cfg(feature = "jxl")]
         ImageFormat::Jxl => {
-            let rating = get_rating(image);
+            let rating = get_rating(image, metadata_cache);
 
-            let file = read(image.path.clone()).unwrap();
+            let file = read_file(&image.path)?;
             use jpegxl_rs::ThreadsRunner;
             let runner = ThreadsRunner::default();
             let decoder = decoder_builder()
@@ -115,192 +1008,808 @@ pub fn load_image(image: &ImageData) -> ImflowImageBuffer {
                     align: 8,
                 })
                 .build()
-                .unwrap();
+                .map_err(LoadError::decode)?;
 
-            let (metadata, buffer) = decoder.decode_with::<u8>(&file).unwrap();
+            let (metadata, buffer) = decoder
+                .decode_with::<u8>(&file)
+                .map_err(LoadError::decode)?;
             let width = metadata.width as usize;
             let height = metadata.height as usize;
+            // SDR content targets 255 nits, anything brighter needs tone mapping
+            let hdr = metadata.intensity_target > 255.0;
 
-            let rgba_buffer = unsafe {
-                Vec::from_raw_parts(
-                    buffer.as_ptr() as *mut u32,
-                    buffer.len() / 4,
-                    buffer.len() / 4,
-                )
-            };
-            std::mem::forget(buffer);
+            let rgba_buffer = rgba_bytes_to_pixels(&buffer);
 
-            println!("Total JXL loading time: {:?}", total_start.elapsed());
+            eprintln!("Total JXL loading time: {:?}", total_start.elapsed());
 
-            ImflowImageBuffer {
+            Ok(ImflowImageBuffer {
                 width,
                 height,
                 rgba_buffer,
                 rating,
-            }
+                hdr,
+            })
+        }
+        #[cfg(not(feature = "jxl"))]
+        ImageFormat::Jxl => unreachable!("built without JXL support"),
+        ImageFormat::Raw => {
+            // Not every RAW file embeds a preview
+            let img = load_raw_preview(image, metadata_cache)
+                .ok_or(LoadError::Decode(NO_RAW_PREVIEW.into()))?;
+            eprintln!(
+                "Total RAW preview loading time: {:?}",
+                total_start.elapsed()
+            );
+            Ok(img)
+        }
+        ImageFormat::Exr => {
+            let img = load_exr(image, metadata_cache, false)?;
+            eprintln!("Total EXR loading time: {:?}", total_start.elapsed());
+            Ok(img)
+        }
+        ImageFormat::Psd => {
+            let img = load_psd(image, metadata_cache)?;
+            eprintln!("Total PSD loading time: {:?}", total_start.elapsed());
+            Ok(img)
         }
         ImageFormat::Jpg => {
-            let rating = get_rating(image);
-
-            let mut buffer: Vec<u8>;
-            let options = DecoderOptions::new_fast().jpeg_set_out_colorspace(ColorSpace::RGBA);
-            let file = read(image.path.clone()).unwrap();
-            let mut decoder = JpegDecoder::new(&file);
-            decoder.set_options(options);
-
-            decoder.decode_headers().unwrap();
-            let info = decoder.info().unwrap();
-            let width = info.width as usize;
-            let height = info.height as usize;
-            buffer = vec![0; width * height * 4];
-            decoder.decode_into(buffer.as_mut_slice()).unwrap();
-
-            let orientation_start = Instant::now();
-            // TODO: Optimize rotation
-            let orientation =
-                Orientation::from_exif(get_orientation(image)).unwrap_or(Orientation::NoTransforms);
-            let image = RgbaImage::from_raw(width as u32, height as u32, buffer).unwrap();
-            let mut dynamic_image = DynamicImage::from(image);
-            dynamic_image.apply_orientation(orientation);
-            let buffer = dynamic_image.as_rgba8().unwrap();
-            let (width, height) = swap_wh(width, height, orientation);
-            let orientation_time = orientation_start.elapsed();
-
-            // Reinterpret to avoid copying
-            let rgba_buffer = unsafe {
-                Vec::from_raw_parts(
-                    buffer.as_ptr() as *mut u32,
-                    buffer.len() / 4,
-                    buffer.len() / 4,
-                )
-            };
-            std::mem::forget(dynamic_image);
-            let total_time = total_start.elapsed();
-            println!("Orientation time: {:?}", orientation_time);
-            println!("Total loading time: {:?}", total_time);
-            ImflowImageBuffer {
-                width,
-                height,
-                rgba_buffer,
-                rating,
+            let file = read_file(&image.path)?;
+            let img = decode_jpeg(image, metadata_cache, &file)?;
+            eprintln!("Total loading time: {:?}", total_start.elapsed());
+            Ok(img)
+        }
+    }
+}
+
+/// Decodes JPEG data into an oriented buffer.
+fn decode_jpeg(
+    image: &ImageData,
+    metadata_cache: &MetadataCache,
+    data: &[u8],
+) -> Result<ImflowImageBuffer, LoadError> {
+    let rating = get_rating(image, metadata_cache);
+
+    let options = DecoderOptions::new_fast().jpeg_set_out_colorspace(ColorSpace::RGBA);
+    let mut decoder = JpegDecoder::new(data);
+    decoder.set_options(options);
+
+    decoder.decode_headers().map_err(LoadError::decode)?;
+    let info = decoder
+        .info()
+        .ok_or(LoadError::Decode("the JPEG has no frame header".into()))?;
+    let width = info.width as usize;
+    let height = info.height as usize;
+    // Decoding into `u32`s from the start saves copying the pixels over afterwards
+    let mut pixels = vec![0u32; width * height];
+    decoder
+        .decode_into(bytemuck::cast_slice_mut(&mut pixels))
+        .map_err(LoadError::decode)?;
+
+    let orientation_start = Instant::now();
+    // TODO: Optimize rotation
+    let orientation = Orientation::from_exif(get_orientation(image, metadata_cache))
+        .unwrap_or(Orientation::NoTransforms);
+    let rgba_buffer = orient_pixels(pixels, width, height, orientation).ok_or(
+        LoadError::Decode("the JPEG is larger than it claims".into()),
+    )?;
+    let (width, height) = swap_wh(width, height, orientation);
+    eprintln!("Orientation time: {:?}", orientation_start.elapsed());
+
+    Ok(ImflowImageBuffer {
+        width,
+        height,
+        rgba_buffer,
+        rating,
+        hdr: false,
+    })
+}
+
+/// Turns decoded pixels upright. Flips and half turns happen in place, quarter turns
+/// need a new buffer. `None` if `pixels` holds fewer than `width` × `height`.
+fn orient_pixels(
+    mut pixels: Vec<u32>,
+    width: usize,
+    height: usize,
+    orientation: Orientation,
+) -> Option<Vec<u32>> {
+    let mut view = image::ImageBuffer::<image::Rgba<u8>, &mut [u8]>::from_raw(
+        width as u32,
+        height as u32,
+        bytemuck::cast_slice_mut(&mut pixels),
+    )?;
+    let turned = match orientation {
+        Orientation::NoTransforms => None,
+        Orientation::Rotate180 => {
+            imageops::rotate180_in_place(&mut view);
+            None
+        }
+        Orientation::FlipHorizontal => {
+            imageops::flip_horizontal_in_place(&mut view);
+            None
+        }
+        Orientation::FlipVertical => {
+            imageops::flip_vertical_in_place(&mut view);
+            None
+        }
+        Orientation::Rotate90 => Some(imageops::rotate90(&view)),
+        Orientation::Rotate270 => Some(imageops::rotate270(&view)),
+        Orientation::Rotate90FlipH => {
+            let mut turned = imageops::rotate90(&view);
+            imageops::flip_horizontal_in_place(&mut turned);
+            Some(turned)
+        }
+        Orientation::Rotate270FlipH => {
+            let mut turned = imageops::rotate270(&view);
+            imageops::flip_horizontal_in_place(&mut turned);
+            Some(turned)
+        }
+    };
+    Some(match turned {
+        Some(turned) => rgba_bytes_to_pixels(turned.as_raw()),
+        None => pixels,
+    })
+}
+
+/// Walks the marker segments of a (possibly truncated) JPEG stream. Returns whether
+/// it is progressive, along with the offset just past every scan that is complete.
+fn jpeg_scan_ends(data: &[u8]) -> (bool, Vec<usize>) {
+    let mut progressive = false;
+    let mut scan_ends = Vec::new();
+    // Skip SOI
+    let mut pos = 2;
+    while pos + 4 <= data.len() && data[pos] == 0xFF {
+        let marker = data[pos + 1];
+        match marker {
+            // Fill bytes before a marker
+            0xFF => {
+                pos += 1;
+                continue;
             }
+            0xD9 => break,
+            // Markers without a payload
+            0x01 | 0xD0..=0xD7 => {
+                pos += 2;
+                continue;
+            }
+            0xC2 => progressive = true,
+            _ => (),
+        }
+        let length = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        pos += 2 + length;
+        if marker != 0xDA {
+            continue;
         }
+        // Entropy-coded data runs until the next marker that is neither a stuffed
+        // zero byte nor a restart marker
+        let Some(end) = (pos..data.len().saturating_sub(1))
+            .find(|&i| data[i] == 0xFF && !matches!(data[i + 1], 0x00 | 0xD0..=0xD7 | 0xFF))
+        else {
+            break;
+        };
+        scan_ends.push(end);
+        pos = end;
     }
+    (progressive, scan_ends)
+}
+
+/// Like `load_image`, but reads progressive JPEGs in chunks through
+/// `read_file_chunked` and passes an early decode of the scans read so far to
+/// `on_partial`. On fast storage the file arrives before `PARTIAL_DECODE_INTERVAL`
+/// passes and no partial decodes are made, and mapped files are never decoded early.
+pub fn load_image_progressive(
+    image: &ImageData,
+    metadata_cache: &MetadataCache,
+    mut on_partial: impl FnMut(ImflowImageBuffer),
+) -> Result<ImflowImageBuffer, LoadError> {
+    if image.format != ImageFormat::Jpg {
+        return load_image(image, metadata_cache);
+    }
+    let mut last_partial = Instant::now();
+    let mut decoded_scans = 0;
+    let data = read_file_chunked(&image.path, |data| {
+        if last_partial.elapsed() < PARTIAL_DECODE_INTERVAL {
+            return true;
+        }
+        let (progressive, scan_ends) = jpeg_scan_ends(data);
+        if !progressive {
+            // Baseline JPEGs can't be shown early, just finish reading
+            return false;
+        }
+        if scan_ends.len() > decoded_scans {
+            decoded_scans = scan_ends.len();
+            // Cutting the stream after the last complete scan and closing it with an
+            // EOI makes the decoder fill in whatever detail it has
+            let mut truncated = data[..scan_ends[decoded_scans - 1]].to_vec();
+            truncated.extend_from_slice(&[0xFF, 0xD9]);
+            if let Ok(partial) = decode_jpeg(image, metadata_cache, &truncated) {
+                on_partial(partial);
+            }
+            last_partial = Instant::now();
+        }
+        true
+    })?;
+
+    decode_jpeg(image, metadata_cache, &data)
 }
 
 pub fn image_to_rgba_buffer(img: DynamicImage) -> Vec<u32> {
-    let flat = img.to_rgba8();
-    let mut buffer = flat.to_vec();
-    let vec = unsafe {
-        Vec::from_raw_parts(
-            buffer.as_mut_ptr() as *mut u32,
-            buffer.len() / 4,
-            buffer.len() / 4,
-        )
-    };
-    mem::forget(buffer);
-    vec
+    rgba_bytes_to_pixels(img.into_rgba8().as_raw())
 }
 
+/// Packs RGBA bytes into one `u32` per pixel. This is always a copy: a `Vec<u8>`
+/// is allocated with an alignment of 1, so its memory can't be handed over to a
+/// `Vec<u32>`. Decoders that can write into a `u32` buffer directly should.
+pub fn rgba_bytes_to_pixels(buffer: &[u8]) -> Vec<u32> {
+    debug_assert_eq!(buffer.len() % 4, 0);
+    bytemuck::pod_collect_to_vec(buffer)
+}
+
+/// Describes the file at `path`, or returns `None` if it isn't a supported image.
+fn image_data(path: PathBuf) -> Option<ImageData> {
+    let format = get_format(&path)?;
+    #[cfg(feature = "jxl")]
+    let has_preview = format == ImageFormat::Jxl && jxl_has_preview(&path);
+    #[cfg(not(feature = "jxl"))]
+    let has_preview = false;
+    let capture_date = read_capture_date(&path);
+    Some(ImageData {
+        path,
+        format,
+        has_preview,
+        capture_date,
+    })
+}
+
+/// Lists the supported images in `dir`, sorted by path. A directory that can't be
+/// read yields no images.
 pub fn load_available_images(dir: PathBuf) -> Vec<ImageData> {
-    fs::read_dir(dir)
-        .unwrap()
-        .map(|f| f.unwrap().path().to_path_buf())
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
         .sorted()
-        .filter_map(|path| {
-            if let Some(format) = get_format(&path) {
-                Some(ImageData { path, format })
-            } else {
-                None
-            }
-        })
+        .collect::<Vec<_>>()
+        // Reading the capture dates opens every file, which adds up in large folders
+        .into_par_iter()
+        .filter_map(image_data)
         .collect::<Vec<ImageData>>()
 }
 
+/// Describes the files in `paths`, keeping their order. Paths that don't lead to a
+/// supported image are returned separately.
+pub fn load_listed_images(paths: Vec<PathBuf>) -> (Vec<ImageData>, Vec<PathBuf>) {
+    paths
+        .into_par_iter()
+        .map(|path| image_data(path.clone()).ok_or(path))
+        .collect::<Vec<_>>()
+        .into_iter()
+        .partition_result()
+}
+
+/// Groups images sharing a directory and file stem (`IMG_1234.CR2` + `IMG_1234.JPG`)
+/// into stacks. Members are ordered by the position of their extension in
+/// `priority`, so the first one is the one to display; unlisted extensions come last.
+pub fn group_stacks(images: Vec<ImageData>, priority: &[String]) -> Vec<Vec<ImageData>> {
+    let rank = |image: &ImageData| {
+        let extension = image
+            .path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+        priority
+            .iter()
+            .position(|preferred| preferred.eq_ignore_ascii_case(&extension))
+            .unwrap_or(priority.len())
+    };
+    let mut stack_ids: HashMap<PathBuf, usize> = HashMap::new();
+    let mut stacks: Vec<Vec<ImageData>> = Vec::new();
+    for image in images {
+        let id = *stack_ids
+            .entry(image.path.with_extension(""))
+            .or_insert_with(|| {
+                stacks.push(Vec::new());
+                stacks.len() - 1
+            });
+        stacks[id].push(image);
+    }
+    for stack in &mut stacks {
+        stack.sort_by_key(|image| rank(image));
+    }
+    stacks
+}
+
 pub fn get_embedded_thumbnail(image: &ImageData) -> Option<Vec<u8>> {
-    let meta = Metadata::new_from_path(&image.path);
-    match meta {
-        Ok(meta) => {
-            if let Some(previews) = meta.get_preview_images() {
-                for preview in previews {
-                    return Some(preview.get_data().unwrap());
+    let meta = Metadata::new_from_path(&image.path).ok()?;
+    let preview = meta.get_preview_images()?.into_iter().next()?;
+    preview.get_data().ok()
+}
+
+pub fn load_thumbnail(
+    path: &ImageData,
+    metadata_cache: &MetadataCache,
+) -> Result<ImflowImageBuffer, LoadError> {
+    #[cfg(feature = "heif")]
+    if path.format == ImageFormat::Heif {
+        return load_heif(path, metadata_cache, true);
+    }
+    #[cfg(feature = "jxl")]
+    if path.format == ImageFormat::Jxl {
+        if path.has_preview
+            && let Some(preview) = load_jxl_preview(path, metadata_cache)
+        {
+            return Ok(preview);
+        }
+        return load_thumbnail_full(path, metadata_cache);
+    }
+    if path.format == ImageFormat::Exr {
+        return load_exr(path, metadata_cache, true);
+    }
+    if path.format == ImageFormat::Psd {
+        return match load_psd_thumbnail(path, metadata_cache) {
+            Some(thumbnail) => Ok(thumbnail),
+            None => load_psd(path, metadata_cache).map(|psd| psd.downsample(640, 480)),
+        };
+    }
+    // The image crate can't decode the RAW data itself
+    if path.format == ImageFormat::Raw {
+        return load_thumbnail_exif(path, metadata_cache)
+            .or_else(|| {
+                load_raw_preview(path, metadata_cache).map(|preview| preview.downsample(640, 480))
+            })
+            .ok_or(LoadError::Decode(NO_RAW_PREVIEW.into()));
+    }
+    match load_thumbnail_exif(path, metadata_cache) {
+        Some(thumbnail) => Ok(thumbnail),
+        None => load_thumbnail_full(path, metadata_cache),
+    }
+}
+
+pub fn load_thumbnail_exif(
+    path: &ImageData,
+    metadata_cache: &MetadataCache,
+) -> Option<ImflowImageBuffer> {
+    let thumbnail = get_embedded_thumbnail(path)?;
+    let mut image = image::ImageReader::new(Cursor::new(thumbnail))
+        .with_guessed_format()
+        .ok()?
+        .decode()
+        .ok()?;
+
+    // Embedded thumbnails are stored unrotated, like the main image
+    let orientation = Orientation::from_exif(get_orientation(path, metadata_cache))
+        .unwrap_or(Orientation::NoTransforms);
+    image.apply_orientation(orientation);
+
+    // apply_orientation already produced the rotated dimensions
+    let width: usize = image.width() as usize;
+    let height: usize = image.height() as usize;
+    let buffer_u32 = image_to_rgba_buffer(image);
+
+    let rating = get_rating(path, metadata_cache);
+
+    Some(ImflowImageBuffer {
+        width,
+        height,
+        rgba_buffer: buffer_u32,
+        rating,
+        hdr: false,
+    })
+}
+
+/// Runs libjxl up to the preview frame. With `decode` unset it stops after the basic
+/// info and the returned buffer is empty. `None` means there is no preview.
+#[cfg(feature = "jxl")]
+fn jxl_preview(data: &[u8], decode: bool) -> Option<(JxlPreviewHeader, Vec<u8>)> {
+    unsafe {
+        let decoder = JxlDecoderCreate(ptr::null());
+        if decoder.is_null() {
+            return None;
+        }
+        let result = run_jxl_preview_decoder(decoder, data, decode);
+        JxlDecoderDestroy(decoder);
+        result
+    }
+}
+
+#[cfg(feature = "jxl")]
+unsafe fn run_jxl_preview_decoder(
+    decoder: *mut JxlDecoder,
+    data: &[u8],
+    decode: bool,
+) -> Option<(JxlPreviewHeader, Vec<u8>)> {
+    let mut events = JxlDecoderStatus::BasicInfo as c_int;
+    if decode {
+        events |= JxlDecoderStatus::PreviewImage as c_int;
+    }
+    let format = JxlPixelFormat {
+        num_channels: 4,
+        data_type: JxlDataType::Uint8,
+        endianness: JxlEndianness::Native,
+        align: 0,
+    };
+    let mut header = None;
+    let mut buffer = Vec::new();
+    unsafe {
+        if JxlDecoderSubscribeEvents(decoder, events) != JxlDecoderStatus::Success
+            || JxlDecoderSetInput(decoder, data.as_ptr(), data.len()) != JxlDecoderStatus::Success
+        {
+            return None;
+        }
+        JxlDecoderCloseInput(decoder);
+        loop {
+            match JxlDecoderProcessInput(decoder) {
+                JxlDecoderStatus::BasicInfo => {
+                    let mut info = MaybeUninit::uninit();
+                    if JxlDecoderGetBasicInfo(decoder, info.as_mut_ptr())
+                        != JxlDecoderStatus::Success
+                    {
+                        return None;
+                    }
+                    let info = info.assume_init();
+                    if info.have_preview != JxlBool::True {
+                        return None;
+                    }
+                    if !decode {
+                        return Some((info.preview, buffer));
+                    }
+                    header = Some(info.preview);
+                }
+                JxlDecoderStatus::NeedPreviewOutBuffer => {
+                    let mut size = 0;
+                    if JxlDecoderPreviewOutBufferSize(decoder, &format, &mut size)
+                        != JxlDecoderStatus::Success
+                    {
+                        return None;
+                    }
+                    buffer.resize(size, 0);
+                    if JxlDecoderSetPreviewOutBuffer(
+                        decoder,
+                        &format,
+                        buffer.as_mut_ptr().cast(),
+                        size,
+                    ) != JxlDecoderStatus::Success
+                    {
+                        return None;
+                    }
                 }
+                JxlDecoderStatus::PreviewImage => return header.map(|header| (header, buffer)),
+                _ => return None,
             }
-            None
         }
-        Err(_) => None,
     }
 }
 
-pub fn load_thumbnail(path: &ImageData) -> ImflowImageBuffer {
-    if path.format == ImageFormat::Heif {
-        return load_heif(path, true);
-    }
-    match load_thumbnail_exif(path) {
-        Some(thumbnail) => return thumbnail,
-        None => load_thumbnail_full(path),
-    }
-}
-
-pub fn load_thumbnail_exif(path: &ImageData) -> Option<ImflowImageBuffer> {
-    match get_embedded_thumbnail(path) {
-        Some(thumbnail) => {
-            let decoder = image::ImageReader::new(Cursor::new(thumbnail))
-                .with_guessed_format()
-                .unwrap();
-            let image = decoder.decode().unwrap();
-
-            let width: usize = image.width() as usize;
-            let height: usize = image.height() as usize;
-            let flat = image.into_rgba8().into_raw();
-            let mut buffer = flat.to_vec();
-            let buffer_u32 = unsafe {
-                Vec::from_raw_parts(
-                    buffer.as_mut_ptr() as *mut u32,
-                    buffer.len() / 4,
-                    buffer.len() / 4,
-                )
+#[cfg(feature = "jxl")]
+pub fn jxl_has_preview(path: &PathBuf) -> bool {
+    // The basic info sits at the very start of the codestream (or the first boxes
+    // of the container), so there is no need to read the whole file
+    let mut head = Vec::new();
+    let Ok(file) = File::open(path) else {
+        return false;
+    };
+    if file.take(64 * 1024).read_to_end(&mut head).is_err() {
+        return false;
+    }
+    jxl_preview(&head, false).is_some()
+}
+
+/// Decodes only the preview frame of a JXL file, skipping the full-resolution image.
+#[cfg(feature = "jxl")]
+pub fn load_jxl_preview(
+    image: &ImageData,
+    metadata_cache: &MetadataCache,
+) -> Option<ImflowImageBuffer> {
+    let data = read_file(&image.path).ok()?;
+    let (header, buffer) = jxl_preview(&data, true)?;
+    Some(ImflowImageBuffer {
+        width: header.xsize as usize,
+        height: header.ysize as usize,
+        rgba_buffer: rgba_bytes_to_pixels(&buffer),
+        rating: get_rating(image, metadata_cache),
+        hdr: false,
+    })
+}
+
+/// RAW files are not demosaiced; the largest embedded JPEG preview is shown instead.
+/// `None` if there is no preview or it doesn't decode.
+pub fn load_raw_preview(
+    image: &ImageData,
+    metadata_cache: &MetadataCache,
+) -> Option<ImflowImageBuffer> {
+    let meta = Metadata::new_from_path(&image.path).ok()?;
+    let preview = meta.get_preview_images().and_then(|previews| {
+        previews
+            .into_iter()
+            .max_by_key(|preview| preview.get_width() as u64 * preview.get_height() as u64)
+    })?;
+    let mut decoded = image::ImageReader::new(Cursor::new(preview.get_data().ok()?))
+        .with_guessed_format()
+        .ok()?
+        .decode()
+        .ok()?;
+
+    let orientation =
+        Orientation::from_exif(meta.get_orientation() as u8).unwrap_or(Orientation::NoTransforms);
+    decoded.apply_orientation(orientation);
+
+    Some(ImflowImageBuffer {
+        width: decoded.width() as usize,
+        height: decoded.height() as usize,
+        rgba_buffer: image_to_rgba_buffer(decoded),
+        rating: get_rating(image, metadata_cache),
+        hdr: false,
+    })
+}
+
+/// Decodes an OpenEXR image and maps its linear half or full float channels onto
+/// 8-bit sRGB: scaled by `EXR_EXPOSURE` and clipped, with no further tone mapping.
+/// Thumbnails are box-downsampled from the full decode, as EXR files rarely carry
+/// a preview.
+pub fn load_exr(
+    image: &ImageData,
+    metadata_cache: &MetadataCache,
+    thumbnail: bool,
+) -> Result<ImflowImageBuffer, LoadError> {
+    let pixels = image::ImageReader::open(&image.path)?
+        .decode()
+        .map_err(LoadError::decode)?
+        .into_rgba32f();
+    let (width, height) = pixels.dimensions();
+    let bytes = pixels
+        .into_raw()
+        .chunks_exact(4)
+        .flat_map(|pixel| {
+            let encode = |linear: f32| {
+                let linear = (linear * EXR_EXPOSURE).clamp(0.0, 1.0);
+                let srgb = if linear <= 0.0031308 {
+                    linear * 12.92
+                } else {
+                    1.055 * linear.powf(1.0 / 2.4) - 0.055
+                };
+                (srgb * 255.0).round() as u8
             };
+            // Alpha is linear coverage and isn't scaled
+            let alpha = (pixel[3].clamp(0.0, 1.0) * 255.0).round() as u8;
+            [encode(pixel[0]), encode(pixel[1]), encode(pixel[2]), alpha]
+        })
+        .collect::<Vec<u8>>();
+    let buffer = ImflowImageBuffer {
+        width: width as usize,
+        height: height as usize,
+        rgba_buffer: rgba_bytes_to_pixels(&bytes),
+        // EXR rarely has any metadata, this is 0 then
+        rating: get_rating(image, metadata_cache),
+        hdr: false,
+    };
+    if thumbnail {
+        Ok(buffer.downsample(640, 480))
+    } else {
+        Ok(buffer)
+    }
+}
 
-            let rating = get_rating(path.into());
+/// Composites the visible layers of a PSD file. Documents the `psd` crate can't
+/// parse, or that have no layers, show the composite Photoshop saves alongside the
+/// layers instead, see `load_psd_merged`.
+pub fn load_psd(
+    image: &ImageData,
+    metadata_cache: &MetadataCache,
+) -> Result<ImflowImageBuffer, LoadError> {
+    let data = read_file(&image.path)?;
+    // The crate panics on some documents it doesn't support, which the fallback
+    // may still read
+    let layers = std::panic::catch_unwind(|| {
+        let psd = psd::Psd::from_bytes(&data).ok()?;
+        if psd.layers().is_empty() {
+            return None;
+        }
+        let rgba = psd
+            .flatten_layers_rgba(&|(_, layer)| layer.visible())
+            .ok()?;
+        Some((psd.width() as usize, psd.height() as usize, rgba))
+    });
+    let Ok(Some((width, height, rgba))) = layers else {
+        return load_psd_merged(image, metadata_cache, &data);
+    };
+    Ok(ImflowImageBuffer {
+        width,
+        height,
+        rgba_buffer: rgba_bytes_to_pixels(&rgba),
+        rating: get_rating(image, metadata_cache),
+        hdr: false,
+    })
+}
 
-            Some(ImflowImageBuffer {
-                width,
-                height,
-                rgba_buffer: buffer_u32,
-                rating,
-            })
+/// Decodes the composite Photoshop saves alongside the layers of a PSD file. In a
+/// file saved with "Maximize compatibility" turned off this is a placeholder.
+fn load_psd_merged(
+    image: &ImageData,
+    metadata_cache: &MetadataCache,
+    data: &[u8],
+) -> Result<ImflowImageBuffer, LoadError> {
+    // Documents are often larger than the decoder's default limit of 16384 pixels
+    let options = DecoderOptions::default()
+        .set_max_width(usize::MAX)
+        .set_max_height(usize::MAX);
+    let mut decoded = zune_image::image::Image::read(data, options).map_err(LoadError::decode)?;
+    decoded
+        .convert_color(ColorSpace::RGBA)
+        .map_err(LoadError::decode)?;
+    let (width, height) = decoded.dimensions();
+    // 16-bit documents are scaled down to 8 bits here
+    let bytes = decoded
+        .flatten_to_u8()
+        .into_iter()
+        .next()
+        .ok_or(LoadError::Decode("the PSD file has no image data".into()))?;
+    Ok(ImflowImageBuffer {
+        width,
+        height,
+        rgba_buffer: rgba_bytes_to_pixels(&bytes),
+        rating: get_rating(image, metadata_cache),
+        hdr: false,
+    })
+}
+
+fn load_psd_thumbnail(
+    image: &ImageData,
+    metadata_cache: &MetadataCache,
+) -> Option<ImflowImageBuffer> {
+    let preview = load_psd_composite_preview(&image.path)?;
+    let decoded = image::load_from_memory(&preview).ok()?;
+    Some(ImflowImageBuffer {
+        width: decoded.width() as usize,
+        height: decoded.height() as usize,
+        rgba_buffer: image_to_rgba_buffer(decoded),
+        rating: get_rating(image, metadata_cache),
+        hdr: false,
+    })
+}
+
+/// Reads the JPEG thumbnail Photoshop keeps among the image resources of a PSD file,
+/// without reading the layer and image data that make up most of the file. Like
+/// `load_psd_merged` it only sees what Photoshop flattened on saving, which doesn't
+/// match the layers in files saved without "Maximize compatibility".
+pub fn load_psd_composite_preview(path: &PathBuf) -> Option<Vec<u8>> {
+    fn read_u32(reader: &mut impl Read) -> Option<u32> {
+        let mut bytes = [0; 4];
+        reader.read_exact(&mut bytes).ok()?;
+        Some(u32::from_be_bytes(bytes))
+    }
+    let mut file = BufReader::new(File::open(path).ok()?);
+    // Signature
+    if read_u32(&mut file)? != u32::from_be_bytes(*b"8BPS") {
+        return None;
+    }
+    // Skip the rest of the 26 byte header, then the color mode data
+    file.seek_relative(22).ok()?;
+    let color_mode_length = read_u32(&mut file)?;
+    file.seek_relative(color_mode_length as i64).ok()?;
+    let resources_length = read_u32(&mut file)?;
+    // The length comes from the file, so a corrupt one mustn't size the allocation
+    let mut resources = Vec::new();
+    file.take(u64::from(resources_length))
+        .read_to_end(&mut resources)
+        .ok()?;
+    if resources.len() != resources_length as usize {
+        return None;
+    }
+
+    let mut pos = 0;
+    while resources.get(pos..pos + 4)? == b"8BIM" {
+        let id = u16::from_be_bytes(resources.get(pos + 4..pos + 6)?.try_into().ok()?);
+        // A Pascal string name, padded to an even length along with its length byte
+        let name_length = *resources.get(pos + 6)? as usize;
+        pos += 6 + (name_length + 1).next_multiple_of(2);
+        let size = u32::from_be_bytes(resources.get(pos..pos + 4)?.try_into().ok()?) as usize;
+        pos += 4;
+        let data = resources.get(pos..pos + size)?;
+        if id == PSD_THUMBNAIL_RESOURCE {
+            // The JPEG data follows a 28 byte header whose first field is 1 for JPEG
+            if data.get(..4)? != [0, 0, 0, 1] {
+                return None;
+            }
+            return data.get(28..).map(<[u8]>::to_vec);
         }
-        _ => None,
+        pos += size.next_multiple_of(2);
     }
+    None
 }
 
-pub fn load_thumbnail_full(path: &ImageData) -> ImflowImageBuffer {
-    let file = BufReader::new(File::open(path.path.clone()).unwrap());
+pub fn load_thumbnail_full(
+    path: &ImageData,
+    metadata_cache: &MetadataCache,
+) -> Result<ImflowImageBuffer, LoadError> {
+    let file = BufReader::new(File::open(&path.path)?);
     let reader = image::ImageReader::new(file);
     let image = reader
-        .with_guessed_format()
-        .unwrap()
+        .with_guessed_format()?
         .decode()
-        .unwrap()
+        .map_err(LoadError::decode)?
         .resize(640, 480, FilterType::Nearest);
     let width = image.width() as usize;
     let height = image.height() as usize;
     let buffer = image_to_rgba_buffer(image);
-    let rating = get_rating(path.into());
+    let rating = get_rating(path, metadata_cache);
 
-    ImflowImageBuffer {
+    Ok(ImflowImageBuffer {
         width,
         height,
         rgba_buffer: buffer,
         rating,
+        hdr: false,
+    })
+}
+
+#[cfg(feature = "heif")]
+pub fn load_heif(
+    path: &ImageData,
+    metadata_cache: &MetadataCache,
+    resize: bool,
+) -> Result<ImflowImageBuffer, LoadError> {
+    if resize && let Some(thumbnail) = load_heif_thumbnail(path, metadata_cache) {
+        return Ok(thumbnail);
+    }
+    load_heif_full(path, metadata_cache, resize)
+}
+
+#[cfg(feature = "heif")]
+fn is_hdr_heif(handle: &ImageHandle) -> bool {
+    handle.color_profile_nclx().is_some_and(|profile| {
+        matches!(
+            profile.transfer_characteristics(),
+            TransferCharacteristics::ITU_R_BT_2100_0_PQ
+                | TransferCharacteristics::ITU_R_BT_2100_0_HLG
+        )
+    })
+}
+
+/// Decodes the first thumbnail item stored in the HEIF container, if there is one.
+/// This is much cheaper than decoding and scaling down the primary image.
+#[cfg(feature = "heif")]
+pub fn load_heif_thumbnail(
+    path: &ImageData,
+    metadata_cache: &MetadataCache,
+) -> Option<ImflowImageBuffer> {
+    let lib_heif = LibHeif::new();
+    let ctx = HeifContext::read_from_file(path.path.to_str()?).ok()?;
+    let handle = ctx.primary_image_handle().ok()?;
+    if handle.number_of_thumbnails() == 0 {
+        return None;
     }
+    let mut thumbnail_ids: Vec<ItemId> = vec![0; 1];
+    handle.thumbnail_ids(&mut thumbnail_ids);
+    let thumbnail = handle.thumbnail(thumbnail_ids[0]).ok()?;
+    let image = lib_heif
+        .decode(
+            &thumbnail,
+            libheif_rs::ColorSpace::Rgb(RgbChroma::Rgba),
+            None,
+        )
+        .ok()?;
+    heif_image_to_buffer(
+        image,
+        get_rating(path, metadata_cache),
+        is_hdr_heif(&handle),
+    )
+    .ok()
 }
 
-pub fn load_heif(path: &ImageData, resize: bool) -> ImflowImageBuffer {
+#[cfg(feature = "heif")]
+pub fn load_heif_full(
+    path: &ImageData,
+    metadata_cache: &MetadataCache,
+    resize: bool,
+) -> Result<ImflowImageBuffer, LoadError> {
     let lib_heif = LibHeif::new();
-    let ctx = HeifContext::read_from_file(path.path.to_str().unwrap()).unwrap();
-    let handle = ctx.primary_image_handle().unwrap();
+    let file_name = path
+        .path
+        .to_str()
+        .ok_or(LoadError::Decode("libheif needs a UTF-8 path".into()))?;
+    let ctx = HeifContext::read_from_file(file_name).map_err(LoadError::decode)?;
+    let handle = ctx.primary_image_handle().map_err(LoadError::decode)?;
+    let hdr = is_hdr_heif(&handle);
     // assert_eq!(handle.width(), 1652);
     // assert_eq!(handle.height(), 1791);
 
@@ -313,7 +1822,7 @@ pub fn load_heif(path: &ImageData, resize: bool) -> ImflowImageBuffer {
     // Decode the image
     let mut image = lib_heif
         .decode(&handle, libheif_rs::ColorSpace::Rgb(RgbChroma::Rgba), None)
-        .unwrap();
+        .map_err(LoadError::decode)?;
     assert_eq!(
         image.color_space(),
         Some(libheif_rs::ColorSpace::Rgb(RgbChroma::Rgba)),
@@ -321,31 +1830,138 @@ pub fn load_heif(path: &ImageData, resize: bool) -> ImflowImageBuffer {
 
     // Scale the image
     if resize {
-        image = image.scale(640, 480, None).unwrap();
+        image = image.scale(640, 480, None).map_err(LoadError::decode)?;
         assert_eq!(image.width(), 640);
         assert_eq!(image.height(), 480);
     }
 
+    heif_image_to_buffer(image, get_rating(path, metadata_cache), hdr)
+}
+
+#[cfg(feature = "heif")]
+fn heif_image_to_buffer(
+    image: Image,
+    rating: i32,
+    hdr: bool,
+) -> Result<ImflowImageBuffer, LoadError> {
     let width = image.width() as usize;
     let height = image.height() as usize;
-    let rating = get_rating(path);
 
     // Get "pixels"
     let planes = image.planes();
-    let interleaved_plane = planes.interleaved.unwrap();
+    let interleaved_plane = planes
+        .interleaved
+        .ok_or(LoadError::Decode("libheif returned planar data".into()))?;
     assert!(!interleaved_plane.data.is_empty());
     assert!(interleaved_plane.stride > 0);
 
-    let rgba_buffer = interleaved_plane.data;
-    // Create a slice of u32 from the u8 slice
-    let u32_slice = unsafe {
-        std::slice::from_raw_parts(rgba_buffer.as_ptr() as *const u32, rgba_buffer.len() / 4)
-    };
+    // The plane is borrowed from libheif, so it has to be copied either way
+    let rgba_buffer = bytemuck::pod_collect_to_vec(interleaved_plane.data);
 
-    ImflowImageBuffer {
+    Ok(ImflowImageBuffer {
         width,
         height,
-        rgba_buffer: u32_slice.to_vec(),
+        rgba_buffer,
         rating,
+        hdr,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn downsample_averages_each_block() {
+        let (width, height) = (4000, 3000);
+        // Alternating columns of black and a colour average out to half the colour
+        let rgba_buffer = (0..width * height)
+            .map(|i| {
+                let pixel = if i % 2 == 0 {
+                    [0, 0, 0, 255]
+                } else {
+                    [200, 100, 50, 255]
+                };
+                u32::from_ne_bytes(pixel)
+            })
+            .collect();
+        let image = ImflowImageBuffer {
+            width,
+            height,
+            rgba_buffer,
+            rating: 3,
+            hdr: false,
+        };
+
+        let small = image.downsample(400, 300);
+        assert_eq!((small.width, small.height), (400, 300));
+        assert_eq!(small.rgba_buffer.len(), 400 * 300);
+        assert!(
+            small
+                .rgba_buffer
+                .iter()
+                .all(|&pixel| pixel.to_ne_bytes() == [100, 50, 25, 255])
+        );
+        assert_eq!(small.rating, 3);
+
+        // The aspect ratio is kept when the box is a different shape
+        let small = image.downsample(400, 400);
+        assert_eq!((small.width, small.height), (400, 300));
+    }
+
+    #[test]
+    fn rgba_bytes_to_pixels_makes_one_pixel_per_four_bytes() {
+        let (width, height) = (7, 5);
+        let bytes = (0..width * height * 4).map(|i| i as u8).collect::<Vec<_>>();
+        let pixels = rgba_bytes_to_pixels(&bytes);
+        assert_eq!(pixels.len(), width * height);
+        // The bytes of each pixel stay in memory order
+        assert_eq!(bytemuck::cast_slice::<u32, u8>(&pixels), bytes.as_slice());
+    }
+
+    #[test]
+    fn orient_pixels_turns_the_buffer() {
+        // A 3×2 image, with each pixel holding its index
+        let pixels = (0..6u32).collect::<Vec<_>>();
+        assert_eq!(
+            orient_pixels(pixels.clone(), 3, 2, Orientation::NoTransforms),
+            Some(pixels.clone())
+        );
+        assert_eq!(
+            orient_pixels(pixels.clone(), 3, 2, Orientation::Rotate90),
+            Some(vec![3, 0, 4, 1, 5, 2])
+        );
+        assert_eq!(
+            orient_pixels(pixels.clone(), 3, 2, Orientation::FlipHorizontal),
+            Some(vec![2, 1, 0, 5, 4, 3])
+        );
+        assert_eq!(orient_pixels(pixels, 4, 2, Orientation::Rotate180), None);
+    }
+
+    #[test]
+    fn rotated_exif_thumbnail_is_portrait() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("portrait.jpg");
+        // Cameras store the pixels of a portrait shot sideways and rotate them on display
+        image::RgbaImage::from_pixel(64, 48, image::Rgba([200, 100, 50, 255]))
+            .save(&path)
+            .unwrap();
+        let mut thumbnail = Cursor::new(Vec::new());
+        DynamicImage::ImageRgb8(image::RgbImage::from_pixel(
+            32,
+            24,
+            image::Rgb([200, 100, 50]),
+        ))
+        .write_to(&mut thumbnail, image::ImageFormat::Jpeg)
+        .unwrap();
+        let meta = Metadata::new_from_path(&path).unwrap();
+        meta.set_orientation(rexiv2::Orientation::Rotate90);
+        meta.set_thumbnail_from_buffer(thumbnail.get_ref());
+        meta.save_to_file(&path).unwrap();
+
+        let image = image_data(path).unwrap();
+        let thumbnail = load_thumbnail_exif(&image, &MetadataCache::default()).unwrap();
+        assert_eq!((thumbnail.width, thumbnail.height), (24, 32));
+        assert!(thumbnail.height > thumbnail.width);
     }
 }
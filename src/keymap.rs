@@ -0,0 +1,495 @@
+use egui::{Key, Modifiers};
+use imflow::image::FlagStatus;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+/// Everything a key can be bound to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    NextImage,
+    PrevImage,
+    NextInBurst,
+    PrevInBurst,
+    JumpFirst,
+    JumpLast,
+    PageForward,
+    PageBack,
+    NextBurst,
+    PrevBurst,
+    JumpToDate,
+    RateUp,
+    RateDown,
+    Rate(u8),
+    ToggleOneToOne,
+    Fit,
+    PanLeft,
+    PanRight,
+    PanUp,
+    PanDown,
+    ZoomIn,
+    ZoomOut,
+    ToggleBurstMode,
+    CycleStackMember,
+    Delete,
+    Rename,
+    OpenInEditor,
+    RevealInFileManager,
+    OpenFolder,
+    ToggleSharpen,
+    ToggleClipping,
+    RotateClockwise,
+    RotateCounterclockwise,
+    FlipHorizontal,
+    FlipVertical,
+    SaveOrientation,
+    CycleHdr,
+    CycleAlphaBackground,
+    CycleSampling,
+    CycleTransformMemory,
+    ClearTransforms,
+    Flag(FlagStatus),
+    CycleFlagFilter,
+    SelectAll,
+    ClearSelection,
+    CopyImage,
+    CopyPaths,
+    /// Copies the current image into the folder configured as `copy_target_<n>`
+    CopyToTarget(u8),
+    /// Saves the full image or the current view as a PNG
+    Export,
+    ToggleStatistics,
+    ToggleInfo,
+    ToggleUi,
+    ToggleAutoHide,
+    ToggleFilmstrip,
+    ToggleCompare,
+    FlickerPrevious,
+    ToggleDebugOverlay,
+    UiZoomIn,
+    UiZoomOut,
+    UiZoomReset,
+    ToggleSettings,
+    ToggleHelp,
+    Quit,
+}
+
+const ACTIONS: [Action; 74] = [
+    Action::NextImage,
+    Action::PrevImage,
+    Action::NextInBurst,
+    Action::PrevInBurst,
+    Action::JumpFirst,
+    Action::JumpLast,
+    Action::PageForward,
+    Action::PageBack,
+    Action::NextBurst,
+    Action::PrevBurst,
+    Action::JumpToDate,
+    Action::RateUp,
+    Action::RateDown,
+    Action::Rate(0),
+    Action::Rate(1),
+    Action::Rate(2),
+    Action::Rate(3),
+    Action::Rate(4),
+    Action::Rate(5),
+    Action::ToggleOneToOne,
+    Action::Fit,
+    Action::PanLeft,
+    Action::PanRight,
+    Action::PanUp,
+    Action::PanDown,
+    Action::ZoomIn,
+    Action::ZoomOut,
+    Action::ToggleBurstMode,
+    Action::CycleStackMember,
+    Action::Delete,
+    Action::Rename,
+    Action::OpenInEditor,
+    Action::RevealInFileManager,
+    Action::OpenFolder,
+    Action::ToggleSharpen,
+    Action::ToggleClipping,
+    Action::RotateClockwise,
+    Action::RotateCounterclockwise,
+    Action::FlipHorizontal,
+    Action::FlipVertical,
+    Action::SaveOrientation,
+    Action::CycleHdr,
+    Action::CycleAlphaBackground,
+    Action::CycleSampling,
+    Action::CycleTransformMemory,
+    Action::ClearTransforms,
+    Action::Flag(FlagStatus::Picked),
+    Action::Flag(FlagStatus::Rejected),
+    Action::Flag(FlagStatus::None),
+    Action::CycleFlagFilter,
+    Action::SelectAll,
+    Action::ClearSelection,
+    Action::CopyImage,
+    Action::CopyPaths,
+    Action::CopyToTarget(1),
+    Action::CopyToTarget(2),
+    Action::CopyToTarget(3),
+    Action::CopyToTarget(4),
+    Action::CopyToTarget(5),
+    Action::Export,
+    Action::ToggleStatistics,
+    Action::ToggleInfo,
+    Action::ToggleUi,
+    Action::ToggleAutoHide,
+    Action::ToggleFilmstrip,
+    Action::ToggleCompare,
+    Action::FlickerPrevious,
+    Action::ToggleDebugOverlay,
+    Action::UiZoomIn,
+    Action::UiZoomOut,
+    Action::UiZoomReset,
+    Action::ToggleSettings,
+    Action::ToggleHelp,
+    Action::Quit,
+];
+
+impl Action {
+    /// Name used in the `[keys]` section of the config file.
+    pub fn name(&self) -> String {
+        match self {
+            Action::NextImage => "next_image".into(),
+            Action::PrevImage => "prev_image".into(),
+            Action::NextInBurst => "next_in_burst".into(),
+            Action::PrevInBurst => "prev_in_burst".into(),
+            Action::JumpFirst => "jump_first".into(),
+            Action::JumpLast => "jump_last".into(),
+            Action::PageForward => "page_forward".into(),
+            Action::PageBack => "page_back".into(),
+            Action::NextBurst => "next_burst".into(),
+            Action::PrevBurst => "prev_burst".into(),
+            Action::JumpToDate => "jump_to_date".into(),
+            Action::RateUp => "rate_up".into(),
+            Action::RateDown => "rate_down".into(),
+            Action::Rate(rating) => format!("rate_{}", rating),
+            Action::ToggleOneToOne => "toggle_one_to_one".into(),
+            Action::Fit => "fit".into(),
+            Action::PanLeft => "pan_left".into(),
+            Action::PanRight => "pan_right".into(),
+            Action::PanUp => "pan_up".into(),
+            Action::PanDown => "pan_down".into(),
+            Action::ZoomIn => "zoom_in".into(),
+            Action::ZoomOut => "zoom_out".into(),
+            Action::ToggleBurstMode => "toggle_burst_mode".into(),
+            Action::CycleStackMember => "cycle_stack_member".into(),
+            Action::Delete => "delete".into(),
+            Action::Rename => "rename".into(),
+            Action::OpenInEditor => "open_in_editor".into(),
+            Action::RevealInFileManager => "reveal_in_file_manager".into(),
+            Action::OpenFolder => "open_folder".into(),
+            Action::ToggleSharpen => "toggle_sharpen".into(),
+            Action::ToggleClipping => "toggle_clipping".into(),
+            Action::RotateClockwise => "rotate_clockwise".into(),
+            Action::RotateCounterclockwise => "rotate_counterclockwise".into(),
+            Action::FlipHorizontal => "flip_horizontal".into(),
+            Action::FlipVertical => "flip_vertical".into(),
+            Action::SaveOrientation => "save_orientation".into(),
+            Action::CycleHdr => "cycle_hdr".into(),
+            Action::CycleAlphaBackground => "cycle_alpha_background".into(),
+            Action::CycleSampling => "cycle_sampling".into(),
+            Action::CycleTransformMemory => "cycle_transform_memory".into(),
+            Action::ClearTransforms => "clear_transforms".into(),
+            Action::Flag(FlagStatus::Picked) => "pick".into(),
+            Action::Flag(FlagStatus::Rejected) => "reject".into(),
+            Action::Flag(FlagStatus::None) => "unflag".into(),
+            Action::CycleFlagFilter => "cycle_flag_filter".into(),
+            Action::SelectAll => "select_all".into(),
+            Action::ClearSelection => "clear_selection".into(),
+            Action::CopyImage => "copy_image".into(),
+            Action::CopyPaths => "copy_paths".into(),
+            Action::CopyToTarget(slot) => format!("copy_to_target_{}", slot),
+            Action::Export => "export".into(),
+            Action::ToggleStatistics => "toggle_statistics".into(),
+            Action::ToggleInfo => "toggle_info".into(),
+            Action::ToggleUi => "toggle_ui".into(),
+            Action::ToggleAutoHide => "toggle_auto_hide".into(),
+            Action::ToggleFilmstrip => "toggle_filmstrip".into(),
+            Action::ToggleCompare => "toggle_compare".into(),
+            Action::FlickerPrevious => "flicker_previous".into(),
+            Action::ToggleDebugOverlay => "toggle_debug_overlay".into(),
+            Action::UiZoomIn => "ui_zoom_in".into(),
+            Action::UiZoomOut => "ui_zoom_out".into(),
+            Action::UiZoomReset => "ui_zoom_reset".into(),
+            Action::ToggleSettings => "toggle_settings".into(),
+            Action::ToggleHelp => "toggle_help".into(),
+            Action::Quit => "quit".into(),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Action> {
+        ACTIONS.into_iter().find(|action| action.name() == name)
+    }
+}
+
+/// A key together with the modifiers that have to be held for it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct KeyBinding {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    pub key: Key,
+}
+
+impl KeyBinding {
+    const fn new(key: Key) -> Self {
+        KeyBinding {
+            ctrl: false,
+            alt: false,
+            shift: false,
+            key,
+        }
+    }
+
+    fn from_event(key: Key, modifiers: &Modifiers) -> Self {
+        KeyBinding {
+            ctrl: modifiers.ctrl || modifiers.command,
+            alt: modifiers.alt,
+            shift: modifiers.shift,
+            key,
+        }
+    }
+
+    /// Parses bindings such as `Space`, `Alt+S` or `Ctrl+Shift+R`.
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut parts = text.split('+').map(str::trim).collect::<Vec<_>>();
+        let mut binding = KeyBinding::new(Key::from_name(parts.pop()?)?);
+        for modifier in parts {
+            match modifier.to_ascii_lowercase().as_str() {
+                "ctrl" | "cmd" => binding.ctrl = true,
+                "alt" => binding.alt = true,
+                "shift" => binding.shift = true,
+                _ => return None,
+            }
+        }
+        Some(binding)
+    }
+}
+
+impl fmt::Display for KeyBinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.ctrl {
+            write!(f, "Ctrl+")?;
+        }
+        if self.alt {
+            write!(f, "Alt+")?;
+        }
+        if self.shift {
+            write!(f, "Shift+")?;
+        }
+        write!(f, "{}", self.key.name())
+    }
+}
+
+fn default_bindings() -> Vec<(KeyBinding, Action)> {
+    let with_alt = |key| KeyBinding {
+        alt: true,
+        ..KeyBinding::new(key)
+    };
+    let with_shift = |key| KeyBinding {
+        shift: true,
+        ..KeyBinding::new(key)
+    };
+    let with_ctrl = |key| KeyBinding {
+        ctrl: true,
+        ..KeyBinding::new(key)
+    };
+    vec![
+        (KeyBinding::new(Key::ArrowRight), Action::NextImage),
+        (KeyBinding::new(Key::ArrowLeft), Action::PrevImage),
+        (with_shift(Key::ArrowRight), Action::NextInBurst),
+        (with_shift(Key::ArrowLeft), Action::PrevInBurst),
+        (KeyBinding::new(Key::Home), Action::JumpFirst),
+        (KeyBinding::new(Key::End), Action::JumpLast),
+        (KeyBinding::new(Key::PageDown), Action::PageForward),
+        (KeyBinding::new(Key::PageUp), Action::PageBack),
+        (with_shift(Key::PageDown), Action::NextBurst),
+        (with_shift(Key::PageUp), Action::PrevBurst),
+        (with_ctrl(Key::D), Action::JumpToDate),
+        (KeyBinding::new(Key::ArrowUp), Action::RateUp),
+        (KeyBinding::new(Key::ArrowDown), Action::RateDown),
+        (KeyBinding::new(Key::Backtick), Action::Rate(0)),
+        (KeyBinding::new(Key::Num0), Action::Rate(0)),
+        (KeyBinding::new(Key::Num1), Action::Rate(1)),
+        (KeyBinding::new(Key::Num2), Action::Rate(2)),
+        (KeyBinding::new(Key::Num3), Action::Rate(3)),
+        (KeyBinding::new(Key::Num4), Action::Rate(4)),
+        (KeyBinding::new(Key::Num5), Action::Rate(5)),
+        (KeyBinding::new(Key::Z), Action::ToggleOneToOne),
+        (KeyBinding::new(Key::F), Action::Fit),
+        (KeyBinding::new(Key::A), Action::PanLeft),
+        (KeyBinding::new(Key::D), Action::PanRight),
+        (KeyBinding::new(Key::W), Action::PanUp),
+        (KeyBinding::new(Key::S), Action::PanDown),
+        (KeyBinding::new(Key::Plus), Action::ZoomIn),
+        // Most layouts need Shift to type a plus
+        (with_shift(Key::Plus), Action::ZoomIn),
+        (KeyBinding::new(Key::Equals), Action::ZoomIn),
+        (KeyBinding::new(Key::Minus), Action::ZoomOut),
+        (KeyBinding::new(Key::B), Action::ToggleBurstMode),
+        (KeyBinding::new(Key::T), Action::CycleStackMember),
+        (KeyBinding::new(Key::Delete), Action::Delete),
+        (KeyBinding::new(Key::F2), Action::Rename),
+        (KeyBinding::new(Key::E), Action::OpenInEditor),
+        (with_shift(Key::E), Action::RevealInFileManager),
+        (with_ctrl(Key::O), Action::OpenFolder),
+        (with_alt(Key::S), Action::ToggleSharpen),
+        (KeyBinding::new(Key::J), Action::ToggleClipping),
+        (KeyBinding::new(Key::R), Action::RotateClockwise),
+        (with_shift(Key::R), Action::RotateCounterclockwise),
+        (KeyBinding::new(Key::H), Action::FlipHorizontal),
+        (KeyBinding::new(Key::V), Action::FlipVertical),
+        (with_ctrl(Key::S), Action::SaveOrientation),
+        (with_alt(Key::H), Action::CycleHdr),
+        (with_alt(Key::B), Action::CycleAlphaBackground),
+        (with_alt(Key::N), Action::CycleSampling),
+        (with_alt(Key::T), Action::CycleTransformMemory),
+        (
+            KeyBinding {
+                ctrl: true,
+                shift: true,
+                ..KeyBinding::new(Key::R)
+            },
+            Action::ClearTransforms,
+        ),
+        (KeyBinding::new(Key::P), Action::Flag(FlagStatus::Picked)),
+        (KeyBinding::new(Key::X), Action::Flag(FlagStatus::Rejected)),
+        (KeyBinding::new(Key::U), Action::Flag(FlagStatus::None)),
+        (with_shift(Key::X), Action::CycleFlagFilter),
+        (with_ctrl(Key::A), Action::SelectAll),
+        (
+            KeyBinding {
+                ctrl: true,
+                shift: true,
+                ..KeyBinding::new(Key::A)
+            },
+            Action::ClearSelection,
+        ),
+        // egui delivers these as a copy event rather than a key press, see
+        // `KeyMap::copy_action`
+        (with_ctrl(Key::C), Action::CopyImage),
+        (
+            KeyBinding {
+                ctrl: true,
+                shift: true,
+                ..KeyBinding::new(Key::C)
+            },
+            Action::CopyPaths,
+        ),
+        // Ctrl+S saves the orientation
+        (
+            KeyBinding {
+                ctrl: true,
+                shift: true,
+                ..KeyBinding::new(Key::S)
+            },
+            Action::Export,
+        ),
+        (with_ctrl(Key::Num1), Action::CopyToTarget(1)),
+        (with_ctrl(Key::Num2), Action::CopyToTarget(2)),
+        (with_ctrl(Key::Num3), Action::CopyToTarget(3)),
+        (with_ctrl(Key::Num4), Action::CopyToTarget(4)),
+        (with_ctrl(Key::Num5), Action::CopyToTarget(5)),
+        (with_shift(Key::I), Action::ToggleStatistics),
+        (KeyBinding::new(Key::I), Action::ToggleInfo),
+        (KeyBinding::new(Key::Tab), Action::ToggleUi),
+        (with_shift(Key::Tab), Action::ToggleAutoHide),
+        (KeyBinding::new(Key::G), Action::ToggleFilmstrip),
+        (KeyBinding::new(Key::C), Action::ToggleCompare),
+        (KeyBinding::new(Key::Backslash), Action::FlickerPrevious),
+        (KeyBinding::new(Key::F3), Action::ToggleDebugOverlay),
+        (with_ctrl(Key::Plus), Action::UiZoomIn),
+        (with_ctrl(Key::Equals), Action::UiZoomIn),
+        (with_ctrl(Key::Minus), Action::UiZoomOut),
+        (with_ctrl(Key::Num0), Action::UiZoomReset),
+        (with_ctrl(Key::Comma), Action::ToggleSettings),
+        (KeyBinding::new(Key::F1), Action::ToggleHelp),
+        (KeyBinding::new(Key::Escape), Action::Quit),
+    ]
+}
+
+pub struct KeyMap {
+    bindings: HashMap<KeyBinding, Action>,
+}
+
+impl KeyMap {
+    /// Builds the default map with the `[keys]` overrides from the config applied.
+    /// An action of `"none"` unbinds the key. Returns warnings for entries that could
+    /// not be understood.
+    pub fn new(overrides: &BTreeMap<String, String>) -> (Self, Vec<String>) {
+        let mut bindings = default_bindings().into_iter().collect::<HashMap<_, _>>();
+        let mut warnings = Vec::new();
+        for (key, action) in overrides {
+            let Some(binding) = KeyBinding::parse(key) else {
+                warnings.push(format!("Unknown key `{}` in [keys]", key));
+                continue;
+            };
+            if action == "none" {
+                bindings.remove(&binding);
+            } else if let Some(action) = Action::from_name(action) {
+                bindings.insert(binding, action);
+            } else {
+                warnings.push(format!("Unknown action `{}` for key `{}`", action, key));
+            }
+        }
+        (KeyMap { bindings }, warnings)
+    }
+
+    /// Looks up the action for a key press. Only a binding with exactly the held
+    /// modifiers matches, so an unbound Ctrl+P does nothing rather than flagging.
+    pub fn action(&self, key: Key, modifiers: &Modifiers) -> Option<Action> {
+        self.bindings
+            .get(&KeyBinding::from_event(key, modifiers))
+            .copied()
+    }
+
+    /// Looks up the action for a copy event, which egui sends in place of the key press
+    /// for Ctrl+C with any other modifiers. Like `action`, the modifiers must match.
+    pub fn copy_action(&self, modifiers: &Modifiers) -> Option<Action> {
+        self.bindings
+            .get(&KeyBinding::from_event(Key::C, modifiers))
+            .copied()
+    }
+
+    /// The effective bindings, sorted by key, for display.
+    pub fn bindings(&self) -> Vec<(KeyBinding, Action)> {
+        let mut bindings = self
+            .bindings
+            .iter()
+            .map(|(binding, action)| (*binding, *action))
+            .collect::<Vec<_>>();
+        bindings.sort_by_key(|(binding, _)| (binding.key, *binding));
+        bindings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn action_needs_the_exact_modifiers() {
+        let (keymap, warnings) = KeyMap::new(&BTreeMap::new());
+        assert!(warnings.is_empty());
+        assert_eq!(
+            keymap.action(Key::ArrowRight, &Modifiers::NONE),
+            Some(Action::NextImage)
+        );
+        assert_eq!(
+            keymap.action(Key::ArrowRight, &Modifiers::SHIFT),
+            Some(Action::NextInBurst)
+        );
+        // Neither Ctrl+Right nor Ctrl+P is bound, so they don't fall back to the plain key
+        assert_eq!(keymap.action(Key::ArrowRight, &Modifiers::CTRL), None);
+        assert_eq!(keymap.action(Key::P, &Modifiers::CTRL), None);
+        assert_eq!(
+            keymap.action(Key::S, &Modifiers::ALT),
+            Some(Action::ToggleSharpen)
+        );
+    }
+}
@@ -0,0 +1,231 @@
+use egui_wgpu::wgpu;
+
+// Screen pixels per image pixel between which the compute resampler replaces
+// plain linear filtering
+pub const LANCZOS_MIN_SCALE: f32 = 0.1;
+pub const LANCZOS_MAX_SCALE: f32 = 0.5;
+
+const WORKGROUP_SIZE: u32 = 8;
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct Resample {
+    src_size: [u32; 2],
+    dst_size: [u32; 2],
+}
+
+/// Separable Lanczos-2 downscaler: a horizontal compute pass into an intermediate
+/// texture followed by a vertical pass into the output texture the fragment shader
+/// samples from.
+pub struct LanczosResampler {
+    bind_group_layout: wgpu::BindGroupLayout,
+    horizontal_pipeline: wgpu::ComputePipeline,
+    vertical_pipeline: wgpu::ComputePipeline,
+    params_buffer: wgpu::Buffer,
+    intermediate: wgpu::Texture,
+    pub output: wgpu::Texture,
+    /// Size of the image currently in `output`, if it is up to date
+    size: Option<(u32, u32)>,
+}
+
+fn create_target(device: &wgpu::Device, label: &str, width: u32, height: u32) -> wgpu::Texture {
+    // Sized to powers of two like the image texture, so zooming rarely reallocates
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width: width.next_power_of_two(),
+            height: height.next_power_of_two(),
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba16Float,
+        usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    })
+}
+
+fn fits(texture: &wgpu::Texture, width: u32, height: u32) -> bool {
+    texture.width() >= width && texture.height() >= height
+}
+
+impl LanczosResampler {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Lanczos Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: wgpu::TextureFormat::Rgba16Float,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Lanczos Shader"),
+            source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Borrowed(include_str!(
+                "lanczos.wgsl"
+            ))),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Lanczos Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let create_pipeline = |entry_point: &str| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("Lanczos Pipeline"),
+                layout: Some(&layout),
+                module: &shader,
+                entry_point: Some(entry_point),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                cache: None,
+            })
+        };
+
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Lanczos Uniform Buffer"),
+            size: std::mem::size_of::<Resample>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            horizontal_pipeline: create_pipeline("horizontal"),
+            vertical_pipeline: create_pipeline("vertical"),
+            bind_group_layout,
+            params_buffer,
+            intermediate: create_target(device, "Lanczos intermediate texture", 1, 1),
+            output: create_target(device, "Lanczos output texture", 1, 1),
+            size: None,
+        }
+    }
+
+    pub fn size(&self) -> Option<(u32, u32)> {
+        self.size
+    }
+
+    /// Marks the output stale, e.g. after a new image was uploaded.
+    pub fn invalidate(&mut self) {
+        self.size = None;
+    }
+
+    /// Downscales the `src_size` region of `source` to `dst_size`. Returns true if the
+    /// output texture had to be reallocated, in which case bind groups sampling it
+    /// must be recreated.
+    pub fn resample(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        source: &wgpu::Texture,
+        src_size: (u32, u32),
+        dst_size: (u32, u32),
+    ) -> bool {
+        if !fits(&self.intermediate, dst_size.0, src_size.1) {
+            self.intermediate = create_target(
+                device,
+                "Lanczos intermediate texture",
+                dst_size.0,
+                src_size.1,
+            );
+        }
+        let reallocated = !fits(&self.output, dst_size.0, dst_size.1);
+        if reallocated {
+            self.output = create_target(device, "Lanczos output texture", dst_size.0, dst_size.1);
+        }
+
+        queue.write_buffer(
+            &self.params_buffer,
+            0,
+            bytemuck::cast_slice(&[Resample {
+                src_size: [src_size.0, src_size.1],
+                dst_size: [dst_size.0, dst_size.1],
+            }]),
+        );
+
+        let source_view = source.create_view(&wgpu::TextureViewDescriptor::default());
+        let intermediate_view = self
+            .intermediate
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let output_view = self
+            .output
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = |input: &wgpu::TextureView, output: &wgpu::TextureView| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Lanczos Bind Group"),
+                layout: &self.bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(input),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(output),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: self.params_buffer.as_entire_binding(),
+                    },
+                ],
+            })
+        };
+        let horizontal_bind_group = bind_group(&source_view, &intermediate_view);
+        let vertical_bind_group = bind_group(&intermediate_view, &output_view);
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Lanczos Encoder"),
+        });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Lanczos Pass"),
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&self.horizontal_pipeline);
+            pass.set_bind_group(0, &horizontal_bind_group, &[]);
+            pass.dispatch_workgroups(
+                dst_size.0.div_ceil(WORKGROUP_SIZE),
+                src_size.1.div_ceil(WORKGROUP_SIZE),
+                1,
+            );
+            pass.set_pipeline(&self.vertical_pipeline);
+            pass.set_bind_group(0, &vertical_bind_group, &[]);
+            pass.dispatch_workgroups(
+                dst_size.0.div_ceil(WORKGROUP_SIZE),
+                dst_size.1.div_ceil(WORKGROUP_SIZE),
+                1,
+            );
+        }
+        queue.submit(Some(encoder.finish()));
+
+        self.size = Some(dst_size);
+        reallocated
+    }
+}
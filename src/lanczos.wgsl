@@ -0,0 +1,64 @@
+struct Resample {
+    src_size: vec2<u32>,
+    dst_size: vec2<u32>,
+};
+@group(0) @binding(0) var source: texture_2d<f32>;
+@group(0) @binding(1) var destination: texture_storage_2d<rgba16float, write>;
+@group(0) @binding(2) var<uniform> resample: Resample;
+
+const PI: f32 = 3.14159265358979;
+
+fn sinc(x: f32) -> f32 {
+    if (abs(x) < 1e-5) {
+        return 1.0;
+    }
+    return sin(PI * x) / (PI * x);
+}
+
+fn lanczos2_kernel(x: f32) -> f32 {
+    if (abs(x) >= 2.0) {
+        return 0.0;
+    }
+    return sinc(x) * sinc(x / 2.0);
+}
+
+// Filters one destination pixel along a single axis. When downscaling the kernel is
+// stretched by the scale ratio so every source pixel contributes.
+fn resample_axis(pixel: vec2<u32>, horizontal: bool) -> vec4<f32> {
+    let src_len = select(resample.src_size.y, resample.src_size.x, horizontal);
+    let dst_len = select(resample.dst_size.y, resample.dst_size.x, horizontal);
+    let i = select(pixel.y, pixel.x, horizontal);
+    let ratio = max(f32(src_len) / f32(dst_len), 1.0);
+    let center = (f32(i) + 0.5) * f32(src_len) / f32(dst_len);
+    let first = max(i32(floor(center - 2.0 * ratio)), 0);
+    let last = min(i32(ceil(center + 2.0 * ratio)), i32(src_len) - 1);
+
+    var sum = vec4<f32>(0.0);
+    var weight_sum = 0.0;
+    for (var s = first; s <= last; s++) {
+        let weight = lanczos2_kernel((f32(s) + 0.5 - center) / ratio);
+        let coords = select(vec2<i32>(i32(pixel.x), s), vec2<i32>(s, i32(pixel.y)), horizontal);
+        sum += weight * textureLoad(source, coords, 0);
+        weight_sum += weight;
+    }
+    // The negative lobes can overshoot at hard edges
+    return clamp(sum / weight_sum, vec4<f32>(0.0), vec4<f32>(1.0));
+}
+
+// Source -> intermediate, which is dst_size.x wide and src_size.y tall
+@compute @workgroup_size(8, 8)
+fn horizontal(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= resample.dst_size.x || id.y >= resample.src_size.y) {
+        return;
+    }
+    textureStore(destination, id.xy, resample_axis(id.xy, true));
+}
+
+// Intermediate -> output
+@compute @workgroup_size(8, 8)
+fn vertical(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= resample.dst_size.x || id.y >= resample.dst_size.y) {
+        return;
+    }
+    textureStore(destination, id.xy, resample_axis(id.xy, false));
+}
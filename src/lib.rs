@@ -1,2 +1,8 @@
+pub mod benchmark;
+pub mod config;
+pub mod dedup;
+pub mod export;
 pub mod image;
+pub mod load_queue;
+pub mod manifest;
 pub mod store;
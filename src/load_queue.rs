@@ -0,0 +1,86 @@
+use crate::image::ImageData;
+use crate::store::MessageType;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::Mutex;
+
+struct PendingLoad {
+    priority: i32,
+    /// Position of the image in the store, which the priority is derived from
+    id: usize,
+    kind: MessageType,
+    image: ImageData,
+}
+
+impl PartialEq for PendingLoad {
+    fn eq(&self, other: &Self) -> bool {
+        self.priority == other.priority
+    }
+}
+
+impl Eq for PendingLoad {}
+
+impl PartialOrd for PendingLoad {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PendingLoad {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority.cmp(&other.priority)
+    }
+}
+
+/// Closer images come first, and any full image before any thumbnail.
+fn priority(kind: MessageType, id: usize, current_id: usize) -> i32 {
+    let distance = id.abs_diff(current_id).min(i32::MAX as usize / 2) as i32;
+    match kind {
+        MessageType::Full => i32::MAX - distance,
+        MessageType::Thumbnail => i32::MAX / 2 - distance,
+    }
+}
+
+/// Decodes waiting for a worker, ordered by distance from the current image.
+///
+/// Jobs on the thread pool don't carry an image of their own. Every job pops the
+/// most urgent load at the moment it starts, so when the user skips through images
+/// the one they stop on overtakes the preloads queued on the way there.
+#[derive(Default)]
+pub struct LoadQueue {
+    pending: Mutex<BinaryHeap<PendingLoad>>,
+}
+
+impl LoadQueue {
+    pub fn push(&self, image: ImageData, kind: MessageType, id: usize, current_id: usize) {
+        self.pending.lock().unwrap().push(PendingLoad {
+            priority: priority(kind, id, current_id),
+            id,
+            kind,
+            image,
+        });
+    }
+
+    pub fn pop(&self) -> Option<(ImageData, MessageType)> {
+        let load = self.pending.lock().unwrap().pop()?;
+        Some((load.image, load.kind))
+    }
+
+    /// Reorders the pending loads after navigating to `current_id`.
+    pub fn reprioritize(&self, current_id: usize) {
+        let mut pending = self.pending.lock().unwrap();
+        *pending = std::mem::take(&mut *pending)
+            .into_iter()
+            .map(|mut load| {
+                load.priority = priority(load.kind, load.id, current_id);
+                load
+            })
+            .collect();
+    }
+
+    /// Drops every pending load, so the jobs still waiting on the pool return
+    /// without decoding anything.
+    pub fn clear(&self) {
+        self.pending.lock().unwrap().clear();
+    }
+}
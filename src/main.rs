@@ -1,26 +1,193 @@
-use clap::Parser;
+use clap::{ArgAction, Parser, Subcommand};
+use imflow::benchmark::benchmark_directory;
+use imflow::config::{Config, parse_backend, parse_hex_color};
+use imflow::export::export_contact_sheet;
+use imflow::image::{ImageData, load_listed_images};
+use imflow::manifest::{ManifestFormat, export_directory};
+use imflow::store::ImageStore;
+use std::io::{BufRead, IsTerminal};
 use std::path::PathBuf;
+use std::sync::Arc;
 
 mod app;
+#[cfg(target_os = "linux")]
+mod dbus;
+mod debug_overlay;
 mod egui_tools;
+mod external;
+mod gpu_profile;
+mod keymap;
+mod lanczos;
+mod map_tile;
+mod settings;
+mod toast;
 
 use winit::event_loop::{ControlFlow, EventLoop};
 
 fn main() {
     let args = Args::parse();
-    let path = args.path.unwrap_or("./test_images".into());
+    if let Some(Command::ImportManifest { manifest, path }) = args.command {
+        let path = path.unwrap_or("./test_images".into());
+        // Through a store, so ratings reach sidecars and stacks as in the viewer
+        let mut store = ImageStore::new(path, &Config::load(), Arc::default());
+        match store.import_manifest(&manifest) {
+            Ok(report) => {
+                for path in &report.unmatched {
+                    eprintln!("No image matches {}", path);
+                }
+                for (path, rating) in &report.invalid_ratings {
+                    eprintln!("Ignoring rating {} of {}: not 0 to 5", rating, path);
+                }
+                for (path, error) in &report.failed {
+                    eprintln!("Failed to write {}: {}", path, error);
+                }
+                println!("{}", report.summary());
+            }
+            Err(e) => {
+                eprintln!("Failed to read manifest: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    let path = args.path.clone().unwrap_or("./test_images".into());
+    if args.benchmark {
+        let report = benchmark_directory(path);
+        if args.json {
+            println!("{}", serde_json::to_string_pretty(&report).unwrap());
+        } else {
+            println!("{}", report);
+        }
+        return;
+    }
+    if let Some(manifest) = args.export_manifest {
+        let format = ManifestFormat::from_path(&manifest);
+        match export_directory(path, &manifest, format) {
+            Ok(count) => println!("Exported {} images to {}", count, manifest.display()),
+            Err(e) => {
+                eprintln!("Failed to export manifest: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if let Some(output) = args.contact_sheet {
+        match export_contact_sheet(path, &output, args.cs_columns, args.cs_rows, args.cs_label) {
+            Ok(count) => println!(
+                "Wrote a contact sheet of {} images to {}",
+                count,
+                output.display()
+            ),
+            Err(e) => {
+                eprintln!("Failed to write contact sheet: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
     #[cfg(not(target_arch = "wasm32"))]
     {
-        pollster::block_on(run(path));
+        let mut config = Config::load();
+        if args.hdr {
+            config.hdr_output = true;
+        }
+        if args.low_power {
+            config.low_power = true;
+        }
+        if args.high_performance {
+            config.low_power = false;
+            config.high_performance = true;
+        }
+        if let Some(backend) = &args.backend {
+            // Only valid names get past clap
+            config.backend = parse_backend(backend).unwrap();
+        }
+        if args.gpu_profile {
+            config.gpu_profile = true;
+        }
+        if args.debug_overlay {
+            config.debug_overlay = true;
+        }
+        if args.no_decorations {
+            config.decorations = false;
+        }
+        if args.wrap_navigation {
+            config.wrap_navigation = true;
+        }
+        if args.darktable_compat {
+            config.darktable_compat = true;
+        }
+        if let Some(background_color) = args.background_color {
+            config.background_color = background_color;
+        }
+        // `find … | imflow` shows the listed files instead of a folder
+        let image_list = read_piped_paths().map(|paths| {
+            let (images, rejected) = load_listed_images(paths);
+            for path in rejected {
+                eprintln!(
+                    "Warning: skipping {}, not a supported image",
+                    path.display()
+                );
+            }
+            images
+        });
+        if args.headless {
+            let mut app = app::App::new(path, config);
+            if let Some(images) = image_list {
+                app.set_image_list(images);
+            }
+            app.run_headless(
+                args.image_count.unwrap_or(usize::MAX),
+                args.save_screenshots.as_deref(),
+            );
+            return;
+        }
+        // Without a path, pick up where the last session left off
+        let path = match config.last_folder.clone() {
+            Some(last_folder)
+                if args.path.is_none() && image_list.is_none() && last_folder.is_dir() =>
+            {
+                last_folder
+            }
+            _ => path,
+        };
+        pollster::block_on(run(path, image_list, config));
+    }
+}
+
+/// Paths piped in on stdin, one per line. `None` when stdin is a terminal or
+/// nothing was piped.
+fn read_piped_paths() -> Option<Vec<PathBuf>> {
+    let stdin = std::io::stdin();
+    if stdin.is_terminal() {
+        return None;
     }
+    let paths = stdin
+        .lock()
+        .lines()
+        .map_while(Result::ok)
+        .map(|line| line.trim_end_matches('\r').to_string())
+        .filter(|line| !line.is_empty())
+        .map(PathBuf::from)
+        .collect::<Vec<_>>();
+    (!paths.is_empty()).then_some(paths)
 }
 
-async fn run(path: PathBuf) {
+async fn run(path: PathBuf, image_list: Option<Vec<ImageData>>, config: Config) {
     let event_loop = EventLoop::new().unwrap();
 
-    event_loop.set_control_flow(ControlFlow::Poll);
+    // Frames are drawn on input, while something moves, and when other threads wake
+    // the loop through the proxy
+    event_loop.set_control_flow(ControlFlow::Wait);
+    let proxy = event_loop.create_proxy();
 
-    let mut app = app::App::new(path);
+    let mut app = app::App::new(path, config);
+    app.set_wake(Arc::new(move || {
+        let _ = proxy.send_event(());
+    }));
+    if let Some(images) = image_list {
+        app.set_image_list(images);
+    }
 
     event_loop.run_app(&mut app).expect("Failed to run app");
 }
@@ -28,5 +195,90 @@ async fn run(path: PathBuf) {
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
     path: Option<PathBuf>,
+    /// Use a 16-bit float swapchain on displays that support it
+    #[arg(long)]
+    hdr: bool,
+    /// Prefer the integrated GPU on dual-GPU systems
+    #[arg(long, conflicts_with = "high_performance")]
+    low_power: bool,
+    /// Prefer the discrete GPU on dual-GPU systems
+    #[arg(long)]
+    high_performance: bool,
+    /// Graphics API to render with: vulkan, metal, dx12, opengl or auto
+    #[arg(
+        long,
+        value_name = "BACKEND",
+        value_parser = ["vulkan", "metal", "dx12", "opengl", "auto"]
+    )]
+    backend: Option<String>,
+    /// Print GPU and CPU frame times every 100 frames
+    #[arg(long)]
+    gpu_profile: bool,
+    /// Show loader queue depth, decode throughput and frame times over the image from
+    /// the start, as F3 does
+    #[arg(long)]
+    debug_overlay: bool,
+    /// Open the window without a title bar and borders
+    #[arg(long)]
+    no_decorations: bool,
+    /// Continue at the first image after the last one and vice versa
+    #[arg(long)]
+    wrap_navigation: bool,
+    /// Read ratings and colour labels from Darktable's XMP sidecars and write ratings
+    /// back to them
+    #[arg(long)]
+    darktable_compat: bool,
+    /// Render the images offscreen, without a window, and print frame timings
+    #[arg(long)]
+    headless: bool,
+    /// Number of images to render in headless mode, all of them by default
+    #[arg(long, value_name = "N", requires = "headless")]
+    image_count: Option<usize>,
+    /// Save every frame rendered in headless mode as a PNG in DIR
+    #[arg(long, value_name = "DIR", requires = "headless")]
+    save_screenshots: Option<PathBuf>,
+    /// Write ratings and metadata to FILE (.csv or .json) and exit without opening a window
+    #[arg(long, value_name = "FILE")]
+    export_manifest: Option<PathBuf>,
+    /// Tile the thumbnails of PATH into one image at FILE (.png or .jpg) and exit
+    /// without opening a window
+    #[arg(long, value_name = "FILE", conflicts_with = "headless")]
+    contact_sheet: Option<PathBuf>,
+    /// Thumbnails per row of the contact sheet
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 5,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..),
+        requires = "contact_sheet"
+    )]
+    cs_columns: usize,
+    /// Only include the first N rows of images in the contact sheet
+    #[arg(long, value_name = "N", requires = "contact_sheet")]
+    cs_rows: Option<usize>,
+    /// Write the file names under the contact sheet thumbnails
+    #[arg(long, value_name = "BOOL", default_value_t = true, action = ArgAction::Set)]
+    cs_label: bool,
+    /// Time the thumbnail and full decode of every image in PATH and exit without
+    /// opening a window
+    #[arg(long, conflicts_with = "headless")]
+    benchmark: bool,
+    /// Print the benchmark report as JSON
+    #[arg(long, requires = "benchmark")]
+    json: bool,
+    /// Background color as an sRGB hex value, e.g. 1a1a1a
+    #[arg(long, value_name = "HEX", value_parser = parse_hex_color)]
+    background_color: Option<[f64; 4]>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Apply ratings and labels from a CSV/JSON manifest to the images in PATH
+    ImportManifest {
+        manifest: PathBuf,
+        path: Option<PathBuf>,
+    },
 }
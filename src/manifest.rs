@@ -0,0 +1,166 @@
+use crate::image::{ImageData, ImageMetadata, load_available_images, load_metadata};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ManifestFormat {
+    Csv,
+    Json,
+}
+
+impl ManifestFormat {
+    /// Picks the format from the file extension, defaulting to CSV.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("json") => ManifestFormat::Json,
+            _ => ManifestFormat::Csv,
+        }
+    }
+}
+
+/// One image in a ratings manifest. The same schema is used for export and import.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ManifestRow {
+    pub path: String,
+    pub rating: Option<i32>,
+    pub label: Option<String>,
+    pub capture_date: Option<String>,
+    pub camera: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+impl ManifestRow {
+    pub fn new(image: &ImageData, metadata: &ImageMetadata) -> Self {
+        let path = image
+            .path
+            .canonicalize()
+            .unwrap_or_else(|_| image.path.clone());
+        ManifestRow {
+            path: path.to_string_lossy().into_owned(),
+            rating: metadata.rating,
+            label: metadata.label.clone(),
+            capture_date: metadata
+                .capture_time
+                .map(|time| time.format("%Y-%m-%dT%H:%M:%S%.3f").to_string()),
+            camera: metadata.camera.clone(),
+            width: (metadata.width > 0).then_some(metadata.width),
+            height: (metadata.height > 0).then_some(metadata.height),
+        }
+    }
+}
+
+pub fn write_manifest(rows: &[ManifestRow], path: &Path, format: ManifestFormat) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    match format {
+        ManifestFormat::Csv => {
+            let mut csv_writer = csv::Writer::from_writer(writer);
+            for row in rows {
+                csv_writer.serialize(row)?;
+            }
+            csv_writer.flush()?;
+        }
+        ManifestFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, rows)?;
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
+
+/// Scans `dir` and writes a manifest of every image without creating an `ImageStore`.
+pub fn export_directory(dir: PathBuf, path: &Path, format: ManifestFormat) -> io::Result<usize> {
+    let rows = load_available_images(dir)
+        .iter()
+        .map(|image| ManifestRow::new(image, &load_metadata(image)))
+        .collect::<Vec<_>>();
+    write_manifest(&rows, path, format)?;
+    Ok(rows.len())
+}
+
+pub fn read_manifest(path: &Path, format: ManifestFormat) -> io::Result<Vec<ManifestRow>> {
+    let reader = BufReader::new(File::open(path)?);
+    match format {
+        ManifestFormat::Csv => csv::Reader::from_reader(reader)
+            .deserialize()
+            .collect::<Result<Vec<ManifestRow>, _>>()
+            .map_err(io::Error::from),
+        ManifestFormat::Json => Ok(serde_json::from_reader(reader)?),
+    }
+}
+
+/// Outcome of applying a manifest to a set of images.
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub applied: usize,
+    /// Manifest paths that did not correspond to any image
+    pub unmatched: Vec<String>,
+    /// Manifest paths whose metadata could not be written, with the error
+    pub failed: Vec<(String, String)>,
+    /// Manifest paths whose rating is outside 0 to 5, with the rating. Their label
+    /// is still applied
+    pub invalid_ratings: Vec<(String, i32)>,
+}
+
+impl ImportReport {
+    pub fn summary(&self) -> String {
+        format!(
+            "Applied {} rows, {} unmatched, {} failed, {} invalid ratings",
+            self.applied,
+            self.unmatched.len(),
+            self.failed.len(),
+            self.invalid_ratings.len()
+        )
+    }
+}
+
+/// Pairs manifest rows with images. Relative paths are resolved against the manifest's
+/// directory and the images' directory; as a last resort a unique file name matches.
+pub fn match_rows(
+    rows: Vec<ManifestRow>,
+    images: &[ImageData],
+    manifest_path: &Path,
+) -> (Vec<(ImageData, ManifestRow)>, Vec<String>) {
+    let canonical = |path: &Path| path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    let by_path: HashMap<PathBuf, &ImageData> = images
+        .iter()
+        .map(|image| (canonical(&image.path), image))
+        .collect();
+    let mut by_name: HashMap<&std::ffi::OsStr, Vec<&ImageData>> = HashMap::new();
+    for image in images {
+        if let Some(name) = image.path.file_name() {
+            by_name.entry(name).or_default().push(image);
+        }
+    }
+    let bases: Vec<PathBuf> = [
+        manifest_path.parent(),
+        images.first().and_then(|image| image.path.parent()),
+    ]
+    .into_iter()
+    .flatten()
+    .map(Path::to_path_buf)
+    .collect();
+
+    let mut matched = Vec::new();
+    let mut unmatched = Vec::new();
+    for row in rows {
+        let row_path = PathBuf::from(&row.path);
+        let found = std::iter::once(row_path.clone())
+            .chain(bases.iter().map(|base| base.join(&row_path)))
+            .find_map(|candidate| by_path.get(&canonical(&candidate)).copied())
+            .or_else(
+                || match row_path.file_name().and_then(|name| by_name.get(name)) {
+                    Some(candidates) if candidates.len() == 1 => Some(candidates[0]),
+                    _ => None,
+                },
+            );
+        match found {
+            Some(image) => matched.push((image.clone(), row)),
+            None => unmatched.push(row.path),
+        }
+    }
+    (matched, unmatched)
+}
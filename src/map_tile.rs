@@ -0,0 +1,219 @@
+use crate::egui_tools::EguiRenderer;
+use imflow::store::Wake;
+use std::collections::{HashMap, HashSet};
+use std::f64::consts::PI;
+use std::fs;
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+const TILE_SERVER: &str = "https://tile.openstreetmap.org";
+// The tile usage policy asks clients to identify themselves
+const USER_AGENT: &str = concat!("imflow/", env!("CARGO_PKG_VERSION"));
+// Tiles are ~20 KiB, anything much larger isn't one
+const MAX_TILE_BYTES: u64 = 1 << 20;
+// A stalled download would otherwise hold up every tile after it
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(10);
+// Beyond this the Mercator projection goes off to infinity
+const MAX_LATITUDE: f64 = 85.051_128_78;
+// Side of the tile in the info panel, in points
+const MAP_TILE_SIZE: f32 = 200.0;
+const MARKER_RADIUS: f32 = 5.0;
+
+/// One 256×256 tile of the slippy map.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TileKey {
+    pub zoom: u8,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl TileKey {
+    /// The tile containing `(latitude, longitude)` at `zoom`, with where in it the
+    /// point lies as a fraction of the tile from its top left corner.
+    pub fn containing((latitude, longitude): (f64, f64), zoom: u8) -> (TileKey, egui::Vec2) {
+        let tiles = f64::from(1u32 << zoom);
+        let latitude = latitude.clamp(-MAX_LATITUDE, MAX_LATITUDE).to_radians();
+        let x = (longitude.clamp(-180.0, 180.0) + 180.0) / 360.0 * tiles;
+        let y = (1.0 - latitude.tan().asinh() / PI) / 2.0 * tiles;
+        // 180° east is the right edge of the last tile rather than a tile of its own
+        let tile_x = x.floor().min(tiles - 1.0);
+        let tile_y = y.floor().min(tiles - 1.0);
+        let key = TileKey {
+            zoom,
+            x: tile_x as u32,
+            y: tile_y as u32,
+        };
+        (key, egui::vec2((x - tile_x) as f32, (y - tile_y) as f32))
+    }
+
+    fn url(&self) -> String {
+        format!("{}/{}/{}/{}.png", TILE_SERVER, self.zoom, self.x, self.y)
+    }
+
+    /// `~/.cache/imflow/map_tiles/{z}/{x}/{y}.png` on Linux.
+    fn cache_path(&self) -> Option<PathBuf> {
+        dirs::cache_dir().map(|dir| {
+            dir.join("imflow")
+                .join("map_tiles")
+                .join(self.zoom.to_string())
+                .join(self.x.to_string())
+                .join(format!("{}.png", self.y))
+        })
+    }
+}
+
+/// Reads the tile from the cache, downloading it into the cache when it isn't
+/// there yet.
+fn fetch_tile(agent: &ureq::Agent, key: TileKey) -> Result<image::RgbaImage, String> {
+    let cache_path = key.cache_path();
+    // A cached file that doesn't decode is downloaded again
+    if let Some(tile) = cache_path.as_ref().and_then(|path| image::open(path).ok()) {
+        return Ok(tile.to_rgba8());
+    }
+    let response = agent.get(&key.url()).call().map_err(|e| e.to_string())?;
+    let mut bytes = Vec::new();
+    response
+        .into_reader()
+        .take(MAX_TILE_BYTES)
+        .read_to_end(&mut bytes)
+        .map_err(|e| e.to_string())?;
+    let tile = image::load_from_memory(&bytes).map_err(|e| e.to_string())?;
+    // Not being able to cache the tile only costs another download
+    if let Some(path) = cache_path
+        && let Some(dir) = path.parent()
+        && fs::create_dir_all(dir).is_ok()
+    {
+        let _ = fs::write(&path, &bytes);
+    }
+    Ok(tile.to_rgba8())
+}
+
+pub enum MapTile {
+    Loading,
+    Failed,
+    Loaded(egui::TextureHandle),
+}
+
+/// Map tiles for the info panel, downloaded one at a time on a background thread
+/// so that scrolling through geotagged images doesn't fire off a request for each.
+pub struct MapTiles {
+    /// Only the tile shown last is kept on the GPU
+    texture: Option<(TileKey, egui::TextureHandle)>,
+    pending: Option<TileKey>,
+    /// Not requested again until restarting
+    failed: HashSet<TileKey>,
+    /// Tiles that arrived while another one was wanted, until it's asked for again
+    arrived: HashMap<TileKey, image::RgbaImage>,
+    /// Shared by the downloads so they reuse the connection
+    agent: ureq::Agent,
+    tx: mpsc::Sender<(TileKey, Result<image::RgbaImage, String>)>,
+    rx: mpsc::Receiver<(TileKey, Result<image::RgbaImage, String>)>,
+}
+
+impl MapTiles {
+    pub fn new() -> Self {
+        let (tx, rx) = mpsc::channel();
+        MapTiles {
+            texture: None,
+            pending: None,
+            failed: HashSet::new(),
+            arrived: HashMap::new(),
+            agent: ureq::AgentBuilder::new()
+                .user_agent(USER_AGENT)
+                .timeout(DOWNLOAD_TIMEOUT)
+                .build(),
+            tx,
+            rx,
+        }
+    }
+
+    /// The texture of `key`, requesting the tile when it isn't loaded yet.
+    pub fn get(&mut self, key: TileKey, renderer: &EguiRenderer, wake: Option<Wake>) -> MapTile {
+        while let Ok((tile, result)) = self.rx.try_recv() {
+            self.pending = None;
+            match result {
+                Ok(image) => {
+                    self.arrived.insert(tile, image);
+                }
+                Err(e) => {
+                    eprintln!("Failed to load map tile {}: {}", tile.url(), e);
+                    self.failed.insert(tile);
+                }
+            }
+        }
+        if let Some((shown, texture)) = &self.texture
+            && *shown == key
+        {
+            return MapTile::Loaded(texture.clone());
+        }
+        if let Some(image) = self.arrived.remove(&key) {
+            let texture = renderer.load_texture(
+                &key.url(),
+                [image.width() as usize, image.height() as usize],
+                image.as_raw(),
+            );
+            // Whatever else arrived is on disk by now
+            self.arrived.clear();
+            self.texture = Some((key, texture.clone()));
+            return MapTile::Loaded(texture);
+        }
+        if self.failed.contains(&key) {
+            return MapTile::Failed;
+        }
+        if self.pending.is_none() {
+            let tx = self.tx.clone();
+            let agent = self.agent.clone();
+            thread::spawn(move || {
+                let _ = tx.send((key, fetch_tile(&agent, key)));
+                if let Some(wake) = wake {
+                    wake();
+                }
+            });
+            self.pending = Some(key);
+        }
+        MapTile::Loading
+    }
+}
+
+/// Draws `tile` with a marker at `position`, a fraction of the tile from its top
+/// left corner, and the attribution the tile licence requires.
+pub fn draw_map_tile(ui: &mut egui::Ui, tile: &MapTile, position: egui::Vec2) {
+    let (rect, _) = ui.allocate_exact_size(egui::Vec2::splat(MAP_TILE_SIZE), egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    let message = match tile {
+        MapTile::Loaded(texture) => {
+            painter.image(
+                texture.id(),
+                rect,
+                egui::Rect::from_min_max(egui::Pos2::ZERO, egui::pos2(1.0, 1.0)),
+                egui::Color32::WHITE,
+            );
+            painter.circle(
+                rect.min + position * rect.size(),
+                MARKER_RADIUS,
+                egui::Color32::from_rgb(230, 50, 50),
+                egui::Stroke::new(2.0, egui::Color32::WHITE),
+            );
+            None
+        }
+        MapTile::Loading => Some("Loading map…"),
+        MapTile::Failed => Some("Map unavailable"),
+    };
+    if let Some(message) = message {
+        painter.rect_filled(rect, 2.0, egui::Color32::from_black_alpha(120));
+        painter.text(
+            rect.center(),
+            egui::Align2::CENTER_CENTER,
+            message,
+            egui::FontId::default(),
+            ui.visuals().weak_text_color(),
+        );
+    }
+    ui.hyperlink_to(
+        egui::RichText::new("© OpenStreetMap contributors").small(),
+        "https://www.openstreetmap.org/copyright",
+    );
+}
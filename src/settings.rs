@@ -0,0 +1,188 @@
+use imflow::config::{
+    AlphaBackground, BackgroundMode, Config, MAX_MAP_TILE_ZOOM, MAX_UI_SCALE, MIN_MAP_TILE_ZOOM,
+    MIN_UI_SCALE, PreloadStrategy, SamplingMode, TransformMemory,
+};
+
+/// A dropdown choosing `value` from `options`.
+fn combo<T: Copy + PartialEq>(ui: &mut egui::Ui, id: &str, value: &mut T, options: &[(T, &str)]) {
+    let selected = options
+        .iter()
+        .find(|(option, _)| option == value)
+        .map_or("", |(_, name)| name);
+    egui::ComboBox::from_id_salt(id)
+        .selected_text(selected)
+        .show_ui(ui, |ui| {
+            for (option, name) in options {
+                ui.selectable_value(value, *option, *name);
+            }
+        });
+}
+
+/// Edits the RGB part of a linear RGBA config color.
+fn color(ui: &mut egui::Ui, color: &mut [f64; 4]) {
+    let mut rgb = [color[0] as f32, color[1] as f32, color[2] as f32];
+    if ui.color_edit_button_rgb(&mut rgb).changed() {
+        for (channel, value) in color.iter_mut().zip(rgb) {
+            *channel = value as f64;
+        }
+    }
+}
+
+/// The contents of the settings window, editing `config` in place. The caller
+/// compares it with its previous state to apply and save what changed.
+pub fn settings_ui(ui: &mut egui::Ui, config: &mut Config) {
+    egui::Grid::new("settings")
+        .num_columns(2)
+        .striped(true)
+        .show(ui, |ui| {
+            ui.label("Background");
+            combo(
+                ui,
+                "background_mode",
+                &mut config.background_mode,
+                &[
+                    (BackgroundMode::Solid, "Solid"),
+                    (BackgroundMode::Gradient, "Gradient"),
+                ],
+            );
+            ui.end_row();
+            match config.background_mode {
+                BackgroundMode::Solid => {
+                    ui.label("Background color");
+                    color(ui, &mut config.background_color);
+                    ui.end_row();
+                }
+                BackgroundMode::Gradient => {
+                    ui.label("Gradient");
+                    ui.horizontal(|ui| {
+                        color(ui, &mut config.gradient_top_color);
+                        color(ui, &mut config.gradient_bottom_color);
+                    });
+                    ui.end_row();
+                }
+            }
+            ui.label("Transparency");
+            combo(
+                ui,
+                "alpha_background",
+                &mut config.alpha_background,
+                &[
+                    (AlphaBackground::Checkerboard, "Checkerboard"),
+                    (AlphaBackground::Black, "Black"),
+                    (AlphaBackground::Gray, "Gray"),
+                    (AlphaBackground::White, "White"),
+                ],
+            );
+            ui.end_row();
+            ui.label("Sampling");
+            combo(
+                ui,
+                "sampling",
+                &mut config.sampling,
+                &[
+                    (SamplingMode::Auto, "Auto"),
+                    (SamplingMode::Linear, "Linear"),
+                    (SamplingMode::Nearest, "Nearest"),
+                ],
+            );
+            ui.end_row();
+
+            ui.label("Zoom and pan");
+            combo(
+                ui,
+                "transform_memory",
+                &mut config.transform_memory,
+                &[
+                    (TransformMemory::PerImage, "Remembered per image"),
+                    (TransformMemory::Keep, "Kept across images"),
+                    (TransformMemory::Reset, "Reset on every image"),
+                ],
+            );
+            ui.end_row();
+            ui.label("Zoom animation");
+            ui.add(
+                egui::Slider::new(&mut config.animation_time_constant, 0.0..=0.3)
+                    .suffix(" s")
+                    .fixed_decimals(2),
+            );
+            ui.end_row();
+            ui.label("Crossfade");
+            ui.add(egui::Slider::new(&mut config.transition_duration_ms, 0..=500).suffix(" ms"));
+            ui.end_row();
+            ui.label("Inertia");
+            ui.checkbox(&mut config.use_inertia, "Keep dragged images gliding");
+            ui.end_row();
+            ui.label("Scrolling");
+            ui.checkbox(&mut config.swap_scroll, "Touchpad zooms, wheel pans");
+            ui.end_row();
+
+            ui.label("Wrap around");
+            ui.checkbox(&mut config.wrap_navigation, "Continue past the last image");
+            ui.end_row();
+            ui.label("Held key speed");
+            ui.add(egui::Slider::new(&mut config.scrub_rate, 1.0..=60.0).suffix(" images/s"));
+            ui.end_row();
+            ui.label("Page size");
+            ui.add(egui::Slider::new(&mut config.page_size, 1..=100).suffix(" images"));
+            ui.end_row();
+            ui.label("Overlays");
+            ui.checkbox(&mut config.auto_hide_overlays, "Fade out when idle");
+            ui.end_row();
+            ui.label("Interface size");
+            ui.add(
+                egui::Slider::new(&mut config.ui_scale, MIN_UI_SCALE..=MAX_UI_SCALE)
+                    .fixed_decimals(1)
+                    .suffix("×"),
+            );
+            ui.end_row();
+            ui.label("Quit");
+            ui.checkbox(&mut config.confirm_quit, "Press Escape twice");
+            ui.end_row();
+            ui.label("Map");
+            ui.checkbox(&mut config.show_map_tile, "Download OpenStreetMap tiles");
+            ui.end_row();
+            if config.show_map_tile {
+                ui.label("Map zoom");
+                ui.add(egui::Slider::new(
+                    &mut config.map_tile_zoom,
+                    MIN_MAP_TILE_ZOOM..=MAX_MAP_TILE_ZOOM,
+                ));
+                ui.end_row();
+            }
+
+            ui.label("Preload");
+            let mut strategies = [
+                (PreloadStrategy::default(), "Ahead and behind"),
+                (PreloadStrategy::Symmetric { radius: 8 }, "Symmetric"),
+                (PreloadStrategy::Adaptive, "Adaptive"),
+            ];
+            // The current strategy keeps its numbers when it is picked again
+            for (strategy, _) in &mut strategies {
+                if std::mem::discriminant(strategy) == std::mem::discriminant(&config.preload) {
+                    *strategy = config.preload;
+                }
+            }
+            combo(ui, "preload", &mut config.preload, &strategies);
+            ui.end_row();
+            match &mut config.preload {
+                PreloadStrategy::Linear { ahead, behind } => {
+                    ui.label("Preload ahead");
+                    ui.add(egui::Slider::new(ahead, 0..=64));
+                    ui.end_row();
+                    ui.label("Preload behind");
+                    ui.add(egui::Slider::new(behind, 0..=64));
+                    ui.end_row();
+                }
+                PreloadStrategy::Symmetric { radius } => {
+                    ui.label("Preload radius");
+                    ui.add(egui::Slider::new(radius, 0..=64));
+                    ui.end_row();
+                }
+                PreloadStrategy::Adaptive => {}
+            }
+        });
+    ui.separator();
+    if ui.button("Restore defaults").clicked() {
+        config.restore_default_settings();
+    }
+}
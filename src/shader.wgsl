@@ -1,7 +1,24 @@
 struct Transforms {
     transform: mat4x4<f32>,
     width: u32,
-    height: u32
+    height: u32,
+    sharpen_enabled: u32,
+    sharpen_amount: f32,
+    zoom: f32,
+    hdr_mode: u32,
+    use_lanczos: u32,
+    resampled_width: u32,
+    resampled_height: u32,
+    // 0 black, 1 gray, 2 white, 3 checkerboard, as in `AlphaBackground`
+    alpha_background: u32,
+    // 1 to sample without interpolation, for inspecting pixels when zoomed in
+    nearest: u32,
+    clipping_enabled: u32,
+    // sRGB levels, a channel at or beyond these is clipped
+    clip_high: f32,
+    clip_low: f32,
+    // Below 1 while the image fades in or out on navigation
+    opacity: f32
 };
 @group(0) @binding(2) var<uniform> transforms: Transforms;
 
@@ -25,12 +42,128 @@ fn vs_main(in: VertexInput) -> VertexOutput {
 
 @group(0) @binding(0) var texture: texture_2d<f32>;
 @group(0) @binding(1) var texture_sampler: sampler;
+@group(0) @binding(3) var resampled_texture: texture_2d<f32>;
+@group(0) @binding(4) var nearest_sampler: sampler;
+
+// Unsharp mask over the 3x3 neighbourhood, clamped to the image region of the texture
+fn sharpen(color: vec4<f32>, pixel: vec2<f32>, scale: vec2<f32>, out_dim: vec2<f32>) -> vec4<f32> {
+    let texel = 1.0 / out_dim;
+    var neighbours = vec4<f32>(0.0);
+    for (var x = -1; x <= 1; x++) {
+        for (var y = -1; y <= 1; y++) {
+            if (x == 0 && y == 0) {
+                continue;
+            }
+            let offset = vec2<f32>(f32(x), f32(y)) * texel;
+            let uv = clamp(pixel + offset, vec2<f32>(0.0), scale - texel * 0.5);
+            neighbours += textureSampleLevel(texture, texture_sampler, uv, 0.0);
+        }
+    }
+    let amount = transforms.sharpen_amount;
+    let sharpened = (1.0 + amount) * color - amount * (neighbours / 8.0);
+    return vec4<f32>(clamp(sharpened.rgb, vec3<f32>(0.0), vec3<f32>(1.0)), color.a);
+}
+
+// ACES filmic tone mapping curve
+fn aces(x: f32) -> f32 {
+    return (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14);
+}
+
+fn tone_map(color: vec4<f32>) -> vec4<f32> {
+    if (transforms.hdr_mode != 1u) {
+        return color;
+    }
+    return vec4<f32>(aces(color.r), aces(color.g), aces(color.b), color.a);
+}
+
+// Size of the checkerboard squares in screen pixels
+const CHECKER_SIZE: f32 = 8.0;
+
+// What transparent parts of the image are composited onto, in linear RGB
+fn alpha_background(position: vec2<f32>) -> vec3<f32> {
+    switch transforms.alpha_background {
+        case 0u: {
+            return vec3<f32>(0.0);
+        }
+        case 1u: {
+            return vec3<f32>(0.214);
+        }
+        case 2u: {
+            return vec3<f32>(1.0);
+        }
+        default: {
+            let square = vec2<u32>(position / CHECKER_SIZE);
+            if ((square.x + square.y) % 2u == 0u) {
+                return vec3<f32>(0.133);
+            }
+            return vec3<f32>(0.319);
+        }
+    }
+}
+
+fn linear_to_srgb(linear: vec3<f32>) -> vec3<f32> {
+    let low = linear * 12.92;
+    let high = 1.055 * pow(linear, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, linear <= vec3<f32>(0.0031308));
+}
+
+// Width of the warning stripes in screen pixels
+const ZEBRA_WIDTH: f32 = 4.0;
+
+// Stripes clipped highlights red and clipped shadows blue
+fn clipping_warning(color: vec4<f32>, position: vec2<f32>) -> vec4<f32> {
+    let stripe = u32((position.x + position.y) / ZEBRA_WIDTH) % 2u == 0u;
+    // Transparent areas show the backdrop, not image data
+    if (!stripe || color.a == 0.0) {
+        return color;
+    }
+    let srgb = linear_to_srgb(color.rgb);
+    if (any(srgb >= vec3<f32>(transforms.clip_high))) {
+        return vec4<f32>(1.0, 0.0, 0.0, 1.0);
+    }
+    if (any(srgb <= vec3<f32>(transforms.clip_low))) {
+        return vec4<f32>(0.0, 0.0, 1.0, 1.0);
+    }
+    return color;
+}
+
+fn composite(color: vec4<f32>, position: vec2<f32>) -> vec4<f32> {
+    return vec4<f32>(mix(alpha_background(position), color.rgb, color.a), 1.0);
+}
 
 @fragment
-fn fs_main(@location(0) uv: vec2<f32>) -> @location(0) vec4<f32> {
+fn fs_main(@builtin(position) position: vec4<f32>, @location(0) uv: vec2<f32>) -> @location(0) vec4<f32> {
+    var color = image_color(uv);
+    if (transforms.clipping_enabled == 1u) {
+        color = clipping_warning(color, position.xy);
+    }
+    return vec4<f32>(composite(color, position.xy).rgb, transforms.opacity);
+}
+
+fn image_color(uv: vec2<f32>) -> vec4<f32> {
+    if (transforms.use_lanczos == 1u) {
+        // Already downscaled to roughly one texel per screen pixel by lanczos.wgsl
+        let resampled_size = vec2<f32>(f32(transforms.resampled_width), f32(transforms.resampled_height));
+        let resampled_dim = vec2<f32>(textureDimensions(resampled_texture));
+        let resampled_scale = resampled_size / resampled_dim;
+        // Kept half a texel inside the content so the padding isn't blended in
+        let resampled_uv = clamp(uv * resampled_scale, vec2<f32>(0.0), resampled_scale - 0.5 / resampled_dim);
+        return tone_map(textureSample(resampled_texture, texture_sampler, resampled_uv));
+    }
     let texture_size = vec2<f32>(f32(transforms.width), f32(transforms.height));
     let out_dim = vec2<f32>(textureDimensions(texture));
     let scale = texture_size / out_dim;
-    let pixel = uv * scale;
-    return textureSample(texture, texture_sampler, pixel);
+    // The texture may be larger than the image, see `sharpen`
+    let pixel = clamp(uv * scale, vec2<f32>(0.0), scale - 0.5 / out_dim);
+    var color: vec4<f32>;
+    if (transforms.nearest == 1u) {
+        color = textureSample(texture, nearest_sampler, pixel);
+    } else {
+        color = textureSample(texture, texture_sampler, pixel);
+    }
+    // Sharpening is meaningless when the image is shown at thumbnail scale
+    if (transforms.sharpen_enabled == 0u || transforms.zoom < 0.5) {
+        return tone_map(color);
+    }
+    return tone_map(sharpen(color, pixel, scale, out_dim));
 }
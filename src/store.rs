@@ -1,14 +1,150 @@
-use crate::image::{ImageData, load_thumbnail};
-use crate::image::{ImflowImageBuffer, load_available_images, load_image};
-use rexiv2::Metadata;
+use crate::config::{Config, PreloadStrategy, StackRating};
+use crate::dedup::{compute_phash, duplicate_pairs};
+use crate::image::load_image_progressive;
+use crate::image::{
+    ColorLabel, FlagStatus, ViewOrientation, darktable_sidecar, missing_placeholder,
+    set_darktable_compat, set_mmap_threshold, write_caption, write_darktable_rating, write_flag,
+    write_label, write_orientation, write_rating, write_sidecar_rating,
+};
+use crate::image::{
+    ImageData, ImageFormat, ImageMetadata, LoadError, MetadataCache, load_thumbnail,
+};
+use crate::image::{ImflowImageBuffer, group_stacks, load_available_images, load_image};
+use crate::load_queue::LoadQueue;
+use crate::manifest::{
+    ImportReport, ManifestFormat, ManifestRow, match_rows, read_manifest, write_manifest,
+};
+use chrono::{NaiveDate, NaiveDateTime, TimeDelta};
+use itertools::Itertools;
+use rayon::prelude::*;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::collections::HashSet;
-use std::path::PathBuf;
-use std::sync::mpsc;
-use std::time::Instant;
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock, mpsc};
+use std::time::{Duration, Instant, SystemTime};
 use threadpool::ThreadPool;
 
-const PRELOAD_NEXT_IMAGE_N: usize = 16;
+// Images preloaded by the adaptive strategy, split between the two directions
+const ADAPTIVE_PRELOAD_WINDOW: usize = 16;
+// Navigation steps the adaptive strategy looks back on
+const NAVIGATION_HISTORY_LEN: usize = 5;
+
+/// What a message from the loader threads carries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageType {
+    Full,
+    Thumbnail,
+}
+
+/// Whether the full resolution version of an image is available yet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoadState {
+    /// Only the thumbnail or an early decode can be shown
+    Loading,
+    Loaded,
+    /// The file is gone or can't be read
+    Missing,
+    /// The file is there, but could not be decoded
+    Failed,
+}
+
+/// Called from other threads once they have sent the UI something to pick up, so a
+/// UI that only redraws on events gets to it.
+pub type Wake = Arc<dyn Fn() + Send + Sync>;
+
+/// A finished load as sent by the loader threads, with how long the decode took.
+type LoadMessage = (
+    ImageData,
+    MessageType,
+    Result<ImflowImageBuffer, LoadError>,
+    Duration,
+);
+
+/// Sent by the background pass of `find_duplicates`.
+pub(crate) enum DuplicateMessage {
+    /// An image was hashed, or had no thumbnail to hash
+    Hashed(ImageData, Option<u64>),
+    /// Every image is hashed, with the similar pairs among them
+    Done(Vec<(ImageData, ImageData)>),
+}
+
+/// How a `find_duplicates` search is coming along, see `check_duplicates`.
+pub enum DuplicateProgress {
+    Hashing { hashed: usize, total: usize },
+    Done(Vec<(ImageData, ImageData)>),
+}
+
+/// How far the thumbnails requested when the store was created have come, shared
+/// with the workers decoding them.
+pub struct LoadingState {
+    pub thumbnails_loaded: AtomicUsize,
+    pub thumbnails_total: usize,
+}
+
+impl LoadingState {
+    pub fn is_done(&self) -> bool {
+        self.thumbnails_loaded.load(Ordering::Relaxed) >= self.thumbnails_total
+    }
+}
+
+/// A snapshot of the loader's work, for the debug overlay.
+#[derive(Clone, Copy, Debug)]
+pub struct LoadStats {
+    /// Workers decoding right now
+    pub active_workers: usize,
+    /// Full images requested but not yet received
+    pub loading: usize,
+    pub loaded: usize,
+    pub thumbnails: usize,
+    /// Loads received since the store was created
+    pub decoded: usize,
+    /// Bytes held by decoded images and thumbnails
+    pub memory: usize,
+    /// How long the full decode of the current image took, once it is loaded
+    pub current_decode_time: Option<Duration>,
+}
+
+/// Which flags navigation stops at, see `ImageStore::filter_flags`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FlagFilter {
+    pub picked: bool,
+    pub rejected: bool,
+    pub unflagged: bool,
+}
+
+impl Default for FlagFilter {
+    fn default() -> Self {
+        FlagFilter {
+            picked: true,
+            rejected: true,
+            unflagged: true,
+        }
+    }
+}
+
+impl FlagFilter {
+    pub fn shows(&self, flag: FlagStatus) -> bool {
+        match flag {
+            FlagStatus::None => self.unflagged,
+            FlagStatus::Picked => self.picked,
+            FlagStatus::Rejected => self.rejected,
+        }
+    }
+}
+
+/// The end of the list that `next_image` wrapped around to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Wrapped {
+    ToStart,
+    ToEnd,
+}
 
 pub struct ImageStore {
     pub(crate) current_image_id: usize,
@@ -17,138 +153,1475 @@ pub struct ImageStore {
     pub(crate) available_images: Vec<ImageData>,
     pub current_image_path: ImageData,
     pub(crate) pool: ThreadPool,
-    pub(crate) loader_rx: mpsc::Receiver<(ImageData, ImflowImageBuffer)>,
-    pub(crate) loader_tx: mpsc::Sender<(ImageData, ImflowImageBuffer)>,
+    /// What the jobs on `pool` decode, most urgent first
+    pub(crate) load_queue: Arc<LoadQueue>,
+    pub(crate) loader_rx: mpsc::Receiver<LoadMessage>,
+    pub(crate) loader_tx: mpsc::Sender<LoadMessage>,
+    /// Early decodes of progressive JPEGs that are still loading
+    pub(crate) partial_rx: mpsc::Receiver<(ImageData, ImflowImageBuffer)>,
+    pub(crate) partial_tx: mpsc::Sender<(ImageData, ImflowImageBuffer)>,
+    /// The latest early decode of each loading image, until it is taken for display
+    pub(crate) partial_images: HashMap<ImageData, ImflowImageBuffer>,
+    /// Thumbnails decoded so far out of those requested up front
+    pub(crate) loading: Arc<LoadingState>,
+    /// See `set_wake`. Shared with the jobs, which may start before it is set.
+    pub(crate) wake: Arc<OnceLock<Wake>>,
+    /// Metadata read in the background by `precompute_all_ratings`
+    pub(crate) metadata_rx: mpsc::Receiver<MetadataRead>,
+    pub(crate) metadata_tx: mpsc::Sender<MetadataRead>,
+    /// Images whose background metadata hasn't arrived yet and wasn't written meanwhile
+    pub(crate) pending_metadata: HashSet<ImageData>,
     pub(crate) currently_loading: HashSet<ImageData>,
+    pub(crate) metadata: HashMap<ImageData, ImageMetadata>,
+    /// Parsed tags shared with the loader threads, see `MetadataCache`
+    pub(crate) metadata_cache: Arc<MetadataCache>,
+    pub(crate) bursts: Vec<Range<usize>>,
+    pub(crate) burst_max_gap: Duration,
+    /// Every file of a multi-file stack, keyed by the member currently displayed
+    pub(crate) stacks: HashMap<ImageData, Vec<ImageData>>,
+    pub(crate) stack_rating: StackRating,
+    pub(crate) wrap_navigation: bool,
+    pub(crate) preload_strategy: PreloadStrategy,
+    /// The last few `next_image` steps, newest last, for the adaptive preload strategy
+    pub(crate) navigation_history: VecDeque<i32>,
+    pub(crate) flag_filter: FlagFilter,
+    /// Files that were deleted or became unreadable after the scan
+    pub(crate) missing: HashSet<ImageData>,
+    /// Files that are there but whose full image couldn't be decoded
+    pub(crate) undecodable: HashSet<ImageData>,
+    /// Files whose thumbnail couldn't be decoded and is the placeholder instead
+    pub(crate) undecodable_thumbnails: HashSet<ImageData>,
+    /// Modification times of the files as they were when their buffers were cached
+    pub(crate) modified: HashMap<ImageData, SystemTime>,
+    pub(crate) placeholder: ImflowImageBuffer,
+    /// Perceptual hashes of the thumbnails, computed on first use
+    pub(crate) phashes: HashMap<ImageData, u64>,
+    /// The running `find_duplicates` search, if any
+    pub(crate) duplicates_rx: Option<mpsc::Receiver<DuplicateMessage>>,
+    /// Images that search is hashing and that haven't changed since it started
+    pub(crate) hashing: HashSet<ImageData>,
+    pub(crate) hashing_total: usize,
+    /// Old to new names of images renamed while a worker was still decoding them
+    pub(crate) renamed: HashMap<ImageData, ImageData>,
+    /// Failures of work the store did on its own, until the app shows them
+    pub(crate) errors: Vec<String>,
+    /// Set while navigation keys are held, see `set_scrubbing`
+    pub(crate) scrubbing: bool,
+    /// The image shown before the current one, for flicker comparisons
+    pub(crate) previous_image: Option<ImageData>,
+    /// Loads received from the workers, see `LoadStats::decoded`
+    pub(crate) decoded: usize,
+    /// How long the full decode of each loaded image took
+    pub(crate) decode_times: HashMap<ImageData, Duration>,
+}
+
+impl Drop for ImageStore {
+    fn drop(&mut self) {
+        // Loads already decoding finish and fail to send, which the workers ignore
+        self.load_queue.clear();
+    }
+}
+
+fn rekey<V>(map: &mut HashMap<ImageData, V>, old: &ImageData, new: &ImageData) {
+    if let Some(value) = map.remove(old) {
+        map.insert(new.clone(), value);
+    }
+}
+
+/// An image's metadata and modification time, read together in the background
+pub(crate) type MetadataRead = (ImageData, ImageMetadata, Option<SystemTime>);
+
+fn modified_time(image: &ImageData) -> Option<SystemTime> {
+    fs::metadata(&image.path)
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+/// Runs a loader, turning a panic inside one of the decoding libraries into a decode
+/// error. The loaders report bad files themselves, this is only the backstop.
+fn guarded(
+    load: impl FnOnce() -> Result<ImflowImageBuffer, LoadError>,
+) -> Result<ImflowImageBuffer, LoadError> {
+    panic::catch_unwind(panic::AssertUnwindSafe(load))
+        .unwrap_or_else(|_| Err(LoadError::Decode("the decoder crashed".to_string())))
+}
+
+/// Whether a failed load means the file is gone rather than that it is corrupt.
+fn is_missing_file(image: &ImageData, error: &LoadError) -> bool {
+    matches!(error, LoadError::Read(_)) || !image.path.is_file()
 }
 
 impl ImageStore {
-    pub fn new(path: PathBuf) -> Self {
+    /// A store of the images in `path`. `metadata_cache` may be shared with earlier
+    /// stores, or loaded from a previous session, see `MetadataCache::load`.
+    pub fn new(path: PathBuf, config: &Config, metadata_cache: Arc<MetadataCache>) -> Self {
+        Self::from_images(load_available_images(path), config, metadata_cache)
+    }
+
+    /// A store of `images` in the given order, rather than of a directory.
+    pub fn from_images(
+        images: Vec<ImageData>,
+        config: &Config,
+        metadata_cache: Arc<MetadataCache>,
+    ) -> Self {
+        // Before any metadata is read
+        set_darktable_compat(config.darktable_compat);
+        set_mmap_threshold(config.mmap_threshold_mb.saturating_mul(1_000_000));
         let current_image_id: usize = 0;
-        let mut loaded_images: HashMap<ImageData, ImflowImageBuffer> = HashMap::new();
-        let mut loaded_thumbnails: HashMap<ImageData, ImflowImageBuffer> = HashMap::new();
-        let available_images = load_available_images(path);
-        let new_path = available_images[0].clone();
+        let mut available_images = Vec::new();
+        let mut stacks = HashMap::new();
+        for stack in group_stacks(images, &config.stack_priority) {
+            available_images.push(stack[0].clone());
+            if stack.len() > 1 {
+                stacks.insert(stack[0].clone(), stack);
+            }
+        }
+        // An empty directory still gets a store, with the placeholder as the current image
+        let new_path = available_images
+            .first()
+            .cloned()
+            .unwrap_or_else(|| ImageData {
+                path: PathBuf::new(),
+                format: ImageFormat::Jpg,
+                has_preview: false,
+                capture_date: None,
+            });
+        let mut missing = HashSet::new();
+        if available_images.is_empty() {
+            missing.insert(new_path.clone());
+        }
 
         let (loader_tx, loader_rx) = mpsc::channel();
+        let (partial_tx, partial_rx) = mpsc::channel();
+        let (metadata_tx, metadata_rx) = mpsc::channel();
 
         let pool = ThreadPool::new(32);
 
         let currently_loading = HashSet::new();
 
-        let total_start = Instant::now();
-        let mut loaded = 0;
-        let to_load = available_images.len();
-        for path in &available_images {
-            let buf = load_thumbnail(path);
-            loaded_thumbnails.insert(path.clone(), buf);
-            loaded += 1;
-            println!("{}/{}", loaded, to_load);
-        }
-        let total_time = total_start.elapsed();
-        println!(
-            "all thumbnails load time: {:?} for {}",
-            total_time,
-            loaded_thumbnails.len()
-        );
-
-        let path = available_images[0].clone();
-        let image = load_image(&path.clone());
-        loaded_images.insert(path, image);
+        let available_images_len = available_images.len();
         let mut state = Self {
             current_image_id,
-            loaded_images,
+            loaded_images: HashMap::new(),
             available_images,
             current_image_path: new_path,
             pool,
+            load_queue: Arc::new(LoadQueue::default()),
             loader_rx,
             loader_tx,
+            partial_rx,
+            partial_tx,
+            partial_images: HashMap::new(),
+            // Every image but the first, whose thumbnail is loaded below
+            loading: Arc::new(LoadingState {
+                thumbnails_loaded: AtomicUsize::new(0),
+                thumbnails_total: available_images_len.saturating_sub(1),
+            }),
+            wake: Arc::new(OnceLock::new()),
+            metadata_rx,
+            metadata_tx,
+            pending_metadata: HashSet::new(),
             currently_loading,
-            loaded_images_thumbnails: loaded_thumbnails,
+            loaded_images_thumbnails: HashMap::new(),
+            metadata: HashMap::new(),
+            metadata_cache,
+            bursts: Vec::new(),
+            burst_max_gap: Duration::from_secs_f32(config.burst_max_gap.max(0.0)),
+            stacks,
+            stack_rating: config.stack_rating,
+            wrap_navigation: config.wrap_navigation,
+            preload_strategy: config.preload,
+            navigation_history: VecDeque::with_capacity(NAVIGATION_HISTORY_LEN),
+            flag_filter: FlagFilter::default(),
+            missing,
+            undecodable: HashSet::new(),
+            undecodable_thumbnails: HashSet::new(),
+            modified: HashMap::new(),
+            placeholder: missing_placeholder(),
+            phashes: HashMap::new(),
+            duplicates_rx: None,
+            hashing: HashSet::new(),
+            hashing_total: 0,
+            renamed: HashMap::new(),
+            errors: Vec::new(),
+            scrubbing: false,
+            decoded: 0,
+            decode_times: HashMap::new(),
+            previous_image: None,
         };
 
-        state.preload_next_images(PRELOAD_NEXT_IMAGE_N);
+        // Only the first image is loaded up front so the window can open right away,
+        // the other thumbnails follow in the background
+        if let Some(path) = state.available_images.first().cloned() {
+            let thumbnail = guarded(|| load_thumbnail(&path, &state.metadata_cache));
+            state.insert_thumbnail(&path, thumbnail);
+            let decode_start = Instant::now();
+            let image = guarded(|| load_image(&path, &state.metadata_cache));
+            state.insert_image(&path, image, decode_start.elapsed());
+            // Read by the loads just now, so the status bar doesn't wait for the rest
+            if let Some(time) = modified_time(&path) {
+                state.modified.insert(path.clone(), time);
+            }
+            let metadata = state.metadata_cache.get(&path);
+            state.metadata.insert(path, metadata);
+        }
+        state.group_bursts(state.burst_max_gap);
+        for id in 1..state.available_images.len() {
+            state.request_thumbnail(id);
+        }
+        state.preload_images();
+        state.precompute_all_ratings();
 
         state
     }
 
+    /// Reads the metadata and modification time of every image in parallel in the
+    /// background, so ratings and flags are known before the thumbnails are decoded.
+    /// This is the only place the store reads them in bulk, see `check_ratings`.
+    pub fn precompute_all_ratings(&mut self) {
+        let images = self
+            .available_images
+            .iter()
+            .filter(|image| !self.metadata.contains_key(image))
+            .cloned()
+            .collect::<Vec<_>>();
+        self.pending_metadata.extend(images.iter().cloned());
+        let tx = self.metadata_tx.clone();
+        let metadata_cache = self.metadata_cache.clone();
+        let wake = self.wake.clone();
+        rayon::spawn(move || {
+            images.par_iter().for_each_with(tx, |tx, image| {
+                // The time first, so a write in between shows up as a change
+                let time = modified_time(image);
+                let _ = tx.send((image.clone(), metadata_cache.get(image), time));
+            });
+            if let Some(wake) = wake.get() {
+                wake();
+            }
+        });
+    }
+
+    /// Stores what `precompute_all_ratings` has read so far and applies the ratings
+    /// to the cached buffers. Metadata written since the task started is newer and
+    /// wins.
+    pub fn check_ratings(&mut self) {
+        while let Ok((image, metadata, time)) = self.metadata_rx.try_recv() {
+            if !self.pending_metadata.remove(&image) {
+                continue;
+            }
+            if let Some(time) = time {
+                self.modified.entry(image.clone()).or_insert(time);
+            }
+            let rating = metadata.rating.unwrap_or(0);
+            self.metadata.insert(image.clone(), metadata);
+            if let Some(thumbnail) = self.loaded_images_thumbnails.get_mut(&image) {
+                thumbnail.rating = rating;
+            }
+            if let Some(full) = self.loaded_images.get_mut(&image) {
+                full.rating = rating;
+            }
+        }
+    }
+
     pub fn set_rating(&mut self, rating: i32) {
-        let meta = Metadata::new_from_path(self.current_image_path.path.clone());
-        match meta {
-            Ok(meta) => {
-                meta.set_tag_numeric("Xmp.xmp.Rating", rating).unwrap();
-                meta.save_to_file(self.current_image_path.path.clone())
-                    .unwrap();
+        let image = self.current_image_path.clone();
+        self.rate_image(&image, rating);
+    }
+
+    /// Like `set_image_rating`, but reports failures instead of returning them.
+    pub fn rate_image(&mut self, image: &ImageData, rating: i32) {
+        if self.is_empty() {
+            return;
+        }
+        if let Err(e) = self.set_image_rating(image, rating) {
+            self.errors.push(format!(
+                "Failed to rate {}: {}",
+                image.path.file_name().unwrap().to_string_lossy(),
+                e
+            ));
+        }
+    }
+
+    /// Failures since the last call, see `rate_image` and `check_loaded_images`.
+    pub fn take_errors(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.errors)
+    }
+
+    /// Writes the rating to the metadata of the image (and the rest of its stack) and
+    /// updates every cached copy of it.
+    pub fn set_image_rating(
+        &mut self,
+        image: &ImageData,
+        rating: i32,
+    ) -> Result<(), rexiv2::Rexiv2Error> {
+        for member in self.stack_members(image) {
+            if !member.path.is_file() {
+                self.mark_missing(&member);
+                return Err(rexiv2::Rexiv2Error::Internal(Some(format!(
+                    "{} is missing",
+                    member.path.display()
+                ))));
+            }
+            if let Some(sidecar) = darktable_sidecar(&member) {
+                write_darktable_rating(&sidecar, rating)?;
+            } else if member.format == ImageFormat::Raw && self.stack_rating == StackRating::Sidecar
+            {
+                write_sidecar_rating(&member, rating)?;
+            } else {
+                write_rating(&member, rating)?;
             }
-            Err(e) => panic!("{:?}", e),
+            self.metadata_cache.invalidate(&member);
+            if let Some(full) = self.loaded_images.get_mut(&member) {
+                full.rating = rating;
+            }
+            if let Some(thumbnail) = self.loaded_images_thumbnails.get_mut(&member) {
+                thumbnail.rating = rating;
+            }
+            // Our own write must not look like an external edit
+            if let Some(time) = modified_time(&member) {
+                self.modified.insert(member.clone(), time);
+            }
+            self.metadata_mut(&member).rating = Some(rating);
+        }
+        Ok(())
+    }
+
+    /// Rotates and mirrors `image` on disk by adding `view` to its EXIF orientation,
+    /// then decodes it again the new way up.
+    pub fn apply_orientation(
+        &mut self,
+        image: &ImageData,
+        view: ViewOrientation,
+    ) -> Result<(), rexiv2::Rexiv2Error> {
+        let saved = ViewOrientation::from_exif(self.metadata_cache.get(image).orientation);
+        write_orientation(image, saved.then(view).to_exif())?;
+        self.metadata_cache.invalidate(image);
+        self.loaded_images.remove(image);
+        self.phashes.remove(image);
+        self.hashing.remove(image);
+        if let Some(time) = modified_time(image) {
+            self.modified.insert(image.clone(), time);
         }
-        if let Some(full) = self.loaded_images.get_mut(&self.current_image_path.clone()) {
-            full.rating = rating;
+        self.pending_metadata.remove(image);
+        self.metadata
+            .insert(image.clone(), self.metadata_cache.get(image));
+        self.loaded_images_thumbnails.remove(image);
+        let thumbnail = guarded(|| load_thumbnail(image, &self.metadata_cache));
+        self.insert_thumbnail(image, thumbnail);
+        if let Some(id) = self.image_id(image) {
+            self.request_load(id);
+        }
+        Ok(())
+    }
+
+    /// The metadata of `image`, for updating it after a write to the file. If the
+    /// background read hasn't delivered it yet it is read now, and what arrives later
+    /// is dropped as out of date.
+    fn metadata_mut(&mut self, image: &ImageData) -> &mut ImageMetadata {
+        self.pending_metadata.remove(image);
+        self.metadata
+            .entry(image.clone())
+            .or_insert_with(|| self.metadata_cache.get(image))
+    }
+
+    pub fn is_missing(&self, image: &ImageData) -> bool {
+        self.missing.contains(image)
+    }
+
+    pub fn get_image_flag(&self, image: &ImageData) -> FlagStatus {
+        self.metadata
+            .get(image)
+            .map(|metadata| metadata.flag)
+            .unwrap_or_default()
+    }
+
+    /// Flags `image` and the rest of its stack.
+    pub fn set_image_flag(
+        &mut self,
+        image: &ImageData,
+        flag: FlagStatus,
+    ) -> Result<(), rexiv2::Rexiv2Error> {
+        for member in self.stack_members(image) {
+            write_flag(&member, flag)?;
+            self.metadata_cache.invalidate(&member);
+            if let Some(time) = modified_time(&member) {
+                self.modified.insert(member.clone(), time);
+            }
+            self.metadata_mut(&member).flag = flag;
         }
-        if let Some(thumbnail) = self
-            .loaded_images_thumbnails
-            .get_mut(&self.current_image_path.clone())
+        Ok(())
+    }
+
+    /// Limits navigation to images with the given flags. The current image stays
+    /// shown even if it is filtered out.
+    pub fn filter_flags(&mut self, show_picked: bool, show_rejected: bool, show_unflagged: bool) {
+        self.flag_filter = FlagFilter {
+            picked: show_picked,
+            rejected: show_rejected,
+            unflagged: show_unflagged,
+        };
+    }
+
+    pub fn flag_filter(&self) -> FlagFilter {
+        self.flag_filter
+    }
+
+    /// Whether navigation passes over `image`.
+    fn is_skipped(&self, image: &ImageData) -> bool {
+        self.is_missing(image) || !self.flag_filter.shows(self.get_image_flag(image))
+    }
+
+    /// Drops everything cached for a file that no longer exists or can't be read.
+    pub fn mark_missing(&mut self, image: &ImageData) {
+        self.loaded_images.remove(image);
+        self.loaded_images_thumbnails.remove(image);
+        self.missing.insert(image.clone());
+    }
+
+    /// Checks that the file behind `image` still exists and hasn't been modified since
+    /// it was cached. Stale buffers are dropped so they get decoded again, in which
+    /// case this returns true.
+    fn refresh_if_changed(&mut self, image: &ImageData) -> bool {
+        let Some(time) = modified_time(image) else {
+            self.mark_missing(image);
+            return false;
+        };
+        let reappeared = self.missing.remove(image);
+        let changed = self
+            .modified
+            .insert(image.clone(), time)
+            .is_some_and(|previous| previous != time);
+        if reappeared || changed {
+            self.loaded_images.remove(image);
+            self.loaded_images_thumbnails.remove(image);
+            self.undecodable.remove(image);
+            self.undecodable_thumbnails.remove(image);
+            self.phashes.remove(image);
+            self.hashing.remove(image);
+            self.pending_metadata.remove(image);
+            self.metadata
+                .insert(image.clone(), self.metadata_cache.get(image));
+        }
+        reappeared || changed
+    }
+
+    /// Decodes the current image and its thumbnail again on the pool if its file
+    /// changed since they were loaded, e.g. in an external editor. Returns whether it
+    /// did, in which case anything made from the old thumbnail is stale.
+    pub fn reload_current_if_changed(&mut self) -> bool {
+        let current = self.current_image_path.clone();
+        if self.is_empty() || !self.refresh_if_changed(&current) {
+            return false;
+        }
+        self.request_thumbnail(self.current_image_id);
+        self.request_load(self.current_image_id);
+        true
+    }
+
+    /// Returns every file in the stack `image` belongs to, or just `image` itself.
+    pub fn stack_members(&self, image: &ImageData) -> Vec<ImageData> {
+        self.stacks
+            .get(image)
+            .cloned()
+            .unwrap_or_else(|| vec![image.clone()])
+    }
+
+    /// Describes the current stack, e.g. "RAW+JPEG (showing JPEG)".
+    pub fn stack_label(&self) -> Option<String> {
+        let members = self.stacks.get(&self.current_image_path)?;
+        let formats = members
+            .iter()
+            .sorted_by_key(|member| member.format != ImageFormat::Raw)
+            .map(|member| member.format.name())
+            .join("+");
+        Some(format!(
+            "{} (showing {})",
+            formats,
+            self.current_image_path.format.name()
+        ))
+    }
+
+    /// Displays the next member of the current stack instead of the current one.
+    pub fn cycle_stack_member(&mut self) {
+        let current = self.current_image_path.clone();
+        let Some(members) = self.stacks.remove(&current) else {
+            return;
+        };
+        let position = members.iter().position(|member| *member == current);
+        let next = members[position.map_or(0, |position| (position + 1) % members.len())].clone();
+        if !self.metadata.contains_key(&next) {
+            self.metadata
+                .insert(next.clone(), self.metadata_cache.get(&next));
+        }
+        if !self.loaded_images_thumbnails.contains_key(&next) {
+            let thumbnail = guarded(|| load_thumbnail(&next, &self.metadata_cache));
+            self.insert_thumbnail(&next, thumbnail);
+        }
+        self.available_images[self.current_image_id] = next.clone();
+        self.stacks.insert(next, members);
+        self.jump_to_image(self.current_image_id);
+    }
+
+    /// Moves the current image and the rest of its stack to the trash.
+    pub fn delete_current_image(&mut self) -> Result<(), trash::Error> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        let current = self.current_image_path.clone();
+        self.delete_images(&[current])
+    }
+
+    /// Moves `images` and the rest of their stacks to the trash in one go. The current
+    /// image stays current if it survives, otherwise the one after it takes its place.
+    pub fn delete_images(&mut self, images: &[ImageData]) -> Result<(), trash::Error> {
+        let members = images
+            .iter()
+            .flat_map(|image| self.stack_members(image))
+            .collect::<Vec<_>>();
+        if members.is_empty() {
+            return Ok(());
+        }
+        trash::delete_all(members.iter().map(|member| &member.path))?;
+        for member in &members {
+            self.loaded_images.remove(member);
+            self.loaded_images_thumbnails.remove(member);
+            self.metadata.remove(member);
+            self.pending_metadata.remove(member);
+        }
+        for image in images {
+            self.stacks.remove(image);
+        }
+        let deleted = images.iter().collect::<HashSet<_>>();
+        let deleted_before = self.available_images[..self.current_image_id]
+            .iter()
+            .filter(|image| deleted.contains(image))
+            .count();
+        self.available_images
+            .retain(|image| !deleted.contains(image));
+        self.group_bursts(self.burst_max_gap);
+        if !self.available_images.is_empty() {
+            let id = (self.current_image_id - deleted_before).min(self.available_images.len() - 1);
+            self.jump_to_image(id);
+        }
+        // Also covers the deleted current image, which the jump made the previous one
+        if self
+            .previous_image
+            .as_ref()
+            .is_some_and(|previous| members.contains(previous))
         {
-            thumbnail.rating = rating;
+            self.previous_image = None;
         }
+        Ok(())
     }
 
-    pub fn get_current_rating(&self) -> i32 {
-        let imbuf = if let Some(full) = self.get_current_image() {
-            // println!("full");
-            full
-        } else {
-            // TODO: this assumes loaded thumbnail
-            self.loaded_images_thumbnails
-                .get(&self.current_image_path)
-                .unwrap()
+    /// Every image navigation stops at, in order.
+    pub fn visible_images(&self) -> Vec<ImageData> {
+        self.available_images
+            .iter()
+            .filter(|image| !self.is_skipped(image))
+            .cloned()
+            .collect()
+    }
+
+    /// The current image's position among `visible_images`, counting from 1, and how
+    /// many of them there are. The position is `None` while the current image is
+    /// itself filtered out or missing.
+    pub fn visible_position(&self) -> (Option<usize>, usize) {
+        let (before, after) = self.available_images.split_at(self.current_image_id);
+        let shown = |images: &[ImageData]| {
+            images
+                .iter()
+                .filter(|image| !self.is_skipped(image))
+                .count()
+        };
+        let position = after
+            .first()
+            .filter(|image| !self.is_skipped(image))
+            .map(|_| shown(before) + 1);
+        (position, shown(before) + shown(after))
+    }
+
+    /// Renames the current image (and the rest of its stack, and XMP sidecars) to
+    /// `new_stem`, keeping each file's extension. Fails without touching anything if
+    /// any of the new names is taken.
+    pub fn rename_current_image(&mut self, new_stem: &str) -> io::Result<()> {
+        if self.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "no image to rename",
+            ));
+        }
+        if new_stem.is_empty() || new_stem.contains(std::path::is_separator) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid file name `{}`", new_stem),
+            ));
+        }
+        let current = self.current_image_path.clone();
+        let renames = self
+            .stack_members(&current)
+            .into_iter()
+            .map(|member| {
+                let mut path = member.path.with_file_name(new_stem);
+                if let Some(extension) = member.path.extension() {
+                    path.set_extension(extension);
+                }
+                let renamed = ImageData {
+                    path,
+                    ..member.clone()
+                };
+                (member, renamed)
+            })
+            .filter(|(old, new)| old.path != new.path)
+            .collect::<Vec<_>>();
+        if let Some((_, new)) = renames.iter().find(|(_, new)| new.path.exists()) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", new.path.display()),
+            ));
+        }
+
+        for (old, new) in &renames {
+            fs::rename(&old.path, &new.path)?;
+            let sidecar = old.path.with_extension("xmp");
+            if sidecar.is_file() {
+                fs::rename(&sidecar, new.path.with_extension("xmp"))?;
+            }
+
+            rekey(&mut self.loaded_images, old, new);
+            rekey(&mut self.loaded_images_thumbnails, old, new);
+            rekey(&mut self.metadata, old, new);
+            rekey(&mut self.modified, old, new);
+            rekey(&mut self.phashes, old, new);
+            rekey(&mut self.decode_times, old, new);
+            for set in [
+                &mut self.missing,
+                &mut self.undecodable,
+                &mut self.undecodable_thumbnails,
+            ] {
+                if set.remove(old) {
+                    set.insert(new.clone());
+                }
+            }
+            // Metadata and hashes read under the old name are dropped, see
+            // `metadata_mut`
+            self.pending_metadata.remove(old);
+            self.hashing.remove(old);
+            if self.currently_loading.remove(old) {
+                self.currently_loading.insert(new.clone());
+                self.renamed.insert(old.clone(), new.clone());
+            }
+            if let Some(image) = self.available_images.iter_mut().find(|image| *image == old) {
+                *image = new.clone();
+            }
+        }
+
+        let rename = |image: &ImageData| {
+            renames
+                .iter()
+                .find(|(old, _)| old == image)
+                .map_or_else(|| image.clone(), |(_, new)| new.clone())
         };
-        imbuf.rating
+        if let Some(members) = self.stacks.remove(&current) {
+            self.stacks
+                .insert(rename(&current), members.iter().map(rename).collect());
+        }
+        self.current_image_path = rename(&current);
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.available_images.is_empty()
     }
 
-    pub fn preload_next_images(&mut self, n: usize) {
-        for image in self
+    pub fn set_image_label(
+        &mut self,
+        image: &ImageData,
+        label: &str,
+    ) -> Result<(), rexiv2::Rexiv2Error> {
+        write_label(image, label)?;
+        self.metadata_cache.invalidate(image);
+        self.metadata_mut(image).label = Some(label.to_string());
+        Ok(())
+    }
+
+    pub fn set_image_caption(
+        &mut self,
+        image: &ImageData,
+        caption: &str,
+    ) -> Result<(), rexiv2::Rexiv2Error> {
+        write_caption(image, caption)?;
+        self.metadata_cache.invalidate(image);
+        self.metadata_mut(image).caption = (!caption.is_empty()).then(|| caption.to_string());
+        Ok(())
+    }
+
+    /// Applies ratings and labels from a manifest written by `export_manifest` (or by
+    /// hand). Rows that match no image, ratings outside 0 to 5 and failed writes are
+    /// reported, not fatal.
+    pub fn import_manifest(&mut self, path: &Path) -> io::Result<ImportReport> {
+        let rows = read_manifest(path, ManifestFormat::from_path(path))?;
+        let (matched, unmatched) = match_rows(rows, &self.available_images, path);
+        let mut report = ImportReport {
+            unmatched,
+            ..Default::default()
+        };
+        for (image, row) in matched {
+            let rating = match row.rating {
+                Some(rating) if !(0..=5).contains(&rating) => {
+                    report.invalid_ratings.push((row.path.clone(), rating));
+                    if row.label.is_none() {
+                        continue;
+                    }
+                    None
+                }
+                rating => rating,
+            };
+            let mut result = Ok(());
+            if let Some(rating) = rating {
+                result = self.set_image_rating(&image, rating);
+            }
+            if let (Ok(()), Some(label)) = (&result, &row.label) {
+                result = self.set_image_label(&image, label);
+            }
+            match result {
+                Ok(()) => report.applied += 1,
+                Err(e) => report.failed.push((row.path, format!("{:?}", e))),
+            }
+        }
+        Ok(report)
+    }
+
+    /// The images the statistics count, either all of them or, with `filtered`, those
+    /// navigation doesn't skip.
+    fn counted_images(&self, filtered: bool) -> impl Iterator<Item = &ImageData> {
+        self.available_images
+            .iter()
+            .filter(move |image| !filtered || !self.is_skipped(image))
+    }
+
+    /// Counts images per rating: index 0 holds unrated images, 1..=6 ratings 0 to 5.
+    /// See `counted_images` for `filtered`.
+    pub fn rating_histogram(&self, filtered: bool) -> [usize; 7] {
+        let mut histogram = [0; 7];
+        for image in self.counted_images(filtered) {
+            match self
+                .metadata
+                .get(image)
+                .and_then(|metadata| metadata.rating)
+            {
+                Some(rating) => histogram[rating.clamp(0, 5) as usize + 1] += 1,
+                None => histogram[0] += 1,
+            }
+        }
+        histogram
+    }
+
+    /// Counts images per colour label: index 0 holds those without one of the
+    /// colour labels, 1..=5 the labels in the order of `ColorLabel::ALL`.
+    pub fn label_histogram(&self, filtered: bool) -> [usize; 6] {
+        let mut histogram = [0; 6];
+        for image in self.counted_images(filtered) {
+            let label = self
+                .metadata
+                .get(image)
+                .and_then(|metadata| metadata.label.as_deref())
+                .and_then(ColorLabel::from_name);
+            match label {
+                Some(label) => histogram[label as usize + 1] += 1,
+                None => histogram[0] += 1,
+            }
+        }
+        histogram
+    }
+
+    /// Starts looking for pairs of images whose thumbnails' perceptual hashes differ
+    /// by less than `threshold` bits. Thumbnails are decoded and hashed in parallel in
+    /// the background, see `check_duplicates`. Hashes are kept until the file changes.
+    /// Does nothing while a search is already running.
+    pub fn find_duplicates(&mut self, threshold: u32) {
+        if self.duplicates_rx.is_some() {
+            return;
+        }
+        // Placeholders would all look alike
+        let unhashed = self
             .available_images
-            .clone()
             .iter()
-            .skip(self.current_image_id)
-            .take(n)
-        {
-            self.request_load(image.clone());
+            .filter(|image| {
+                !self.phashes.contains_key(image)
+                    && !self.missing.contains(image)
+                    && !self.undecodable_thumbnails.contains(image)
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        let images = self.available_images.clone();
+        let mut hashes = self.phashes.clone();
+        self.hashing = unhashed.iter().cloned().collect();
+        self.hashing_total = unhashed.len();
+        let (tx, rx) = mpsc::channel();
+        self.duplicates_rx = Some(rx);
+        let metadata_cache = self.metadata_cache.clone();
+        let wake = self.wake.clone();
+        rayon::spawn(move || {
+            let wake = || {
+                if let Some(wake) = wake.get() {
+                    wake();
+                }
+            };
+            let hashed = unhashed
+                .par_iter()
+                .map_with(tx.clone(), |tx, image| {
+                    let hash = guarded(|| load_thumbnail(image, &metadata_cache))
+                        .ok()
+                        .map(|thumbnail| compute_phash(&thumbnail));
+                    let _ = tx.send(DuplicateMessage::Hashed(image.clone(), hash));
+                    wake();
+                    (image.clone(), hash)
+                })
+                .collect::<Vec<_>>();
+            hashes.extend(
+                hashed
+                    .into_iter()
+                    .filter_map(|(image, hash)| Some((image, hash?))),
+            );
+            let hashes = images
+                .into_iter()
+                .filter_map(|image| {
+                    let hash = *hashes.get(&image)?;
+                    Some((image, hash))
+                })
+                .collect::<Vec<_>>();
+            let _ = tx.send(DuplicateMessage::Done(duplicate_pairs(&hashes, threshold)));
+            wake();
+        });
+    }
+
+    /// Keeps the hashes the `find_duplicates` search has computed so far. Returns how
+    /// far it has come if anything arrived, and the pairs once it is done.
+    pub fn check_duplicates(&mut self) -> Option<DuplicateProgress> {
+        let rx = self.duplicates_rx.as_ref()?;
+        let mut arrived = false;
+        while let Ok(message) = rx.try_recv() {
+            match message {
+                DuplicateMessage::Hashed(image, hash) => {
+                    arrived = true;
+                    // Gone from `hashing` if the file changed meanwhile
+                    if self.hashing.remove(&image)
+                        && let Some(hash) = hash
+                    {
+                        self.phashes.insert(image, hash);
+                    }
+                }
+                DuplicateMessage::Done(pairs) => {
+                    self.duplicates_rx = None;
+                    self.hashing.clear();
+                    return Some(DuplicateProgress::Done(pairs));
+                }
+            }
+        }
+        arrived.then(|| DuplicateProgress::Hashing {
+            hashed: self.hashing_total - self.hashing.len(),
+            total: self.hashing_total,
+        })
+    }
+
+    pub fn export_manifest(&self, path: &Path, format: ManifestFormat) -> io::Result<()> {
+        let rows = self
+            .available_images
+            .iter()
+            .map(|image| {
+                let metadata = self.metadata.get(image).cloned().unwrap_or_default();
+                ManifestRow::new(image, &metadata)
+            })
+            .collect::<Vec<_>>();
+        write_manifest(&rows, path, format)
+    }
+
+    /// Drops the pending loads and waits up to `timeout` for the decodes already
+    /// running. Returns whether they all finished in time.
+    pub fn shutdown(&self, timeout: Duration) -> bool {
+        self.load_queue.clear();
+        let pool = self.pool.clone();
+        let (tx, rx) = mpsc::channel();
+        // A worker stuck in a decoder is abandoned along with this thread
+        std::thread::spawn(move || {
+            pool.join();
+            let _ = tx.send(());
+        });
+        rx.recv_timeout(timeout).is_ok()
+    }
+
+    pub fn get_current_rating(&self) -> i32 {
+        self.get_image_rating(&self.current_image_path)
+    }
+
+    pub fn get_image_rating(&self, image: &ImageData) -> i32 {
+        if self.is_missing(image) {
+            return self
+                .metadata
+                .get(image)
+                .and_then(|metadata| metadata.rating)
+                .unwrap_or(0);
+        }
+        // Until `precompute_all_ratings` gets to the image, a buffer that was loaded
+        // meanwhile read the same tags
+        match self.metadata.get(image) {
+            Some(metadata) => metadata.rating.unwrap_or(0),
+            None => self
+                .get_image(image)
+                .or_else(|| self.thumbnail_for(image))
+                .map_or(0, |imbuf| imbuf.rating),
         }
     }
 
-    pub fn request_load(&mut self, path: ImageData) {
-        if self.loaded_images.contains_key(&path) || self.currently_loading.contains(&path) {
+    /// Requests the images in the preload window around the current one.
+    pub fn preload_images(&mut self) {
+        let (behind, ahead) = self.preload_window();
+        let start = self.current_image_id.saturating_sub(behind);
+        let end = (self.current_image_id + ahead + 1).min(self.available_images.len());
+        for id in start..end {
+            self.request_load(id);
+        }
+    }
+
+    /// Switches the preload window, requesting whatever it newly covers.
+    pub fn set_preload_strategy(&mut self, strategy: PreloadStrategy) {
+        self.preload_strategy = strategy;
+        self.preload_images();
+    }
+
+    pub fn set_wrap_navigation(&mut self, wrap_navigation: bool) {
+        self.wrap_navigation = wrap_navigation;
+    }
+
+    /// How many images before and after the current one to preload.
+    pub fn preload_window(&self) -> (usize, usize) {
+        match self.preload_strategy {
+            PreloadStrategy::Linear { ahead, behind } => (behind, ahead),
+            PreloadStrategy::Symmetric { radius } => (radius, radius),
+            PreloadStrategy::Adaptive => self.adaptive_preload_window(),
+        }
+    }
+
+    /// Splits `ADAPTIVE_PRELOAD_WINDOW` by the share of recent steps in each
+    /// direction, newer steps counting more. Leans forward before any navigation, and
+    /// always keeps one image on each side for a change of direction.
+    fn adaptive_preload_window(&self) -> (usize, usize) {
+        let (forward, total) = self
+            .navigation_history
+            .iter()
+            .enumerate()
+            .filter(|(_, change)| **change != 0)
+            .fold((0.0, 0.0), |(forward, total), (age, change)| {
+                let weight = (age + 1) as f32;
+                let forward = if *change > 0 {
+                    forward + weight
+                } else {
+                    forward
+                };
+                (forward, total + weight)
+            });
+        let share = if total > 0.0 { forward / total } else { 1.0 };
+        let ahead = ((ADAPTIVE_PRELOAD_WINDOW as f32 * share).round() as usize)
+            .clamp(1, ADAPTIVE_PRELOAD_WINDOW - 1);
+        (ADAPTIVE_PRELOAD_WINDOW - ahead, ahead)
+    }
+
+    fn record_navigation(&mut self, change: i32) {
+        if self.navigation_history.len() == NAVIGATION_HISTORY_LEN {
+            self.navigation_history.pop_front();
+        }
+        self.navigation_history.push_back(change);
+    }
+
+    /// Returns up to `before` images preceding and `after` images following the
+    /// current one (inclusive of the current image), in display order, along with
+    /// their thumbnails if those are already loaded. Images that navigation skips
+    /// over are left out, apart from the current one.
+    pub fn neighbors(
+        &self,
+        before: usize,
+        after: usize,
+    ) -> Vec<(usize, &ImageData, Option<&ImflowImageBuffer>)> {
+        if self.available_images.is_empty() {
+            return Vec::new();
+        }
+        let current = self.current_image_id;
+        let shown = |id: &usize| !self.is_skipped(&self.available_images[*id]);
+        let mut ids = (0..current)
+            .rev()
+            .filter(shown)
+            .take(before)
+            .collect::<Vec<_>>();
+        ids.reverse();
+        ids.push(current);
+        ids.extend(
+            (current + 1..self.available_images.len())
+                .filter(shown)
+                .take(after),
+        );
+        ids.into_iter()
+            .map(|id| {
+                let image = &self.available_images[id];
+                (id, image, self.thumbnail_for(image))
+            })
+            .collect()
+    }
+
+    /// Has the background loads call `wake` whenever they deliver something for
+    /// `check_loaded_images`. Only the first call has an effect.
+    pub fn set_wake(&self, wake: Wake) {
+        let _ = self.wake.set(wake);
+    }
+
+    /// The function passed to `set_wake`, for other work the UI waits on.
+    pub fn wake(&self) -> Option<Wake> {
+        self.wake.get().cloned()
+    }
+
+    /// `(loaded, total)` of the thumbnails requested up front, until all of them
+    /// have been decoded or have failed.
+    pub fn thumbnail_progress(&self) -> Option<(usize, usize)> {
+        (!self.loading.is_done()).then(|| {
+            (
+                self.loading.thumbnails_loaded.load(Ordering::Relaxed),
+                self.loading.thumbnails_total,
+            )
+        })
+    }
+
+    /// Returns the thumbnail for `image` if it has been loaded, without decoding it.
+    pub fn thumbnail_for(&self, image: &ImageData) -> Option<&ImflowImageBuffer> {
+        self.loaded_images_thumbnails.get(image)
+    }
+
+    pub fn request_load(&mut self, id: usize) {
+        let path = self.available_images[id].clone();
+        if self.loaded_images.contains_key(&path)
+            || self.currently_loading.contains(&path)
+            || self.missing.contains(&path)
+            || self.undecodable.contains(&path)
+        {
             return;
         }
-        let tx = self.loader_tx.clone();
         self.currently_loading.insert(path.clone());
+        self.queue_load(path, MessageType::Full, id);
+    }
+
+    /// Decodes the thumbnail of the image at `id` on the pool. Until it arrives
+    /// `get_thumbnail` loads it on demand.
+    pub fn request_thumbnail(&mut self, id: usize) {
+        let path = self.available_images[id].clone();
+        self.queue_load(path, MessageType::Thumbnail, id);
+    }
 
+    fn queue_load(&mut self, path: ImageData, kind: MessageType, id: usize) {
+        self.load_queue.push(path, kind, id, self.current_image_id);
+        let queue = self.load_queue.clone();
+        let tx = self.loader_tx.clone();
+        let partial_tx = self.partial_tx.clone();
+        let loading = self.loading.clone();
+        let metadata_cache = self.metadata_cache.clone();
+        let wake = self.wake.clone();
         self.pool.execute(move || {
-            let image = load_image(&path.clone());
-            let _ = tx.send((path, image));
+            // Not necessarily the load queued along with this job, see `LoadQueue`
+            let Some((path, kind)) = queue.pop() else {
+                return;
+            };
+            let decode_start = Instant::now();
+            let image = if !path.path.is_file() {
+                Err(LoadError::Read(io::ErrorKind::NotFound.into()))
+            } else if kind == MessageType::Full {
+                guarded(|| {
+                    load_image_progressive(&path, &metadata_cache, |partial| {
+                        let _ = partial_tx.send((path.clone(), partial));
+                        if let Some(wake) = wake.get() {
+                            wake();
+                        }
+                    })
+                })
+            } else {
+                guarded(|| load_thumbnail(&path, &metadata_cache))
+            };
+            if kind == MessageType::Thumbnail {
+                loading.thumbnails_loaded.fetch_add(1, Ordering::Relaxed);
+            }
+            let _ = tx.send((path, kind, image, decode_start.elapsed()));
+            if let Some(wake) = wake.get() {
+                wake();
+            }
         });
     }
 
     pub fn check_loaded_images(&mut self) {
-        while let Ok((path, image)) = self.loader_rx.try_recv() {
-            self.loaded_images.insert(path.clone(), image);
+        self.check_ratings();
+        while let Ok((path, partial)) = self.partial_rx.try_recv() {
+            let path = self.renamed.get(&path).cloned().unwrap_or(path);
+            if self.currently_loading.contains(&path) {
+                self.partial_images.insert(path, partial);
+            }
+        }
+        while let Ok((path, message_type, image, decode_time)) = self.loader_rx.try_recv() {
+            self.decoded += 1;
+            if message_type == MessageType::Thumbnail {
+                let path = self.renamed.get(&path).cloned().unwrap_or(path);
+                self.insert_thumbnail(&path, image);
+                continue;
+            }
+            let path = self.renamed.remove(&path).unwrap_or(path);
             self.currently_loading.remove(&path);
+            self.partial_images.remove(&path);
+            self.insert_image(&path, image, decode_time);
+        }
+    }
+
+    /// Caches a thumbnail unless one was loaded on demand in the meantime. One that
+    /// doesn't decode is replaced by the placeholder, so it isn't tried again; whether
+    /// the image itself decodes is up to its full load.
+    fn insert_thumbnail(
+        &mut self,
+        image: &ImageData,
+        thumbnail: Result<ImflowImageBuffer, LoadError>,
+    ) {
+        let thumbnail = match thumbnail {
+            Ok(thumbnail) => thumbnail,
+            Err(e) if is_missing_file(image, &e) => {
+                self.mark_missing(image);
+                return;
+            }
+            Err(e) => {
+                eprintln!(
+                    "Failed to decode the thumbnail of {}: {}",
+                    image.path.display(),
+                    e
+                );
+                self.undecodable_thumbnails.insert(image.clone());
+                ImflowImageBuffer {
+                    rating: self
+                        .metadata
+                        .get(image)
+                        .and_then(|metadata| metadata.rating)
+                        .unwrap_or(0),
+                    ..missing_placeholder()
+                }
+            }
+        };
+        self.loaded_images_thumbnails
+            .entry(image.clone())
+            .or_insert(thumbnail);
+    }
+
+    /// Caches a full decode, or records why there is none.
+    fn insert_image(
+        &mut self,
+        image: &ImageData,
+        result: Result<ImflowImageBuffer, LoadError>,
+        decode_time: Duration,
+    ) {
+        let name = image.path.file_name().unwrap_or_default().to_string_lossy();
+        match result {
+            Ok(buffer) => {
+                self.decode_times.insert(image.clone(), decode_time);
+                self.loaded_images.insert(image.clone(), buffer);
+            }
+            Err(e) if is_missing_file(image, &e) => {
+                self.errors.push(format!("Could not load {}", name));
+                self.mark_missing(image);
+            }
+            Err(e) => {
+                self.errors.push(format!("Can't decode {}: {}", name, e));
+                self.undecodable.insert(image.clone());
+            }
         }
     }
 
-    pub fn next_image(&mut self, change: i32) {
-        self.current_image_id = (self.current_image_id as i32 + change)
-            .clamp(0, self.available_images.len() as i32 - 1)
-            as usize;
+    /// Moves `change` images forward or backward, skipping over missing and filtered
+    /// out files. Past either end it stops, or continues at the other end with
+    /// `wrap_navigation`.
+    pub fn next_image(&mut self, change: i32) -> Option<Wrapped> {
+        if self.is_empty() {
+            return None;
+        }
+        self.record_navigation(change);
+        if self.wrap_navigation {
+            return self.next_image_wrapping(change);
+        }
+        let last = self.available_images.len() as i32 - 1;
+        let current = self.current_image_id as i32;
+        let target = (current + change).clamp(0, last);
+        let skipped = |id: i32| self.is_skipped(&self.available_images[id as usize]);
+        let mut id = target;
+        while change != 0 && (0..=last).contains(&id) && skipped(id) {
+            id += change.signum();
+        }
+        if !(0..=last).contains(&id) {
+            // Everything from the target to the end is hidden, so settle for the
+            // visible image closest to the target on the way back
+            id = target;
+            while id != current && skipped(id) {
+                id -= change.signum();
+            }
+            if id == current {
+                return None;
+            }
+        }
+        self.jump_to_image(id as usize);
+        None
+    }
+
+    fn next_image_wrapping(&mut self, change: i32) -> Option<Wrapped> {
+        let len = self.available_images.len() as i64;
+        let mut new_id = self.current_image_id as i64 + change as i64;
+        let mut skipped = 0;
+        while change != 0
+            && self.is_skipped(&self.available_images[new_id.rem_euclid(len) as usize])
+        {
+            // Every image is skipped
+            if skipped == len {
+                return None;
+            }
+            new_id += change.signum() as i64;
+            skipped += 1;
+        }
+        self.jump_to_image(new_id.rem_euclid(len) as usize);
+        if new_id >= len {
+            Some(Wrapped::ToStart)
+        } else if new_id < 0 {
+            Some(Wrapped::ToEnd)
+        } else {
+            None
+        }
+    }
+
+    /// Jumps to the first image that isn't missing or filtered out.
+    pub fn jump_first(&mut self) {
+        if let Some(id) = self
+            .available_images
+            .iter()
+            .position(|image| !self.is_skipped(image))
+        {
+            self.jump_to_image(id);
+        }
+    }
+
+    /// Jumps to the last image that isn't missing or filtered out.
+    pub fn jump_last(&mut self) {
+        if let Some(id) = self
+            .available_images
+            .iter()
+            .rposition(|image| !self.is_skipped(image))
+        {
+            self.jump_to_image(id);
+        }
+    }
+
+    pub fn jump_to_image(&mut self, id: usize) {
+        if self.is_empty() {
+            return;
+        }
+        self.current_image_id = id.min(self.available_images.len() - 1);
 
         let new_path = self.available_images[self.current_image_id].clone();
-        if !self.loaded_images.contains_key(&new_path) {
-            self.request_load(new_path.clone());
+        self.refresh_if_changed(&new_path);
+        // Images passed over while scrubbing were never really looked at
+        if new_path != self.current_image_path && !self.scrubbing {
+            self.previous_image = Some(self.current_image_path.clone());
         }
         self.current_image_path = new_path;
-        self.preload_next_images(PRELOAD_NEXT_IMAGE_N);
+        if !self.scrubbing {
+            self.load_queue.reprioritize(self.current_image_id);
+            self.request_load(self.current_image_id);
+            self.preload_images();
+        }
+    }
+
+    /// While scrubbing, moving between images shows thumbnails only and queues no full
+    /// decodes, which would otherwise pile up for every image passed over. Ending the
+    /// scrub loads the image it stopped on.
+    pub fn set_scrubbing(&mut self, scrubbing: bool) {
+        let was_scrubbing = self.scrubbing;
+        self.scrubbing = scrubbing;
+        if was_scrubbing && !scrubbing {
+            self.jump_to_image(self.current_image_id);
+        }
+    }
+
+    pub fn is_scrubbing(&self) -> bool {
+        self.scrubbing
+    }
+
+    /// Splits the images into runs of consecutive frames whose capture times are
+    /// at most `max_gap` apart. Images without a capture time end up on their own.
+    pub fn group_bursts(&mut self, max_gap: Duration) -> &[Range<usize>] {
+        let max_gap = TimeDelta::from_std(max_gap).unwrap_or(TimeDelta::MAX);
+        let mut bursts = Vec::new();
+        let mut start = 0;
+        for id in 1..=self.available_images.len() {
+            let continues = id < self.available_images.len()
+                && match (
+                    self.capture_time(&self.available_images[id - 1]),
+                    self.capture_time(&self.available_images[id]),
+                ) {
+                    (Some(prev), Some(next)) => (next - prev).abs() <= max_gap,
+                    _ => false,
+                };
+            if !continues {
+                bursts.push(start..id);
+                start = id;
+            }
+        }
+        self.bursts = bursts;
+        &self.bursts
+    }
+
+    pub fn metadata(&self, image: &ImageData) -> Option<&ImageMetadata> {
+        self.metadata.get(image)
+    }
+
+    pub fn capture_time(&self, image: &ImageData) -> Option<NaiveDateTime> {
+        self.metadata
+            .get(image)
+            .and_then(|metadata| metadata.capture_time)
+    }
+
+    pub fn current_burst(&self) -> Option<&Range<usize>> {
+        self.bursts
+            .iter()
+            .find(|burst| burst.contains(&self.current_image_id))
+    }
+
+    pub fn current_image_id(&self) -> usize {
+        self.current_image_id
+    }
+
+    /// Number of images taken on each day, over the images navigation stops at.
+    pub fn capture_date_counts(&self) -> BTreeMap<NaiveDate, usize> {
+        let mut counts = BTreeMap::new();
+        for image in &self.available_images {
+            if let Some(date) = image.capture_date
+                && !self.is_skipped(image)
+            {
+                *counts.entry(date).or_default() += 1;
+            }
+        }
+        counts
+    }
+
+    /// Jumps to the first image taken on `date`. Returns false if there is none.
+    pub fn jump_to_date(&mut self, date: NaiveDate) -> bool {
+        let Some(id) = self
+            .available_images
+            .iter()
+            .position(|image| image.capture_date == Some(date) && !self.is_skipped(image))
+        else {
+            return false;
+        };
+        self.jump_to_image(id);
+        true
+    }
+
+    pub fn image_id(&self, image: &ImageData) -> Option<usize> {
+        self.available_images
+            .iter()
+            .position(|other| other == image)
+    }
+
+    pub fn image_count(&self) -> usize {
+        self.available_images.len()
+    }
+
+    pub fn load_stats(&self) -> LoadStats {
+        LoadStats {
+            active_workers: self.pool.active_count(),
+            loading: self.currently_loading.len(),
+            loaded: self.loaded_images.len(),
+            thumbnails: self.loaded_images_thumbnails.len(),
+            decoded: self.decoded,
+            memory: self
+                .loaded_images
+                .values()
+                .chain(self.loaded_images_thumbnails.values())
+                .map(|imbuf| imbuf.rgba_buffer.len() * 4)
+                .sum(),
+            current_decode_time: self.decode_times.get(&self.current_image_path).copied(),
+        }
+    }
+
+    /// Jumps to the first frame of the burst `change` groups away from the current one.
+    pub fn next_burst(&mut self, change: i32) {
+        let Some(current) = self
+            .bursts
+            .iter()
+            .position(|burst| burst.contains(&self.current_image_id))
+        else {
+            self.next_image(change);
+            return;
+        };
+        let target = (current as i32 + change).clamp(0, self.bursts.len() as i32 - 1) as usize;
+        self.jump_to_image(self.bursts[target].start);
+    }
+
+    /// Jumps to the first frame of the `change`th burst of more than one frame from
+    /// the current image, passing over single shots. Stays put if there is none.
+    pub fn skip_to_burst(&mut self, change: i32) {
+        let current = self.current_image_id;
+        let sequences = self.bursts.iter().filter(|burst| burst.len() > 1);
+        let target = if change > 0 {
+            sequences
+                .filter(|burst| burst.start > current)
+                .nth(change as usize - 1)
+        } else {
+            // Going back from inside a burst starts counting at its own first frame
+            sequences
+                .rev()
+                .filter(|burst| burst.start < current)
+                .nth(change.unsigned_abs() as usize - 1)
+        };
+        if let Some(target) = target.cloned() {
+            self.jump_to_image(target.start);
+        }
+    }
+
+    /// The current burst's number among the bursts of more than one frame, and how
+    /// many of those there are. `None` outside of them.
+    pub fn burst_number(&self) -> Option<(usize, usize)> {
+        let mut sequences = self.bursts.iter().filter(|burst| burst.len() > 1);
+        let count = sequences.clone().count();
+        let index = sequences.position(|burst| burst.contains(&self.current_image_id))?;
+        Some((index + 1, count))
+    }
+
+    /// Moves within the current burst without leaving it.
+    pub fn next_image_in_burst(&mut self, change: i32) {
+        let Some(burst) = self.current_burst().cloned() else {
+            self.next_image(change);
+            return;
+        };
+        let id = (self.current_image_id as i32 + change)
+            .clamp(burst.start as i32, burst.end as i32 - 1) as usize;
+        self.jump_to_image(id);
+    }
+
+    /// Takes the newest early decode of the current image, if one arrived since the
+    /// last call.
+    pub fn take_partial_image(&mut self) -> Option<ImflowImageBuffer> {
+        self.partial_images.remove(&self.current_image_path)
+    }
+
+    pub fn previous_image(&self) -> Option<&ImageData> {
+        self.previous_image.as_ref()
+    }
+
+    pub fn load_state(&self, image: &ImageData) -> LoadState {
+        if self.loaded_images.contains_key(image) {
+            LoadState::Loaded
+        } else if self.is_missing(image) {
+            LoadState::Missing
+        } else if self.undecodable.contains(image) {
+            LoadState::Failed
+        } else {
+            LoadState::Loading
+        }
     }
 
     pub fn get_current_image(&self) -> Option<&ImflowImageBuffer> {
@@ -160,22 +1633,239 @@ impl ImageStore {
     }
 
     pub fn get_thumbnail(&mut self) -> &ImflowImageBuffer {
-        if self
-            .loaded_images_thumbnails
-            .contains_key(&self.current_image_path)
-        {
-            return self
-                .loaded_images_thumbnails
-                .get(&self.current_image_path)
+        let current = self.current_image_path.clone();
+        if !self.is_missing(&current) && !self.loaded_images_thumbnails.contains_key(&current) {
+            let thumbnail = guarded(|| load_thumbnail(&current, &self.metadata_cache));
+            self.insert_thumbnail(&current, thumbnail);
+        }
+        // Missing files have their thumbnail dropped
+        self.loaded_images_thumbnails
+            .get(&current)
+            .unwrap_or(&self.placeholder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// A store over `count` solid 4×4 JPEGs named `0.jpg`, `1.jpg` and so on. The
+    /// directory is deleted when the returned `TempDir` is dropped. Each store has
+    /// a metadata cache of its own, so tests don't see what other tests read.
+    fn synthetic_store(count: u8, config: &Config) -> (TempDir, ImageStore) {
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..count {
+            ::image::RgbImage::from_pixel(4, 4, ::image::Rgb([i * 40, 128, 255 - i * 40]))
+                .save(dir.path().join(format!("{}.jpg", i)))
                 .unwrap();
         }
+        let store = ImageStore::new(dir.path().to_path_buf(), config, Arc::default());
+        (dir, store)
+    }
 
-        let buf = load_thumbnail(&self.current_image_path);
-        self.loaded_images_thumbnails
-            .insert(self.current_image_path.clone(), buf);
-        return self
-            .loaded_images_thumbnails
-            .get(&self.current_image_path)
-            .unwrap();
+    fn file_name(image: &ImageData) -> &str {
+        image.path.file_name().unwrap().to_str().unwrap()
+    }
+
+    #[test]
+    fn next_image_moves_both_ways_and_stops_at_the_ends() {
+        let (_dir, mut store) = synthetic_store(3, &Config::default());
+        assert_eq!(store.image_count(), 3);
+        assert_eq!(file_name(&store.current_image_path), "0.jpg");
+
+        store.next_image(1);
+        assert_eq!(store.current_image_id(), 1);
+        assert_eq!(file_name(&store.current_image_path), "1.jpg");
+
+        assert!(store.next_image(5).is_none());
+        assert_eq!(store.current_image_id(), 2);
+
+        store.next_image(-1);
+        assert_eq!(file_name(&store.current_image_path), "1.jpg");
+        store.next_image(-5);
+        assert_eq!(store.current_image_id(), 0);
+    }
+
+    #[test]
+    fn next_image_wraps_when_enabled() {
+        let config = Config {
+            wrap_navigation: true,
+            ..Config::default()
+        };
+        let (_dir, mut store) = synthetic_store(3, &config);
+        assert!(matches!(store.next_image(-1), Some(Wrapped::ToEnd)));
+        assert_eq!(store.current_image_id(), 2);
+        assert!(matches!(store.next_image(1), Some(Wrapped::ToStart)));
+        assert_eq!(store.current_image_id(), 0);
+    }
+
+    #[test]
+    fn rating_round_trips_through_the_file() {
+        let config = Config::default();
+        let (dir, mut store) = synthetic_store(2, &config);
+        assert_eq!(store.get_current_rating(), 0);
+
+        store.set_rating(4);
+        assert!(store.take_errors().is_empty());
+        assert_eq!(store.get_current_rating(), 4);
+
+        let reopened = ImageStore::new(dir.path().to_path_buf(), &config, Arc::default());
+        assert_eq!(reopened.get_current_rating(), 4);
+        assert_eq!(reopened.get_image_rating(&reopened.available_images[1]), 0);
+    }
+
+    #[test]
+    fn preloading_twice_submits_each_image_once() {
+        let (_dir, mut store) = synthetic_store(3, &Config::default());
+        // The first image is decoded up front, the other two are preloading
+        assert_eq!(store.currently_loading.len(), 2);
+        store.preload_images();
+        assert_eq!(store.currently_loading.len(), 2);
+
+        store.pool.join();
+        store.check_loaded_images();
+        // One full image and one thumbnail for each image after the first
+        assert_eq!(store.load_stats().decoded, 4);
+        assert!(store.currently_loading.is_empty());
+    }
+
+    #[test]
+    fn check_loaded_images_moves_finished_loads() {
+        let config = Config {
+            preload: PreloadStrategy::Linear {
+                ahead: 0,
+                behind: 0,
+            },
+            ..Config::default()
+        };
+        let (_dir, mut store) = synthetic_store(2, &config);
+        let second = store.available_images[1].clone();
+        assert!(store.currently_loading.is_empty());
+
+        store.request_load(1);
+        assert!(store.currently_loading.contains(&second));
+        assert!(!store.loaded_images.contains_key(&second));
+
+        store.pool.join();
+        store.check_loaded_images();
+        assert!(!store.currently_loading.contains(&second));
+        let image = store.get_image(&second).unwrap();
+        assert_eq!((image.width, image.height), (4, 4));
+    }
+
+    #[test]
+    fn flag_filter_skips_hidden_images() {
+        let (_dir, mut store) = synthetic_store(3, &Config::default());
+        let second = store.available_images[1].clone();
+        store.set_image_flag(&second, FlagStatus::Rejected).unwrap();
+        store.filter_flags(true, false, true);
+
+        store.next_image(1);
+        assert_eq!(file_name(&store.current_image_path), "2.jpg");
+        store.next_image(-1);
+        assert_eq!(file_name(&store.current_image_path), "0.jpg");
+        assert_eq!(store.visible_images().len(), 2);
+        assert_eq!(store.visible_position(), (Some(1), 2));
+        store.next_image(1);
+        assert_eq!(store.visible_position(), (Some(2), 2));
+
+        // Filtering out the image on screen leaves it there without a position
+        store.filter_flags(false, true, false);
+        assert_eq!(store.visible_position(), (None, 1));
+    }
+
+    #[test]
+    fn histograms_count_the_filtered_images_apart() {
+        let (_dir, mut store) = synthetic_store(3, &Config::default());
+        let [first, second, third] = [0, 1, 2].map(|id| store.available_images[id].clone());
+        store.set_image_rating(&first, 5).unwrap();
+        store.set_image_flag(&first, FlagStatus::Rejected).unwrap();
+        store.set_image_rating(&second, 2).unwrap();
+        store.set_image_label(&third, "red").unwrap();
+        store.filter_flags(true, false, true);
+
+        assert_eq!(store.rating_histogram(false), [1, 0, 0, 1, 0, 0, 1]);
+        assert_eq!(store.rating_histogram(true), [1, 0, 0, 1, 0, 0, 0]);
+        assert_eq!(store.label_histogram(false), [2, 1, 0, 0, 0, 0]);
+        assert_eq!(store.label_histogram(true), [1, 1, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn next_image_stops_short_of_hidden_images_at_the_end() {
+        let (_dir, mut store) = synthetic_store(4, &Config::default());
+        let last = store.available_images[3].clone();
+        store.set_image_flag(&last, FlagStatus::Rejected).unwrap();
+        store.filter_flags(true, false, true);
+
+        store.next_image(10);
+        assert_eq!(file_name(&store.current_image_path), "2.jpg");
+        store.next_image(1);
+        assert_eq!(file_name(&store.current_image_path), "2.jpg");
+    }
+
+    fn neighbor_ids(store: &ImageStore, before: usize, after: usize) -> Vec<usize> {
+        store
+            .neighbors(before, after)
+            .into_iter()
+            .map(|(id, _, _)| id)
+            .collect()
+    }
+
+    #[test]
+    fn neighbors_are_cut_short_at_the_ends() {
+        let (_dir, mut store) = synthetic_store(5, &Config::default());
+        assert_eq!(neighbor_ids(&store, 2, 2), [0, 1, 2]);
+
+        store.jump_to_image(2);
+        assert_eq!(neighbor_ids(&store, 1, 1), [1, 2, 3]);
+        assert_eq!(neighbor_ids(&store, 0, 0), [2]);
+
+        store.jump_last();
+        assert_eq!(neighbor_ids(&store, 2, 2), [2, 3, 4]);
+        assert_eq!(neighbor_ids(&store, 10, 10), [0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn neighbors_leave_out_filtered_images() {
+        let (_dir, mut store) = synthetic_store(5, &Config::default());
+        for id in [1, 3] {
+            let image = store.available_images[id].clone();
+            store.set_image_flag(&image, FlagStatus::Rejected).unwrap();
+        }
+        store.filter_flags(true, false, true);
+
+        store.jump_to_image(2);
+        assert_eq!(neighbor_ids(&store, 1, 1), [0, 2, 4]);
+        assert_eq!(neighbor_ids(&store, 5, 5), [0, 2, 4]);
+
+        // The current image stays in even when it is filtered out itself
+        store.jump_to_image(3);
+        assert_eq!(neighbor_ids(&store, 1, 1), [2, 3, 4]);
+
+        store.filter_flags(false, true, false);
+        store.jump_first();
+        assert_eq!(neighbor_ids(&store, 2, 2), [1, 3]);
+    }
+
+    #[test]
+    fn thumbnail_for_does_not_decode() {
+        // A fresh directory and cache, so nothing is decoded or cached beforehand
+        let (_dir, mut store) = synthetic_store(2, &Config::default());
+        let first = store.available_images[0].clone();
+        let second = store.available_images[1].clone();
+        // Only the first thumbnail is decoded up front
+        assert!(store.thumbnail_for(&first).is_some());
+        assert!(store.thumbnail_for(&second).is_none());
+        assert!(
+            store
+                .neighbors(0, 1)
+                .iter()
+                .all(|(id, _, thumbnail)| thumbnail.is_some() == (*id == 0))
+        );
+
+        store.pool.join();
+        store.check_loaded_images();
+        assert!(store.thumbnail_for(&second).is_some());
     }
 }
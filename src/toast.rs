@@ -0,0 +1,159 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+const DEFAULT_DURATION: Duration = Duration::from_secs(2);
+// Toasts fade out over the end of their duration
+const FADE_DURATION: Duration = Duration::from_millis(500);
+// Older toasts wait off screen until there is room for them
+const MAX_VISIBLE: usize = 4;
+// Gap between stacked toasts and from the window edge, in points
+const TOAST_SPACING: f32 = 8.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ToastKind {
+    Info,
+    Warning,
+    Error,
+}
+
+impl ToastKind {
+    fn color(self) -> Option<egui::Color32> {
+        match self {
+            ToastKind::Info => None,
+            ToastKind::Warning => Some(egui::Color32::YELLOW),
+            ToastKind::Error => Some(egui::Color32::LIGHT_RED),
+        }
+    }
+}
+
+pub struct Toast {
+    pub message: String,
+    pub kind: ToastKind,
+    pub created_at: Instant,
+    pub duration: Duration,
+    /// How many times the same message was pushed while this toast was up
+    pub count: usize,
+    /// Keeps the egui window of a toast apart from the ones around it
+    id: u64,
+    /// Set on toasts that report progress, see `push_progress`
+    tag: Option<&'static str>,
+}
+
+impl Toast {
+    fn is_expired(&self, now: Instant) -> bool {
+        now.duration_since(self.created_at) >= self.duration
+    }
+
+    fn opacity(&self, now: Instant) -> f32 {
+        let remaining = self
+            .duration
+            .saturating_sub(now.duration_since(self.created_at));
+        (remaining.as_secs_f32() / FADE_DURATION.as_secs_f32()).min(1.0)
+    }
+
+    fn text(&self) -> String {
+        if self.count > 1 {
+            format!("{} ×{}", self.message, self.count)
+        } else {
+            self.message.clone()
+        }
+    }
+}
+
+/// Short messages shown in the top right corner until they fade out or are clicked
+/// away. Pushing a message that is already up restarts it with a counter instead of
+/// stacking a copy.
+#[derive(Default)]
+pub struct ToastQueue {
+    toasts: VecDeque<Toast>,
+    next_id: u64,
+}
+
+impl ToastQueue {
+    pub fn push(&mut self, kind: ToastKind, message: impl Into<String>) {
+        let message = message.into();
+        let now = Instant::now();
+        let count = match self.toasts.iter().position(|toast| {
+            toast.kind == kind && toast.message == message && !toast.is_expired(now)
+        }) {
+            Some(index) => self.toasts.remove(index).unwrap().count + 1,
+            None => 1,
+        };
+        self.toasts.push_back(Toast {
+            message,
+            kind,
+            created_at: now,
+            duration: DEFAULT_DURATION,
+            count,
+            id: self.next_id,
+            tag: None,
+        });
+        self.next_id += 1;
+    }
+
+    /// Shows `message` in the toast tagged `tag`, updating it in place while it is
+    /// still up, for work that reports how far it has come.
+    pub fn push_progress(&mut self, tag: &'static str, message: impl Into<String>) {
+        let message = message.into();
+        let now = Instant::now();
+        if let Some(toast) = self
+            .toasts
+            .iter_mut()
+            .find(|toast| toast.tag == Some(tag) && !toast.is_expired(now))
+        {
+            toast.message = message;
+            toast.created_at = now;
+            return;
+        }
+        self.push(ToastKind::Info, message);
+        self.toasts.back_mut().unwrap().tag = Some(tag);
+    }
+
+    pub fn push_info(&mut self, message: impl Into<String>) {
+        self.push(ToastKind::Info, message);
+    }
+
+    pub fn push_warning(&mut self, message: impl Into<String>) {
+        self.push(ToastKind::Warning, message);
+    }
+
+    pub fn push_error(&mut self, message: impl Into<String>) {
+        self.push(ToastKind::Error, message);
+    }
+
+    /// Drops expired toasts and draws the newest few, newest at the top.
+    pub fn show(&mut self, ctx: &egui::Context) {
+        let now = Instant::now();
+        self.toasts.retain(|toast| !toast.is_expired(now));
+        if !self.toasts.is_empty() {
+            // Keeps the fade going when nothing else is redrawing
+            ctx.request_repaint();
+        }
+        let mut dismissed = None;
+        let mut offset = TOAST_SPACING;
+        for toast in self.toasts.iter().rev().take(MAX_VISIBLE) {
+            let opacity = toast.opacity(now);
+            let response = egui::Window::new(format!("toast_{}", toast.id))
+                .title_bar(false)
+                .resizable(false)
+                .frame(egui::Frame::popup(&ctx.style()).multiply_with_opacity(opacity))
+                .anchor(egui::Align2::RIGHT_TOP, [-TOAST_SPACING, offset])
+                .show(ctx, |ui| {
+                    ui.multiply_opacity(opacity);
+                    let mut text = egui::RichText::new(toast.text());
+                    if let Some(color) = toast.kind.color() {
+                        text = text.color(color);
+                    }
+                    ui.add(egui::Label::new(text).sense(egui::Sense::click()))
+                        .clicked()
+                });
+            if let Some(response) = response {
+                offset += response.response.rect.height() + TOAST_SPACING;
+                if response.inner == Some(true) {
+                    dismissed = Some(toast.id);
+                }
+            }
+        }
+        self.toasts.retain(|toast| Some(toast.id) != dismissed);
+    }
+}